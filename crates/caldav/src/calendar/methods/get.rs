@@ -21,7 +21,7 @@ pub async fn route_get<C: CalendarStore, DP: DavPushStore>(
     user: Principal,
     method: Method,
 ) -> Result<Response, Error> {
-    if !user.is_principal(&principal) {
+    if !user.is_principal(&principal) || !user.can_access_collection(&principal, &calendar_id) {
         return Err(crate::Error::Unauthorized);
     }
 
@@ -22,7 +22,7 @@ pub async fn route_import<C: CalendarStore, DP: DavPushStore>(
     overwrite: Option<TypedHeader<Overwrite>>,
     body: String,
 ) -> Result<Response, Error> {
-    if !user.is_principal(&principal) {
+    if !user.is_principal(&principal) || !user.can_access_collection(&principal, &cal_id) {
         return Err(Error::Unauthorized);
     }
 
@@ -72,7 +72,7 @@ pub async fn route_mkcalendar<C: CalendarStore, DP: DavPushStore>(
     method: Method,
     body: String,
 ) -> Result<Response, Error> {
-    if !user.is_principal(&principal) {
+    if !user.is_principal(&principal) || !user.can_access_collection(&principal, &cal_id) {
         return Err(Error::Unauthorized);
     }
 
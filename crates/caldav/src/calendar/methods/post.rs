@@ -20,7 +20,7 @@ pub async fn route_post<C: CalendarStore, DP: DavPushStore>(
     State(resource_service): State<CalendarResourceService<C, DP>>,
     body: String,
 ) -> Result<Response, Error> {
-    if !user.is_principal(&principal) {
+    if !user.is_principal(&principal) || !user.can_access_collection(&principal, &cal_id) {
         return Err(Error::Unauthorized);
     }
 
@@ -31,6 +31,7 @@ pub async fn route_post<C: CalendarStore, DP: DavPushStore>(
     let calendar_resource = CalendarResource {
         cal: calendar,
         read_only: true,
+        vapid_public_key: None,
     };
 
     if !calendar_resource
@@ -58,6 +58,7 @@ fn objects_response(
     not_found: Vec<String>,
     path: &str,
     principal: &str,
+    cal_id: &str,
     puri: &impl PrincipalUri,
     user: &Principal,
     prop: &PropfindType<CalendarObjectPropWrapperName>,
@@ -74,6 +75,7 @@ fn objects_response(
                 object,
                 object_id,
                 principal: principal.to_owned(),
+                calendar_id: cal_id.to_owned(),
             }
             .propfind(&path, prop, None, puri, user)?,
         );
@@ -105,7 +107,7 @@ pub async fn route_report_calendar<C: CalendarStore, DP: DavPushStore>(
     matched_path: MatchedPath,
     body: String,
 ) -> Result<impl IntoResponse, Error> {
-    if !user.is_principal(&principal) {
+    if !user.is_principal(&principal) || !user.can_access_collection(&principal, &cal_id) {
         return Err(Error::Unauthorized);
     }
 
@@ -117,7 +119,16 @@ pub async fn route_report_calendar<C: CalendarStore, DP: DavPushStore>(
             let objects =
                 get_objects_calendar_query(cal_query, &principal, &cal_id, cal_store.as_ref())
                     .await?;
-            objects_response(objects, vec![], uri.path(), &principal, &puri, &user, props)?
+            objects_response(
+                objects,
+                vec![],
+                uri.path(),
+                &principal,
+                &cal_id,
+                &puri,
+                &user,
+                props,
+            )?
         }
         ReportRequest::CalendarMultiget(cal_multiget) => {
             let (objects, not_found) = get_objects_calendar_multiget(
@@ -133,6 +144,7 @@ pub async fn route_report_calendar<C: CalendarStore, DP: DavPushStore>(
                 not_found,
                 uri.path(),
                 &principal,
+                &cal_id,
                 &puri,
                 &user,
                 props,
@@ -334,6 +346,7 @@ END:VCALENDAR"
             vec!["/caldav/principal/user/not%20found.ics".to_string()],
             "/caldav/principal/user%40rustical.dev/cal",
             "user@rustical.dev",
+            "cal",
             &CalDavPrincipalUri::new("/caldav"),
             &Principal {
                 id: "user@rustical.dev".to_string(),
@@ -341,6 +354,7 @@ END:VCALENDAR"
                 principal_type: rustical_store::auth::PrincipalType::Individual,
                 password: None,
                 memberships: vec![],
+                app_token: None,
             },
             &PropfindType::Propname,
         )
@@ -42,6 +42,7 @@ pub async fn handle_sync_collection<C: CalendarStore>(
                 object,
                 object_id,
                 principal: principal.to_owned(),
+                calendar_id: cal_id.to_owned(),
             }
             .propfind(&path, &sync_collection.prop, None, puri, user)?,
         );
@@ -16,7 +16,7 @@ use rustical_dav::resourcetype;
 use rustical_dav::xml::{HrefElement, Resourcetype, SupportedReportSet};
 use rustical_dav_push::{DavPushExtension, DavPushExtensionProp};
 use rustical_store::Calendar;
-use rustical_store::auth::Principal;
+use rustical_store::auth::{DavService, Principal};
 use rustical_xml::{EnumVariants, PropName};
 use rustical_xml::{XmlDeserialize, XmlSerialize};
 use serde::Deserialize;
@@ -74,6 +74,7 @@ pub enum CalendarPropWrapper {
 pub struct CalendarResource {
     pub cal: Calendar,
     pub read_only: bool,
+    pub vapid_public_key: Option<String>,
 }
 
 impl ResourceName for CalendarResource {
@@ -98,6 +99,10 @@ impl DavPushExtension for CalendarResource {
     fn get_topic(&self) -> String {
         self.cal.push_topic.clone()
     }
+
+    fn get_vapid_public_key(&self) -> Option<&str> {
+        self.vapid_public_key.as_deref()
+    }
 }
 
 impl Resource for CalendarResource {
@@ -324,14 +329,17 @@ impl Resource for CalendarResource {
     }
 
     fn get_user_privileges(&self, user: &Principal) -> Result<UserPrivilegeSet, Self::Error> {
-        if self.cal.subscription_url.is_some() || self.read_only {
-            return Ok(UserPrivilegeSet::owner_write_properties(
-                user.is_principal(&self.cal.principal),
-            ));
-        }
-
-        Ok(UserPrivilegeSet::owner_only(
-            user.is_principal(&self.cal.principal),
+        let is_owner = user.is_principal(&self.cal.principal);
+        let privileges = if self.cal.subscription_url.is_some() || self.read_only {
+            UserPrivilegeSet::owner_write_properties(is_owner)
+        } else {
+            UserPrivilegeSet::owner_only(is_owner)
+        };
+        Ok(user.scoped_privileges(
+            DavService::Caldav,
+            &self.cal.principal,
+            Some(&self.cal.id),
+            privileges,
         ))
     }
 }
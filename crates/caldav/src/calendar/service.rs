@@ -23,6 +23,7 @@ pub struct CalendarResourceService<C: CalendarStore, DP: DavPushStore> {
     pub(crate) cal_store: Arc<C>,
     pub(crate) dav_push_store: Arc<DP>,
     pub(crate) config: Arc<CalDavConfig>,
+    pub(crate) vapid_public_key: Option<String>,
 }
 
 impl<C: CalendarStore, DP: DavPushStore> Clone for CalendarResourceService<C, DP> {
@@ -31,6 +32,7 @@ impl<C: CalendarStore, DP: DavPushStore> Clone for CalendarResourceService<C, DP
             cal_store: self.cal_store.clone(),
             dav_push_store: self.dav_push_store.clone(),
             config: self.config.clone(),
+            vapid_public_key: self.vapid_public_key.clone(),
         }
     }
 }
@@ -40,11 +42,13 @@ impl<C: CalendarStore, DP: DavPushStore> CalendarResourceService<C, DP> {
         cal_store: Arc<C>,
         dav_push_store: Arc<DP>,
         config: Arc<CalDavConfig>,
+        vapid_public_key: Option<String>,
     ) -> Self {
         Self {
             cal_store,
             dav_push_store,
             config,
+            vapid_public_key,
         }
     }
 }
@@ -72,6 +76,7 @@ impl<C: CalendarStore, DP: DavPushStore> ResourceService for CalendarResourceSer
         Ok(CalendarResource {
             cal: calendar,
             read_only: self.cal_store.is_read_only(cal_id),
+            vapid_public_key: self.vapid_public_key.clone(),
         })
     }
 
@@ -88,6 +93,7 @@ impl<C: CalendarStore, DP: DavPushStore> ResourceService for CalendarResourceSer
                 object,
                 object_id,
                 principal: principal.to_owned(),
+                calendar_id: cal_id.to_owned(),
             })
             .collect())
     }
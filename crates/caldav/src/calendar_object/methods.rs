@@ -28,7 +28,7 @@ pub async fn get_event<C: CalendarStore>(
     user: Principal,
     method: Method,
 ) -> Result<Response, Error> {
-    if !user.is_principal(&principal) {
+    if !user.is_principal(&principal) || !user.can_access_collection(&principal, &calendar_id) {
         return Err(crate::Error::Unauthorized);
     }
 
@@ -70,7 +70,7 @@ pub async fn put_event<C: CalendarStore>(
     header_map: HeaderMap,
     body: String,
 ) -> Result<Response, Error> {
-    if !user.is_principal(&principal) {
+    if !user.is_principal(&principal) || !user.can_access_collection(&principal, &calendar_id) {
         return Err(crate::Error::Unauthorized);
     }
 
@@ -13,7 +13,7 @@ use rustical_dav::{
     xml::Resourcetype,
 };
 use rustical_ical::CalendarObject;
-use rustical_store::auth::Principal;
+use rustical_store::auth::{DavService, Principal};
 use std::borrow::Cow;
 
 #[derive(Clone, From, Into)]
@@ -21,6 +21,7 @@ pub struct CalendarObjectResource {
     pub object: CalendarObject,
     pub object_id: String,
     pub principal: String,
+    pub calendar_id: String,
 }
 
 impl ResourceName for CalendarObjectResource {
@@ -92,8 +93,11 @@ impl Resource for CalendarObjectResource {
     }
 
     fn get_user_privileges(&self, user: &Principal) -> Result<UserPrivilegeSet, Self::Error> {
-        Ok(UserPrivilegeSet::owner_only(
-            user.is_principal(&self.principal),
+        Ok(user.scoped_privileges(
+            DavService::Caldav,
+            &self.principal,
+            Some(&self.calendar_id),
+            UserPrivilegeSet::owner_only(user.is_principal(&self.principal)),
         ))
     }
 }
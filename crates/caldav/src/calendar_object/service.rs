@@ -70,6 +70,7 @@ impl<C: CalendarStore> ResourceService for CalendarObjectResourceService<C> {
             object,
             object_id: object_id.to_owned(),
             principal: principal.to_owned(),
+            calendar_id: calendar_id.to_owned(),
         })
     }
 
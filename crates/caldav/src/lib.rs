@@ -10,7 +10,7 @@ use rustical_dav::rfc_3986_percent_encode;
 use rustical_dav_push::DavPushStore;
 use rustical_store::CalendarStore;
 use rustical_store::auth::middleware::AuthenticationLayer;
-use rustical_store::auth::{AuthenticationProvider, Principal};
+use rustical_store::auth::{AuthenticationProvider, DavService, Principal};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
@@ -46,6 +46,7 @@ pub fn caldav_router<AP: AuthenticationProvider, C: CalendarStore, DP: DavPushSt
     dav_push_store: Arc<DP>,
     simplified_home_set: bool,
     config: Arc<CalDavConfig>,
+    vapid_public_key: Option<String>,
 ) -> Router {
     Router::new().nest(
         prefix,
@@ -55,9 +56,10 @@ pub fn caldav_router<AP: AuthenticationProvider, C: CalendarStore, DP: DavPushSt
             cal_store: store,
             simplified_home_set,
             config,
+            vapid_public_key,
         })
         .axum_router()
-        .layer(AuthenticationLayer::new(auth_provider))
+        .layer(AuthenticationLayer::new(auth_provider).with_service(DavService::Caldav))
         .layer(Extension(CalDavPrincipalUri(prefix))),
     )
 }
@@ -5,7 +5,7 @@ use rustical_dav::privileges::UserPrivilegeSet;
 use rustical_dav::resource::{PrincipalUri, Resource, ResourceName};
 use rustical_dav::resourcetype;
 use rustical_dav::xml::{GroupMemberSet, GroupMembership, Resourcetype, SupportedReportSet};
-use rustical_store::auth::Principal;
+use rustical_store::auth::{DavService, Principal};
 use std::borrow::Cow;
 
 mod service;
@@ -119,8 +119,11 @@ impl Resource for PrincipalResource {
     }
 
     fn get_user_privileges(&self, user: &Principal) -> Result<UserPrivilegeSet, Self::Error> {
-        Ok(UserPrivilegeSet::owner_only(
-            user.is_principal(&self.principal.id),
+        Ok(user.scoped_privileges(
+            DavService::Caldav,
+            &self.principal.id,
+            None,
+            UserPrivilegeSet::owner_only(user.is_principal(&self.principal.id)),
         ))
     }
 }
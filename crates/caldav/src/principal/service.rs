@@ -19,6 +19,8 @@ pub struct PrincipalResourceService<AP: AuthenticationProvider, DP: DavPushStore
     // If true only return the principal as the calendar home set, otherwise also groups
     pub(crate) simplified_home_set: bool,
     pub(crate) config: Arc<CalDavConfig>,
+    // Advertised to clients through the push-transports property
+    pub(crate) vapid_public_key: Option<String>,
 }
 
 impl<AP: AuthenticationProvider, DP: DavPushStore, CS: CalendarStore> Clone
@@ -31,6 +33,7 @@ impl<AP: AuthenticationProvider, DP: DavPushStore, CS: CalendarStore> Clone
             cal_store: self.cal_store.clone(),
             simplified_home_set: self.simplified_home_set,
             config: self.config.clone(),
+            vapid_public_key: self.vapid_public_key.clone(),
         }
     }
 }
@@ -75,6 +78,7 @@ impl<AP: AuthenticationProvider, DP: DavPushStore, CS: CalendarStore> ResourceSe
             .into_iter()
             .map(|cal| CalendarResource {
                 read_only: self.cal_store.is_read_only(&cal.id),
+                vapid_public_key: self.vapid_public_key.clone(),
                 cal,
             })
             .collect())
@@ -88,6 +92,7 @@ impl<AP: AuthenticationProvider, DP: DavPushStore, CS: CalendarStore> ResourceSe
                     self.cal_store.clone(),
                     self.dav_push_store.clone(),
                     self.config.clone(),
+                    self.vapid_public_key.clone(),
                 )
                 .axum_router(),
             )
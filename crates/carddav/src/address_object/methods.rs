@@ -30,14 +30,14 @@ pub async fn get_object<AS: AddressbookStore>(
     user: Principal,
     method: Method,
 ) -> Result<Response, Error> {
-    if !user.is_principal(&principal) {
+    if !user.is_principal(&principal) || !user.can_access_collection(&principal, &addressbook_id) {
         return Err(Error::Unauthorized);
     }
 
     let addressbook = addr_store
         .get_addressbook(&principal, &addressbook_id, false)
         .await?;
-    let addressbook_resource = AddressbookResource(addressbook);
+    let addressbook_resource = AddressbookResource::from(addressbook);
     if !addressbook_resource
         .get_user_privileges(&user)?
         .has(&UserPrivilege::Read)
@@ -74,7 +74,7 @@ pub async fn put_object<AS: AddressbookStore>(
     header_map: HeaderMap,
     body: String,
 ) -> Result<Response, Error> {
-    if !user.is_principal(&principal) {
+    if !user.is_principal(&principal) || !user.can_access_collection(&principal, &addressbook_id) {
         return Err(Error::Unauthorized);
     }
 
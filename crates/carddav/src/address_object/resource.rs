@@ -17,12 +17,13 @@ use rustical_dav::{
     xml::Resourcetype,
 };
 use rustical_ical::AddressObject;
-use rustical_store::auth::Principal;
+use rustical_store::auth::{DavService, Principal};
 
 #[derive(Clone, From, Into)]
 pub struct AddressObjectResource {
     pub object: AddressObject,
     pub principal: String,
+    pub addressbook_id: String,
     pub object_id: String,
 }
 
@@ -88,8 +89,11 @@ impl Resource for AddressObjectResource {
     }
 
     fn get_user_privileges(&self, user: &Principal) -> Result<UserPrivilegeSet, Self::Error> {
-        Ok(UserPrivilegeSet::owner_only(
-            user.is_principal(&self.principal),
+        Ok(user.scoped_privileges(
+            DavService::Carddav,
+            &self.principal,
+            Some(&self.addressbook_id),
+            UserPrivilegeSet::owner_only(user.is_principal(&self.principal)),
         ))
     }
 }
@@ -59,6 +59,7 @@ impl<AS: AddressbookStore> ResourceService for AddressObjectResourceService<AS>
             object,
             object_id: object_id.to_owned(),
             principal: principal.to_owned(),
+            addressbook_id: addressbook_id.to_owned(),
         })
     }
 
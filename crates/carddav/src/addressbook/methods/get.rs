@@ -22,14 +22,14 @@ pub async fn route_get<AS: AddressbookStore, DP: DavPushStore>(
     user: Principal,
     method: Method,
 ) -> Result<Response, Error> {
-    if !user.is_principal(&principal) {
+    if !user.is_principal(&principal) || !user.can_access_collection(&principal, &addressbook_id) {
         return Err(Error::Unauthorized);
     }
 
     let addressbook = addr_store
         .get_addressbook(&principal, &addressbook_id, false)
         .await?;
-    let addressbook_resource = AddressbookResource(addressbook.clone());
+    let addressbook_resource = AddressbookResource::from(addressbook.clone());
     if !addressbook_resource
         .get_user_privileges(&user)?
         .has(&UserPrivilege::Read)
@@ -21,7 +21,7 @@ pub async fn route_import<AS: AddressbookStore, DP: DavPushStore>(
     State(resource_service): State<AddressbookResourceService<AS, DP>>,
     body: String,
 ) -> Result<Response, Error> {
-    if !user.is_principal(&principal) {
+    if !user.is_principal(&principal) || !user.can_access_collection(&principal, &addressbook_id) {
         return Err(Error::Unauthorized);
     }
 
@@ -49,7 +49,7 @@ pub async fn route_mkcol<AS: AddressbookStore, DP: DavPushStore>(
     State(AddressbookResourceService { addr_store, .. }): State<AddressbookResourceService<AS, DP>>,
     body: String,
 ) -> Result<Response, Error> {
-    if !user.is_principal(&principal) {
+    if !user.is_principal(&principal) || !user.can_access_collection(&principal, &addressbook_id) {
         return Err(Error::Unauthorized);
     }
 
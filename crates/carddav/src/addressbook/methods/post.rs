@@ -20,7 +20,7 @@ pub async fn route_post<AS: AddressbookStore, DP: DavPushStore>(
     State(resource_service): State<AddressbookResourceService<AS, DP>>,
     body: String,
 ) -> Result<Response, Error> {
-    if !user.is_principal(&principal) {
+    if !user.is_principal(&principal) || !user.can_access_collection(&principal, &addr_id) {
         return Err(Error::Unauthorized);
     }
 
@@ -28,7 +28,7 @@ pub async fn route_post<AS: AddressbookStore, DP: DavPushStore>(
         .addr_store
         .get_addressbook(&principal, &addr_id, false)
         .await?;
-    let addressbook_resource = AddressbookResource(addressbook);
+    let addressbook_resource = AddressbookResource::from(addressbook);
     if !addressbook_resource
         .get_user_privileges(&user)?
         .has(&UserPrivilege::Read)
@@ -52,7 +52,7 @@ pub async fn route_post<AS: AddressbookStore, DP: DavPushStore>(
             .web_push_subscription
             .push_resource
             .clone(),
-        topic: addressbook_resource.0.push_topic,
+        topic: addressbook_resource.addressbook.push_topic,
         expiration: expires.naive_local(),
         public_key: request
             .subscription
@@ -97,6 +97,7 @@ pub async fn handle_addressbook_multiget<AS: AddressbookStore>(
                 object,
                 object_id,
                 principal: principal.to_owned(),
+                addressbook_id: cal_id.to_owned(),
             }
             .propfind(&path, prop, None, puri, user)?,
         );
@@ -63,6 +63,7 @@ fn objects_response(
     not_found: Vec<String>,
     path: &str,
     principal: &str,
+    addressbook_id: &str,
     puri: &impl PrincipalUri,
     user: &Principal,
     prop: &PropfindType<AddressObjectPropWrapperName>,
@@ -79,6 +80,7 @@ fn objects_response(
                 object,
                 object_id,
                 principal: principal.to_owned(),
+                addressbook_id: addressbook_id.to_owned(),
             }
             .propfind(&path, prop, None, puri, user)?,
         );
@@ -109,7 +111,7 @@ pub async fn route_report_addressbook<AS: AddressbookStore, DP: DavPushStore>(
     State(AddressbookResourceService { addr_store, .. }): State<AddressbookResourceService<AS, DP>>,
     body: String,
 ) -> Result<impl IntoResponse, Error> {
-    if !user.is_principal(&principal) {
+    if !user.is_principal(&principal) || !user.can_access_collection(&principal, &addressbook_id) {
         return Err(Error::Unauthorized);
     }
 
@@ -154,6 +156,7 @@ pub async fn route_report_addressbook<AS: AddressbookStore, DP: DavPushStore>(
                 vec![],
                 uri.path(),
                 &principal,
+                &addressbook_id,
                 &puri,
                 &user,
                 &addr_query.prop,
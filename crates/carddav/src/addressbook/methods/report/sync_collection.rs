@@ -46,6 +46,7 @@ pub async fn handle_sync_collection<AS: AddressbookStore>(
                 object,
                 object_id,
                 principal: principal.to_owned(),
+                addressbook_id: addressbook_id.to_owned(),
             }
             .propfind(&path, &sync_collection.prop, None, puri, user)?,
         );
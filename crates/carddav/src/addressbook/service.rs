@@ -24,13 +24,19 @@ use tower::Service;
 pub struct AddressbookResourceService<AS: AddressbookStore, DP: DavPushStore> {
     pub(crate) addr_store: Arc<AS>,
     pub(crate) dav_push_store: Arc<DP>,
+    pub(crate) vapid_public_key: Option<String>,
 }
 
 impl<A: AddressbookStore, DP: DavPushStore> AddressbookResourceService<A, DP> {
-    pub const fn new(addr_store: Arc<A>, dav_push_store: Arc<DP>) -> Self {
+    pub const fn new(
+        addr_store: Arc<A>,
+        dav_push_store: Arc<DP>,
+        vapid_public_key: Option<String>,
+    ) -> Self {
         Self {
             addr_store,
             dav_push_store,
+            vapid_public_key,
         }
     }
 }
@@ -40,6 +46,7 @@ impl<A: AddressbookStore, DP: DavPushStore> Clone for AddressbookResourceService
         Self {
             addr_store: self.addr_store.clone(),
             dav_push_store: self.dav_push_store.clone(),
+            vapid_public_key: self.vapid_public_key.clone(),
         }
     }
 }
@@ -67,7 +74,10 @@ impl<AS: AddressbookStore, DP: DavPushStore> ResourceService
             .get_addressbook(principal, addressbook_id, show_deleted)
             .await
             .map_err(|_e| Error::NotFound)?;
-        Ok(addressbook.into())
+        Ok(AddressbookResource {
+            addressbook,
+            vapid_public_key: self.vapid_public_key.clone(),
+        })
     }
 
     async fn get_members(
@@ -83,6 +93,7 @@ impl<AS: AddressbookStore, DP: DavPushStore> ResourceService
                 object_id,
                 object,
                 principal: principal.to_owned(),
+                addressbook_id: addressbook_id.to_owned(),
             })
             .collect())
     }
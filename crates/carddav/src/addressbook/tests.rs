@@ -18,6 +18,7 @@ fn test_propfind() {
         principal_type: rustical_store::auth::PrincipalType::Individual,
         password: None,
         memberships: vec!["group".to_string()],
+        app_token: None,
     };
 
     let addressbook = Addressbook {
@@ -30,7 +31,7 @@ fn test_propfind() {
         push_topic: "asdasd".to_string(),
     };
 
-    let resource = AddressbookResource(addressbook.clone());
+    let resource = AddressbookResource::from(addressbook.clone());
     let response = resource
         .propfind(
             &format!(
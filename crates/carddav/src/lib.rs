@@ -14,7 +14,7 @@ use rustical_dav_push::DavPushStore;
 use rustical_store::auth::middleware::AuthenticationLayer;
 use rustical_store::{
     AddressbookStore,
-    auth::{AuthenticationProvider, Principal},
+    auth::{AuthenticationProvider, DavService, Principal},
 };
 use std::sync::Arc;
 
@@ -47,15 +47,20 @@ pub fn carddav_router<AP: AuthenticationProvider, A: AddressbookStore, DP: DavPu
     auth_provider: Arc<AP>,
     store: Arc<A>,
     dav_push_store: Arc<DP>,
+    vapid_public_key: Option<String>,
 ) -> Router {
-    let principal_service =
-        PrincipalResourceService::new(store, auth_provider.clone(), dav_push_store);
+    let principal_service = PrincipalResourceService::new(
+        store,
+        auth_provider.clone(),
+        dav_push_store,
+        vapid_public_key,
+    );
     Router::new()
         .nest(
             prefix,
             RootResourceService::<_, Principal, CardDavPrincipalUri>::new(principal_service)
                 .axum_router()
-                .layer(AuthenticationLayer::new(auth_provider))
+                .layer(AuthenticationLayer::new(auth_provider).with_service(DavService::Carddav))
                 .layer(Extension(CardDavPrincipalUri(prefix))),
         )
         .route(
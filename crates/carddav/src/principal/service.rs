@@ -19,6 +19,8 @@ pub struct PrincipalResourceService<
     addr_store: Arc<A>,
     auth_provider: Arc<AP>,
     dav_push_store: Arc<DP>,
+    // Advertised to clients through the push-transports property
+    vapid_public_key: Option<String>,
 }
 
 impl<A: AddressbookStore, AP: AuthenticationProvider, DP: DavPushStore> Clone
@@ -29,6 +31,7 @@ impl<A: AddressbookStore, AP: AuthenticationProvider, DP: DavPushStore> Clone
             addr_store: self.addr_store.clone(),
             auth_provider: self.auth_provider.clone(),
             dav_push_store: self.dav_push_store.clone(),
+            vapid_public_key: self.vapid_public_key.clone(),
         }
     }
 }
@@ -36,11 +39,17 @@ impl<A: AddressbookStore, AP: AuthenticationProvider, DP: DavPushStore> Clone
 impl<A: AddressbookStore, AP: AuthenticationProvider, DP: DavPushStore>
     PrincipalResourceService<A, AP, DP>
 {
-    pub const fn new(addr_store: Arc<A>, auth_provider: Arc<AP>, dav_push_store: Arc<DP>) -> Self {
+    pub const fn new(
+        addr_store: Arc<A>,
+        auth_provider: Arc<AP>,
+        dav_push_store: Arc<DP>,
+        vapid_public_key: Option<String>,
+    ) -> Self {
         Self {
             addr_store,
             auth_provider,
             dav_push_store,
+            vapid_public_key,
         }
     }
 }
@@ -81,7 +90,10 @@ impl<A: AddressbookStore, AP: AuthenticationProvider, DP: DavPushStore> Resource
         let addressbooks = self.addr_store.get_addressbooks(principal).await?;
         Ok(addressbooks
             .into_iter()
-            .map(AddressbookResource::from)
+            .map(|addressbook| AddressbookResource {
+                addressbook,
+                vapid_public_key: self.vapid_public_key.clone(),
+            })
             .collect())
     }
 
@@ -92,6 +104,7 @@ impl<A: AddressbookStore, AP: AuthenticationProvider, DP: DavPushStore> Resource
                 AddressbookResourceService::new(
                     self.addr_store.clone(),
                     self.dav_push_store.clone(),
+                    self.vapid_public_key.clone(),
                 )
                 .axum_router(),
             )
@@ -104,6 +104,16 @@ impl UserPrivilegeSet {
         }
     }
 
+    /// Drops all write privileges
+    #[must_use]
+    pub fn without_write(self) -> Self {
+        if self.has(&UserPrivilege::Read) {
+            Self::read_only()
+        } else {
+            Self::default()
+        }
+    }
+
     #[must_use]
     pub fn write_properties() -> Self {
         Self {
@@ -62,8 +62,13 @@ pub async fn route_propfind<R: ResourceService>(
 
     let mut member_responses = Vec::new();
     if depth != &Depth::Zero {
-        // TODO: authorization check for member resources
         for member in resource_service.get_members(path_components).await? {
+            if !member
+                .get_user_privileges(principal)?
+                .has(&UserPrivilege::Read)
+            {
+                continue;
+            }
             member_responses.push(member.propfind(
                 &format!(
                     "{}/{}{}",
@@ -4,17 +4,17 @@ mod extension;
 mod prop;
 pub mod register;
 use base64::Engine;
-use chrono::Utc;
-use derive_more::Constructor;
+use chrono::{DateTime, TimeDelta, Utc};
 pub use extension::*;
-use http::{HeaderValue, Method, header};
+use http::{HeaderValue, Method, StatusCode, header};
 pub use prop::*;
 use reqwest::{Body, Url};
-use rustical_store::{CollectionOperation, CollectionOperationInfo};
-use rustical_xml::{XmlRootTag, XmlSerialize, XmlSerializeRoot};
+use rustical_dav::xml::TagList;
+use rustical_store::{Event, EventReceiver, MAX_RETRY_DELAY, retry_delay};
+use rustical_xml::{NamespaceOwned, XmlRootTag, XmlSerialize, XmlSerializeRoot};
 use std::{collections::HashMap, sync::Arc, time::Duration};
-use tokio::sync::mpsc::Receiver;
-use tracing::{error, info, warn};
+use tokio::sync::Notify;
+use tracing::{debug, error, info, warn};
 
 mod endpoints;
 pub use endpoints::subscription_service;
@@ -25,12 +25,21 @@ pub use store::*;
 mod subscription;
 pub use subscription::*;
 
+mod vapid;
+pub use vapid::*;
+
 #[derive(XmlSerialize, Debug)]
 pub struct ContentUpdate {
     #[xml(ns = "rustical_dav::namespace::NS_DAV")]
     sync_token: Option<String>,
 }
 
+#[derive(XmlSerialize, Debug)]
+pub struct PropUpdate {
+    #[xml(ns = "rustical_dav::namespace::NS_DAV")]
+    prop: TagList,
+}
+
 #[derive(XmlSerialize, XmlRootTag, Debug)]
 #[xml(root = "push-message", ns = "rustical_dav::namespace::NS_DAVPUSH")]
 #[xml(ns_prefix(
@@ -42,42 +51,159 @@ struct PushMessage {
     topic: String,
     #[xml(ns = "rustical_dav::namespace::NS_DAVPUSH")]
     content_update: Option<ContentUpdate>,
+    #[xml(ns = "rustical_dav::namespace::NS_DAVPUSH")]
+    prop_update: Option<PropUpdate>,
+}
+
+// Collects all operations on a topic that happened since the last batch was sent out
+#[derive(Debug, Default)]
+struct PendingMessage {
+    sync_token: Option<String>,
+    props: Vec<(Option<NamespaceOwned>, String)>,
+    deleted: bool,
+}
+
+impl PendingMessage {
+    fn add(&mut self, event: Event) {
+        match event {
+            Event::ObjectsChanged { sync_token, .. } => {
+                self.sync_token = Some(sync_token);
+            }
+            Event::CollectionUpdated { props, .. } => {
+                for prop in props {
+                    if !self.props.contains(&prop) {
+                        self.props.push(prop);
+                    }
+                }
+            }
+            Event::CollectionDeleted { .. } => self.deleted = true,
+            _ => {}
+        }
+    }
+
+    fn into_push_message(self, topic: String) -> PushMessage {
+        if self.deleted {
+            // A message without any updates tells the client that the collection is gone
+            return PushMessage {
+                topic,
+                content_update: None,
+                prop_update: None,
+            };
+        }
+        PushMessage {
+            topic,
+            content_update: self.sync_token.map(|sync_token| ContentUpdate {
+                sync_token: Some(sync_token),
+            }),
+            prop_update: (!self.props.is_empty()).then(|| PropUpdate {
+                prop: TagList::from(self.props),
+            }),
+        }
+    }
 }
 
-#[derive(Debug, Constructor)]
+const DELIVERY_BATCH_SIZE: i64 = 100;
+
+#[derive(Debug)]
 pub struct DavPushController<S: SubscriptionStore> {
     allowed_push_servers: Option<Vec<String>>,
+    max_delivery_attempts: u32,
     sub_store: Arc<S>,
+    vapid: Option<Vapid>,
+    outbox_notify: Notify,
 }
 
 impl<S: SubscriptionStore> DavPushController<S> {
-    pub async fn notifier(&self, mut recv: Receiver<CollectionOperation>) {
+    pub fn new(
+        allowed_push_servers: Option<Vec<String>>,
+        max_delivery_attempts: u32,
+        sub_store: Arc<S>,
+        vapid: Option<Vapid>,
+    ) -> Self {
+        Self {
+            allowed_push_servers,
+            max_delivery_attempts,
+            sub_store,
+            vapid,
+            outbox_notify: Notify::new(),
+        }
+    }
+
+    pub async fn notifier(&self, mut recv: EventReceiver) {
         loop {
             // Make sure we don't flood the subscribers
             tokio::time::sleep(Duration::from_secs(10)).await;
             let mut messages = vec![];
             recv.recv_many(&mut messages, 100).await;
+            self.enqueue_events(messages).await;
+            if recv.take_lagged() {
+                self.enqueue_resync().await;
+            }
+        }
+    }
 
-            // Right now we just have to show the latest content update by topic
-            // This might become more complicated in the future depending on what kind of updates
-            // we add
-            let mut latest_messages = HashMap::new();
-            for message in messages {
-                if matches!(message.data, CollectionOperationInfo::Content { .. }) {
-                    latest_messages.insert(message.topic.clone(), message);
-                }
+    /// Puts the push messages for the events into the outbox,
+    /// the deliverer of any process on the same store sends them out
+    pub async fn enqueue_events(&self, events: Vec<Event>) {
+        // Merge all operations on the same topic into a single push message
+        let mut pending_messages: HashMap<String, PendingMessage> = HashMap::new();
+        for event in events {
+            let Some(collection) = event.collection() else {
+                continue;
+            };
+            pending_messages
+                .entry(collection.push_topic.clone())
+                .or_default()
+                .add(event);
+        }
+
+        for (topic, pending) in pending_messages {
+            let deleted = pending.deleted;
+            self.enqueue_message(pending.into_push_message(topic), deleted)
+                .await;
+        }
+        self.outbox_notify.notify_one();
+    }
+
+    /// Tells the subscribers of every topic to sync since the events in between were missed
+    pub async fn enqueue_resync(&self) {
+        let topics = match self.sub_store.get_topics().await {
+            Ok(topics) => topics,
+            Err(err) => {
+                error!("Could not load push topics: {err}");
+                return;
             }
-            let messages = latest_messages.into_values();
+        };
+        warn!(
+            topics = topics.len(),
+            "Notifying all DAV Push subscribers after missed events"
+        );
+        for topic in topics {
+            // A content update without a sync token only says that something changed
+            let push_message = PushMessage {
+                topic,
+                content_update: Some(ContentUpdate { sync_token: None }),
+                prop_update: None,
+            };
+            self.enqueue_message(push_message, false).await;
+        }
+        self.outbox_notify.notify_one();
+    }
 
-            for message in messages {
-                self.send_message(message).await;
+    /// Delivers the messages from the outbox and retries failed deliveries
+    pub async fn deliverer(&self) {
+        loop {
+            tokio::select! {
+                () = self.outbox_notify.notified() => {},
+                () = tokio::time::sleep(Duration::from_secs(10)) => {},
             }
+            self.process_outbox().await;
         }
     }
 
     #[allow(clippy::cognitive_complexity)]
-    async fn send_message(&self, message: CollectionOperation) {
-        let subscriptions = match self.sub_store.get_subscriptions(&message.topic).await {
+    async fn enqueue_message(&self, push_message: PushMessage, collection_deleted: bool) {
+        let subscriptions = match self.sub_store.get_subscriptions(&push_message.topic).await {
             Ok(subs) => subs,
             Err(err) => {
                 error!("{err}");
@@ -89,24 +215,6 @@ impl<S: SubscriptionStore> DavPushController<S> {
             return;
         }
 
-        if matches!(message.data, CollectionOperationInfo::Delete) {
-            // Collection has been deleted, but we cannot handle that
-            return;
-        }
-
-        let content_update = if let CollectionOperationInfo::Content { sync_token } = message.data {
-            Some(ContentUpdate {
-                sync_token: Some(sync_token),
-            })
-        } else {
-            None
-        };
-
-        let push_message = PushMessage {
-            topic: message.topic,
-            content_update,
-        };
-
         let payload = match push_message.serialize_to_string() {
             Ok(payload) => payload,
             Err(err) => {
@@ -146,16 +254,116 @@ impl<S: SubscriptionStore> DavPushController<S> {
                 }
             }
 
-            if let Err(err) = send_payload(&payload, &subsciption).await {
-                error!("An error occured sending out a push notification: {err}");
-                if err.is_permament_error() {
-                    warn!(
-                        "Deleting subscription {} on topic {}",
-                        subsciption.id, subsciption.topic
+            if let Err(err) = self
+                .sub_store
+                .enqueue_delivery(&subsciption.id, &payload, collection_deleted)
+                .await
+            {
+                error!("Could not enqueue push message: {err}");
+            }
+        }
+    }
+
+    async fn process_outbox(&self) {
+        let now = Utc::now().naive_utc();
+        let deliveries = match self
+            .sub_store
+            .get_due_deliveries(now, DELIVERY_BATCH_SIZE)
+            .await
+        {
+            Ok(deliveries) => deliveries,
+            Err(err) => {
+                error!("Could not load push deliveries: {err}");
+                return;
+            }
+        };
+        if i64::try_from(deliveries.len()) == Ok(DELIVERY_BATCH_SIZE) {
+            // There might be more due deliveries, come back right after this batch
+            self.outbox_notify.notify_one();
+        }
+
+        for delivery in deliveries {
+            self.deliver(delivery).await;
+        }
+
+        match self.sub_store.count_deliveries().await {
+            Ok(0) => {}
+            Ok(queue_depth) => debug!(queue_depth, "DAV Push deliveries pending"),
+            Err(err) => error!("Could not count push deliveries: {err}"),
+        }
+    }
+
+    #[allow(clippy::cognitive_complexity)]
+    async fn deliver(&self, delivery: PushDelivery) {
+        let subscription = match self
+            .sub_store
+            .get_subscription(&delivery.subscription_id)
+            .await
+        {
+            Ok(subscription) => subscription,
+            Err(rustical_store::Error::NotFound) => {
+                // The subscription is gone, so is the delivery
+                self.try_delete_delivery(delivery.id).await;
+                return;
+            }
+            Err(err) => {
+                error!("{err}");
+                return;
+            }
+        };
+
+        let err = match send_payload(&delivery.payload, &subscription, self.vapid.as_ref()).await {
+            Ok(()) => {
+                self.try_delete_delivery(delivery.id).await;
+                if delivery.unsubscribe {
+                    info!(
+                        "Deleting subscription {} on topic {} because the collection has been deleted",
+                        subscription.id, subscription.topic
                     );
-                    self.try_delete_subscription(&subsciption.id).await;
+                    self.try_delete_subscription(&subscription.id).await;
                 }
+                return;
+            }
+            Err(err) => err,
+        };
+
+        error!("An error occured sending out a push notification: {err}");
+        if err.is_permament_error() {
+            warn!(
+                "Deleting subscription {} on topic {}",
+                subscription.id, subscription.topic
+            );
+            self.try_delete_subscription(&subscription.id).await;
+            return;
+        }
+
+        let attempts = delivery.attempts + 1;
+        if !err.is_retryable() || attempts >= i64::from(self.max_delivery_attempts) {
+            warn!(
+                "Giving up on push message for subscription {} on topic {} after {attempts} attempts",
+                subscription.id, subscription.topic
+            );
+            self.try_delete_delivery(delivery.id).await;
+            if delivery.unsubscribe {
+                self.try_delete_subscription(&subscription.id).await;
             }
+            return;
+        }
+
+        let delay = err
+            .retry_after()
+            .and_then(|retry_after| TimeDelta::from_std(retry_after).ok())
+            .map_or_else(
+                || retry_delay(delivery.attempts),
+                |delay| delay.min(MAX_RETRY_DELAY),
+            );
+        let next_attempt = Utc::now().naive_utc() + delay;
+        if let Err(err) = self
+            .sub_store
+            .reschedule_delivery(delivery.id, attempts, next_attempt)
+            .await
+        {
+            error!("Error rescheduling push delivery: {err}");
         }
     }
 
@@ -164,15 +372,25 @@ impl<S: SubscriptionStore> DavPushController<S> {
             error!("Error deleting subsciption: {err}");
         }
     }
+
+    async fn try_delete_delivery(&self, delivery_id: i64) {
+        if let Err(err) = self.sub_store.delete_delivery(delivery_id).await {
+            error!("Error deleting push delivery: {err}");
+        }
+    }
 }
 
-async fn send_payload(payload: &str, subsciption: &Subscription) -> Result<(), NotifierError> {
+async fn send_payload(
+    payload: &str,
+    subsciption: &Subscription,
+    vapid: Option<&Vapid>,
+) -> Result<(), NotifierError> {
     if subsciption.public_key_type != "p256dh" {
         return Err(NotifierError::InvalidPublicKeyType(
             subsciption.public_key_type.clone(),
         ));
     }
-    let endpoint = subsciption
+    let endpoint: Url = subsciption
         .push_resource
         .parse()
         .map_err(|_| NotifierError::InvalidEndpointUrl(subsciption.push_resource.clone()))?;
@@ -191,6 +409,10 @@ async fn send_payload(payload: &str, subsciption: &Subscription) -> Result<(), N
 
     let payload = ece::encrypt(&ua_public, &auth_secret, payload.as_bytes())?;
 
+    let authorization = vapid
+        .map(|vapid| vapid.authorization(&endpoint))
+        .transpose()?;
+
     let mut request = reqwest::Request::new(Method::POST, endpoint);
     *request.body_mut() = Some(Body::from(payload));
     let hdrs = request.headers_mut();
@@ -203,11 +425,39 @@ async fn send_payload(payload: &str, subsciption: &Subscription) -> Result<(), N
         HeaderValue::from_static("application/octet-stream"),
     );
     hdrs.insert("TTL", HeaderValue::from(60));
-    client.execute(request).await?;
+    if let Some(authorization) = authorization {
+        hdrs.insert(
+            header::AUTHORIZATION,
+            HeaderValue::try_from(authorization).map_err(|_| NotifierError::InvalidKeyEncoding)?,
+        );
+    }
+    let response = client.execute(request).await?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let retry_after = response
+            .headers()
+            .get(header::RETRY_AFTER)
+            .and_then(parse_retry_after);
+        return Err(NotifierError::PushServiceError {
+            status,
+            retry_after,
+        });
+    }
 
     Ok(())
 }
 
+// Retry-After is either a number of seconds or an HTTP date
+fn parse_retry_after(value: &HeaderValue) -> Option<Duration> {
+    let value = value.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&Utc) - Utc::now()).to_std().ok()
+}
+
 #[derive(Debug, thiserror::Error)]
 enum NotifierError {
     #[error("Invalid public key type: {0}")]
@@ -219,7 +469,14 @@ enum NotifierError {
     #[error(transparent)]
     EceError(#[from] ece::Error),
     #[error(transparent)]
+    VapidError(#[from] VapidError),
+    #[error(transparent)]
     ReqwestError(#[from] reqwest::Error),
+    #[error("Push service responded with status {status}")]
+    PushServiceError {
+        status: StatusCode,
+        retry_after: Option<Duration>,
+    },
 }
 
 impl NotifierError {
@@ -233,17 +490,245 @@ impl NotifierError {
                 err,
                 ece::Error::InvalidAuthSecret | ece::Error::InvalidKeyLength
             ),
-            Self::ReqwestError(_) => false,
+            Self::ReqwestError(_) | Self::VapidError(_) => false,
+            // The push service tells us that the subscription does not exist anymore
+            Self::PushServiceError { status, .. } => {
+                matches!(*status, StatusCode::NOT_FOUND | StatusCode::GONE)
+            }
+        }
+    }
+
+    // Decide whether the delivery should be attempted again later
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::ReqwestError(_) => true,
+            Self::PushServiceError { status, .. } => {
+                status.is_server_error()
+                    || matches!(
+                        *status,
+                        StatusCode::TOO_MANY_REQUESTS | StatusCode::REQUEST_TIMEOUT
+                    )
+            }
+            _ => false,
+        }
+    }
+
+    pub const fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::PushServiceError { retry_after, .. } => *retry_after,
+            _ => None,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{Subscription, send_payload};
+    use crate::{NotifierError, PendingMessage, Subscription, Transports, Vapid, send_payload};
+    use axum::{Router, http::HeaderMap, routing::post};
     use base64::Engine;
     use chrono::NaiveDateTime;
     use ece::generate_keypair_and_auth_secret;
+    use http::StatusCode;
+    use rustical_dav::namespace::NS_DAV;
+    use rustical_store::{CollectionRef, CollectionType, Event};
+    use rustical_xml::{XmlRootTag, XmlSerialize, XmlSerializeRoot};
+    use std::time::Duration;
+
+    fn test_subscription(push_resource: String) -> Subscription {
+        let (keypair, auth_secret) = generate_keypair_and_auth_secret().unwrap();
+        Subscription {
+            id: "asd".to_string(),
+            topic: "asd".to_string(),
+            expiration: NaiveDateTime::MAX,
+            push_resource,
+            public_key: base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .encode(keypair.pub_as_raw().unwrap()),
+            public_key_type: "p256dh".to_string(),
+            auth_secret: base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(auth_secret),
+        }
+    }
+
+    // Stand-in for a push service that always responds with the same status
+    async fn push_endpoint(status: StatusCode, headers: HeaderMap) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route(
+            "/push",
+            post(move || {
+                let headers = headers.clone();
+                async move { (status, headers) }
+            }),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{addr}/push")
+    }
+
+    #[tokio::test]
+    async fn test_push_service_responses() {
+        let endpoint = push_endpoint(StatusCode::CREATED, HeaderMap::new()).await;
+        send_payload("hello", &test_subscription(endpoint), None)
+            .await
+            .unwrap();
+
+        let endpoint = push_endpoint(StatusCode::GONE, HeaderMap::new()).await;
+        let err = send_payload("hello", &test_subscription(endpoint), None)
+            .await
+            .unwrap_err();
+        assert!(err.is_permament_error());
+        assert!(!err.is_retryable());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(http::header::RETRY_AFTER, "120".parse().unwrap());
+        let endpoint = push_endpoint(StatusCode::TOO_MANY_REQUESTS, headers).await;
+        let err = send_payload("hello", &test_subscription(endpoint), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            NotifierError::PushServiceError {
+                status: StatusCode::TOO_MANY_REQUESTS,
+                ..
+            }
+        ));
+        assert!(!err.is_permament_error());
+        assert!(err.is_retryable());
+        assert_eq!(err.retry_after(), Some(Duration::from_secs(120)));
+
+        let endpoint = push_endpoint(StatusCode::BAD_GATEWAY, HeaderMap::new()).await;
+        let err = send_payload("hello", &test_subscription(endpoint), None)
+            .await
+            .unwrap_err();
+        assert!(err.is_retryable());
+        assert_eq!(err.retry_after(), None);
+    }
+
+    #[tokio::test]
+    async fn test_vapid_authorization() {
+        // Stand-in for a push service that requires VAPID
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route(
+            "/push",
+            post(
+                async |headers: HeaderMap| match headers.get(http::header::AUTHORIZATION) {
+                    Some(value) if value.to_str().unwrap().starts_with("vapid t=") => {
+                        StatusCode::CREATED
+                    }
+                    _ => StatusCode::UNAUTHORIZED,
+                },
+            ),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let endpoint = format!("http://{addr}/push");
+
+        let err = send_payload("hello", &test_subscription(endpoint.clone()), None)
+            .await
+            .unwrap_err();
+        assert!(!err.is_retryable());
+
+        let vapid = Vapid::new(Vapid::generate_key().unwrap(), None).unwrap();
+        send_payload("hello", &test_subscription(endpoint), Some(&vapid))
+            .await
+            .unwrap();
+    }
+
+    fn test_collection() -> CollectionRef {
+        CollectionRef {
+            principal: "user".to_owned(),
+            collection_type: CollectionType::Calendar,
+            id: "cal".to_owned(),
+            push_topic: "topic".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_push_message_prop_update() {
+        let mut pending = PendingMessage::default();
+        pending.add(Event::ObjectsChanged {
+            collection: test_collection(),
+            sync_token: "github.com/lennart-k/rustical/ns/1".to_owned(),
+            changes: vec![],
+        });
+        pending.add(Event::CollectionUpdated {
+            collection: test_collection(),
+            props: vec![(Some(NS_DAV.into()), "displayname".to_owned())],
+        });
+        pending.add(Event::ObjectsChanged {
+            collection: test_collection(),
+            sync_token: "github.com/lennart-k/rustical/ns/2".to_owned(),
+            changes: vec![],
+        });
+        let out = pending
+            .into_push_message("topic".to_owned())
+            .serialize_to_string()
+            .unwrap();
+        assert_eq!(
+            out,
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<push-message xmlns="https://bitfire.at/webdav-push" xmlns:D="DAV:">
+    <topic>topic</topic>
+    <content-update>
+        <D:sync-token>github.com/lennart-k/rustical/ns/2</D:sync-token>
+    </content-update>
+    <prop-update>
+        <D:prop>
+            <displayname xmlns="DAV:"/>
+        </D:prop>
+    </prop-update>
+</push-message>"#
+        );
+    }
+
+    #[test]
+    fn test_push_message_deleted() {
+        let mut pending = PendingMessage::default();
+        pending.add(Event::ObjectsChanged {
+            collection: test_collection(),
+            sync_token: "github.com/lennart-k/rustical/ns/1".to_owned(),
+            changes: vec![],
+        });
+        pending.add(Event::CollectionDeleted {
+            collection: test_collection(),
+            trashed: true,
+        });
+        let out = pending
+            .into_push_message("topic".to_owned())
+            .serialize_to_string()
+            .unwrap();
+        assert_eq!(
+            out,
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<push-message xmlns="https://bitfire.at/webdav-push" xmlns:D="DAV:">
+    <topic>topic</topic>
+</push-message>"#
+        );
+    }
+
+    #[test]
+    fn test_transports_vapid_public_key() {
+        #[derive(XmlSerialize, XmlRootTag)]
+        #[xml(root = "transports", ns = "rustical_dav::namespace::NS_DAVPUSH")]
+        #[xml(ns_prefix(rustical_dav::namespace::NS_DAVPUSH = ""))]
+        struct Document {
+            #[xml(ty = "untagged")]
+            transports: Transports,
+        }
+
+        let out = Document {
+            transports: Transports::new(Some("BKey")),
+        }
+        .serialize_to_string()
+        .unwrap();
+        assert_eq!(
+            out,
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<transports xmlns="https://bitfire.at/webdav-push">
+    <web-push>
+        <vapid-public-key type="p256ecdsa">BKey</vapid-public-key>
+    </web-push>
+</transports>"#
+        );
+    }
 
     #[tokio::test]
     async fn test_ntfy_request() {
@@ -263,6 +748,7 @@ mod tests {
                 public_key_type: "p256dh".to_string(),
                 auth_secret,
             },
+            None,
         )
         .await
         .unwrap();
@@ -1,10 +1,54 @@
+use quick_xml::events::attributes::Attribute;
+use quick_xml::name::Namespace;
 use rustical_dav::header::Depth;
 use rustical_xml::{Unparsed, XmlDeserialize, XmlSerialize};
+use std::collections::HashMap;
 
 #[derive(Debug, Clone, XmlSerialize, PartialEq, Eq)]
 pub enum Transport {
     #[xml(ns = "rustical_dav::namespace::NS_DAVPUSH")]
-    WebPush,
+    WebPush(WebPushTransport),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct WebPushTransport {
+    vapid_public_key: Option<VapidPublicKey>,
+}
+
+impl XmlSerialize for WebPushTransport {
+    fn serialize(
+        &self,
+        ns: Option<Namespace>,
+        tag: Option<&str>,
+        namespaces: &HashMap<Namespace, &str>,
+        writer: &mut quick_xml::Writer<&mut Vec<u8>>,
+    ) -> std::io::Result<()> {
+        #[derive(XmlSerialize)]
+        struct FakeWebPushTransport {
+            #[xml(ns = "rustical_dav::namespace::NS_DAVPUSH")]
+            vapid_public_key: VapidPublicKey,
+        }
+        // Without a VAPID key this stays an empty element
+        match &self.vapid_public_key {
+            Some(vapid_public_key) => FakeWebPushTransport {
+                vapid_public_key: vapid_public_key.clone(),
+            }
+            .serialize(ns, tag, namespaces, writer),
+            None => ().serialize(ns, tag, namespaces, writer),
+        }
+    }
+
+    fn attributes<'a>(&self) -> Option<Vec<Attribute<'a>>> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, XmlSerialize, PartialEq, Eq)]
+pub struct VapidPublicKey {
+    #[xml(ty = "attr", rename = "type")]
+    key_type: String,
+    #[xml(ty = "text")]
+    key: String,
 }
 
 #[derive(Debug, Clone, XmlSerialize, PartialEq, Eq)]
@@ -14,14 +58,27 @@ pub struct Transports {
     transports: Vec<Transport>,
 }
 
-impl Default for Transports {
-    fn default() -> Self {
+impl Transports {
+    /// Clients use the VAPID key as application server key when subscribing
+    #[must_use]
+    pub fn new(vapid_public_key: Option<&str>) -> Self {
         Self {
-            transports: vec![Transport::WebPush],
+            transports: vec![Transport::WebPush(WebPushTransport {
+                vapid_public_key: vapid_public_key.map(|key| VapidPublicKey {
+                    key_type: "p256ecdsa".to_owned(),
+                    key: key.to_owned(),
+                }),
+            })],
         }
     }
 }
 
+impl Default for Transports {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
 #[derive(XmlSerialize, XmlDeserialize, PartialEq, Eq, Clone, Debug)]
 pub struct SupportedTriggers(#[xml(flatten, ty = "untagged")] pub Vec<Trigger>);
 
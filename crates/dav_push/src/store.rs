@@ -1,14 +1,47 @@
-use crate::Subscription;
+use crate::{PushDelivery, Subscription};
 use async_trait::async_trait;
+use chrono::NaiveDateTime;
 use rustical_store::Error;
 
 #[async_trait]
 pub trait SubscriptionStore: Send + Sync + 'static {
     async fn get_subscriptions(&self, topic: &str) -> Result<Vec<Subscription>, Error>;
+    /// Topics with at least one subscription
+    async fn get_topics(&self) -> Result<Vec<String>, Error>;
     async fn get_subscription(&self, id: &str) -> Result<Subscription, Error>;
     /// Returns whether a subscription under the id already existed
     async fn upsert_subscription(&self, sub: Subscription) -> Result<bool, Error>;
+    /// Also removes all pending deliveries for the subscription
     async fn delete_subscription(&self, id: &str) -> Result<(), Error>;
+
+    /// Puts a push message into the outbox
+    async fn enqueue_delivery(
+        &self,
+        subscription_id: &str,
+        payload: &str,
+        unsubscribe: bool,
+    ) -> Result<(), Error>;
+    /// Returns up to `limit` deliveries whose next attempt is due at `now`
+    async fn get_due_deliveries(
+        &self,
+        now: NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<PushDelivery>, Error>;
+    /// Records a failed attempt and schedules the next one
+    async fn reschedule_delivery(
+        &self,
+        id: i64,
+        attempts: i64,
+        next_attempt: NaiveDateTime,
+    ) -> Result<(), Error>;
+    async fn delete_delivery(&self, id: i64) -> Result<(), Error>;
+    /// Number of deliveries in the outbox
+    async fn count_deliveries(&self) -> Result<u64, Error>;
+
+    /// Returns the PEM encoded VAPID private key of the server
+    async fn get_vapid_key(&self) -> Result<Option<String>, Error>;
+    /// Stores the VAPID private key unless the server already has one
+    async fn insert_vapid_key(&self, private_key: &str) -> Result<(), Error>;
 }
 
 pub trait DavPushStore: SubscriptionStore {}
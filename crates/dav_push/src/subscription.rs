@@ -1,5 +1,6 @@
 use chrono::{DateTime, NaiveDateTime, TimeZone};
 
+#[derive(Debug, Clone)]
 pub struct Subscription {
     pub id: String,
     pub topic: String,
@@ -18,3 +19,17 @@ impl Subscription {
         self.expiration < now.naive_utc()
     }
 }
+
+/// A push message waiting in the outbox to be delivered to a subscription
+#[derive(Debug, Clone)]
+pub struct PushDelivery {
+    pub id: i64,
+    pub subscription_id: String,
+    // Unencrypted push message, encryption happens for every attempt
+    pub payload: String,
+    pub attempts: i64,
+    // UTC
+    pub next_attempt: NaiveDateTime,
+    // The collection has been deleted so the subscription can be removed after delivery
+    pub unsubscribe: bool,
+}
@@ -0,0 +1,184 @@
+use crate::SubscriptionStore;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{TimeDelta, Utc};
+use openssl::bn::BigNumContext;
+use openssl::ec::{EcGroup, EcKey, PointConversionForm};
+use openssl::ecdsa::EcdsaSig;
+use openssl::error::ErrorStack;
+use openssl::nid::Nid;
+use openssl::pkey::Private;
+use reqwest::Url;
+use serde::Serialize;
+use tracing::info;
+
+// RFC 8292 allows up to 24 hours
+const TOKEN_LIFETIME: TimeDelta = TimeDelta::hours(12);
+
+#[derive(Debug, thiserror::Error)]
+pub enum VapidError {
+    #[error(transparent)]
+    StoreError(#[from] rustical_store::Error),
+    #[error(transparent)]
+    OpensslError(#[from] ErrorStack),
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+}
+
+#[derive(Serialize)]
+struct Claims<'a> {
+    aud: &'a str,
+    exp: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sub: Option<&'a str>,
+}
+
+/// Server key to authenticate push deliveries with VAPID (RFC 8292)
+pub struct Vapid {
+    key: EcKey<Private>,
+    // Uncompressed P-256 point, base64url encoded
+    public_key: String,
+    subject: Option<String>,
+}
+
+impl std::fmt::Debug for Vapid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Vapid")
+            .field("public_key", &self.public_key)
+            .field("subject", &self.subject)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Vapid {
+    pub fn new(key: EcKey<Private>, subject: Option<String>) -> Result<Self, ErrorStack> {
+        let mut ctx = BigNumContext::new()?;
+        let public_key =
+            key.public_key()
+                .to_bytes(key.group(), PointConversionForm::UNCOMPRESSED, &mut ctx)?;
+        Ok(Self {
+            public_key: URL_SAFE_NO_PAD.encode(public_key),
+            key,
+            subject,
+        })
+    }
+
+    pub fn generate_key() -> Result<EcKey<Private>, ErrorStack> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+        EcKey::generate(&group)
+    }
+
+    /// Loads the server key from the store, a new key is generated on first use
+    pub async fn load_or_generate<S: SubscriptionStore>(
+        store: &S,
+        subject: Option<String>,
+    ) -> Result<Self, VapidError> {
+        if let Some(pem) = store.get_vapid_key().await? {
+            return Ok(Self::new(
+                EcKey::private_key_from_pem(pem.as_bytes())?,
+                subject,
+            )?);
+        }
+        info!("Generating VAPID key for DAV Push");
+        let pem =
+            String::from_utf8_lossy(&Self::generate_key()?.private_key_to_pem()?).into_owned();
+        store.insert_vapid_key(&pem).await?;
+        // Another instance might have been faster
+        let pem = store
+            .get_vapid_key()
+            .await?
+            .ok_or(rustical_store::Error::NotFound)?;
+        Ok(Self::new(
+            EcKey::private_key_from_pem(pem.as_bytes())?,
+            subject,
+        )?)
+    }
+
+    pub fn public_key(&self) -> &str {
+        &self.public_key
+    }
+
+    /// Signs an ES256 JWT for the origin of the push endpoint
+    pub fn sign_token(&self, endpoint: &Url) -> Result<String, VapidError> {
+        let audience = endpoint.origin().ascii_serialization();
+        let claims = Claims {
+            aud: &audience,
+            exp: (Utc::now() + TOKEN_LIFETIME).timestamp(),
+            sub: self.subject.as_deref(),
+        };
+        let signing_input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(r#"{"typ":"JWT","alg":"ES256"}"#),
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims)?)
+        );
+        let signature = EcdsaSig::sign(&openssl::sha::sha256(signing_input.as_bytes()), &self.key)?;
+        // JWS wants the raw r || s representation instead of DER
+        let mut raw_signature = signature.r().to_vec_padded(32)?;
+        raw_signature.extend(signature.s().to_vec_padded(32)?);
+        Ok(format!(
+            "{signing_input}.{}",
+            URL_SAFE_NO_PAD.encode(raw_signature)
+        ))
+    }
+
+    /// Value of the Authorization header for a delivery to the push endpoint
+    pub fn authorization(&self, endpoint: &Url) -> Result<String, VapidError> {
+        Ok(format!(
+            "vapid t={}, k={}",
+            self.sign_token(endpoint)?,
+            self.public_key
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Vapid;
+    use base64::Engine;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use openssl::bn::BigNum;
+    use openssl::ecdsa::EcdsaSig;
+    use reqwest::Url;
+
+    #[test]
+    fn test_vapid_token() {
+        let vapid = Vapid::new(
+            Vapid::generate_key().unwrap(),
+            Some("mailto:admin@example.com".to_owned()),
+        )
+        .unwrap();
+        assert_eq!(
+            URL_SAFE_NO_PAD.decode(vapid.public_key()).unwrap().len(),
+            65
+        );
+
+        let endpoint = Url::parse("https://push.example.com/some/endpoint").unwrap();
+        let authorization = vapid.authorization(&endpoint).unwrap();
+        let (token, key) = authorization
+            .strip_prefix("vapid t=")
+            .unwrap()
+            .split_once(", k=")
+            .unwrap();
+        assert_eq!(key, vapid.public_key());
+
+        let (signing_input, signature) = token.rsplit_once('.').unwrap();
+        let (_header, claims) = signing_input.split_once('.').unwrap();
+        let claims: serde_json::Value =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(claims).unwrap()).unwrap();
+        assert_eq!(claims["aud"], "https://push.example.com");
+        assert_eq!(claims["sub"], "mailto:admin@example.com");
+
+        let signature = URL_SAFE_NO_PAD.decode(signature).unwrap();
+        assert_eq!(signature.len(), 64);
+        let signature = EcdsaSig::from_private_components(
+            BigNum::from_slice(&signature[..32]).unwrap(),
+            BigNum::from_slice(&signature[32..]).unwrap(),
+        )
+        .unwrap();
+        assert!(
+            signature
+                .verify(&openssl::sha::sha256(signing_input.as_bytes()), &vapid.key)
+                .unwrap()
+        );
+    }
+}
@@ -7,3 +7,5 @@ export { EditCalendarForm } from './edit-calendar-form.ts'
 export { ImportAddressbookForm } from './import-addressbook-form.ts'
 export { ImportCalendarForm } from './import-calendar-form.ts'
 export { GenerateAppTokenForm } from './generate-app-token-form.ts'
+export { PasskeyLoginForm } from './passkey-login-form.ts'
+export { RegisterPasskeyForm } from './register-passkey-form.ts'
@@ -49,6 +49,20 @@ export class GenerateAppTokenForm extends LitElement {
     return html`
       <form method="POST" action=${`/frontend/user/${this.user}/app_token`} @submit=${this.onSubmit} ${ref(this.form)}>
         <input type="text" name="name" placeholder="App name" required />
+        <details class="token-scope">
+          <summary>Restrict access</summary>
+          <label><input type="checkbox" name="caldav" value="true" /> CalDAV only</label>
+          <label><input type="checkbox" name="carddav" value="true" /> CardDAV only</label>
+          <label><input type="checkbox" name="read_only" value="true" /> Read-only</label>
+          <label>
+            Collections
+            <input type="text" name="collections" placeholder="work, family" />
+          </label>
+          <label>
+            Expires
+            <input type="date" name="expires_at" />
+          </label>
+        </details>
         <div class="generate-actions">
           <button type="submit" class="primary">Generate</button>
           ${this.uaApple ? html`
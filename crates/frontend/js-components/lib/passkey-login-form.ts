@@ -0,0 +1,68 @@
+import { html, LitElement } from "lit";
+import { customElement, property } from "lit/decorators.js";
+import { Ref, createRef, ref } from 'lit/directives/ref.js';
+import { decodeRequestOptions, encodeAssertion, passkeysSupported } from "./webauthn.ts";
+
+
+@customElement("passkey-login-form")
+export class PasskeyLoginForm extends LitElement {
+  @property()
+  redirect_uri: string = ''
+
+  form: Ref<HTMLFormElement> = createRef()
+
+  protected override createRenderRoot() {
+    return this
+  }
+
+  async onSubmit(e: SubmitEvent) {
+    e.preventDefault();
+    if (!passkeysSupported()) {
+      alert('Passkeys are not supported by this browser');
+      return;
+    }
+    const form = this.form.value
+    const res = await fetch(form.action, {
+      method: form.method,
+      body: new URLSearchParams(new FormData(form)),
+      headers: { 'Content-Type': 'application/x-www-form-urlencoded' }
+    });
+    if (!res.ok) {
+      alert('Error: ' + await res.text());
+      return;
+    }
+
+    let credential: PublicKeyCredential
+    try {
+      credential = await navigator.credentials.get({ publicKey: decodeRequestOptions(await res.json()) }) as PublicKeyCredential
+    } catch {
+      // Cancelled by the user
+      return;
+    }
+    const finish = await fetch('/frontend/login/passkey/finish', {
+      method: 'POST',
+      body: JSON.stringify(encodeAssertion(credential)),
+      headers: { 'Content-Type': 'application/json' }
+    });
+    if (!finish.ok) {
+      alert('Passkey login failed');
+      return;
+    }
+    window.location.href = await finish.text();
+  }
+
+  override render() {
+    return html`
+      <form method="POST" action="/frontend/login/passkey/start" @submit=${this.onSubmit} ${ref(this.form)}>
+        <label>
+          Username
+          <input type="text" name="username" placeholder="username" autocomplete="username" required />
+        </label>
+        ${this.redirect_uri ? html`
+          <input type="hidden" name="redirect_uri" value=${this.redirect_uri} />
+        ` : null}
+        <button type="submit" class="margin-top-m">Login with a passkey</button>
+      </form>
+    `
+  }
+}
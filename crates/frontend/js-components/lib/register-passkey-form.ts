@@ -0,0 +1,62 @@
+import { html, LitElement } from "lit";
+import { customElement, property } from "lit/decorators.js";
+import { Ref, createRef, ref } from 'lit/directives/ref.js';
+import { decodeCreationOptions, encodeRegistration, passkeysSupported } from "./webauthn.ts";
+
+
+@customElement("register-passkey-form")
+export class RegisterPasskeyForm extends LitElement {
+  @property()
+  user: string = ''
+
+  form: Ref<HTMLFormElement> = createRef()
+
+  protected override createRenderRoot() {
+    return this
+  }
+
+  async onSubmit(e: SubmitEvent) {
+    e.preventDefault();
+    if (!passkeysSupported()) {
+      alert('Passkeys are not supported by this browser');
+      return;
+    }
+    const form = this.form.value
+    const res = await fetch(form.action, {
+      method: form.method,
+      body: new URLSearchParams(new FormData(form)),
+      headers: { 'Content-Type': 'application/x-www-form-urlencoded' }
+    });
+    if (!res.ok) {
+      alert('Error: ' + await res.text());
+      return;
+    }
+
+    let credential: PublicKeyCredential
+    try {
+      credential = await navigator.credentials.create({ publicKey: decodeCreationOptions(await res.json()) }) as PublicKeyCredential
+    } catch {
+      // Cancelled by the user or the authenticator already holds a passkey
+      return;
+    }
+    const finish = await fetch(`/frontend/user/${this.user}/passkey/finish`, {
+      method: 'POST',
+      body: JSON.stringify(encodeRegistration(credential)),
+      headers: { 'Content-Type': 'application/json' }
+    });
+    if (!finish.ok) {
+      alert('Error: ' + await finish.text());
+      return;
+    }
+    location.reload();
+  }
+
+  override render() {
+    return html`
+      <form method="POST" action=${`/frontend/user/${this.user}/passkey/start`} @submit=${this.onSubmit} ${ref(this.form)}>
+        <input type="text" name="name" placeholder="Passkey name" required />
+        <button type="submit" class="primary">Add passkey</button>
+      </form>
+    `
+  }
+}
@@ -0,0 +1,64 @@
+// The WebAuthn browser API works with ArrayBuffers while the server sends base64url strings
+
+export const base64UrlToBuffer = (value: string): ArrayBuffer => {
+  const base64 = value.replace(/-/g, '+').replace(/_/g, '/')
+  return Uint8Array.from(atob(base64), c => c.charCodeAt(0)).buffer
+}
+
+export const bufferToBase64Url = (buffer: ArrayBuffer): string => {
+  return btoa(String.fromCharCode(...new Uint8Array(buffer)))
+    .replace(/\+/g, '-')
+    .replace(/\//g, '_')
+    .replace(/=+$/, '')
+}
+
+export const passkeysSupported = (): boolean => {
+  return window.PublicKeyCredential !== undefined
+}
+
+export const decodeCreationOptions = ({ publicKey }: any): PublicKeyCredentialCreationOptions => {
+  return {
+    ...publicKey,
+    challenge: base64UrlToBuffer(publicKey.challenge),
+    user: { ...publicKey.user, id: base64UrlToBuffer(publicKey.user.id) },
+    excludeCredentials: (publicKey.excludeCredentials ?? []).map((cred: any) => ({ ...cred, id: base64UrlToBuffer(cred.id) })),
+  }
+}
+
+export const decodeRequestOptions = ({ publicKey }: any): PublicKeyCredentialRequestOptions => {
+  return {
+    ...publicKey,
+    challenge: base64UrlToBuffer(publicKey.challenge),
+    allowCredentials: (publicKey.allowCredentials ?? []).map((cred: any) => ({ ...cred, id: base64UrlToBuffer(cred.id) })),
+  }
+}
+
+export const encodeRegistration = (credential: PublicKeyCredential) => {
+  const response = credential.response as AuthenticatorAttestationResponse
+  return {
+    id: credential.id,
+    rawId: bufferToBase64Url(credential.rawId),
+    type: credential.type,
+    extensions: credential.getClientExtensionResults(),
+    response: {
+      attestationObject: bufferToBase64Url(response.attestationObject),
+      clientDataJSON: bufferToBase64Url(response.clientDataJSON),
+    },
+  }
+}
+
+export const encodeAssertion = (credential: PublicKeyCredential) => {
+  const response = credential.response as AuthenticatorAssertionResponse
+  return {
+    id: credential.id,
+    rawId: bufferToBase64Url(credential.rawId),
+    type: credential.type,
+    extensions: credential.getClientExtensionResults(),
+    response: {
+      authenticatorData: bufferToBase64Url(response.authenticatorData),
+      clientDataJSON: bufferToBase64Url(response.clientDataJSON),
+      signature: bufferToBase64Url(response.signature),
+      userHandle: response.userHandle ? bufferToBase64Url(response.userHandle) : null,
+    },
+  }
+}
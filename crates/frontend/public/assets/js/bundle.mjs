@@ -1640,6 +1640,20 @@ var GenerateAppTokenForm = class GenerateAppTokenForm extends i$2 {
 		return b`
       <form method="POST" action=${`/frontend/user/${this.user}/app_token`} @submit=${this.onSubmit} ${n(this.form)}>
         <input type="text" name="name" placeholder="App name" required />
+        <details class="token-scope">
+          <summary>Restrict access</summary>
+          <label><input type="checkbox" name="caldav" value="true" /> CalDAV only</label>
+          <label><input type="checkbox" name="carddav" value="true" /> CardDAV only</label>
+          <label><input type="checkbox" name="read_only" value="true" /> Read-only</label>
+          <label>
+            Collections
+            <input type="text" name="collections" placeholder="work, family" />
+          </label>
+          <label>
+            Expires
+            <input type="date" name="expires_at" />
+          </label>
+        </details>
         <div class="generate-actions">
           <button type="submit" class="primary">Generate</button>
           ${this.uaApple ? b`
@@ -1664,4 +1678,184 @@ __decorate([n$3()], GenerateAppTokenForm.prototype, "token", void 0);
 __decorate([n$3()], GenerateAppTokenForm.prototype, "uaApple", void 0);
 GenerateAppTokenForm = __decorate([t$2("generate-app-token-form")], GenerateAppTokenForm);
 //#endregion
-export { CreateAddressbookForm, CreateBirthdayCalendarForm, CreateCalendarForm, DeleteButton, EditAddressbookForm, EditCalendarForm, GenerateAppTokenForm, ImportAddressbookForm, ImportCalendarForm };
+//#region lib/webauthn.ts
+const base64UrlToBuffer = (value) => {
+	const base64 = value.replace(/-/g, "+").replace(/_/g, "/");
+	return Uint8Array.from(atob(base64), (c) => c.charCodeAt(0)).buffer;
+};
+const bufferToBase64Url = (buffer) => {
+	return btoa(String.fromCharCode(...new Uint8Array(buffer))).replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+};
+const passkeysSupported = () => {
+	return window.PublicKeyCredential !== void 0;
+};
+const decodeCreationOptions = ({ publicKey }) => {
+	return {
+		...publicKey,
+		challenge: base64UrlToBuffer(publicKey.challenge),
+		user: {
+			...publicKey.user,
+			id: base64UrlToBuffer(publicKey.user.id)
+		},
+		excludeCredentials: (publicKey.excludeCredentials ?? []).map((cred) => ({
+			...cred,
+			id: base64UrlToBuffer(cred.id)
+		}))
+	};
+};
+const decodeRequestOptions = ({ publicKey }) => {
+	return {
+		...publicKey,
+		challenge: base64UrlToBuffer(publicKey.challenge),
+		allowCredentials: (publicKey.allowCredentials ?? []).map((cred) => ({
+			...cred,
+			id: base64UrlToBuffer(cred.id)
+		}))
+	};
+};
+const encodeRegistration = (credential) => {
+	const response = credential.response;
+	return {
+		id: credential.id,
+		rawId: bufferToBase64Url(credential.rawId),
+		type: credential.type,
+		extensions: credential.getClientExtensionResults(),
+		response: {
+			attestationObject: bufferToBase64Url(response.attestationObject),
+			clientDataJSON: bufferToBase64Url(response.clientDataJSON)
+		}
+	};
+};
+const encodeAssertion = (credential) => {
+	const response = credential.response;
+	return {
+		id: credential.id,
+		rawId: bufferToBase64Url(credential.rawId),
+		type: credential.type,
+		extensions: credential.getClientExtensionResults(),
+		response: {
+			authenticatorData: bufferToBase64Url(response.authenticatorData),
+			clientDataJSON: bufferToBase64Url(response.clientDataJSON),
+			signature: bufferToBase64Url(response.signature),
+			userHandle: response.userHandle ? bufferToBase64Url(response.userHandle) : null
+		}
+	};
+};
+//#endregion
+//#region lib/passkey-login-form.ts
+var PasskeyLoginForm = class PasskeyLoginForm extends i$2 {
+	constructor(..._args) {
+		super(..._args);
+		this.redirect_uri = "";
+		this.form = e();
+	}
+	createRenderRoot() {
+		return this;
+	}
+	async onSubmit(e) {
+		e.preventDefault();
+		if (!passkeysSupported()) {
+			alert("Passkeys are not supported by this browser");
+			return;
+		}
+		const form = this.form.value;
+		const res = await fetch(form.action, {
+			method: form.method,
+			body: new URLSearchParams(new FormData(form)),
+			headers: { "Content-Type": "application/x-www-form-urlencoded" }
+		});
+		if (!res.ok) {
+			alert("Error: " + await res.text());
+			return;
+		}
+		let credential;
+		try {
+			credential = await navigator.credentials.get({ publicKey: decodeRequestOptions(await res.json()) });
+		} catch {
+			return;
+		}
+		const finish = await fetch("/frontend/login/passkey/finish", {
+			method: "POST",
+			body: JSON.stringify(encodeAssertion(credential)),
+			headers: { "Content-Type": "application/json" }
+		});
+		if (!finish.ok) {
+			alert("Passkey login failed");
+			return;
+		}
+		window.location.href = await finish.text();
+	}
+	render() {
+		return b`
+      <form method="POST" action="/frontend/login/passkey/start" @submit=${this.onSubmit} ${n(this.form)}>
+        <label>
+          Username
+          <input type="text" name="username" placeholder="username" autocomplete="username" required />
+        </label>
+        ${this.redirect_uri ? b`
+          <input type="hidden" name="redirect_uri" value=${this.redirect_uri} />
+        ` : null}
+        <button type="submit" class="margin-top-m">Login with a passkey</button>
+      </form>
+    `;
+	}
+};
+__decorate([n$3()], PasskeyLoginForm.prototype, "redirect_uri", void 0);
+PasskeyLoginForm = __decorate([t$2("passkey-login-form")], PasskeyLoginForm);
+//#endregion
+//#region lib/register-passkey-form.ts
+var RegisterPasskeyForm = class RegisterPasskeyForm extends i$2 {
+	constructor(..._args) {
+		super(..._args);
+		this.user = "";
+		this.form = e();
+	}
+	createRenderRoot() {
+		return this;
+	}
+	async onSubmit(e) {
+		e.preventDefault();
+		if (!passkeysSupported()) {
+			alert("Passkeys are not supported by this browser");
+			return;
+		}
+		const form = this.form.value;
+		const res = await fetch(form.action, {
+			method: form.method,
+			body: new URLSearchParams(new FormData(form)),
+			headers: { "Content-Type": "application/x-www-form-urlencoded" }
+		});
+		if (!res.ok) {
+			alert("Error: " + await res.text());
+			return;
+		}
+		let credential;
+		try {
+			credential = await navigator.credentials.create({ publicKey: decodeCreationOptions(await res.json()) });
+		} catch {
+			return;
+		}
+		const finish = await fetch(`/frontend/user/${this.user}/passkey/finish`, {
+			method: "POST",
+			body: JSON.stringify(encodeRegistration(credential)),
+			headers: { "Content-Type": "application/json" }
+		});
+		if (!finish.ok) {
+			alert("Error: " + await finish.text());
+			return;
+		}
+		location.reload();
+	}
+	render() {
+		return b`
+      <form method="POST" action=${`/frontend/user/${this.user}/passkey/start`} @submit=${this.onSubmit} ${n(this.form)}>
+        <input type="text" name="name" placeholder="Passkey name" required />
+        <button type="submit" class="primary">Add passkey</button>
+      </form>
+    `;
+	}
+};
+__decorate([n$3()], RegisterPasskeyForm.prototype, "user", void 0);
+RegisterPasskeyForm = __decorate([t$2("register-passkey-form")], RegisterPasskeyForm);
+//#endregion
+export { CreateAddressbookForm, CreateBirthdayCalendarForm, CreateCalendarForm, DeleteButton, EditAddressbookForm, EditCalendarForm, GenerateAppTokenForm, ImportAddressbookForm, ImportCalendarForm, PasskeyLoginForm, RegisterPasskeyForm };
@@ -10,12 +10,14 @@ use axum::{
 use headers::{ContentType, HeaderMapExt};
 use http::{Method, StatusCode};
 use routes::{addressbooks::route_addressbooks, calendars::route_calendars};
-use rustical_oidc::{OidcConfig, OidcServiceConfig, oidc_router};
+use rustical_oidc::{OidcProviders, OidcServiceConfig, oidc_router};
 use rustical_store::{
-    AddressbookStore, CalendarStore, PrefixedCalendarStore,
+    AddressbookStore, CalendarStore, PrefixedCalendarStore, SESSION_KEY_USER, SessionStore,
     auth::{AuthenticationProvider, middleware::AuthenticationLayer},
 };
+use rustical_webhook::WebhookStore;
 use std::sync::Arc;
+use tracing::error;
 use url::Url;
 
 mod assets;
@@ -25,16 +27,35 @@ mod oidc_user_store;
 pub(crate) mod pages;
 mod routes;
 
-pub use config::FrontendConfig;
-use oidc_user_store::OidcUserStore;
+pub use config::{FrontendConfig, PasskeyConfig, PasswordResetConfig, TotpPolicy};
+pub use oidc_user_store::OidcUserStore;
 
 use crate::routes::{
     addressbook::{route_addressbook, route_addressbook_restore},
+    admin::{
+        require_admin, route_admin, route_admin_principal, route_delete_membership,
+        route_delete_principal, route_delete_principal_app_token, route_delete_principal_totp,
+        route_post_edit_principal, route_post_membership, route_post_principal,
+    },
     app_token::{route_delete_app_token, route_post_app_token},
     calendar::{route_calendar, route_calendar_restore},
     login::{route_get_login, route_post_login, route_post_logout},
+    passkey::{
+        build_webauthn, route_delete_passkey, route_delete_password,
+        route_post_passkey_login_finish, route_post_passkey_login_start,
+        route_post_passkey_registration_finish, route_post_passkey_registration_start,
+    },
+    password::{
+        PasswordResetMailer, route_get_password_forgot, route_get_password_reset,
+        route_post_password, route_post_password_forgot, route_post_password_reset,
+    },
     timezones::route_timezones,
+    totp::{
+        route_delete_totp, route_get_login_totp, route_get_totp_setup, route_post_login_totp,
+        route_post_totp_setup,
+    },
     user::{route_get_home, route_root, route_user_named},
+    webhook::{route_delete_webhook, route_post_webhook, route_webhooks},
 };
 #[cfg(not(feature = "dev"))]
 use assets::{Assets, EmbedService};
@@ -43,14 +64,29 @@ pub fn frontend_router<
     AP: AuthenticationProvider,
     CS: CalendarStore,
     AS: AddressbookStore + PrefixedCalendarStore,
+    WS: WebhookStore + SessionStore,
 >(
     prefix: &'static str,
     auth_provider: Arc<AP>,
     cal_store: Arc<CS>,
     addr_store: Arc<AS>,
+    webhook_store: Arc<WS>,
     frontend_config: FrontendConfig,
-    oidc_config: Option<OidcConfig>,
+    oidc_providers: Option<OidcProviders>,
 ) -> Router {
+    let webauthn = frontend_config.passkeys.as_ref().and_then(|config| {
+        build_webauthn(config)
+            .inspect_err(|err| error!("Passkey login is disabled, invalid configuration: {err}"))
+            .ok()
+            .map(Arc::new)
+    });
+    let password_reset = frontend_config.password_reset.as_ref().and_then(|config| {
+        PasswordResetMailer::new(config)
+            .inspect_err(|err| error!("Password reset is disabled, invalid configuration: {err}"))
+            .ok()
+            .map(Arc::new)
+    });
+
     let user_router = Router::new()
         .route("/", get(route_get_home))
         .route("/{user}", get(route_user_named::<AP>))
@@ -61,6 +97,27 @@ pub fn frontend_router<
             "/{user}/app_token/{id}/delete",
             post(route_delete_app_token::<AP>),
         )
+        // Second factor
+        .route(
+            "/{user}/totp",
+            get(route_get_totp_setup::<AP>).post(route_post_totp_setup::<AP>),
+        )
+        .route("/{user}/totp/delete", post(route_delete_totp::<AP>))
+        // Passkeys
+        .route(
+            "/{user}/passkey/start",
+            post(route_post_passkey_registration_start::<AP>),
+        )
+        .route(
+            "/{user}/passkey/finish",
+            post(route_post_passkey_registration_finish::<AP>),
+        )
+        .route(
+            "/{user}/passkey/{id}/delete",
+            post(route_delete_passkey::<AP>),
+        )
+        .route("/{user}/password", post(route_post_password::<AP, WS>))
+        .route("/{user}/password/delete", post(route_delete_password::<AP>))
         // Calendar
         .route("/{user}/calendar", get(route_calendars::<CS>))
         .route("/{user}/calendar/{calendar}", get(route_calendar::<CS>))
@@ -78,12 +135,74 @@ pub fn frontend_router<
             "/{user}/addressbook/{addressbook}/restore",
             post(route_addressbook_restore::<AS>),
         )
+        // Webhooks
+        .route(
+            "/{user}/webhook",
+            get(route_webhooks::<WS>).post(route_post_webhook::<WS>),
+        )
+        .route(
+            "/{user}/webhook/{id}/delete",
+            post(route_delete_webhook::<WS>),
+        )
+        .layer(middleware::from_fn(unauthorized_handler));
+
+    let admin_router = Router::new()
+        .route(
+            "/",
+            get(route_admin::<AP, CS, AS>).post(route_post_principal::<AP>),
+        )
+        .route(
+            "/principal/{principal}",
+            get(route_admin_principal::<AP, CS, AS>).post(route_post_edit_principal::<AP, WS>),
+        )
+        .route(
+            "/principal/{principal}/delete",
+            post(route_delete_principal::<AP, WS>),
+        )
+        .route(
+            "/principal/{principal}/membership",
+            post(route_post_membership::<AP>),
+        )
+        .route(
+            "/principal/{principal}/membership/{group}/delete",
+            post(route_delete_membership::<AP>),
+        )
+        .route(
+            "/principal/{principal}/totp/delete",
+            post(route_delete_principal_totp::<AP>),
+        )
+        .route(
+            "/principal/{principal}/app_token/{id}/delete",
+            post(route_delete_principal_app_token::<AP>),
+        )
+        .layer(middleware::from_fn(require_admin))
         .layer(middleware::from_fn(unauthorized_handler));
 
     let router = Router::new()
         .route("/", get(route_root))
         .nest("/user", user_router)
+        .nest("/admin", admin_router)
         .route("/login", get(route_get_login).post(route_post_login::<AP>))
+        .route(
+            "/login/totp",
+            get(route_get_login_totp::<AP>).post(route_post_login_totp::<AP>),
+        )
+        .route(
+            "/login/forgot",
+            get(route_get_password_forgot).post(route_post_password_forgot::<AP>),
+        )
+        .route(
+            "/login/reset",
+            get(route_get_password_reset::<AP>).post(route_post_password_reset::<AP, WS>),
+        )
+        .route(
+            "/login/passkey/start",
+            post(route_post_passkey_login_start::<AP>),
+        )
+        .route(
+            "/login/passkey/finish",
+            post(route_post_passkey_login_finish::<AP>),
+        )
         .route("/logout", post(route_post_logout))
         .route(
             "/_timezones.json",
@@ -98,15 +217,17 @@ pub fn frontend_router<
         tower_http::services::ServeDir::new(concat!(env!("CARGO_MANIFEST_DIR"), "/public/assets")),
     );
 
-    if let Some(oidc_config) = oidc_config.clone() {
+    for (id, oidc_config) in oidc_providers.iter().flat_map(OidcProviders::iter) {
+        let path = oidc_login_path(oidc_providers.as_ref(), id);
         router = router.nest(
-            "/login/oidc",
+            &path,
             oidc_router(
-                oidc_config,
+                oidc_config.clone(),
                 OidcServiceConfig {
+                    provider_id: id.to_owned(),
                     default_redirect_path: "/frontend/user",
-                    session_key_user_id: "user",
-                    callback_path: "/frontend/login/oidc/callback",
+                    session_key_user_id: SESSION_KEY_USER,
+                    callback_path: format!("{prefix}{path}/callback"),
                 },
                 OidcUserStore(auth_provider.clone()),
             ),
@@ -118,14 +239,26 @@ pub fn frontend_router<
         .layer(Extension(auth_provider))
         .layer(Extension(cal_store))
         .layer(Extension(addr_store))
+        .layer(Extension(webhook_store))
         .layer(Extension(frontend_config))
-        .layer(Extension(oidc_config));
+        .layer(Extension(webauthn))
+        .layer(Extension(password_reset))
+        .layer(Extension(oidc_providers));
 
     Router::new()
         .nest(prefix, router)
         .route("/", get(async || Redirect::to(prefix)))
 }
 
+/// Path of an OIDC provider's routes below the frontend prefix
+pub(crate) fn oidc_login_path(providers: Option<&OidcProviders>, id: &str) -> String {
+    match providers {
+        Some(OidcProviders::Multiple(_)) => format!("/login/oidc/{id}"),
+        // Keeps the callback url of existing setups
+        _ => "/login/oidc".to_owned(),
+    }
+}
+
 async fn unauthorized_handler(mut request: Request, next: Next) -> Response {
     let meth = request.method().clone();
     let OriginalUri(uri) = request.extract_parts().await.unwrap();
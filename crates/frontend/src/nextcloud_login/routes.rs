@@ -13,7 +13,7 @@ use axum_extra::TypedHeader;
 use chrono::{Duration, Utc};
 use headers::{HeaderMapExt, Host, UserAgent};
 use http::{HeaderMap, StatusCode};
-use rustical_store::auth::{AuthenticationProvider, Principal};
+use rustical_store::auth::{AppTokenScope, AuthenticationProvider, Principal};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tracing::instrument;
@@ -83,6 +83,8 @@ pub async fn post_nextcloud_poll<AP: AuthenticationProvider>(
                     &response.login_name,
                     flow.app_name.clone(),
                     response.app_password.clone(),
+                    AppTokenScope::default(),
+                    None,
                 )
                 .await?;
             flows.remove(&flow_id);
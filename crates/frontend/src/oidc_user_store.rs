@@ -1,6 +1,8 @@
 use async_trait::async_trait;
 use rustical_oidc::UserStore;
-use rustical_store::auth::{AuthenticationProvider, Principal, PrincipalType};
+use rustical_store::auth::{
+    AuthenticationProvider, Principal, PrincipalType, sync_group_memberships,
+};
 use std::sync::Arc;
 
 pub struct OidcUserStore<AP: AuthenticationProvider>(pub Arc<AP>);
@@ -14,16 +16,34 @@ impl<AP: AuthenticationProvider> Clone for OidcUserStore<AP> {
 #[async_trait]
 impl<AP: AuthenticationProvider> UserStore for OidcUserStore<AP> {
     type Error = rustical_store::Error;
+    type User = Principal;
 
     async fn user_exists(&self, id: &str) -> Result<bool, Self::Error> {
         Ok(self.0.get_principal(id).await?.is_some())
     }
 
-    /// Ensures a principal with id exists.
+    async fn get_user(&self, id: &str) -> Result<Option<Principal>, Self::Error> {
+        self.0.get_principal(id).await
+    }
+
+    async fn user_active(&self, id: &str) -> Result<bool, Self::Error> {
+        self.0.is_active(id).await
+    }
+
+    async fn get_user_provider(&self, id: &str) -> Result<Option<String>, Self::Error> {
+        self.0.get_identity_provider(id).await
+    }
+
+    /// Ensures a principal with id exists and links it to the provider.
     /// Also adds memberships, but does NOT remove previous ones
     /// If assigning a membership fails (e.g. due to the principal not existing),
     /// the method will not fail but only log an error.
-    async fn ensure_user(&self, id: &str, memberships: &[&str]) -> Result<(), Self::Error> {
+    async fn ensure_user(
+        &self,
+        id: &str,
+        provider_id: &str,
+        memberships: &[&str],
+    ) -> Result<(), Self::Error> {
         // Ensure user exists at all
         match self
             .0
@@ -34,6 +54,7 @@ impl<AP: AuthenticationProvider> UserStore for OidcUserStore<AP> {
                     principal_type: PrincipalType::default(),
                     password: None,
                     memberships: vec![],
+                    app_token: None,
                 },
                 false,
             )
@@ -43,6 +64,10 @@ impl<AP: AuthenticationProvider> UserStore for OidcUserStore<AP> {
             Err(err) => return Err(err),
         }
 
+        if self.0.get_identity_provider(id).await?.is_none() {
+            self.0.set_identity_provider(id, provider_id).await?;
+        }
+
         // Add additional memberships
         let Some(user) = self.0.get_principal(id).await? else {
             return Err(rustical_store::Error::NotFound);
@@ -59,4 +84,12 @@ impl<AP: AuthenticationProvider> UserStore for OidcUserStore<AP> {
 
         Ok(())
     }
+
+    async fn sync_memberships(&self, id: &str, memberships: &[String]) -> Result<(), Self::Error> {
+        let Some(user) = self.0.get_principal(id).await? else {
+            return Err(rustical_store::Error::NotFound);
+        };
+        sync_group_memberships(self.0.as_ref(), &user, memberships).await?;
+        Ok(())
+    }
 }
@@ -0,0 +1,308 @@
+use crate::{FrontendConfig, pages::DefaultLayoutData};
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::{
+    Extension, Form,
+    extract::{Path, Request},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+use http::StatusCode;
+use rustical_store::{
+    AddressbookStore, CalendarStore, CollectionMetadata, Error, SessionStore,
+    auth::{AppToken, AuthenticationProvider, Principal, PrincipalType, hash_password},
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+const PRINCIPAL_TYPES: [PrincipalType; 5] = [
+    PrincipalType::Individual,
+    PrincipalType::Group,
+    PrincipalType::Resource,
+    PrincipalType::Room,
+    PrincipalType::Unknown,
+];
+
+/// Rejects everyone but admins from the admin section
+pub async fn require_admin(
+    Extension(config): Extension<FrontendConfig>,
+    user: Principal,
+    request: Request,
+    next: Next,
+) -> Response {
+    // Managing principals needs an interactive login, an app token is not enough
+    if user.app_token.is_some() || !config.is_admin(&user) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    next.run(request).await
+}
+
+/// Collections of a principal and their combined size
+#[derive(Debug, Default)]
+pub struct PrincipalUsage {
+    pub calendars: usize,
+    pub addressbooks: usize,
+    pub objects: CollectionMetadata,
+}
+
+impl PrincipalUsage {
+    fn add(&mut self, meta: &CollectionMetadata) {
+        self.objects.len += meta.len;
+        self.objects.deleted_len += meta.deleted_len;
+        self.objects.size += meta.size;
+        self.objects.deleted_size += meta.deleted_size;
+    }
+}
+
+async fn principal_usage<CS: CalendarStore, AS: AddressbookStore>(
+    cal_store: &CS,
+    addr_store: &AS,
+    principal: &str,
+) -> Result<PrincipalUsage, Error> {
+    let mut usage = PrincipalUsage::default();
+    for calendar in cal_store.get_calendars(principal).await? {
+        usage.calendars += 1;
+        usage.add(&cal_store.calendar_metadata(principal, &calendar.id).await?);
+    }
+    for addressbook in addr_store.get_addressbooks(principal).await? {
+        usage.addressbooks += 1;
+        usage.add(
+            &addr_store
+                .addressbook_metadata(principal, &addressbook.id)
+                .await?,
+        );
+    }
+    Ok(usage)
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "pages/admin.html")]
+struct AdminPage {
+    user: Principal,
+    principals: Vec<(Principal, PrincipalUsage)>,
+    principal_types: [PrincipalType; 5],
+}
+
+impl DefaultLayoutData for AdminPage {
+    fn get_user(&self) -> Option<&Principal> {
+        Some(&self.user)
+    }
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "pages/admin_principal.html")]
+struct AdminPrincipalPage {
+    user: Principal,
+    principal: Principal,
+    email: Option<String>,
+    usage: PrincipalUsage,
+    app_tokens: Vec<AppToken>,
+    totp_enabled: bool,
+    // Principals it can become a member of
+    groups: Vec<String>,
+    principal_types: [PrincipalType; 5],
+}
+
+impl DefaultLayoutData for AdminPrincipalPage {
+    fn get_user(&self) -> Option<&Principal> {
+        Some(&self.user)
+    }
+}
+
+fn admin_principal_path(id: &str) -> String {
+    format!("/frontend/admin/principal/{id}")
+}
+
+fn parse_principal_type(value: &str) -> Result<PrincipalType, Response> {
+    PrincipalType::try_from(value)
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()).into_response())
+}
+
+pub async fn route_admin<AP: AuthenticationProvider, CS: CalendarStore, AS: AddressbookStore>(
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Extension(cal_store): Extension<Arc<CS>>,
+    Extension(addr_store): Extension<Arc<AS>>,
+    user: Principal,
+) -> Result<Response, Error> {
+    let mut principals = vec![];
+    for principal in auth_provider.get_principals().await? {
+        let usage = principal_usage(cal_store.as_ref(), addr_store.as_ref(), &principal.id).await?;
+        principals.push((principal, usage));
+    }
+    Ok(AdminPage {
+        user,
+        principals,
+        principal_types: PRINCIPAL_TYPES,
+    }
+    .into_response())
+}
+
+pub async fn route_admin_principal<
+    AP: AuthenticationProvider,
+    CS: CalendarStore,
+    AS: AddressbookStore,
+>(
+    Path(id): Path<String>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Extension(cal_store): Extension<Arc<CS>>,
+    Extension(addr_store): Extension<Arc<AS>>,
+    user: Principal,
+) -> Result<Response, Error> {
+    let Some(principal) = auth_provider.get_principal(&id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let groups = auth_provider
+        .get_principals()
+        .await?
+        .into_iter()
+        .map(|group| group.id)
+        .filter(|group| group != &principal.id && !principal.memberships.contains(group))
+        .collect();
+    Ok(AdminPrincipalPage {
+        usage: principal_usage(cal_store.as_ref(), addr_store.as_ref(), &id).await?,
+        app_tokens: auth_provider.get_app_tokens(&id).await?,
+        totp_enabled: auth_provider.get_totp(&id).await?.is_some(),
+        email: auth_provider.get_email(&id).await?,
+        groups,
+        principal,
+        user,
+        principal_types: PRINCIPAL_TYPES,
+    }
+    .into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PostPrincipalForm {
+    id: String,
+    #[serde(default)]
+    displayname: String,
+    principal_type: String,
+    #[serde(default)]
+    password: String,
+}
+
+pub async fn route_post_principal<AP: AuthenticationProvider>(
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Form(form): Form<PostPrincipalForm>,
+) -> Result<Response, Error> {
+    let principal_type = match parse_principal_type(&form.principal_type) {
+        Ok(principal_type) => principal_type,
+        Err(response) => return Ok(response),
+    };
+    if auth_provider.get_principal(&form.id).await?.is_some() {
+        return Ok((StatusCode::CONFLICT, "The principal already exists").into_response());
+    }
+    let path = admin_principal_path(&form.id);
+    auth_provider
+        .insert_principal(
+            Principal {
+                id: form.id,
+                displayname: Some(form.displayname).filter(|name| !name.is_empty()),
+                principal_type,
+                password: Some(form.password)
+                    .filter(|password| !password.is_empty())
+                    .as_deref()
+                    .map(hash_password),
+                memberships: vec![],
+                app_token: None,
+            },
+            false,
+        )
+        .await?;
+    Ok(Redirect::to(&path).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PostEditPrincipalForm {
+    #[serde(default)]
+    displayname: String,
+    principal_type: String,
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    password: String,
+    remove_password: Option<String>,
+}
+
+pub async fn route_post_edit_principal<AP: AuthenticationProvider, SS: SessionStore>(
+    Path(id): Path<String>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Extension(session_store): Extension<Arc<SS>>,
+    Form(form): Form<PostEditPrincipalForm>,
+) -> Result<Response, Error> {
+    let Some(mut principal) = auth_provider.get_principal(&id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    principal.principal_type = match parse_principal_type(&form.principal_type) {
+        Ok(principal_type) => principal_type,
+        Err(response) => return Ok(response),
+    };
+    principal.displayname = Some(form.displayname).filter(|name| !name.is_empty());
+    let password_changed = form.remove_password.is_some() || !form.password.is_empty();
+    if form.remove_password.is_some() {
+        principal.password = None;
+    } else if !form.password.is_empty() {
+        principal.password = Some(hash_password(&form.password));
+    }
+    auth_provider.insert_principal(principal, true).await?;
+    let email = form.email.trim();
+    auth_provider
+        .set_email(&id, Some(email).filter(|email| !email.is_empty()))
+        .await?;
+    if password_changed {
+        session_store.revoke_sessions(&id).await?;
+    }
+    Ok(Redirect::to(&admin_principal_path(&id)).into_response())
+}
+
+pub async fn route_delete_principal<AP: AuthenticationProvider, SS: SessionStore>(
+    Path(id): Path<String>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Extension(session_store): Extension<Arc<SS>>,
+    user: Principal,
+) -> Result<Response, Error> {
+    if id == user.id {
+        return Ok((StatusCode::BAD_REQUEST, "You cannot delete yourself").into_response());
+    }
+    auth_provider.remove_principal(&id).await?;
+    session_store.revoke_sessions(&id).await?;
+    Ok(Redirect::to("/frontend/admin").into_response())
+}
+
+pub async fn route_delete_principal_totp<AP: AuthenticationProvider>(
+    Path(id): Path<String>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+) -> Result<Response, Error> {
+    auth_provider.set_totp(&id, None).await?;
+    Ok(Redirect::to(&admin_principal_path(&id)).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PostMembershipForm {
+    member_of: String,
+}
+
+pub async fn route_post_membership<AP: AuthenticationProvider>(
+    Path(id): Path<String>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Form(PostMembershipForm { member_of }): Form<PostMembershipForm>,
+) -> Result<Response, Error> {
+    auth_provider.add_membership(&id, &member_of).await?;
+    Ok(Redirect::to(&admin_principal_path(&id)).into_response())
+}
+
+pub async fn route_delete_membership<AP: AuthenticationProvider>(
+    Path((id, member_of)): Path<(String, String)>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+) -> Result<Response, Error> {
+    auth_provider.remove_membership(&id, &member_of).await?;
+    Ok(Redirect::to(&admin_principal_path(&id)).into_response())
+}
+
+pub async fn route_delete_principal_app_token<AP: AuthenticationProvider>(
+    Path((id, token_id)): Path<(String, String)>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+) -> Result<Response, Error> {
+    auth_provider.remove_app_token(&id, &token_id).await?;
+    Ok(Redirect::to(&admin_principal_path(&id)).into_response())
+}
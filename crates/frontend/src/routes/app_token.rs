@@ -8,11 +8,12 @@ use axum::{
     response::{IntoResponse, Redirect, Response},
 };
 use axum_extra::TypedHeader;
+use chrono::{DateTime, NaiveDate, Utc};
 use headers::{ContentType, HeaderMapExt, Host};
 use http::{HeaderValue, StatusCode, header};
 use rand::{RngExt, distr::Alphanumeric};
 use rustical_dav::rfc_3986_percent_encode;
-use rustical_store::auth::{AuthenticationProvider, Principal};
+use rustical_store::auth::{AppTokenScope, AuthenticationProvider, DavService, Principal};
 use serde::Deserialize;
 
 pub fn generate_app_token() -> String {
@@ -38,6 +39,56 @@ pub struct PostAppTokenForm {
     name: String,
     #[serde(default)]
     apple: bool,
+    #[serde(default)]
+    caldav: bool,
+    #[serde(default)]
+    carddav: bool,
+    #[serde(default)]
+    read_only: bool,
+    /// Comma-separated collection ids, optionally prefixed with `<principal>/`
+    #[serde(default)]
+    collections: String,
+    /// Date in `YYYY-MM-DD` format, the token is valid until the end of that day
+    #[serde(default)]
+    expires_at: String,
+}
+
+impl PostAppTokenForm {
+    fn scope(&self, user: &Principal) -> AppTokenScope {
+        let mut services = vec![];
+        if self.caldav {
+            services.push(DavService::Caldav);
+        }
+        if self.carddav {
+            services.push(DavService::Carddav);
+        }
+        let collections = self
+            .collections
+            .split(',')
+            .map(str::trim)
+            .filter(|collection| !collection.is_empty())
+            .map(|collection| {
+                if collection.contains('/') {
+                    collection.to_owned()
+                } else {
+                    format!("{}/{collection}", user.id)
+                }
+            })
+            .collect();
+        AppTokenScope {
+            services,
+            read_only: self.read_only,
+            collections,
+        }
+    }
+
+    fn expires_at(&self) -> Result<Option<DateTime<Utc>>, chrono::ParseError> {
+        if self.expires_at.is_empty() {
+            return Ok(None);
+        }
+        let date = NaiveDate::parse_from_str(&self.expires_at, "%Y-%m-%d")?;
+        Ok(date.and_hms_opt(23, 59, 59).map(|time| time.and_utc()))
+    }
 }
 
 pub async fn route_post_app_token<AP: AuthenticationProvider>(
@@ -45,15 +96,20 @@ pub async fn route_post_app_token<AP: AuthenticationProvider>(
     Extension(auth_provider): Extension<Arc<AP>>,
     Path(user_id): Path<String>,
     TypedHeader(host): TypedHeader<Host>,
-    Form(PostAppTokenForm { apple, name }): Form<PostAppTokenForm>,
+    Form(form): Form<PostAppTokenForm>,
 ) -> Result<Response, rustical_store::Error> {
-    if name.is_empty() {
+    if form.name.is_empty() {
         return Ok((StatusCode::BAD_REQUEST, "empty app name").into_response());
     }
     assert_eq!(user_id, user.id);
+    let Ok(expires_at) = form.expires_at() else {
+        return Ok((StatusCode::BAD_REQUEST, "invalid expiry date").into_response());
+    };
+    let scope = form.scope(&user);
+    let PostAppTokenForm { name, apple, .. } = form;
     let token = generate_app_token();
     let mut token_id = auth_provider
-        .add_app_token(&user.id, name.clone(), token.clone())
+        .add_app_token(&user.id, name.clone(), token.clone(), scope, expires_at)
         .await?;
     // Get first 4 characters of token identifier
     token_id.truncate(4);
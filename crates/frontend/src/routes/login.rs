@@ -1,4 +1,7 @@
-use crate::{FrontendConfig, OidcConfig, pages::DefaultLayoutData};
+use crate::{
+    FrontendConfig, TotpPolicy, oidc_login_path, pages::DefaultLayoutData,
+    routes::password::PasswordResetMailer,
+};
 use askama::Template;
 use askama_web::WebTemplate;
 use axum::{
@@ -9,19 +12,30 @@ use axum::{
 use axum_extra::TypedHeader;
 use headers::Host;
 use http::StatusCode;
-use rustical_store::auth::AuthenticationProvider;
-use serde::Deserialize;
+use rustical_oidc::OidcProviders;
+use rustical_store::{
+    SESSION_KEY_USER,
+    auth::{AuthenticationProvider, ClientIp, LoginProtection},
+};
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use tower_sessions::Session;
+use tower_sessions::{Expiry, Session, cookie::time::Duration};
 use tracing::{instrument, warn};
 use url::Url;
+use webauthn_rs::prelude::Webauthn;
+
+/// Session key of the login awaiting the second factor
+pub const SESSION_KEY_PENDING_LOGIN: &str = "pending_login";
 
 #[derive(Template, WebTemplate)]
 #[template(path = "pages/login.html")]
 struct LoginPage<'a> {
     redirect_uri: Option<String>,
-    oidc_data: Option<OidcProviderData<'a>>,
+    oidc_providers: Vec<OidcProviderData<'a>>,
     allow_password_login: bool,
+    passkey_login: bool,
+    remember_me: bool,
+    password_reset: bool,
 }
 
 impl DefaultLayoutData for LoginPage<'_> {
@@ -40,23 +54,29 @@ pub struct GetLoginQuery {
     redirect_uri: Option<String>,
 }
 
-#[instrument(skip(config, oidc_config))]
+#[instrument(skip(config, oidc_providers, webauthn, password_reset))]
 pub async fn route_get_login(
     Query(GetLoginQuery { redirect_uri }): Query<GetLoginQuery>,
     Extension(config): Extension<FrontendConfig>,
-    Extension(oidc_config): Extension<Option<OidcConfig>>,
+    Extension(oidc_providers): Extension<Option<OidcProviders>>,
+    Extension(webauthn): Extension<Option<Arc<Webauthn>>>,
+    Extension(password_reset): Extension<Option<Arc<PasswordResetMailer>>>,
 ) -> Response {
-    let oidc_data = oidc_config
-        .as_ref()
-        .as_ref()
-        .map(|oidc_config| OidcProviderData {
+    let oidc_providers = oidc_providers
+        .iter()
+        .flat_map(OidcProviders::iter)
+        .map(|(id, oidc_config)| OidcProviderData {
             name: &oidc_config.name,
-            redirect_url: "/frontend/login/oidc".to_owned(),
-        });
+            redirect_url: format!("/frontend{}", oidc_login_path(oidc_providers.as_ref(), id)),
+        })
+        .collect();
     LoginPage {
         redirect_uri,
         allow_password_login: config.allow_password_login,
-        oidc_data,
+        passkey_login: webauthn.is_some(),
+        remember_me: config.remember_me_days > 0,
+        password_reset: password_reset.is_some(),
+        oidc_providers,
     }
     .into_response()
 }
@@ -66,45 +86,121 @@ pub struct PostLoginForm {
     username: String,
     password: String,
     redirect_uri: Option<String>,
+    remember_me: Option<String>,
+}
+
+/// A login that passed the password check and awaits the second factor
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingLogin {
+    pub user_id: String,
+    pub redirect_uri: String,
+    pub remember_me: bool,
+    pub failed_attempts: u8,
+}
+
+pub fn locked_out_response() -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        "Too many failed login attempts, try again later",
+    )
+        .into_response()
+}
+
+/// Resolves the redirect target after login, ensuring that it never goes cross-origin
+pub fn login_redirect_path(host: &Host, redirect_uri: Option<String>) -> String {
+    let base_url: Url = format!("https://{host}").parse().unwrap();
+    if let Some(redirect_uri) = redirect_uri
+        && let Ok(redirect_url) = base_url.join(&redirect_uri)
+        && redirect_url.origin() == base_url.origin()
+    {
+        redirect_url.path().to_owned()
+    } else {
+        "/frontend/user".to_owned()
+    }
+}
+
+/// Logs the session in as `user_id`
+pub async fn complete_login(
+    session: &Session,
+    config: &FrontendConfig,
+    user_id: String,
+    remember_me: bool,
+) {
+    session
+        .remove_value(SESSION_KEY_PENDING_LOGIN)
+        .await
+        .unwrap();
+    session.insert(SESSION_KEY_USER, user_id).await.unwrap();
+    if remember_me && config.remember_me_days > 0 {
+        session.set_expiry(Some(Expiry::OnInactivity(Duration::days(
+            config.remember_me_days.into(),
+        ))));
+    }
 }
 
 // #[instrument(skip(password, auth_provider, config))]
 pub async fn route_post_login<AP: AuthenticationProvider>(
     Extension(auth_provider): Extension<Arc<AP>>,
     Extension(config): Extension<FrontendConfig>,
+    protection: LoginProtection,
+    ClientIp(client_ip): ClientIp,
     session: Session,
     TypedHeader(host): TypedHeader<Host>,
     Form(PostLoginForm {
         username,
         password,
         redirect_uri,
+        remember_me,
     }): Form<PostLoginForm>,
-) -> Response {
+) -> Result<Response, rustical_store::Error> {
     if !config.allow_password_login {
-        return StatusCode::METHOD_NOT_ALLOWED.into_response();
+        return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
     }
-    let default_redirect = "/frontend/user".to_string();
-    // Ensure that redirect_uri never goes cross-origin
-    let base_url: Url = format!("https://{host}").parse().unwrap();
-    let redirect_uri = if let Some(redirect_uri) = redirect_uri
-        && let Ok(redirect_url) = base_url.join(&redirect_uri)
-        && redirect_url.origin() == base_url.origin()
+    let redirect_uri = login_redirect_path(&host, redirect_uri);
+
+    if protection
+        .locked_until(auth_provider.as_ref(), &username, client_ip)
+        .await?
+        .is_some()
     {
-        redirect_url.path().to_owned()
-    } else {
-        default_redirect
+        return Ok(locked_out_response());
+    }
+    let user = match auth_provider.validate_password(&username, &password).await {
+        Ok(user) => user,
+        // Not counted as a failed login, e.g. if the LDAP server is unreachable
+        Err(err) => {
+            warn!("Could not validate the password of {username}: {err}");
+            return Ok(StatusCode::UNAUTHORIZED.into_response());
+        }
     };
-
-    if let Ok(Some(user)) = auth_provider.validate_password(&username, &password).await {
-        session.insert("user", user.id).await.unwrap();
-        Redirect::to(&redirect_uri).into_response()
-    } else {
-        warn!("Failed password login attempt as {username}");
-        StatusCode::UNAUTHORIZED.into_response()
+    let Some(user) = user else {
+        protection
+            .record_failure(auth_provider.as_ref(), &username, client_ip, "password")
+            .await?;
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    };
+    let remember_me = remember_me.is_some();
+    if config.totp == TotpPolicy::Required || auth_provider.get_totp(&user.id).await?.is_some() {
+        let pending = PendingLogin {
+            user_id: user.id,
+            redirect_uri,
+            remember_me,
+            failed_attempts: 0,
+        };
+        session
+            .insert(SESSION_KEY_PENDING_LOGIN, pending)
+            .await
+            .unwrap();
+        return Ok(Redirect::to("/frontend/login/totp").into_response());
     }
+    protection
+        .record_success(auth_provider.as_ref(), &user.id)
+        .await?;
+    complete_login(&session, &config, user.id, remember_me).await;
+    Ok(Redirect::to(&redirect_uri).into_response())
 }
 
 pub async fn route_post_logout(session: Session) -> Redirect {
-    session.remove_value("user").await.unwrap();
+    session.remove_value(SESSION_KEY_USER).await.unwrap();
     Redirect::to("/")
 }
@@ -1,8 +1,13 @@
 pub mod addressbook;
 pub mod addressbooks;
+pub mod admin;
 pub mod app_token;
 pub mod calendar;
 pub mod calendars;
 pub mod login;
+pub mod passkey;
+pub mod password;
 pub mod timezones;
+pub mod totp;
 pub mod user;
+pub mod webhook;
@@ -0,0 +1,310 @@
+use crate::{
+    FrontendConfig, PasskeyConfig,
+    routes::login::{complete_login, login_redirect_path},
+};
+use axum::{
+    Extension, Form, Json,
+    extract::Path,
+    response::{IntoResponse, Redirect, Response},
+};
+use axum_extra::TypedHeader;
+use chrono::Utc;
+use headers::Host;
+use http::StatusCode;
+use rustical_store::{
+    Error,
+    auth::{AuthenticationProvider, PasskeyCredential, Principal},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tower_sessions::Session;
+use tracing::warn;
+use webauthn_rs::prelude::{
+    AuthenticationResult, Passkey, PasskeyAuthentication, PasskeyRegistration, PublicKeyCredential,
+    RegisterPublicKeyCredential, Url, Uuid, Webauthn, WebauthnBuilder, WebauthnError,
+};
+
+/// Session key of the authentication ceremony in progress
+const SESSION_KEY_PASSKEY_LOGIN: &str = "passkey_login";
+/// Session key of the registration ceremony in progress
+const SESSION_KEY_PASSKEY_REGISTRATION: &str = "passkey_registration";
+
+#[derive(Serialize, Deserialize)]
+struct PasskeyLogin {
+    user_id: String,
+    redirect_uri: String,
+    state: PasskeyAuthentication,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PasskeyEnrollment {
+    name: String,
+    state: PasskeyRegistration,
+}
+
+/// Sets up the relying party for the configured domain
+pub fn build_webauthn(config: &PasskeyConfig) -> Result<Webauthn, WebauthnError> {
+    let origin = Url::parse(&config.origin).map_err(|_| WebauthnError::Configuration)?;
+    WebauthnBuilder::new(&config.rp_id, &origin)?
+        .rp_name("RustiCal")
+        .build()
+}
+
+// Authenticators group passkeys by user handle, so it has to be stable per principal
+fn user_handle(principal: &str) -> Uuid {
+    Uuid::new_v5(&Uuid::NAMESPACE_OID, principal.as_bytes())
+}
+
+fn parse_passkey(credential: &PasskeyCredential) -> Result<Passkey, Error> {
+    serde_json::from_str(&credential.credential).map_err(|err| Error::Other(err.into()))
+}
+
+fn serialize_passkey(passkey: &Passkey) -> Result<String, Error> {
+    serde_json::to_string(passkey).map_err(|err| Error::Other(err.into()))
+}
+
+fn webauthn_error(err: &WebauthnError) -> Response {
+    warn!("Passkey ceremony failed: {err}");
+    (StatusCode::BAD_REQUEST, "Passkey verification failed").into_response()
+}
+
+/// Stores the new signature counter of the passkey that was used
+async fn update_passkey<AP: AuthenticationProvider>(
+    auth_provider: &AP,
+    principal: &str,
+    result: &AuthenticationResult,
+) -> Result<(), Error> {
+    for mut credential in auth_provider.get_passkeys(principal).await? {
+        let mut passkey = parse_passkey(&credential)?;
+        if passkey.update_credential(result) == Some(true) {
+            credential.credential = serialize_passkey(&passkey)?;
+            auth_provider.upsert_passkey(principal, credential).await?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct PostPasskeyLoginForm {
+    username: String,
+    redirect_uri: Option<String>,
+}
+
+pub async fn route_post_passkey_login_start<AP: AuthenticationProvider>(
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Extension(webauthn): Extension<Option<Arc<Webauthn>>>,
+    session: Session,
+    TypedHeader(host): TypedHeader<Host>,
+    Form(PostPasskeyLoginForm {
+        username,
+        redirect_uri,
+    }): Form<PostPasskeyLoginForm>,
+) -> Result<Response, Error> {
+    let Some(webauthn) = webauthn else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let passkeys = auth_provider
+        .get_passkeys(&username)
+        .await?
+        .iter()
+        .map(parse_passkey)
+        .collect::<Result<Vec<_>, _>>()?;
+    if passkeys.is_empty() {
+        return Ok((StatusCode::UNAUTHORIZED, "No passkey registered").into_response());
+    }
+    let (challenge, state) = match webauthn.start_passkey_authentication(&passkeys) {
+        Ok(ceremony) => ceremony,
+        Err(err) => return Ok(webauthn_error(&err)),
+    };
+    let login = PasskeyLogin {
+        user_id: username,
+        redirect_uri: login_redirect_path(&host, redirect_uri),
+        state,
+    };
+    session
+        .insert(SESSION_KEY_PASSKEY_LOGIN, login)
+        .await
+        .unwrap();
+    Ok(Json(challenge).into_response())
+}
+
+/// Responds with the path to redirect to
+pub async fn route_post_passkey_login_finish<AP: AuthenticationProvider>(
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Extension(webauthn): Extension<Option<Arc<Webauthn>>>,
+    Extension(config): Extension<FrontendConfig>,
+    session: Session,
+    Json(credential): Json<PublicKeyCredential>,
+) -> Result<Response, Error> {
+    let Some(webauthn) = webauthn else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let Some(login) = session
+        .remove::<PasskeyLogin>(SESSION_KEY_PASSKEY_LOGIN)
+        .await
+        .unwrap()
+    else {
+        return Ok(StatusCode::BAD_REQUEST.into_response());
+    };
+    let result = match webauthn.finish_passkey_authentication(&credential, &login.state) {
+        Ok(result) => result,
+        Err(err) => {
+            warn!("Failed passkey login attempt as {}: {err}", login.user_id);
+            return Ok(StatusCode::UNAUTHORIZED.into_response());
+        }
+    };
+    if result.needs_update() {
+        update_passkey(auth_provider.as_ref(), &login.user_id, &result).await?;
+    }
+    // A passkey already combines possession and user verification, so no TOTP is asked for
+    complete_login(&session, &config, login.user_id, false).await;
+    Ok(login.redirect_uri.into_response())
+}
+
+#[derive(Deserialize)]
+pub struct PostPasskeyRegistrationForm {
+    name: String,
+}
+
+pub async fn route_post_passkey_registration_start<AP: AuthenticationProvider>(
+    Path(user_id): Path<String>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Extension(webauthn): Extension<Option<Arc<Webauthn>>>,
+    user: Principal,
+    session: Session,
+    Form(PostPasskeyRegistrationForm { name }): Form<PostPasskeyRegistrationForm>,
+) -> Result<Response, Error> {
+    let Some(webauthn) = webauthn else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    if user_id != user.id {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+    if user.app_token.is_some() {
+        return Ok(StatusCode::FORBIDDEN.into_response());
+    }
+    if name.is_empty() {
+        return Ok((StatusCode::BAD_REQUEST, "empty passkey name").into_response());
+    }
+    // Prevents registering the same authenticator twice
+    let exclude_credentials = auth_provider
+        .get_passkeys(&user.id)
+        .await?
+        .iter()
+        .map(|credential| parse_passkey(credential).map(|passkey| passkey.cred_id().clone()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let (challenge, state) = match webauthn.start_passkey_registration(
+        user_handle(&user.id),
+        &user.id,
+        user.displayname.as_deref().unwrap_or(&user.id),
+        Some(exclude_credentials),
+    ) {
+        Ok(ceremony) => ceremony,
+        Err(err) => return Ok(webauthn_error(&err)),
+    };
+    session
+        .insert(
+            SESSION_KEY_PASSKEY_REGISTRATION,
+            PasskeyEnrollment { name, state },
+        )
+        .await
+        .unwrap();
+    Ok(Json(challenge).into_response())
+}
+
+pub async fn route_post_passkey_registration_finish<AP: AuthenticationProvider>(
+    Path(user_id): Path<String>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Extension(webauthn): Extension<Option<Arc<Webauthn>>>,
+    user: Principal,
+    session: Session,
+    Json(credential): Json<RegisterPublicKeyCredential>,
+) -> Result<Response, Error> {
+    let Some(webauthn) = webauthn else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    if user_id != user.id {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+    if user.app_token.is_some() {
+        return Ok(StatusCode::FORBIDDEN.into_response());
+    }
+    let Some(enrollment) = session
+        .remove::<PasskeyEnrollment>(SESSION_KEY_PASSKEY_REGISTRATION)
+        .await
+        .unwrap()
+    else {
+        return Ok(StatusCode::BAD_REQUEST.into_response());
+    };
+    let passkey = match webauthn.finish_passkey_registration(&credential, &enrollment.state) {
+        Ok(passkey) => passkey,
+        Err(err) => return Ok(webauthn_error(&err)),
+    };
+    auth_provider
+        .upsert_passkey(
+            &user.id,
+            PasskeyCredential {
+                id: hex::encode(passkey.cred_id()),
+                name: enrollment.name,
+                credential: serialize_passkey(&passkey)?,
+                created_at: Some(Utc::now()),
+            },
+        )
+        .await?;
+    Ok(StatusCode::CREATED.into_response())
+}
+
+pub async fn route_delete_passkey<AP: AuthenticationProvider>(
+    Path((user_id, id)): Path<(String, String)>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+    user: Principal,
+) -> Result<Response, Error> {
+    if user_id != user.id {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+    if user.app_token.is_some() {
+        return Ok(StatusCode::FORBIDDEN.into_response());
+    }
+    let passkeys = auth_provider.get_passkeys(&user.id).await?;
+    // Would lock a passkey-only account out
+    if user.password.is_none() && passkeys.iter().all(|passkey| passkey.id == id) {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            "The last passkey of an account without password cannot be deleted",
+        )
+            .into_response());
+    }
+    auth_provider.remove_passkey(&user.id, &id).await?;
+    Ok(Redirect::to(&format!("/frontend/user/{}", user.id)).into_response())
+}
+
+/// Turns the principal into a passkey-only account
+pub async fn route_delete_password<AP: AuthenticationProvider>(
+    Path(user_id): Path<String>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Extension(config): Extension<FrontendConfig>,
+    user: Principal,
+) -> Result<Response, Error> {
+    if user_id != user.id {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+    if user.app_token.is_some() {
+        return Ok(StatusCode::FORBIDDEN.into_response());
+    }
+    if !config
+        .passkeys
+        .as_ref()
+        .is_some_and(|passkeys| passkeys.allow_passkey_only)
+    {
+        return Ok(StatusCode::FORBIDDEN.into_response());
+    }
+    if auth_provider.get_passkeys(&user.id).await?.is_empty() {
+        return Ok((StatusCode::BAD_REQUEST, "Register a passkey first").into_response());
+    }
+    let Some(mut principal) = auth_provider.get_principal(&user.id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    principal.password = None;
+    auth_provider.insert_principal(principal, true).await?;
+    Ok(Redirect::to(&format!("/frontend/user/{}", user.id)).into_response())
+}
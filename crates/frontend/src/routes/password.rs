@@ -0,0 +1,394 @@
+use crate::{PasswordResetConfig, pages::DefaultLayoutData, routes::login::locked_out_response};
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::{
+    Extension, Form,
+    extract::{Path, Query},
+    response::{IntoResponse, Redirect, Response},
+};
+use chrono::{DateTime, Duration, Utc};
+use http::StatusCode;
+use lettre::{
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+    message::{Mailbox, header::ContentType},
+};
+use rustical_store::{
+    Error, SessionStore,
+    auth::{
+        AuthenticationProvider, ClientIp, LoginProtection, Principal, hash_password,
+        load_or_generate_app_token_pepper, password_reset_signature,
+        verify_password_reset_signature,
+    },
+};
+use serde::Deserialize;
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::RwLock;
+use tower_sessions::Session;
+use tracing::{error, info, warn};
+use url::Url;
+
+// Another link is only sent to a principal after this many minutes
+const RESEND_COOLDOWN_MINUTES: i64 = 5;
+
+/// The reset page below `base_url`, which may include the path prefix of a reverse proxy
+fn reset_url(base_url: &str) -> Result<Url, url::ParseError> {
+    let mut base = Url::parse(base_url)?;
+    if !base.path().ends_with('/') {
+        base.set_path(&format!("{}/", base.path()));
+    }
+    base.join("frontend/login/reset")
+}
+
+/// Sends the password reset links, only present if `frontend.password_reset` is configured
+pub struct PasswordResetMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    reset_url: Url,
+    link_expiry_minutes: u32,
+    // When the last link was sent to a principal
+    last_sent: RwLock<HashMap<String, DateTime<Utc>>>,
+}
+
+impl PasswordResetMailer {
+    pub fn new(config: &PasswordResetConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            transport: AsyncSmtpTransport::<Tokio1Executor>::from_url(&config.smtp_url)?.build(),
+            from: config.from.parse()?,
+            reset_url: reset_url(&config.base_url)?,
+            link_expiry_minutes: config.link_expiry_minutes,
+            last_sent: RwLock::default(),
+        })
+    }
+
+    /// Returns false if a link was sent to the principal recently
+    async fn claim_cooldown(&self, principal: &str) -> bool {
+        let now = Utc::now();
+        let mut last_sent = self.last_sent.write().await;
+        last_sent.retain(|_, sent_at| now - *sent_at < Duration::minutes(RESEND_COOLDOWN_MINUTES));
+        if last_sent.contains_key(principal) {
+            return false;
+        }
+        last_sent.insert(principal.to_owned(), now);
+        true
+    }
+
+    fn reset_link(&self, pepper: &str, principal: &Principal) -> Url {
+        let expires = (Utc::now() + Duration::minutes(self.link_expiry_minutes.into())).timestamp();
+        let mut link = self.reset_url.clone();
+        link.query_pairs_mut()
+            .append_pair("principal", &principal.id)
+            .append_pair("expires", &expires.to_string())
+            .append_pair(
+                "signature",
+                &password_reset_signature(pepper, principal, expires),
+            );
+        link
+    }
+
+    async fn send_link(&self, principal: &str, email: &str, link: &Url) -> anyhow::Result<()> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(email.parse()?)
+            .subject("Reset your RustiCal password")
+            .header(ContentType::TEXT_PLAIN)
+            .body(format!(
+                "Someone asked to reset the password of {principal}.\n\n\
+                Open this link within {} minutes to choose a new password:\n\
+                {link}\n\n\
+                If that wasn't you, you can ignore this email.\n",
+                self.link_expiry_minutes
+            ))?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+/// Returns why a new password is rejected
+fn new_password_error(password: &str, confirmation: &str) -> Option<&'static str> {
+    if password.is_empty() {
+        Some("The new password must not be empty")
+    } else if password != confirmation {
+        Some("The passwords don't match")
+    } else {
+        None
+    }
+}
+
+/// Whether the password is kept by RustiCal, as opposed to principals without one
+/// (passkeys only, LDAP) or linked to an OIDC provider. Setting one for them would add a way in.
+async fn has_local_password<AP: AuthenticationProvider>(
+    auth_provider: &AP,
+    principal: &Principal,
+) -> Result<bool, Error> {
+    Ok(principal.password.is_some()
+        && auth_provider
+            .get_identity_provider(&principal.id)
+            .await?
+            .is_none())
+}
+
+#[derive(Deserialize)]
+pub struct PostPasswordForm {
+    current_password: String,
+    new_password: String,
+    confirm_password: String,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn route_post_password<AP: AuthenticationProvider, SS: SessionStore>(
+    Path(user_id): Path<String>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Extension(session_store): Extension<Arc<SS>>,
+    protection: LoginProtection,
+    ClientIp(client_ip): ClientIp,
+    session: Session,
+    user: Principal,
+    Form(form): Form<PostPasswordForm>,
+) -> Result<Response, Error> {
+    if user_id != user.id {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+    if user.app_token.is_some() {
+        return Ok(StatusCode::FORBIDDEN.into_response());
+    }
+    if !has_local_password(auth_provider.as_ref(), &user).await? {
+        return Ok((
+            StatusCode::FORBIDDEN,
+            "The password of this principal is not managed by RustiCal",
+        )
+            .into_response());
+    }
+    if let Some(err) = new_password_error(&form.new_password, &form.confirm_password) {
+        return Ok((StatusCode::BAD_REQUEST, err).into_response());
+    }
+    if protection
+        .locked_until(auth_provider.as_ref(), &user.id, client_ip)
+        .await?
+        .is_some()
+    {
+        return Ok(locked_out_response());
+    }
+    if auth_provider
+        .validate_password(&user.id, &form.current_password)
+        .await?
+        .is_none()
+    {
+        protection
+            .record_failure(auth_provider.as_ref(), &user.id, client_ip, "password")
+            .await?;
+        return Ok((StatusCode::FORBIDDEN, "The current password is wrong").into_response());
+    }
+    let Some(mut principal) = auth_provider.get_principal(&user.id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    principal.password = Some(hash_password(&form.new_password));
+    auth_provider.insert_principal(principal, true).await?;
+    session_store.revoke_sessions(&user.id).await?;
+    // Revoking removed this session as well, it is stored again under a new id
+    session
+        .cycle_id()
+        .await
+        .map_err(|err| Error::Other(err.into()))?;
+    Ok(Redirect::to(&format!("/frontend/user/{}", user.id)).into_response())
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "pages/password_forgot.html")]
+struct PasswordForgotPage {
+    sent: bool,
+}
+
+impl DefaultLayoutData for PasswordForgotPage {
+    fn get_user(&self) -> Option<&Principal> {
+        None
+    }
+}
+
+pub async fn route_get_password_forgot(
+    Extension(mailer): Extension<Option<Arc<PasswordResetMailer>>>,
+) -> Response {
+    if mailer.is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    PasswordForgotPage { sent: false }.into_response()
+}
+
+#[derive(Deserialize)]
+pub struct PostPasswordForgotForm {
+    username: String,
+}
+
+pub async fn route_post_password_forgot<AP: AuthenticationProvider>(
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Extension(mailer): Extension<Option<Arc<PasswordResetMailer>>>,
+    Form(PostPasswordForgotForm { username }): Form<PostPasswordForgotForm>,
+) -> Result<Response, Error> {
+    let Some(mailer) = mailer else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    if let Some(principal) = auth_provider.get_principal(&username).await?
+        && let Some(email) = auth_provider.get_email(&principal.id).await?
+        && has_local_password(auth_provider.as_ref(), &principal).await?
+        && mailer.claim_cooldown(&principal.id).await
+    {
+        let pepper = load_or_generate_app_token_pepper(auth_provider.as_ref()).await?;
+        let link = mailer.reset_link(&pepper, &principal);
+        // Sending in the background keeps the response time from revealing which principals exist
+        tokio::spawn(async move {
+            match mailer.send_link(&principal.id, &email, &link).await {
+                Ok(()) => info!("Sent a password reset link to {}", principal.id),
+                Err(err) => error!(
+                    "Could not send the password reset link to {}: {err}",
+                    principal.id
+                ),
+            }
+        });
+    }
+    // The same answer for unknown principals and those without an email address or local password
+    Ok(PasswordForgotPage { sent: true }.into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetLink {
+    principal: String,
+    expires: i64,
+    signature: String,
+}
+
+/// Returns the principal of a valid and unexpired reset link
+async fn verify_reset_link<AP: AuthenticationProvider>(
+    auth_provider: &AP,
+    link: &ResetLink,
+) -> Result<Option<Principal>, Error> {
+    let Some(principal) = auth_provider.get_principal(&link.principal).await? else {
+        return Ok(None);
+    };
+    if !has_local_password(auth_provider, &principal).await? {
+        return Ok(None);
+    }
+    let pepper = load_or_generate_app_token_pepper(auth_provider).await?;
+    Ok(verify_password_reset_signature(
+        &pepper,
+        &principal,
+        link.expires,
+        &link.signature,
+        Utc::now(),
+    )
+    .then_some(principal))
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "pages/password_reset.html")]
+struct PasswordResetPage {
+    // None if the link is invalid
+    link: Option<ResetLink>,
+    error: Option<&'static str>,
+}
+
+impl DefaultLayoutData for PasswordResetPage {
+    fn get_user(&self) -> Option<&Principal> {
+        None
+    }
+}
+
+fn invalid_link_response() -> Response {
+    warn!(target: "rustical::auth", "Rejected an invalid password reset link");
+    (
+        StatusCode::BAD_REQUEST,
+        PasswordResetPage {
+            link: None,
+            error: None,
+        },
+    )
+        .into_response()
+}
+
+pub async fn route_get_password_reset<AP: AuthenticationProvider>(
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Extension(mailer): Extension<Option<Arc<PasswordResetMailer>>>,
+    Query(link): Query<ResetLink>,
+) -> Result<Response, Error> {
+    if mailer.is_none() {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    if verify_reset_link(auth_provider.as_ref(), &link)
+        .await?
+        .is_none()
+    {
+        return Ok(invalid_link_response());
+    }
+    Ok(PasswordResetPage {
+        link: Some(link),
+        error: None,
+    }
+    .into_response())
+}
+
+#[derive(Deserialize)]
+pub struct PostPasswordResetForm {
+    principal: String,
+    expires: i64,
+    signature: String,
+    new_password: String,
+    confirm_password: String,
+}
+
+pub async fn route_post_password_reset<AP: AuthenticationProvider, SS: SessionStore>(
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Extension(session_store): Extension<Arc<SS>>,
+    Extension(mailer): Extension<Option<Arc<PasswordResetMailer>>>,
+    protection: LoginProtection,
+    Form(form): Form<PostPasswordResetForm>,
+) -> Result<Response, Error> {
+    if mailer.is_none() {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    let link = ResetLink {
+        principal: form.principal,
+        expires: form.expires,
+        signature: form.signature,
+    };
+    let Some(mut principal) = verify_reset_link(auth_provider.as_ref(), &link).await? else {
+        return Ok(invalid_link_response());
+    };
+    if let Some(err) = new_password_error(&form.new_password, &form.confirm_password) {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            PasswordResetPage {
+                link: Some(link),
+                error: Some(err),
+            },
+        )
+            .into_response());
+    }
+    principal.password = Some(hash_password(&form.new_password));
+    auth_provider.insert_principal(principal, true).await?;
+    session_store.revoke_sessions(&link.principal).await?;
+    // A lockout from guessing the forgotten password is lifted
+    protection
+        .record_success(auth_provider.as_ref(), &link.principal)
+        .await?;
+    info!(target: "rustical::auth", "Password of {} was reset", link.principal);
+    Ok(Redirect::to("/frontend/login").into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::reset_url;
+
+    #[test]
+    fn test_reset_url() {
+        for base_url in ["https://dav.example.com", "https://dav.example.com/"] {
+            assert_eq!(
+                reset_url(base_url).unwrap().as_str(),
+                "https://dav.example.com/frontend/login/reset"
+            );
+        }
+        for base_url in ["https://example.com/dav", "https://example.com/dav/"] {
+            assert_eq!(
+                reset_url(base_url).unwrap().as_str(),
+                "https://example.com/dav/frontend/login/reset"
+            );
+        }
+    }
+}
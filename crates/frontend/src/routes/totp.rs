@@ -0,0 +1,334 @@
+use crate::{
+    FrontendConfig,
+    pages::DefaultLayoutData,
+    routes::login::{PendingLogin, SESSION_KEY_PENDING_LOGIN, complete_login, locked_out_response},
+};
+use anyhow::anyhow;
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::{
+    Extension, Form,
+    extract::Path,
+    response::{IntoResponse, Redirect, Response},
+};
+use http::StatusCode;
+use rustical_store::{
+    Error,
+    auth::{
+        AuthenticationProvider, ClientIp, LoginProtection, Principal, Totp,
+        generate_recovery_codes, generate_totp_secret, totp_code_step, totp_generator,
+        validate_totp,
+    },
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use tower_sessions::Session;
+
+/// Session key of the secret of a TOTP setup that is yet to be confirmed
+const SESSION_KEY_TOTP_SECRET: &str = "totp_secret";
+// A pending login is dropped after this many wrong codes
+const MAX_FAILED_ATTEMPTS: u8 = 5;
+
+struct TotpEnrollment {
+    secret: String,
+    url: String,
+    qr_base64: String,
+}
+
+impl TotpEnrollment {
+    fn new(secret: String, principal: &str) -> Result<Self, Error> {
+        let totp = totp_generator(&secret, principal)?;
+        let qr_base64 = totp
+            .get_qr_base64()
+            .map_err(|err| Error::Other(anyhow!(err)))?;
+        Ok(Self {
+            url: totp.get_url(),
+            secret,
+            qr_base64,
+        })
+    }
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "pages/totp.html")]
+struct TotpPage {
+    user: Option<Principal>,
+    action: String,
+    enrollment: Option<TotpEnrollment>,
+    // Replacing an enabled second factor requires its current code
+    require_current_code: bool,
+    error: Option<&'static str>,
+}
+
+impl DefaultLayoutData for TotpPage {
+    fn get_user(&self) -> Option<&Principal> {
+        self.user.as_ref()
+    }
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "pages/recovery_codes.html")]
+struct RecoveryCodesPage {
+    user: Option<Principal>,
+    recovery_codes: Vec<String>,
+    continue_url: String,
+}
+
+impl DefaultLayoutData for RecoveryCodesPage {
+    fn get_user(&self) -> Option<&Principal> {
+        self.user.as_ref()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PostTotpForm {
+    code: String,
+    /// Code of the second factor that is replaced
+    #[serde(default)]
+    current_code: Option<String>,
+}
+
+/// Returns the secret of the setup in progress, starting a new one if there is none
+async fn enrollment_secret(session: &Session) -> String {
+    if let Some(secret) = session.get(SESSION_KEY_TOTP_SECRET).await.unwrap() {
+        return secret;
+    }
+    let secret = generate_totp_secret();
+    session
+        .insert(SESSION_KEY_TOTP_SECRET, &secret)
+        .await
+        .unwrap();
+    secret
+}
+
+/// Confirms the setup in progress with a code of the authenticator app
+/// and returns the new recovery codes
+async fn confirm_enrollment<AP: AuthenticationProvider>(
+    auth_provider: &AP,
+    session: &Session,
+    principal: &str,
+    code: &str,
+) -> Result<Option<Vec<String>>, Error> {
+    let Some(secret) = session
+        .get::<String>(SESSION_KEY_TOTP_SECRET)
+        .await
+        .unwrap()
+    else {
+        return Ok(None);
+    };
+    let Some(step) = totp_code_step(&secret, principal, code)? else {
+        return Ok(None);
+    };
+    let (recovery_codes, hashes) = generate_recovery_codes();
+    auth_provider
+        .set_totp(
+            principal,
+            Some(Totp {
+                secret: secret.into(),
+                recovery_codes: hashes,
+            }),
+        )
+        .await?;
+    // The confirmation code must not be usable for a login
+    auth_provider.use_totp_step(principal, step).await?;
+    session.remove_value(SESSION_KEY_TOTP_SECRET).await.unwrap();
+    Ok(Some(recovery_codes))
+}
+
+pub async fn route_get_login_totp<AP: AuthenticationProvider>(
+    Extension(auth_provider): Extension<Arc<AP>>,
+    session: Session,
+) -> Result<Response, Error> {
+    let Some(pending) = session
+        .get::<PendingLogin>(SESSION_KEY_PENDING_LOGIN)
+        .await
+        .unwrap()
+    else {
+        return Ok(Redirect::to("/frontend/login").into_response());
+    };
+    // Required but not set up yet
+    let enrollment = if auth_provider.get_totp(&pending.user_id).await?.is_none() {
+        let secret = enrollment_secret(&session).await;
+        Some(TotpEnrollment::new(secret, &pending.user_id)?)
+    } else {
+        None
+    };
+    Ok(TotpPage {
+        user: None,
+        action: "/frontend/login/totp".to_owned(),
+        enrollment,
+        require_current_code: false,
+        error: None,
+    }
+    .into_response())
+}
+
+pub async fn route_post_login_totp<AP: AuthenticationProvider>(
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Extension(config): Extension<FrontendConfig>,
+    protection: LoginProtection,
+    ClientIp(client_ip): ClientIp,
+    session: Session,
+    Form(PostTotpForm { code, .. }): Form<PostTotpForm>,
+) -> Result<Response, Error> {
+    let Some(mut pending) = session
+        .get::<PendingLogin>(SESSION_KEY_PENDING_LOGIN)
+        .await
+        .unwrap()
+    else {
+        return Ok(Redirect::to("/frontend/login").into_response());
+    };
+    if protection
+        .locked_until(auth_provider.as_ref(), &pending.user_id, client_ip)
+        .await?
+        .is_some()
+    {
+        return Ok(locked_out_response());
+    }
+
+    let recovery_codes = if auth_provider.get_totp(&pending.user_id).await?.is_some() {
+        validate_totp(auth_provider.as_ref(), &pending.user_id, &code)
+            .await?
+            .then(Vec::new)
+    } else {
+        confirm_enrollment(auth_provider.as_ref(), &session, &pending.user_id, &code).await?
+    };
+
+    let Some(recovery_codes) = recovery_codes else {
+        protection
+            .record_failure(auth_provider.as_ref(), &pending.user_id, client_ip, "totp")
+            .await?;
+        pending.failed_attempts += 1;
+        if pending.failed_attempts >= MAX_FAILED_ATTEMPTS {
+            session
+                .remove_value(SESSION_KEY_PENDING_LOGIN)
+                .await
+                .unwrap();
+            return Ok(Redirect::to("/frontend/login").into_response());
+        }
+        let enrollment = match session.get(SESSION_KEY_TOTP_SECRET).await.unwrap() {
+            Some(secret) => Some(TotpEnrollment::new(secret, &pending.user_id)?),
+            None => None,
+        };
+        session
+            .insert(SESSION_KEY_PENDING_LOGIN, pending)
+            .await
+            .unwrap();
+        return Ok((
+            StatusCode::UNAUTHORIZED,
+            TotpPage {
+                user: None,
+                action: "/frontend/login/totp".to_owned(),
+                enrollment,
+                require_current_code: false,
+                error: Some("Invalid code"),
+            },
+        )
+            .into_response());
+    };
+
+    protection
+        .record_success(auth_provider.as_ref(), &pending.user_id)
+        .await?;
+    complete_login(&session, &config, pending.user_id, pending.remember_me).await;
+    if recovery_codes.is_empty() {
+        return Ok(Redirect::to(&pending.redirect_uri).into_response());
+    }
+    Ok(RecoveryCodesPage {
+        user: None,
+        recovery_codes,
+        continue_url: pending.redirect_uri,
+    }
+    .into_response())
+}
+
+pub async fn route_get_totp_setup<AP: AuthenticationProvider>(
+    Path(user_id): Path<String>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+    user: Principal,
+    session: Session,
+) -> Result<Response, Error> {
+    if user_id != user.id {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+    if user.app_token.is_some() {
+        return Ok(StatusCode::FORBIDDEN.into_response());
+    }
+    let secret = enrollment_secret(&session).await;
+    Ok(TotpPage {
+        enrollment: Some(TotpEnrollment::new(secret, &user.id)?),
+        action: format!("/frontend/user/{}/totp", user.id),
+        require_current_code: auth_provider.get_totp(&user.id).await?.is_some(),
+        user: Some(user),
+        error: None,
+    }
+    .into_response())
+}
+
+pub async fn route_post_totp_setup<AP: AuthenticationProvider>(
+    Path(user_id): Path<String>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+    user: Principal,
+    session: Session,
+    Form(PostTotpForm { code, current_code }): Form<PostTotpForm>,
+) -> Result<Response, Error> {
+    if user_id != user.id {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+    if user.app_token.is_some() {
+        return Ok(StatusCode::FORBIDDEN.into_response());
+    }
+    let require_current_code = auth_provider.get_totp(&user.id).await?.is_some();
+    let error = if require_current_code
+        && !validate_totp(
+            auth_provider.as_ref(),
+            &user.id,
+            current_code.as_deref().unwrap_or_default(),
+        )
+        .await?
+    {
+        "Invalid current code"
+    } else if let Some(recovery_codes) =
+        confirm_enrollment(auth_provider.as_ref(), &session, &user.id, &code).await?
+    {
+        return Ok(RecoveryCodesPage {
+            continue_url: format!("/frontend/user/{}", user.id),
+            user: Some(user),
+            recovery_codes,
+        }
+        .into_response());
+    } else {
+        "Invalid code"
+    };
+    let secret = enrollment_secret(&session).await;
+    Ok((
+        StatusCode::BAD_REQUEST,
+        TotpPage {
+            enrollment: Some(TotpEnrollment::new(secret, &user.id)?),
+            action: format!("/frontend/user/{}/totp", user.id),
+            require_current_code,
+            user: Some(user),
+            error: Some(error),
+        },
+    )
+        .into_response())
+}
+
+pub async fn route_delete_totp<AP: AuthenticationProvider>(
+    Path(user_id): Path<String>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+    user: Principal,
+    Form(PostTotpForm { code, .. }): Form<PostTotpForm>,
+) -> Result<Response, Error> {
+    if user_id != user.id {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+    if user.app_token.is_some() {
+        return Ok(StatusCode::FORBIDDEN.into_response());
+    }
+    if !validate_totp(auth_provider.as_ref(), &user.id, &code).await? {
+        return Ok((StatusCode::BAD_REQUEST, "invalid code").into_response());
+    }
+    auth_provider.set_totp(&user.id, None).await?;
+    Ok(Redirect::to(&format!("/frontend/user/{}", user.id)).into_response())
+}
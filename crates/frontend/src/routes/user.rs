@@ -1,6 +1,9 @@
 use std::sync::Arc;
 
-use crate::pages::user::{Section, UserPage};
+use crate::{
+    FrontendConfig,
+    pages::user::{Section, UserPage},
+};
 use askama::Template;
 use askama_web::WebTemplate;
 use axum::{
@@ -11,7 +14,8 @@ use axum::{
 use axum_extra::TypedHeader;
 use headers::{HeaderMapExt, Host, UserAgent};
 use http::{HeaderMap, StatusCode};
-use rustical_store::auth::{AppToken, AuthenticationProvider, Principal};
+use rustical_store::auth::{AppToken, AuthenticationProvider, PasskeyCredential, Principal};
+use webauthn_rs::prelude::Webauthn;
 
 impl Section for ProfileSection {
     fn name() -> &'static str {
@@ -24,12 +28,20 @@ impl Section for ProfileSection {
 pub struct ProfileSection {
     pub user: Principal,
     pub app_tokens: Vec<AppToken>,
+    // Number of unused recovery codes if TOTP is enabled
+    pub totp_recovery_codes: Option<usize>,
+    // None if passkeys are disabled
+    pub passkeys: Option<Vec<PasskeyCredential>>,
+    pub can_remove_password: bool,
     pub davx5_hostname: Option<String>,
+    pub is_admin: bool,
 }
 
 pub async fn route_user_named<AP: AuthenticationProvider>(
     Path(user_id): Path<String>,
     Extension(auth_provider): Extension<Arc<AP>>,
+    Extension(config): Extension<FrontendConfig>,
+    Extension(webauthn): Extension<Option<Arc<Webauthn>>>,
     TypedHeader(host): TypedHeader<Host>,
     user: Principal,
     headers: HeaderMap,
@@ -42,11 +54,33 @@ pub async fn route_user_named<AP: AuthenticationProvider>(
     let davx5_hostname =
         ua.and_then(|ua| ua.as_str().contains("Android").then_some(host.to_string()));
 
+    let passkeys = if webauthn.is_some() {
+        Some(auth_provider.get_passkeys(&user.id).await.unwrap())
+    } else {
+        None
+    };
+    let can_remove_password = user.password.is_some()
+        && config
+            .passkeys
+            .as_ref()
+            .is_some_and(|passkeys| passkeys.allow_passkey_only)
+        && passkeys
+            .as_ref()
+            .is_some_and(|passkeys| !passkeys.is_empty());
+
     UserPage {
         section: ProfileSection {
             user: user.clone(),
             app_tokens: auth_provider.get_app_tokens(&user.id).await.unwrap(),
+            totp_recovery_codes: auth_provider
+                .get_totp(&user.id)
+                .await
+                .unwrap()
+                .map(|totp| totp.recovery_codes.len()),
+            passkeys,
+            can_remove_password,
             davx5_hostname,
+            is_admin: config.is_admin(&user),
         },
         user,
     }
@@ -0,0 +1,106 @@
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::{
+    Extension, Form,
+    extract::Path,
+    response::{IntoResponse, Redirect, Response},
+};
+use http::StatusCode;
+use rustical_store::{CollectionType, auth::Principal};
+use rustical_webhook::{Webhook, WebhookDelivery, WebhookStore};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::pages::user::{Section, UserPage};
+
+// Number of deliveries shown in the log of each webhook
+const DELIVERY_LOG_LEN: i64 = 10;
+
+impl Section for WebhooksSection {
+    fn name() -> &'static str {
+        "webhooks"
+    }
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "components/sections/webhooks_section.html")]
+pub struct WebhooksSection {
+    pub user: Principal,
+    pub webhooks: Vec<(Webhook, Vec<WebhookDelivery>)>,
+}
+
+pub async fn route_webhooks<WS: WebhookStore>(
+    Path(user_id): Path<String>,
+    Extension(webhook_store): Extension<Arc<WS>>,
+    user: Principal,
+) -> Result<Response, rustical_store::Error> {
+    if user_id != user.id {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+
+    let mut webhooks = vec![];
+    for principal in user.memberships() {
+        for webhook in webhook_store.get_webhooks(principal).await? {
+            let deliveries = webhook_store
+                .get_deliveries(&webhook.id, DELIVERY_LOG_LEN)
+                .await?;
+            webhooks.push((webhook, deliveries));
+        }
+    }
+
+    Ok(UserPage {
+        section: WebhooksSection {
+            user: user.clone(),
+            webhooks,
+        },
+        user,
+    }
+    .into_response())
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PostWebhookForm {
+    principal: String,
+    url: String,
+    #[serde(default)]
+    collection_type: String,
+    #[serde(default)]
+    collection_id: String,
+}
+
+pub async fn route_post_webhook<WS: WebhookStore>(
+    Path(user_id): Path<String>,
+    Extension(webhook_store): Extension<Arc<WS>>,
+    user: Principal,
+    Form(form): Form<PostWebhookForm>,
+) -> Result<Response, rustical_store::Error> {
+    if user_id != user.id || !user.is_principal(&form.principal) {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+    if url::Url::parse(&form.url).is_err() {
+        return Ok((StatusCode::BAD_REQUEST, "invalid url").into_response());
+    }
+    let collection = if form.collection_id.is_empty() {
+        None
+    } else {
+        let collection_type = CollectionType::try_from(form.collection_type.as_str())?;
+        Some((collection_type, form.collection_id))
+    };
+    webhook_store
+        .insert_webhook(Webhook::new(form.principal, collection, form.url))
+        .await?;
+    Ok(Redirect::to(&format!("/frontend/user/{user_id}/webhook")).into_response())
+}
+
+pub async fn route_delete_webhook<WS: WebhookStore>(
+    Path((user_id, webhook_id)): Path<(String, String)>,
+    Extension(webhook_store): Extension<Arc<WS>>,
+    user: Principal,
+) -> Result<Response, rustical_store::Error> {
+    let webhook = webhook_store.get_webhook(&webhook_id).await?;
+    if user_id != user.id || !user.is_principal(&webhook.principal) {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+    webhook_store.delete_webhook(&webhook.id).await?;
+    Ok(Redirect::to(&format!("/frontend/user/{user_id}/webhook")).into_response())
+}
@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+
+fn default_user_filter() -> String {
+    "(uid={username})".to_owned()
+}
+
+fn default_user_id_attribute() -> String {
+    "uid".to_owned()
+}
+
+fn default_displayname_attribute() -> String {
+    "cn".to_owned()
+}
+
+fn default_group_filter() -> String {
+    "(member={dn})".to_owned()
+}
+
+fn default_group_id_attribute() -> String {
+    "cn".to_owned()
+}
+
+const fn default_timeout_secs() -> u64 {
+    10
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LdapConfig {
+    /// `ldap://` or `ldaps://` url of the directory
+    pub url: String,
+    #[serde(default)]
+    pub starttls: bool,
+    /// Account to search users and groups with, binds anonymously if unset
+    pub bind_dn: Option<String>,
+    pub bind_password: Option<String>,
+    pub user_base_dn: String,
+    /// `{username}` is replaced with the escaped login name
+    #[serde(default = "default_user_filter")]
+    pub user_filter: String,
+    #[serde(default = "default_user_id_attribute")]
+    pub user_id_attribute: String,
+    #[serde(default = "default_displayname_attribute")]
+    pub displayname_attribute: String,
+    /// Group memberships are only synchronised if this is set
+    pub group_base_dn: Option<String>,
+    /// `{dn}` is replaced with the user's dn and `{username}` with the user id,
+    /// e.g. `(memberUid={username})` for posixGroups
+    #[serde(default = "default_group_filter")]
+    pub group_filter: String,
+    #[serde(default = "default_group_id_attribute")]
+    pub group_id_attribute: String,
+    pub require_group: Option<String>,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
@@ -0,0 +1,10 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+#![allow(clippy::missing_errors_doc)]
+pub use config::LdapConfig;
+pub use provider::LdapAuthenticationProvider;
+
+mod config;
+mod provider;
+
+#[cfg(all(test, feature = "test-ldap"))]
+mod tests;
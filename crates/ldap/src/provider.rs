@@ -0,0 +1,426 @@
+use crate::LdapConfig;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use ldap3::{Ldap, LdapConnAsync, LdapConnSettings, LdapError, Scope, SearchEntry, ldap_escape};
+use rustical_store::{
+    Error,
+    auth::{
+        AppToken, AppTokenScope, AuthenticationProvider, LoginFailureStore, LoginFailures,
+        LoginSubject, PasskeyCredential, Principal, PrincipalType, SecondFactorStore, Totp,
+        sync_group_memberships,
+    },
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{instrument, warn};
+
+// LDAP result code of a failed bind
+const INVALID_CREDENTIALS: u32 = 49;
+
+#[derive(Debug)]
+struct LdapUser {
+    id: String,
+    displayname: Option<String>,
+    groups: Vec<String>,
+}
+
+fn attribute(entry: &SearchEntry, name: &str) -> Option<String> {
+    entry
+        .attrs
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .and_then(|(_, values)| values.first().cloned())
+}
+
+/// Authenticates passwords against an LDAP directory and provisions the principals
+/// and their group memberships in the local store, where everything else still lives.
+#[derive(Debug)]
+pub struct LdapAuthenticationProvider<AP: AuthenticationProvider> {
+    config: LdapConfig,
+    inner: Arc<AP>,
+}
+
+impl<AP: AuthenticationProvider> LdapAuthenticationProvider<AP> {
+    pub const fn new(config: LdapConfig, inner: Arc<AP>) -> Self {
+        Self { config, inner }
+    }
+
+    async fn connect(&self) -> Result<Ldap, LdapError> {
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(Duration::from_secs(self.config.timeout_secs))
+            .set_starttls(self.config.starttls);
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.config.url).await?;
+        tokio::spawn(async move {
+            if let Err(err) = conn.drive().await {
+                warn!("LDAP connection error: {err}");
+            }
+        });
+        self.bind_service_account(&mut ldap).await?;
+        Ok(ldap)
+    }
+
+    async fn bind_service_account(&self, ldap: &mut Ldap) -> Result<(), LdapError> {
+        if let Some(bind_dn) = &self.config.bind_dn {
+            ldap.simple_bind(
+                bind_dn,
+                self.config.bind_password.as_deref().unwrap_or_default(),
+            )
+            .await?
+            .success()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the directory entry of `username` if `password` is correct
+    async fn authenticate(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<Option<LdapUser>, LdapError> {
+        let mut ldap = self.connect().await?;
+        let filter = self
+            .config
+            .user_filter
+            .replace("{username}", &ldap_escape(username));
+        let (entries, _) = ldap
+            .search(
+                &self.config.user_base_dn,
+                Scope::Subtree,
+                &filter,
+                vec![
+                    self.config.user_id_attribute.as_str(),
+                    self.config.displayname_attribute.as_str(),
+                ],
+            )
+            .await?
+            .success()?;
+        // Unknown or ambiguous
+        let mut entries = entries.into_iter();
+        let (Some(entry), None) = (entries.next(), entries.next()) else {
+            ldap.unbind().await?;
+            return Ok(None);
+        };
+        let entry = SearchEntry::construct(entry);
+        let Some(id) = attribute(&entry, &self.config.user_id_attribute) else {
+            warn!(
+                "LDAP user {} has no {} attribute",
+                entry.dn, self.config.user_id_attribute
+            );
+            ldap.unbind().await?;
+            return Ok(None);
+        };
+
+        let bind = ldap.simple_bind(&entry.dn, password).await?;
+        if bind.rc == INVALID_CREDENTIALS {
+            ldap.unbind().await?;
+            return Ok(None);
+        }
+        bind.success()?;
+
+        let mut groups = vec![];
+        if let Some(group_base_dn) = &self.config.group_base_dn {
+            // Search groups with the service account again
+            self.bind_service_account(&mut ldap).await?;
+            let filter = self
+                .config
+                .group_filter
+                .replace("{dn}", &ldap_escape(&entry.dn))
+                .replace("{username}", &ldap_escape(&id));
+            let (entries, _) = ldap
+                .search(
+                    group_base_dn,
+                    Scope::Subtree,
+                    &filter,
+                    vec![self.config.group_id_attribute.as_str()],
+                )
+                .await?
+                .success()?;
+            groups = entries
+                .into_iter()
+                .filter_map(|entry| {
+                    attribute(
+                        &SearchEntry::construct(entry),
+                        &self.config.group_id_attribute,
+                    )
+                })
+                .collect();
+        }
+        ldap.unbind().await?;
+
+        Ok(Some(LdapUser {
+            id,
+            displayname: attribute(&entry, &self.config.displayname_attribute),
+            groups,
+        }))
+    }
+
+    /// Creates or updates the principal and replaces its group memberships with the directory's
+    async fn provision(&self, user: LdapUser) -> Result<Principal, Error> {
+        match self.inner.get_principal(&user.id).await? {
+            Some(principal) if principal.displayname == user.displayname => {}
+            Some(principal) => {
+                self.inner
+                    .insert_principal(
+                        Principal {
+                            displayname: user.displayname.clone(),
+                            ..principal
+                        },
+                        true,
+                    )
+                    .await?;
+            }
+            None => {
+                self.inner
+                    .insert_principal(
+                        Principal {
+                            id: user.id.clone(),
+                            displayname: user.displayname.clone(),
+                            principal_type: PrincipalType::Individual,
+                            password: None,
+                            memberships: vec![],
+                            app_token: None,
+                        },
+                        false,
+                    )
+                    .await?;
+            }
+        }
+
+        if self.config.group_base_dn.is_some() {
+            let principal = self
+                .inner
+                .get_principal(&user.id)
+                .await?
+                .ok_or(Error::NotFound)?;
+            sync_group_memberships(self.inner.as_ref(), &principal, &user.groups).await?;
+        }
+
+        self.inner
+            .get_principal(&user.id)
+            .await?
+            .ok_or(Error::NotFound)
+    }
+}
+
+#[async_trait]
+impl<AP: AuthenticationProvider> AuthenticationProvider for LdapAuthenticationProvider<AP> {
+    async fn get_principals(&self) -> Result<Vec<Principal>, Error> {
+        self.inner.get_principals().await
+    }
+
+    async fn get_principal(&self, id: &str) -> Result<Option<Principal>, Error> {
+        self.inner.get_principal(id).await
+    }
+
+    async fn remove_principal(&self, id: &str) -> Result<(), Error> {
+        self.inner.remove_principal(id).await
+    }
+
+    async fn purge_principal(&self, id: &str) -> Result<(), Error> {
+        self.inner.purge_principal(id).await
+    }
+
+    async fn transfer_collections(&self, from: &str, to: &str) -> Result<(), Error> {
+        self.inner.transfer_collections(from, to).await
+    }
+
+    async fn insert_principal(&self, user: Principal, overwrite: bool) -> Result<(), Error> {
+        self.inner.insert_principal(user, overwrite).await
+    }
+
+    #[instrument(skip(self, password_input))]
+    async fn validate_password(
+        &self,
+        user_id: &str,
+        password_input: &str,
+    ) -> Result<Option<Principal>, Error> {
+        // An empty password would be an unauthenticated bind which always succeeds
+        if password_input.is_empty() {
+            return Ok(None);
+        }
+        let user = tokio::time::timeout(
+            Duration::from_secs(self.config.timeout_secs),
+            self.authenticate(user_id, password_input),
+        )
+        .await
+        .map_err(|err| Error::Other(err.into()))?
+        .map_err(|err| Error::Other(err.into()))?;
+        let Some(user) = user else {
+            return Ok(None);
+        };
+        if let Some(group) = &self.config.require_group
+            && !user.groups.contains(group)
+        {
+            return Ok(None);
+        }
+        if !self.inner.is_active(user_id).await? {
+            return Ok(None);
+        }
+        self.provision(user).await.map(Some)
+    }
+
+    async fn add_app_token(
+        &self,
+        user_id: &str,
+        name: String,
+        token: String,
+        scope: AppTokenScope,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<String, Error> {
+        self.inner
+            .add_app_token(user_id, name, token, scope, expires_at)
+            .await
+    }
+
+    async fn insert_app_token(&self, user_id: &str, token: AppToken) -> Result<(), Error> {
+        self.inner.insert_app_token(user_id, token).await
+    }
+
+    async fn remove_app_token(&self, user_id: &str, token_id: &str) -> Result<(), Error> {
+        self.inner.remove_app_token(user_id, token_id).await
+    }
+
+    async fn get_app_tokens(&self, principal: &str) -> Result<Vec<AppToken>, Error> {
+        self.inner.get_app_tokens(principal).await
+    }
+
+    async fn get_app_token_by_digest(
+        &self,
+        user_id: &str,
+        digest: &str,
+    ) -> Result<Option<AppToken>, Error> {
+        self.inner.get_app_token_by_digest(user_id, digest).await
+    }
+
+    async fn set_app_token_digest(
+        &self,
+        user_id: &str,
+        token_id: &str,
+        digest: &str,
+    ) -> Result<(), Error> {
+        self.inner
+            .set_app_token_digest(user_id, token_id, digest)
+            .await
+    }
+
+    async fn get_app_token_pepper(&self) -> Result<Option<String>, Error> {
+        self.inner.get_app_token_pepper().await
+    }
+
+    async fn insert_app_token_pepper(&self, pepper: &str) -> Result<(), Error> {
+        self.inner.insert_app_token_pepper(pepper).await
+    }
+
+    async fn record_app_token_use(
+        &self,
+        user_id: &str,
+        token_id: &str,
+        user_agent: Option<String>,
+    ) -> Result<(), Error> {
+        self.inner
+            .record_app_token_use(user_id, token_id, user_agent)
+            .await
+    }
+
+    async fn add_membership(&self, principal: &str, member_of: &str) -> Result<(), Error> {
+        self.inner.add_membership(principal, member_of).await
+    }
+
+    async fn remove_membership(&self, principal: &str, member_of: &str) -> Result<(), Error> {
+        self.inner.remove_membership(principal, member_of).await
+    }
+
+    async fn list_members(&self, principal: &str) -> Result<Vec<String>, Error> {
+        self.inner.list_members(principal).await
+    }
+
+    async fn get_identity_provider(&self, principal: &str) -> Result<Option<String>, Error> {
+        self.inner.get_identity_provider(principal).await
+    }
+
+    async fn set_identity_provider(&self, principal: &str, provider: &str) -> Result<(), Error> {
+        self.inner.set_identity_provider(principal, provider).await
+    }
+
+    async fn get_email(&self, principal: &str) -> Result<Option<String>, Error> {
+        self.inner.get_email(principal).await
+    }
+
+    async fn set_email(&self, principal: &str, email: Option<&str>) -> Result<(), Error> {
+        self.inner.set_email(principal, email).await
+    }
+
+    async fn is_active(&self, principal: &str) -> Result<bool, Error> {
+        self.inner.is_active(principal).await
+    }
+
+    async fn set_active(&self, principal: &str, active: bool) -> Result<(), Error> {
+        self.inner.set_active(principal, active).await
+    }
+}
+
+#[async_trait]
+impl<AP: AuthenticationProvider> SecondFactorStore for LdapAuthenticationProvider<AP> {
+    async fn get_totp(&self, principal: &str) -> Result<Option<Totp>, Error> {
+        self.inner.get_totp(principal).await
+    }
+
+    async fn set_totp(&self, principal: &str, totp: Option<Totp>) -> Result<(), Error> {
+        self.inner.set_totp(principal, totp).await
+    }
+
+    async fn use_totp_step(&self, principal: &str, step: i64) -> Result<bool, Error> {
+        self.inner.use_totp_step(principal, step).await
+    }
+
+    async fn get_passkeys(&self, principal: &str) -> Result<Vec<PasskeyCredential>, Error> {
+        self.inner.get_passkeys(principal).await
+    }
+
+    async fn upsert_passkey(
+        &self,
+        principal: &str,
+        passkey: PasskeyCredential,
+    ) -> Result<(), Error> {
+        self.inner.upsert_passkey(principal, passkey).await
+    }
+
+    async fn remove_passkey(&self, principal: &str, id: &str) -> Result<(), Error> {
+        self.inner.remove_passkey(principal, id).await
+    }
+}
+
+#[async_trait]
+impl<AP: AuthenticationProvider> LoginFailureStore for LdapAuthenticationProvider<AP> {
+    async fn get_login_failures(
+        &self,
+        subject: &LoginSubject,
+    ) -> Result<Option<LoginFailures>, Error> {
+        self.inner.get_login_failures(subject).await
+    }
+
+    async fn list_login_failures(&self) -> Result<Vec<LoginFailures>, Error> {
+        self.inner.list_login_failures().await
+    }
+
+    async fn add_login_failure(
+        &self,
+        subject: &LoginSubject,
+        now: DateTime<Utc>,
+        window_start: DateTime<Utc>,
+        max_failures: u32,
+        lock_until: DateTime<Utc>,
+    ) -> Result<LoginFailures, Error> {
+        self.inner
+            .add_login_failure(subject, now, window_start, max_failures, lock_until)
+            .await
+    }
+
+    async fn remove_login_failures(&self, subject: &LoginSubject) -> Result<(), Error> {
+        self.inner.remove_login_failures(subject).await
+    }
+
+    async fn prune_login_failures(&self, before: DateTime<Utc>) -> Result<u64, Error> {
+        self.inner.prune_login_failures(before).await
+    }
+}
@@ -0,0 +1,142 @@
+//! Runs against a glauth instance started with `tests/glauth.cfg` at `RUSTICAL_TEST_LDAP_URL`
+use crate::{LdapAuthenticationProvider, LdapConfig};
+use rustical_store::EventBus;
+use rustical_store::auth::{AppTokenScope, AuthenticationProvider, Principal, PrincipalType};
+use rustical_store_memory::{MemoryDb, principal_store::MemoryPrincipalStore};
+use std::sync::Arc;
+
+fn config() -> LdapConfig {
+    LdapConfig {
+        url: std::env::var("RUSTICAL_TEST_LDAP_URL")
+            .expect("RUSTICAL_TEST_LDAP_URL must be set to run the LDAP tests"),
+        starttls: false,
+        bind_dn: Some("cn=search,ou=svcaccts,dc=example,dc=com".to_owned()),
+        bind_password: Some("search-password".to_owned()),
+        user_base_dn: "dc=example,dc=com".to_owned(),
+        user_filter: "(&(objectClass=posixAccount)(uid={username}))".to_owned(),
+        user_id_attribute: "uid".to_owned(),
+        displayname_attribute: "givenName".to_owned(),
+        group_base_dn: Some("dc=example,dc=com".to_owned()),
+        group_filter: "(&(objectClass=posixGroup)(memberUid={username}))".to_owned(),
+        group_id_attribute: "cn".to_owned(),
+        require_group: None,
+        timeout_secs: 5,
+    }
+}
+
+fn provider(config: LdapConfig) -> LdapAuthenticationProvider<MemoryPrincipalStore> {
+    LdapAuthenticationProvider::new(
+        config,
+        Arc::new(MemoryPrincipalStore::new(
+            MemoryDb::default(),
+            EventBus::default(),
+        )),
+    )
+}
+
+fn principal(id: &str, principal_type: PrincipalType) -> Principal {
+    Principal {
+        id: id.to_owned(),
+        displayname: None,
+        principal_type,
+        password: None,
+        memberships: vec![],
+        app_token: None,
+    }
+}
+
+#[tokio::test]
+async fn test_login_provisions_principal() {
+    let provider = provider(config());
+
+    let user = provider
+        .validate_password("alice", "alice-password")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(user.id, "alice");
+    assert_eq!(user.displayname.as_deref(), Some("Alice"));
+    let mut memberships = user.memberships.clone();
+    memberships.sort();
+    assert_eq!(memberships, vec!["family", "staff"]);
+    let group = provider.get_principal("staff").await.unwrap().unwrap();
+    assert_eq!(group.principal_type, PrincipalType::Group);
+
+    // App tokens live in the local store
+    let token_id = provider
+        .add_app_token(
+            "alice",
+            "phone".to_owned(),
+            "token".to_owned(),
+            AppTokenScope::default(),
+            None,
+        )
+        .await
+        .unwrap();
+    assert!(
+        provider
+            .validate_app_token("alice", &format!("{token_id}_token"))
+            .await
+            .unwrap()
+            .is_some()
+    );
+}
+
+#[tokio::test]
+async fn test_invalid_credentials() {
+    let provider = provider(config());
+
+    for (user, password) in [
+        ("alice", "wrong"),
+        ("alice", ""),
+        ("nobody", "alice-password"),
+        ("*", "alice-password"),
+    ] {
+        assert!(
+            provider
+                .validate_password(user, password)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+    assert!(provider.get_principal("alice").await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_memberships_follow_directory() {
+    let provider = provider(config());
+    for principal in [
+        principal("alice", PrincipalType::Individual),
+        principal("former", PrincipalType::Group),
+        principal("room", PrincipalType::Room),
+    ] {
+        provider.insert_principal(principal, false).await.unwrap();
+    }
+    provider.add_membership("alice", "former").await.unwrap();
+    provider.add_membership("alice", "room").await.unwrap();
+
+    let user = provider
+        .validate_password("alice", "alice-password")
+        .await
+        .unwrap()
+        .unwrap();
+    let mut memberships = user.memberships.clone();
+    memberships.sort();
+    assert_eq!(memberships, vec!["family", "room", "staff"]);
+}
+
+#[tokio::test]
+async fn test_require_group() {
+    let provider = provider(LdapConfig {
+        require_group: Some("admins".to_owned()),
+        ..config()
+    });
+    assert!(
+        provider
+            .validate_password("alice", "alice-password")
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
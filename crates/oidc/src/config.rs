@@ -4,7 +4,7 @@ use openidconnect::{
     UserInfoClaims,
 };
 use serde::{Deserialize, Deserializer, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 #[derive(Deserialize, Serialize, Clone, Default)]
 #[serde(rename_all = "snake_case")]
@@ -23,17 +23,29 @@ impl UserIdClaim {
         &self,
         claims: &UserInfoClaims<AC, GC>,
     ) -> Result<String, OidcError> {
-        Ok(match self {
-            Self::Sub => claims.subject().to_string(),
-            Self::PreferredUsername => claims
+        self.select(
+            claims.subject(),
+            claims
                 .preferred_username()
-                .ok_or(OidcError::Other("Missing preferred_username claim"))?
-                .to_string(),
-            Self::Email => claims
-                .email()
-                .ok_or(OidcError::Other("Missing email claim"))?
-                .to_string(),
-        })
+                .map(|username| username.as_str()),
+            claims.email().map(|email| email.as_str()),
+        )
+    }
+
+    pub(crate) fn select(
+        &self,
+        sub: &str,
+        preferred_username: Option<&str>,
+        email: Option<&str>,
+    ) -> Result<String, OidcError> {
+        Ok(match self {
+            Self::Sub => sub,
+            Self::PreferredUsername => {
+                preferred_username.ok_or(OidcError::Other("Missing preferred_username claim"))?
+            }
+            Self::Email => email.ok_or(OidcError::Other("Missing email claim"))?,
+        }
+        .to_owned())
     }
 }
 
@@ -58,6 +70,10 @@ where
     Ok(ClientId::new(string_val))
 }
 
+fn default_groups_claim() -> String {
+    "groups".to_owned()
+}
+
 #[derive(Deserialize, Serialize, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct OidcConfig {
@@ -76,4 +92,65 @@ pub struct OidcConfig {
     pub additional_audiences: Vec<Audience>,
     #[serde(default)]
     pub assign_memberships: HashMap<String, Vec<String>>,
+    /// Claim containing the user's groups
+    #[serde(default = "default_groups_claim")]
+    pub groups_claim: String,
+    /// Make the group memberships follow the groups claim on every login
+    #[serde(default)]
+    pub sync_groups: bool,
+    /// Principal ids of synced groups, other groups keep their name
+    #[serde(default)]
+    pub group_mapping: HashMap<String, String>,
+    /// Accept JWT access tokens from the issuer as `Authorization: Bearer` on the DAV endpoints
+    #[serde(default)]
+    pub bearer_auth: bool,
+    /// Audience of access tokens meant for RustiCal, for providers that don't type them as `at+jwt`
+    #[serde(default)]
+    pub bearer_audience: Option<String>,
+}
+
+impl OidcConfig {
+    /// Memberships assigned to the given OIDC groups
+    pub(crate) fn assigned_memberships(&self, groups: &[String]) -> Vec<&str> {
+        groups
+            .iter()
+            .filter_map(|group| self.assign_memberships.get(group))
+            .flatten()
+            .map(String::as_str)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// Memberships that the given OIDC groups result in with `sync_groups`
+    pub(crate) fn synced_memberships(&self, groups: &[String]) -> Vec<String> {
+        let mut memberships: HashSet<&str> = groups
+            .iter()
+            .map(|group| self.group_mapping.get(group).unwrap_or(group).as_str())
+            .collect();
+        memberships.extend(self.assigned_memberships(groups));
+        memberships.into_iter().map(str::to_owned).collect()
+    }
+}
+
+/// Either a single provider or several providers by their id
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum OidcProviders {
+    Single(Box<OidcConfig>),
+    Multiple(BTreeMap<String, OidcConfig>),
+}
+
+impl OidcProviders {
+    /// Id of a single provider
+    pub const DEFAULT_ID: &str = "default";
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&str, &OidcConfig)> + '_> {
+        match self {
+            Self::Single(config) => Box::new(std::iter::once((Self::DEFAULT_ID, config.as_ref()))),
+            Self::Multiple(configs) => {
+                Box::new(configs.iter().map(|(id, config)| (id.as_str(), config)))
+            }
+        }
+    }
 }
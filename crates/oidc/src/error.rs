@@ -16,6 +16,18 @@ pub enum OidcError {
     #[error("User is not in authorised group for OIDC login")]
     NotInAuthorisedGroup,
 
+    #[error("User is linked to another identity provider")]
+    LinkedToOtherProvider,
+
+    #[error("User is not linked to an identity provider")]
+    NotLinkedToProvider,
+
+    #[error("User is deactivated")]
+    UserDeactivated,
+
+    #[error("User store error: {0}")]
+    UserStore(String),
+
     #[error(transparent)]
     OidcConfigurationError(#[from] ConfigurationError),
 
@@ -32,7 +44,11 @@ pub enum OidcError {
 impl IntoResponse for OidcError {
     fn into_response(self) -> axum::response::Response {
         let status_code = match self {
-            Self::SignupDisabled | Self::NotInAuthorisedGroup => StatusCode::UNAUTHORIZED,
+            Self::SignupDisabled
+            | Self::NotInAuthorisedGroup
+            | Self::LinkedToOtherProvider
+            | Self::NotLinkedToProvider
+            | Self::UserDeactivated => StatusCode::UNAUTHORIZED,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         };
         (status_code, self.to_string()).into_response()
@@ -5,11 +5,14 @@ use axum::{
     Extension, Router,
     routing::{get, post},
 };
-pub use config::OidcConfig;
+pub use bearer::{BearerAuthLayer, BearerTokenValidator};
+pub use config::{OidcConfig, OidcProviders};
 use openidconnect::{CsrfToken, Nonce, PkceCodeVerifier};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 pub use user_store::UserStore;
 
+mod bearer;
 mod config;
 mod error;
 mod routes;
@@ -27,18 +30,35 @@ struct OidcState {
 
 #[derive(Debug, Clone)]
 pub struct OidcServiceConfig {
+    pub provider_id: String,
     pub default_redirect_path: &'static str,
     pub session_key_user_id: &'static str,
-    pub callback_path: &'static str,
+    pub callback_path: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct GroupAdditionalClaims {
-    #[serde(default)]
-    groups: Option<Vec<String>>,
+/// Claims besides the standard ones, like the groups claim
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct OtherClaims {
+    #[serde(flatten)]
+    claims: HashMap<String, serde_json::Value>,
 }
 
-impl openidconnect::AdditionalClaims for GroupAdditionalClaims {}
+impl openidconnect::AdditionalClaims for OtherClaims {}
+
+impl OtherClaims {
+    /// Group names from a claim that is either a list or a single string
+    fn groups(&self, claim: &str) -> Vec<String> {
+        match self.claims.get(claim) {
+            Some(serde_json::Value::Array(groups)) => groups
+                .iter()
+                .filter_map(serde_json::Value::as_str)
+                .map(str::to_owned)
+                .collect(),
+            Some(serde_json::Value::String(group)) => vec![group.to_owned()],
+            _ => vec![],
+        }
+    }
+}
 
 pub fn oidc_router<US: UserStore>(
     config: OidcConfig,
@@ -1,7 +1,8 @@
 use crate::OidcState;
 pub use crate::config::OidcConfig;
 pub use crate::user_store::UserStore;
-use crate::{GroupAdditionalClaims, OidcServiceConfig};
+use crate::user_store::authorize_user;
+use crate::{OidcServiceConfig, OtherClaims};
 use crate::{SESSION_KEY_OIDC_STATE, error::OidcError};
 use axum::{
     Extension, Form,
@@ -19,10 +20,9 @@ use openidconnect::{
 };
 use reqwest::Url;
 use serde::Deserialize;
-use std::collections::HashSet;
 use tower_sessions::Session;
 
-fn get_http_client() -> reqwest::Client {
+pub(crate) fn get_http_client() -> reqwest::Client {
     reqwest::ClientBuilder::new()
         // Following redirects opens the client up to SSRF vulnerabilities.
         .redirect(reqwest::redirect::Policy::none())
@@ -154,7 +154,7 @@ async fn resolve_oidc_callback(
         .ok_or(OidcError::Other("OIDC provider did not return an ID token"))?
         .claims(id_token_verifier, nonce)?;
 
-    let user_info_claims: UserInfoClaims<GroupAdditionalClaims, CoreGenderClaim> = oidc_client
+    let user_info_claims: UserInfoClaims<OtherClaims, CoreGenderClaim> = oidc_client
         .user_info(
             token_response.access_token().clone(),
             Some(id_claims.subject().clone()),
@@ -167,10 +167,7 @@ async fn resolve_oidc_callback(
 
     let groups = user_info_claims
         .additional_claims()
-        .groups
-        // .as_deref()
-        .clone()
-        .unwrap_or_default();
+        .groups(&config.groups_claim);
 
     Ok((user_id, groups))
 }
@@ -197,42 +194,21 @@ pub async fn route_get_oidc_callback<US: UserStore>(
     let (user_id, groups) = resolve_oidc_callback(
         &oidc_config,
         &host,
-        service_config.callback_path,
+        &service_config.callback_path,
         code,
         oidc_state.pkce_verifier,
         &oidc_state.nonce,
     )
     .await?;
 
-    if let Some(require_group) = &oidc_config.require_group
-        && !groups.contains(require_group)
-    {
-        return Err(OidcError::NotInAuthorisedGroup);
-    }
-
-    // Assign membership based on the OIDC group.
-    let assign_memberships: Vec<_> = groups
-        .iter()
-        .filter_map(|group| oidc_config.assign_memberships.get(group))
-        .flatten()
-        .map(String::as_str)
-        .collect::<HashSet<_>>()
-        .into_iter()
-        .collect();
-
-    let user_exists = match user_store.user_exists(&user_id).await {
-        Ok(exists) => exists,
-        Err(err) => return Ok(err.into_response()),
-    };
-    // User does not exist
-    if !user_exists && !oidc_config.allow_sign_up {
-        return Err(OidcError::SignupDisabled);
-    }
-    // Create new user. This is also executed when the user already exists
-    // since it also ensures the correct group memberships
-    if let Err(err) = user_store.ensure_user(&user_id, &assign_memberships).await {
-        return Ok(err.into_response());
-    }
+    authorize_user(
+        &user_store,
+        &service_config.provider_id,
+        &oidc_config,
+        &user_id,
+        &groups,
+    )
+    .await?;
 
     let default_redirect = service_config.default_redirect_path.to_owned();
     let base_url: Url = format!("https://{host}").parse().unwrap();
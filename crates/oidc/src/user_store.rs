@@ -1,12 +1,286 @@
+use crate::{OidcConfig, error::OidcError};
 use async_trait::async_trait;
 use axum::response::IntoResponse;
 
 #[async_trait]
 pub trait UserStore: 'static + Send + Sync + Clone {
-    type Error: IntoResponse;
+    type Error: IntoResponse + std::fmt::Display;
+    type User: Clone + Send + Sync + 'static;
 
     async fn user_exists(&self, id: &str) -> Result<bool, Self::Error>;
-    /// Ensures a user with id and memberhips exists
+    async fn get_user(&self, id: &str) -> Result<Option<Self::User>, Self::Error>;
+    /// Deactivated users can't log in
+    async fn user_active(&self, id: &str) -> Result<bool, Self::Error>;
+    /// Returns the id of the provider the user is linked to
+    async fn get_user_provider(&self, id: &str) -> Result<Option<String>, Self::Error>;
+    /// Ensures a user with id and memberhips exists and is linked to the provider
     /// Note that memberships is ONLY ADDITIVE
-    async fn ensure_user(&self, id: &str, memberships: &[&str]) -> Result<(), Self::Error>;
+    async fn ensure_user(
+        &self,
+        id: &str,
+        provider_id: &str,
+        memberships: &[&str],
+    ) -> Result<(), Self::Error>;
+    /// Adds and removes group memberships to match `memberships`
+    async fn sync_memberships(&self, id: &str, memberships: &[String]) -> Result<(), Self::Error>;
+}
+
+/// Checks that the user may log in through the provider
+/// and signs it up or assigns its memberships
+pub(crate) async fn authorize_user<US: UserStore>(
+    user_store: &US,
+    provider_id: &str,
+    config: &OidcConfig,
+    user_id: &str,
+    groups: &[String],
+) -> Result<(), OidcError> {
+    if let Some(require_group) = &config.require_group
+        && !groups.contains(require_group)
+    {
+        return Err(OidcError::NotInAuthorisedGroup);
+    }
+    let store_error = |err: US::Error| OidcError::UserStore(err.to_string());
+
+    let user_exists = user_store.user_exists(user_id).await.map_err(store_error)?;
+    // User does not exist
+    if !user_exists && !config.allow_sign_up {
+        return Err(OidcError::SignupDisabled);
+    }
+    if user_exists && !user_store.user_active(user_id).await.map_err(store_error)? {
+        return Err(OidcError::UserDeactivated);
+    }
+    // Principals with the same id from different providers are different users
+    let linked_provider = if user_exists {
+        user_store
+            .get_user_provider(user_id)
+            .await
+            .map_err(store_error)?
+    } else {
+        None
+    };
+    match linked_provider {
+        Some(linked) if linked != provider_id => return Err(OidcError::LinkedToOtherProvider),
+        // Principals not created through OIDC, e.g. with a local password, are linked by an admin
+        None if user_exists => return Err(OidcError::NotLinkedToProvider),
+        _ => {}
+    }
+
+    // Assign membership based on the OIDC group.
+    let assign_memberships = config.assigned_memberships(groups);
+    if config.sync_groups {
+        if !user_exists {
+            user_store
+                .ensure_user(user_id, provider_id, &[])
+                .await
+                .map_err(store_error)?;
+        }
+        user_store
+            .sync_memberships(user_id, &config.synced_memberships(groups))
+            .await
+            .map_err(store_error)?;
+    } else if !user_exists || !assign_memberships.is_empty() {
+        // Create new user. This is also executed when the user already exists
+        // since it also ensures the correct group memberships
+        user_store
+            .ensure_user(user_id, provider_id, &assign_memberships)
+            .await
+            .map_err(store_error)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::{
+        collections::HashMap,
+        convert::Infallible,
+        sync::{Arc, Mutex},
+    };
+
+    #[derive(Clone, Default)]
+    struct TestUser {
+        provider: Option<String>,
+        memberships: Vec<String>,
+    }
+
+    #[derive(Clone, Default)]
+    struct TestUserStore(Arc<Mutex<HashMap<String, TestUser>>>);
+
+    impl TestUserStore {
+        fn memberships(&self, id: &str) -> Vec<String> {
+            let mut memberships = self.0.lock().unwrap()[id].memberships.clone();
+            memberships.sort();
+            memberships
+        }
+    }
+
+    #[async_trait]
+    impl UserStore for TestUserStore {
+        type Error = Infallible;
+        type User = TestUser;
+
+        async fn user_exists(&self, id: &str) -> Result<bool, Infallible> {
+            Ok(self.0.lock().unwrap().contains_key(id))
+        }
+
+        async fn get_user(&self, id: &str) -> Result<Option<TestUser>, Infallible> {
+            Ok(self.0.lock().unwrap().get(id).cloned())
+        }
+
+        async fn user_active(&self, _id: &str) -> Result<bool, Infallible> {
+            Ok(true)
+        }
+
+        async fn get_user_provider(&self, id: &str) -> Result<Option<String>, Infallible> {
+            Ok(self
+                .0
+                .lock()
+                .unwrap()
+                .get(id)
+                .and_then(|user| user.provider.clone()))
+        }
+
+        async fn ensure_user(
+            &self,
+            id: &str,
+            provider_id: &str,
+            memberships: &[&str],
+        ) -> Result<(), Infallible> {
+            let mut users = self.0.lock().unwrap();
+            let user = users.entry(id.to_owned()).or_default();
+            user.provider = Some(provider_id.to_owned());
+            for membership in memberships {
+                if !user
+                    .memberships
+                    .iter()
+                    .any(|existing| existing == membership)
+                {
+                    user.memberships.push((*membership).to_owned());
+                }
+            }
+            Ok(())
+        }
+
+        async fn sync_memberships(
+            &self,
+            id: &str,
+            memberships: &[String],
+        ) -> Result<(), Infallible> {
+            self.0.lock().unwrap().get_mut(id).unwrap().memberships = memberships.to_vec();
+            Ok(())
+        }
+    }
+
+    fn config(sync_groups: bool) -> OidcConfig {
+        serde_json::from_value(json!({
+            "name": "Test",
+            "issuer": "https://id.example.com",
+            "client_id": "rustical",
+            "scopes": [],
+            "allow_sign_up": true,
+            "assign_memberships": {"staff": ["everyone"]},
+            "sync_groups": sync_groups,
+            "group_mapping": {"staff": "employees"},
+        }))
+        .unwrap()
+    }
+
+    fn groups(groups: &[&str]) -> Vec<String> {
+        groups.iter().map(|group| (*group).to_owned()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_sync_groups() {
+        let user_store = TestUserStore::default();
+        let config = config(true);
+
+        authorize_user(
+            &user_store,
+            "default",
+            &config,
+            "alice",
+            &groups(&["staff", "ops"]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            user_store.memberships("alice"),
+            ["employees", "everyone", "ops"]
+        );
+        assert_eq!(
+            user_store
+                .get_user_provider("alice")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("default")
+        );
+
+        // Groups the user left in the identity provider are removed
+        authorize_user(&user_store, "default", &config, "alice", &groups(&["ops"]))
+            .await
+            .unwrap();
+        assert_eq!(user_store.memberships("alice"), ["ops"]);
+    }
+
+    #[tokio::test]
+    async fn test_assigned_memberships_are_additive() {
+        let user_store = TestUserStore::default();
+        let config = config(false);
+
+        authorize_user(
+            &user_store,
+            "default",
+            &config,
+            "alice",
+            &groups(&["staff", "ops"]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(user_store.memberships("alice"), ["everyone"]);
+
+        // Without sync_groups memberships are only added
+        user_store
+            .ensure_user("alice", "default", &["manual"])
+            .await
+            .unwrap();
+        authorize_user(&user_store, "default", &config, "alice", &groups(&[]))
+            .await
+            .unwrap();
+        assert_eq!(user_store.memberships("alice"), ["everyone", "manual"]);
+    }
+
+    #[tokio::test]
+    async fn test_other_provider() {
+        let user_store = TestUserStore::default();
+        user_store
+            .ensure_user("alice", "partners", &[])
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            authorize_user(&user_store, "default", &config(true), "alice", &[]).await,
+            Err(OidcError::LinkedToOtherProvider)
+        ));
+        assert!(user_store.memberships("alice").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unlinked_principal() {
+        // Created locally, e.g. with a password
+        let user_store = TestUserStore::default();
+        user_store
+            .0
+            .lock()
+            .unwrap()
+            .insert("alice".to_owned(), TestUser::default());
+
+        assert!(matches!(
+            authorize_user(&user_store, "default", &config(false), "alice", &[]).await,
+            Err(OidcError::NotLinkedToProvider)
+        ));
+        assert_eq!(user_store.get_user_provider("alice").await.unwrap(), None);
+    }
 }
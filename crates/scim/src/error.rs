@@ -0,0 +1,97 @@
+use crate::{ERROR_SCHEMA, ScimJson};
+use axum::response::{IntoResponse, Response};
+use http::StatusCode;
+use serde::Serialize;
+use tracing::error;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScimError {
+    #[error("Missing or invalid bearer token")]
+    Unauthorized,
+
+    #[error("Resource not found")]
+    NotFound,
+
+    #[error("Resource already exists")]
+    Uniqueness,
+
+    #[error("{0}")]
+    InvalidFilter(String),
+
+    #[error("{0}")]
+    InvalidValue(String),
+
+    #[error("{0}")]
+    Mutability(String),
+
+    #[error(transparent)]
+    Store(rustical_store::Error),
+}
+
+impl From<rustical_store::Error> for ScimError {
+    fn from(value: rustical_store::Error) -> Self {
+        match value {
+            rustical_store::Error::NotFound => Self::NotFound,
+            rustical_store::Error::AlreadyExists => Self::Uniqueness,
+            err @ (rustical_store::Error::InvalidPrincipalId
+            | rustical_store::Error::InvalidPrincipalType(_)) => {
+                Self::InvalidValue(err.to_string())
+            }
+            err => Self::Store(err),
+        }
+    }
+}
+
+impl ScimError {
+    const fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Uniqueness => StatusCode::CONFLICT,
+            Self::InvalidFilter(_) | Self::InvalidValue(_) | Self::Mutability(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::Store(err) => err.status_code(),
+        }
+    }
+
+    const fn scim_type(&self) -> Option<&'static str> {
+        match self {
+            Self::Uniqueness => Some("uniqueness"),
+            Self::InvalidFilter(_) => Some("invalidFilter"),
+            Self::InvalidValue(_) => Some("invalidValue"),
+            Self::Mutability(_) => Some("mutability"),
+            _ => None,
+        }
+    }
+}
+
+/// <https://datatracker.ietf.org/doc/html/rfc7644#section-3.12>
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ErrorResponse {
+    schemas: [&'static str; 1],
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scim_type: Option<&'static str>,
+    detail: String,
+}
+
+impl IntoResponse for ScimError {
+    fn into_response(self) -> Response {
+        let status = self.status_code();
+        if status == StatusCode::INTERNAL_SERVER_ERROR {
+            error!("{self}");
+        }
+        ScimJson(
+            status,
+            ErrorResponse {
+                schemas: [ERROR_SCHEMA],
+                status: status.as_u16().to_string(),
+                scim_type: self.scim_type(),
+                detail: self.to_string(),
+            },
+        )
+        .into_response()
+    }
+}
@@ -0,0 +1,181 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+#![allow(clippy::missing_errors_doc)]
+//! SCIM 2.0 provisioning API (RFC 7643, RFC 7644) for identity providers
+//!
+//! Users are the principals that aren't groups, their `userName` is the principal id.
+//! Groups are the [`PrincipalType::Group`](rustical_store::auth::PrincipalType::Group) principals,
+//! the `displayName` a group is created with becomes its principal id.
+//! Deactivating or deleting a user revokes its access but keeps the principal and its collections.
+use axum::{
+    Extension, Router,
+    extract::Request,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use headers::{Authorization, HeaderMapExt, authorization::Bearer};
+use http::{HeaderValue, StatusCode, header};
+use routes::{
+    route_delete_group, route_delete_user, route_get_group, route_get_groups, route_get_user,
+    route_get_users, route_patch_group, route_patch_user, route_post_group, route_post_user,
+    route_put_group, route_put_user,
+};
+use rustical_store::{SessionStore, auth::AuthenticationProvider};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use tracing::warn;
+
+mod error;
+mod resources;
+mod routes;
+
+pub use error::ScimError;
+
+pub const USER_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+pub const GROUP_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+const LIST_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+const ERROR_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScimConfig {
+    /// Bearer token the identity provider authenticates with
+    pub token: String,
+    /// Id of the OIDC provider the users log in with, created users are linked to it
+    #[serde(default)]
+    pub identity_provider: Option<String>,
+}
+
+/// Path the API is served at, for the `meta.location` of resources
+#[derive(Debug, Clone, Copy)]
+struct Prefix(&'static str);
+
+/// A JSON response with the `application/scim+json` content type
+struct ScimJson<T>(StatusCode, T);
+
+impl<T: Serialize> IntoResponse for ScimJson<T> {
+    fn into_response(self) -> Response {
+        let mut response = (self.0, axum::Json(self.1)).into_response();
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/scim+json"),
+        );
+        response
+    }
+}
+
+async fn require_token(
+    Extension(config): Extension<Arc<ScimConfig>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // Compare equal length digests in constant time so neither the token nor its length leaks
+    let authorized = request
+        .headers()
+        .typed_get::<Authorization<Bearer>>()
+        .is_some_and(|Authorization(bearer)| {
+            Sha256::digest(bearer.token())
+                .as_slice()
+                .ct_eq(Sha256::digest(&config.token).as_slice())
+                .into()
+        });
+    if !authorized {
+        warn!(target: "rustical::auth", "Rejected SCIM request without valid token");
+        let mut response = ScimError::Unauthorized.into_response();
+        response
+            .headers_mut()
+            .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        return response;
+    }
+    next.run(request).await
+}
+
+async fn route_service_provider_config() -> Response {
+    ScimJson(
+        StatusCode::OK,
+        json!({
+            "schemas": ["urn:ietf:params:scim:schemas:core:2.0:ServiceProviderConfig"],
+            "patch": {"supported": true},
+            "bulk": {"supported": false, "maxOperations": 0, "maxPayloadSize": 0},
+            "filter": {"supported": true, "maxResults": 1000},
+            "changePassword": {"supported": true},
+            "sort": {"supported": false},
+            "etag": {"supported": false},
+            "authenticationSchemes": [{
+                "type": "oauthbearertoken",
+                "name": "Bearer token",
+                "description": "The token configured in scim.token",
+                "primary": true
+            }]
+        }),
+    )
+    .into_response()
+}
+
+async fn route_resource_types(Extension(Prefix(prefix)): Extension<Prefix>) -> Response {
+    let resource_type = |name: &str, endpoint: &str, schema: &str| {
+        json!({
+            "schemas": ["urn:ietf:params:scim:schemas:core:2.0:ResourceType"],
+            "id": name,
+            "name": name,
+            "endpoint": endpoint,
+            "schema": schema,
+            "meta": {
+                "resourceType": "ResourceType",
+                "location": format!("{prefix}/ResourceTypes/{name}"),
+            }
+        })
+    };
+    let resources = vec![
+        resource_type("User", "/Users", USER_SCHEMA),
+        resource_type("Group", "/Groups", GROUP_SCHEMA),
+    ];
+    ScimJson(
+        StatusCode::OK,
+        resources::ListResponse::page(resources, None, None),
+    )
+    .into_response()
+}
+
+pub fn scim_router<AP: AuthenticationProvider, SS: SessionStore>(
+    prefix: &'static str,
+    config: ScimConfig,
+    auth_provider: Arc<AP>,
+    session_store: Arc<SS>,
+) -> Router {
+    let router = Router::new()
+        .route("/ServiceProviderConfig", get(route_service_provider_config))
+        .route("/ResourceTypes", get(route_resource_types))
+        .route(
+            "/Users",
+            get(route_get_users::<AP>).post(route_post_user::<AP>),
+        )
+        .route(
+            "/Users/{id}",
+            get(route_get_user::<AP>)
+                .put(route_put_user::<AP, SS>)
+                .patch(route_patch_user::<AP, SS>)
+                .delete(route_delete_user::<AP, SS>),
+        )
+        .route(
+            "/Groups",
+            get(route_get_groups::<AP>).post(route_post_group::<AP>),
+        )
+        .route(
+            "/Groups/{id}",
+            get(route_get_group::<AP>)
+                .put(route_put_group::<AP>)
+                .patch(route_patch_group::<AP>)
+                .delete(route_delete_group::<AP>),
+        )
+        .layer(middleware::from_fn(require_token))
+        .layer(Extension(Arc::new(config)))
+        .layer(Extension(Prefix(prefix)))
+        .layer(Extension(auth_provider))
+        .layer(Extension(session_store));
+
+    Router::new().nest(prefix, router)
+}
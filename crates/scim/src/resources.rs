@@ -0,0 +1,384 @@
+use crate::{GROUP_SCHEMA, LIST_SCHEMA, USER_SCHEMA, error::ScimError};
+use rustical_store::auth::Principal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Meta {
+    pub resource_type: &'static str,
+    pub location: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    pub schemas: [&'static str; 1],
+    pub id: String,
+    pub user_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub emails: Vec<Email>,
+    pub active: bool,
+    pub meta: Meta,
+}
+
+impl User {
+    pub fn new(prefix: &str, principal: Principal, email: Option<String>, active: bool) -> Self {
+        Self {
+            schemas: [USER_SCHEMA],
+            meta: Meta {
+                resource_type: "User",
+                location: format!("{prefix}/Users/{}", principal.id),
+            },
+            user_name: principal.id.clone(),
+            id: principal.id,
+            display_name: principal.displayname,
+            emails: email
+                .map(|value| Email {
+                    value,
+                    primary: true,
+                })
+                .into_iter()
+                .collect(),
+            active,
+        }
+    }
+}
+
+/// Principals have a single email address, the one password reset links are sent to
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Email {
+    pub value: String,
+    #[serde(default)]
+    pub primary: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Member {
+    pub value: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Group {
+    pub schemas: [&'static str; 1],
+    pub id: String,
+    pub display_name: String,
+    pub members: Vec<Member>,
+    pub meta: Meta,
+}
+
+impl Group {
+    pub fn new(prefix: &str, principal: Principal, members: Vec<String>) -> Self {
+        Self {
+            schemas: [GROUP_SCHEMA],
+            meta: Meta {
+                resource_type: "Group",
+                location: format!("{prefix}/Groups/{}", principal.id),
+            },
+            display_name: principal
+                .displayname
+                .unwrap_or_else(|| principal.id.clone()),
+            id: principal.id,
+            members: members.into_iter().map(|value| Member { value }).collect(),
+        }
+    }
+}
+
+/// <https://datatracker.ietf.org/doc/html/rfc7644#section-3.4.2>
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListResponse<T> {
+    pub schemas: [&'static str; 1],
+    pub total_results: usize,
+    pub start_index: usize,
+    pub items_per_page: usize,
+    #[serde(rename = "Resources")]
+    pub resources: Vec<T>,
+}
+
+impl<T> ListResponse<T> {
+    /// Takes one page of `resources`, `start_index` counts from 1
+    pub fn page(resources: Vec<T>, start_index: Option<usize>, count: Option<usize>) -> Self {
+        let total_results = resources.len();
+        let start_index = start_index.unwrap_or(1).max(1);
+        let resources: Vec<T> = resources
+            .into_iter()
+            .skip(start_index - 1)
+            .take(count.unwrap_or(usize::MAX))
+            .collect();
+        Self {
+            schemas: [LIST_SCHEMA],
+            total_results,
+            start_index,
+            items_per_page: resources.len(),
+            resources,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListQuery {
+    pub filter: Option<String>,
+    pub start_index: Option<usize>,
+    pub count: Option<usize>,
+}
+
+/// Parses the only filter identity providers need for matching, `<attribute> eq "<value>"`.
+/// Returns the lowercase attribute name and the value.
+pub fn parse_filter(filter: &str) -> Result<(String, String), ScimError> {
+    let invalid = || ScimError::InvalidFilter(format!("Unsupported filter: {filter}"));
+    let (attribute, rest) = filter
+        .trim()
+        .split_once(char::is_whitespace)
+        .ok_or_else(invalid)?;
+    let (operator, value) = rest
+        .trim()
+        .split_once(char::is_whitespace)
+        .ok_or_else(invalid)?;
+    if !operator.eq_ignore_ascii_case("eq") {
+        return Err(invalid());
+    }
+    let value: String = serde_json::from_str(value.trim()).map_err(|_| invalid())?;
+    Ok((attribute.to_ascii_lowercase(), value))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Name {
+    pub formatted: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserInput {
+    pub user_name: String,
+    pub display_name: Option<String>,
+    pub name: Option<Name>,
+    pub active: Option<Value>,
+    pub password: Option<String>,
+    #[serde(default)]
+    pub emails: Vec<Email>,
+}
+
+impl UserInput {
+    pub fn displayname(&self) -> Option<String> {
+        self.display_name
+            .clone()
+            .or_else(|| self.name.as_ref()?.formatted.clone())
+    }
+
+    pub fn active(&self) -> Result<bool, ScimError> {
+        self.active.as_ref().map_or(Ok(true), parse_bool)
+    }
+
+    pub fn email(&self) -> Option<String> {
+        primary_email(&self.emails)
+    }
+}
+
+fn primary_email(emails: &[Email]) -> Option<String> {
+    emails
+        .iter()
+        .find(|email| email.primary)
+        .or_else(|| emails.first())
+        .map(|email| email.value.clone())
+}
+
+/// The primary address of an `emails` value
+pub fn parse_emails(value: Value) -> Result<Option<String>, ScimError> {
+    let emails: Vec<Email> = match value {
+        Value::Null => vec![],
+        Value::Array(_) => serde_json::from_value(value)
+            .map_err(|err| ScimError::InvalidValue(format!("Invalid emails: {err}")))?,
+        value => vec![
+            serde_json::from_value(value)
+                .map_err(|err| ScimError::InvalidValue(format!("Invalid emails: {err}")))?,
+        ],
+    };
+    Ok(primary_email(&emails))
+}
+
+/// Whether a patch path sets the address of an email, e.g. `emails[type eq "work"].value`
+pub fn is_email_value_path(path: &str) -> bool {
+    path.starts_with("emails[") && path.ends_with("].value")
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupInput {
+    pub display_name: String,
+    #[serde(default)]
+    pub members: Vec<Member>,
+}
+
+/// <https://datatracker.ietf.org/doc/html/rfc7644#section-3.5.2>
+#[derive(Debug, Deserialize)]
+pub struct PatchRequest {
+    #[serde(rename = "Operations")]
+    pub operations: Vec<PatchOperation>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PatchOp {
+    Add,
+    Remove,
+    Replace,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PatchOperation {
+    op: String,
+    pub path: Option<String>,
+    pub value: Option<Value>,
+}
+
+impl PatchOperation {
+    pub fn op(&self) -> Result<PatchOp, ScimError> {
+        match self.op.to_ascii_lowercase().as_str() {
+            "add" => Ok(PatchOp::Add),
+            "remove" => Ok(PatchOp::Remove),
+            "replace" => Ok(PatchOp::Replace),
+            _ => Err(ScimError::InvalidValue(format!(
+                "Invalid patch operation: {}",
+                self.op
+            ))),
+        }
+    }
+
+    /// The attributes to set, an operation without path carries them as an object
+    pub fn attributes(&self) -> Result<Vec<(String, Value)>, ScimError> {
+        let value = self.value.clone().unwrap_or(Value::Null);
+        match (&self.path, value) {
+            (Some(path), value) => Ok(vec![(path.to_ascii_lowercase(), value)]),
+            (None, Value::Object(attributes)) => Ok(attributes
+                .into_iter()
+                .map(|(attribute, value)| (attribute.to_ascii_lowercase(), value))
+                .collect()),
+            (None, _) => Err(ScimError::InvalidValue(
+                "A patch operation without path needs an object value".to_owned(),
+            )),
+        }
+    }
+}
+
+/// Some identity providers send booleans as strings
+pub fn parse_bool(value: &Value) -> Result<bool, ScimError> {
+    match value {
+        Value::Bool(value) => Ok(*value),
+        Value::String(value) if value.eq_ignore_ascii_case("true") => Ok(true),
+        Value::String(value) if value.eq_ignore_ascii_case("false") => Ok(false),
+        _ => Err(ScimError::InvalidValue(format!(
+            "Expected a boolean: {value}"
+        ))),
+    }
+}
+
+pub fn parse_string(value: &Value) -> Result<Option<String>, ScimError> {
+    match value {
+        Value::Null => Ok(None),
+        Value::String(value) if value.is_empty() => Ok(None),
+        Value::String(value) => Ok(Some(value.clone())),
+        _ => Err(ScimError::InvalidValue(format!(
+            "Expected a string: {value}"
+        ))),
+    }
+}
+
+/// Member ids from a list of `{"value": "<id>"}` objects
+pub fn parse_members(value: Value) -> Result<Vec<String>, ScimError> {
+    let members: Vec<Member> = match value {
+        Value::Null => vec![],
+        Value::Array(_) => serde_json::from_value(value)
+            .map_err(|err| ScimError::InvalidValue(format!("Invalid members: {err}")))?,
+        // Some identity providers send a single member without array
+        value => vec![
+            serde_json::from_value(value)
+                .map_err(|err| ScimError::InvalidValue(format!("Invalid members: {err}")))?,
+        ],
+    };
+    Ok(members.into_iter().map(|member| member.value).collect())
+}
+
+/// The member of a `members[value eq "<id>"]` path
+pub fn parse_member_path(path: &str) -> Result<Option<String>, ScimError> {
+    let Some(filter) = path
+        .strip_prefix("members[")
+        .and_then(|filter| filter.strip_suffix(']'))
+    else {
+        return Ok(None);
+    };
+    match parse_filter(filter)? {
+        (attribute, value) if attribute == "value" => Ok(Some(value)),
+        _ => Err(ScimError::InvalidFilter(format!(
+            "Unsupported filter: {filter}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        PatchRequest, is_email_value_path, parse_bool, parse_emails, parse_filter,
+        parse_member_path, parse_members,
+    };
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_filter() {
+        assert_eq!(
+            parse_filter(r#"userName eq "alice""#).unwrap(),
+            ("username".to_owned(), "alice".to_owned())
+        );
+        assert_eq!(
+            parse_filter(r#"displayName Eq "IT \"Staff\"""#).unwrap(),
+            ("displayname".to_owned(), r#"IT "Staff""#.to_owned())
+        );
+        assert!(parse_filter(r#"userName sw "a""#).is_err());
+        assert!(parse_filter("userName eq alice").is_err());
+    }
+
+    #[test]
+    fn test_parse_patch() {
+        let request: PatchRequest = serde_json::from_value(json!({
+            "schemas": ["urn:ietf:params:scim:api:messages:2.0:PatchOp"],
+            "Operations": [
+                {"op": "Replace", "path": "active", "value": "False"},
+                {"op": "replace", "value": {"displayName": "Alice", "active": true}},
+                {"op": "remove", "path": "members[value eq \"bob\"]"}
+            ]
+        }))
+        .unwrap();
+        let attributes = request.operations[0].attributes().unwrap();
+        assert_eq!(attributes[0].0, "active");
+        assert!(!parse_bool(&attributes[0].1).unwrap());
+        let attributes = request.operations[1].attributes().unwrap();
+        assert!(attributes.iter().any(|(name, _)| name == "displayname"));
+        assert_eq!(
+            parse_member_path(request.operations[2].path.as_deref().unwrap()).unwrap(),
+            Some("bob".to_owned())
+        );
+        assert_eq!(
+            parse_members(json!([{"value": "alice"}, {"value": "bob"}])).unwrap(),
+            vec!["alice", "bob"]
+        );
+    }
+
+    #[test]
+    fn test_parse_emails() {
+        assert_eq!(
+            parse_emails(json!([
+                {"value": "alice@example.org", "type": "home"},
+                {"value": "alice@example.com", "type": "work", "primary": true}
+            ]))
+            .unwrap(),
+            Some("alice@example.com".to_owned())
+        );
+        assert_eq!(parse_emails(json!([])).unwrap(), None);
+        assert!(is_email_value_path(r#"emails[type eq "work"].value"#));
+        assert!(!is_email_value_path("emails"));
+    }
+}
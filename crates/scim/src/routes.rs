@@ -0,0 +1,497 @@
+use crate::{
+    Prefix, ScimConfig, ScimJson,
+    error::ScimError,
+    resources::{
+        Group, GroupInput, ListQuery, ListResponse, PatchOp, PatchRequest, User, UserInput,
+        is_email_value_path, parse_bool, parse_emails, parse_filter, parse_member_path,
+        parse_members, parse_string,
+    },
+};
+use axum::{
+    Extension, Json,
+    extract::{Path, Query},
+    response::{IntoResponse, Response},
+};
+use http::StatusCode;
+use rustical_store::{
+    SessionStore,
+    auth::{AuthenticationProvider, Principal, PrincipalType, hash_password},
+};
+use std::sync::Arc;
+use tracing::info;
+
+type ScimResult = Result<Response, ScimError>;
+
+fn user_filter(filter: Option<&str>) -> Result<Option<String>, ScimError> {
+    match filter.map(parse_filter).transpose()? {
+        None => Ok(None),
+        Some((attribute, value)) if attribute == "username" || attribute == "id" => Ok(Some(value)),
+        Some((attribute, _)) => Err(ScimError::InvalidFilter(format!(
+            "Cannot filter users by {attribute}"
+        ))),
+    }
+}
+
+fn group_name(principal: &Principal) -> &str {
+    principal.displayname.as_deref().unwrap_or(&principal.id)
+}
+
+async fn get_user_principal<AP: AuthenticationProvider>(
+    auth_provider: &AP,
+    id: &str,
+) -> Result<Principal, ScimError> {
+    auth_provider
+        .get_principal(id)
+        .await?
+        .filter(|principal| principal.principal_type != PrincipalType::Group)
+        .ok_or(ScimError::NotFound)
+}
+
+async fn get_group_principal<AP: AuthenticationProvider>(
+    auth_provider: &AP,
+    id: &str,
+) -> Result<Principal, ScimError> {
+    auth_provider
+        .get_principal(id)
+        .await?
+        .filter(|principal| principal.principal_type == PrincipalType::Group)
+        .ok_or(ScimError::NotFound)
+}
+
+/// Revokes every access of a user but keeps its collections:
+/// Its password, app tokens and second factors are removed, its sessions ended
+/// and it's marked inactive, so that no login method lets it in anymore.
+async fn deactivate_user<AP: AuthenticationProvider, SS: SessionStore>(
+    auth_provider: &AP,
+    session_store: &SS,
+    mut principal: Principal,
+) -> Result<Principal, ScimError> {
+    auth_provider.set_active(&principal.id, false).await?;
+    principal.password = None;
+    auth_provider
+        .insert_principal(principal.clone(), true)
+        .await?;
+    for app_token in auth_provider.get_app_tokens(&principal.id).await? {
+        auth_provider
+            .remove_app_token(&principal.id, &app_token.id)
+            .await?;
+    }
+    auth_provider.set_totp(&principal.id, None).await?;
+    for passkey in auth_provider.get_passkeys(&principal.id).await? {
+        auth_provider
+            .remove_passkey(&principal.id, &passkey.id)
+            .await?;
+    }
+    session_store.revoke_sessions(&principal.id).await?;
+    info!("Deprovisioned user {}", principal.id);
+    Ok(principal)
+}
+
+/// Saves a user or, if it got deactivated, revokes its access.
+/// Reactivating a user doesn't bring back the credentials it had.
+async fn save_user<AP: AuthenticationProvider, SS: SessionStore>(
+    auth_provider: &AP,
+    session_store: &SS,
+    prefix: Prefix,
+    mut principal: Principal,
+    email: Option<String>,
+    active: bool,
+    password: Option<String>,
+) -> ScimResult {
+    if !active {
+        auth_provider
+            .set_email(&principal.id, email.as_deref())
+            .await?;
+        let principal = deactivate_user(auth_provider, session_store, principal).await?;
+        return Ok(
+            ScimJson(StatusCode::OK, User::new(prefix.0, principal, email, false)).into_response(),
+        );
+    }
+    let password_changed = password.is_some();
+    principal.password = password
+        .as_deref()
+        .map(hash_password)
+        .or(principal.password);
+    auth_provider
+        .insert_principal(principal.clone(), true)
+        .await?;
+    auth_provider
+        .set_email(&principal.id, email.as_deref())
+        .await?;
+    if password_changed {
+        session_store.revoke_sessions(&principal.id).await?;
+    }
+    if !auth_provider.is_active(&principal.id).await? {
+        auth_provider.set_active(&principal.id, true).await?;
+        info!("Reactivated user {}", principal.id);
+    }
+    Ok(ScimJson(StatusCode::OK, User::new(prefix.0, principal, email, true)).into_response())
+}
+
+async fn add_member<AP: AuthenticationProvider>(
+    auth_provider: &AP,
+    group: &str,
+    member: &str,
+) -> Result<(), ScimError> {
+    if auth_provider.get_principal(member).await?.is_none() {
+        return Err(ScimError::InvalidValue(format!(
+            "Member {member} does not exist"
+        )));
+    }
+    auth_provider.add_membership(member, group).await?;
+    Ok(())
+}
+
+async fn set_members<AP: AuthenticationProvider>(
+    auth_provider: &AP,
+    group: &str,
+    members: &[String],
+) -> Result<(), ScimError> {
+    let current = auth_provider.list_members(group).await?;
+    for member in current.iter().filter(|member| !members.contains(member)) {
+        auth_provider.remove_membership(member, group).await?;
+    }
+    for member in members.iter().filter(|member| !current.contains(member)) {
+        add_member(auth_provider, group, member).await?;
+    }
+    Ok(())
+}
+
+async fn group_response<AP: AuthenticationProvider>(
+    auth_provider: &AP,
+    prefix: Prefix,
+    status: StatusCode,
+    principal: Principal,
+) -> ScimResult {
+    let members = auth_provider.list_members(&principal.id).await?;
+    Ok(ScimJson(status, Group::new(prefix.0, principal, members)).into_response())
+}
+
+pub async fn route_get_users<AP: AuthenticationProvider>(
+    Extension(prefix): Extension<Prefix>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Query(query): Query<ListQuery>,
+) -> ScimResult {
+    let user_name = user_filter(query.filter.as_deref())?;
+    let mut users = vec![];
+    for principal in auth_provider.get_principals().await? {
+        if principal.principal_type == PrincipalType::Group
+            || user_name.as_ref().is_some_and(|id| id != &principal.id)
+        {
+            continue;
+        }
+        let email = auth_provider.get_email(&principal.id).await?;
+        let active = auth_provider.is_active(&principal.id).await?;
+        users.push(User::new(prefix.0, principal, email, active));
+    }
+    Ok(ScimJson(
+        StatusCode::OK,
+        ListResponse::page(users, query.start_index, query.count),
+    )
+    .into_response())
+}
+
+pub async fn route_post_user<AP: AuthenticationProvider>(
+    Extension(prefix): Extension<Prefix>,
+    Extension(config): Extension<Arc<ScimConfig>>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Json(input): Json<UserInput>,
+) -> ScimResult {
+    if !input.active()? {
+        return Err(ScimError::InvalidValue(
+            "Inactive users are not provisioned".to_owned(),
+        ));
+    }
+    let principal = Principal {
+        displayname: input.displayname(),
+        password: input.password.as_deref().map(hash_password),
+        id: input.user_name,
+        principal_type: PrincipalType::Individual,
+        memberships: vec![],
+        app_token: None,
+    };
+    auth_provider
+        .insert_principal(principal.clone(), false)
+        .await?;
+    let email = input.email();
+    auth_provider
+        .set_email(&principal.id, email.as_deref())
+        .await?;
+    if let Some(identity_provider) = &config.identity_provider {
+        auth_provider
+            .set_identity_provider(&principal.id, identity_provider)
+            .await?;
+    }
+    info!("Provisioned user {}", principal.id);
+    Ok(ScimJson(
+        StatusCode::CREATED,
+        User::new(prefix.0, principal, email, true),
+    )
+    .into_response())
+}
+
+pub async fn route_get_user<AP: AuthenticationProvider>(
+    Path(id): Path<String>,
+    Extension(prefix): Extension<Prefix>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+) -> ScimResult {
+    let principal = get_user_principal(auth_provider.as_ref(), &id).await?;
+    let email = auth_provider.get_email(&principal.id).await?;
+    let active = auth_provider.is_active(&principal.id).await?;
+    Ok(ScimJson(
+        StatusCode::OK,
+        User::new(prefix.0, principal, email, active),
+    )
+    .into_response())
+}
+
+pub async fn route_put_user<AP: AuthenticationProvider, SS: SessionStore>(
+    Path(id): Path<String>,
+    Extension(prefix): Extension<Prefix>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Extension(session_store): Extension<Arc<SS>>,
+    Json(input): Json<UserInput>,
+) -> ScimResult {
+    let mut principal = get_user_principal(auth_provider.as_ref(), &id).await?;
+    if input.user_name != principal.id {
+        return Err(ScimError::Mutability(
+            "userName cannot be changed".to_owned(),
+        ));
+    }
+    principal.displayname = input.displayname();
+    save_user(
+        auth_provider.as_ref(),
+        session_store.as_ref(),
+        prefix,
+        principal,
+        input.email(),
+        input.active()?,
+        input.password,
+    )
+    .await
+}
+
+pub async fn route_patch_user<AP: AuthenticationProvider, SS: SessionStore>(
+    Path(id): Path<String>,
+    Extension(prefix): Extension<Prefix>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Extension(session_store): Extension<Arc<SS>>,
+    Json(request): Json<PatchRequest>,
+) -> ScimResult {
+    let mut principal = get_user_principal(auth_provider.as_ref(), &id).await?;
+    let mut email = auth_provider.get_email(&principal.id).await?;
+    let mut active = auth_provider.is_active(&principal.id).await?;
+    let mut password = None;
+    for operation in &request.operations {
+        let op = operation.op()?;
+        for (attribute, value) in operation.attributes()? {
+            match (&op, attribute.as_str()) {
+                (PatchOp::Remove, "displayname") => principal.displayname = None,
+                (PatchOp::Remove, "emails") => email = None,
+                (PatchOp::Remove, path) if is_email_value_path(path) => email = None,
+                (PatchOp::Remove, _) => {}
+                (_, "active") => active = parse_bool(&value)?,
+                (_, "displayname") => principal.displayname = parse_string(&value)?,
+                (_, "emails") => email = parse_emails(value)?,
+                (_, path) if is_email_value_path(path) => email = parse_string(&value)?,
+                (_, "password") => password = parse_string(&value)?,
+                (_, "username") => {
+                    if parse_string(&value)?.as_deref() != Some(principal.id.as_str()) {
+                        return Err(ScimError::Mutability(
+                            "userName cannot be changed".to_owned(),
+                        ));
+                    }
+                }
+                // Attributes like phoneNumbers that principals don't have
+                _ => {}
+            }
+        }
+    }
+    save_user(
+        auth_provider.as_ref(),
+        session_store.as_ref(),
+        prefix,
+        principal,
+        email,
+        active,
+        password,
+    )
+    .await
+}
+
+pub async fn route_delete_user<AP: AuthenticationProvider, SS: SessionStore>(
+    Path(id): Path<String>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Extension(session_store): Extension<Arc<SS>>,
+) -> ScimResult {
+    let principal = get_user_principal(auth_provider.as_ref(), &id).await?;
+    deactivate_user(auth_provider.as_ref(), session_store.as_ref(), principal).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+pub async fn route_get_groups<AP: AuthenticationProvider>(
+    Extension(prefix): Extension<Prefix>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Query(query): Query<ListQuery>,
+) -> ScimResult {
+    let filter = query.filter.as_deref().map(parse_filter).transpose()?;
+    if let Some((attribute, _)) = &filter
+        && attribute != "displayname"
+        && attribute != "id"
+    {
+        return Err(ScimError::InvalidFilter(format!(
+            "Cannot filter groups by {attribute}"
+        )));
+    }
+    let mut groups = vec![];
+    for principal in auth_provider.get_principals().await? {
+        if principal.principal_type != PrincipalType::Group {
+            continue;
+        }
+        match &filter {
+            Some((attribute, value)) if attribute == "id" && value != &principal.id => continue,
+            Some((attribute, value))
+                if attribute == "displayname" && value != group_name(&principal) =>
+            {
+                continue;
+            }
+            _ => {}
+        }
+        let members = auth_provider.list_members(&principal.id).await?;
+        groups.push(Group::new(prefix.0, principal, members));
+    }
+    Ok(ScimJson(
+        StatusCode::OK,
+        ListResponse::page(groups, query.start_index, query.count),
+    )
+    .into_response())
+}
+
+pub async fn route_post_group<AP: AuthenticationProvider>(
+    Extension(prefix): Extension<Prefix>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Json(input): Json<GroupInput>,
+) -> ScimResult {
+    // Same ids as the groups created from OIDC claims or proxy headers
+    let principal = Principal {
+        id: input.display_name,
+        displayname: None,
+        principal_type: PrincipalType::Group,
+        password: None,
+        memberships: vec![],
+        app_token: None,
+    };
+    auth_provider
+        .insert_principal(principal.clone(), false)
+        .await?;
+    info!("Provisioned group {}", principal.id);
+    for member in input.members {
+        add_member(auth_provider.as_ref(), &principal.id, &member.value).await?;
+    }
+    group_response(
+        auth_provider.as_ref(),
+        prefix,
+        StatusCode::CREATED,
+        principal,
+    )
+    .await
+}
+
+pub async fn route_get_group<AP: AuthenticationProvider>(
+    Path(id): Path<String>,
+    Extension(prefix): Extension<Prefix>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+) -> ScimResult {
+    let principal = get_group_principal(auth_provider.as_ref(), &id).await?;
+    group_response(auth_provider.as_ref(), prefix, StatusCode::OK, principal).await
+}
+
+pub async fn route_put_group<AP: AuthenticationProvider>(
+    Path(id): Path<String>,
+    Extension(prefix): Extension<Prefix>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Json(input): Json<GroupInput>,
+) -> ScimResult {
+    let mut principal = get_group_principal(auth_provider.as_ref(), &id).await?;
+    principal.displayname = Some(input.display_name).filter(|name| name != &principal.id);
+    auth_provider
+        .insert_principal(principal.clone(), true)
+        .await?;
+    let members: Vec<String> = input
+        .members
+        .into_iter()
+        .map(|member| member.value)
+        .collect();
+    set_members(auth_provider.as_ref(), &principal.id, &members).await?;
+    group_response(auth_provider.as_ref(), prefix, StatusCode::OK, principal).await
+}
+
+pub async fn route_patch_group<AP: AuthenticationProvider>(
+    Path(id): Path<String>,
+    Extension(prefix): Extension<Prefix>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+    Json(request): Json<PatchRequest>,
+) -> ScimResult {
+    let mut principal = get_group_principal(auth_provider.as_ref(), &id).await?;
+    let displayname = principal.displayname.clone();
+    for operation in request.operations {
+        let op = operation.op()?;
+        if op == PatchOp::Remove {
+            let path = operation.path.as_deref().unwrap_or_default();
+            let members = if let Some(member) = parse_member_path(path)? {
+                vec![member]
+            } else if path.eq_ignore_ascii_case("members") {
+                match operation.value {
+                    Some(value) => parse_members(value)?,
+                    None => auth_provider.list_members(&principal.id).await?,
+                }
+            } else {
+                vec![]
+            };
+            for member in members {
+                auth_provider
+                    .remove_membership(&member, &principal.id)
+                    .await?;
+            }
+            continue;
+        }
+        for (attribute, value) in operation.attributes()? {
+            match attribute.as_str() {
+                "members" if op == PatchOp::Replace => {
+                    set_members(
+                        auth_provider.as_ref(),
+                        &principal.id,
+                        &parse_members(value)?,
+                    )
+                    .await?;
+                }
+                "members" => {
+                    for member in parse_members(value)? {
+                        add_member(auth_provider.as_ref(), &principal.id, &member).await?;
+                    }
+                }
+                "displayname" => {
+                    principal.displayname =
+                        parse_string(&value)?.filter(|name| name != &principal.id);
+                }
+                _ => {}
+            }
+        }
+    }
+    if principal.displayname != displayname {
+        auth_provider
+            .insert_principal(principal.clone(), true)
+            .await?;
+    }
+    group_response(auth_provider.as_ref(), prefix, StatusCode::OK, principal).await
+}
+
+pub async fn route_delete_group<AP: AuthenticationProvider>(
+    Path(id): Path<String>,
+    Extension(auth_provider): Extension<Arc<AP>>,
+) -> ScimResult {
+    let principal = get_group_principal(auth_provider.as_ref(), &id).await?;
+    auth_provider.remove_principal(&principal.id).await?;
+    info!("Deprovisioned group {}", principal.id);
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
@@ -1,8 +1,10 @@
 use crate::synctoken::format_synctoken;
 use chrono::NaiveDateTime;
-use serde::Serialize;
+use rustical_dav::namespace::{NS_CARDDAV, NS_DAV};
+use rustical_xml::NamespaceOwned;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Addressbook {
     pub id: String,
     pub principal: String,
@@ -18,4 +20,20 @@ impl Addressbook {
     pub fn format_synctoken(&self) -> String {
         format_synctoken(self.synctoken)
     }
+
+    /// Returns the names of the WebDAV properties that differ from another version of this addressbook
+    #[must_use]
+    pub fn changed_properties(&self, other: &Self) -> Vec<(Option<NamespaceOwned>, String)> {
+        let mut props = vec![];
+        if self.displayname != other.displayname {
+            props.push((Some(NS_DAV.into()), "displayname".to_owned()));
+        }
+        if self.description != other.description {
+            props.push((
+                Some(NS_CARDDAV.into()),
+                "addressbook-description".to_owned(),
+            ));
+        }
+        props
+    }
 }
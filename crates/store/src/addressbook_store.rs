@@ -38,6 +38,14 @@ pub trait AddressbookReadStore: Send + Sync + 'static {
         object_id: &str,
         show_deleted: bool,
     ) -> Result<AddressObject, Error>;
+    /// Objects in the trash bin, also if the addressbook itself is trashed
+    async fn get_deleted_objects(
+        &self,
+        _principal: &str,
+        _addressbook_id: &str,
+    ) -> Result<Vec<(String, AddressObject)>, Error> {
+        Ok(vec![])
+    }
 }
 
 #[async_trait]
@@ -48,6 +56,7 @@ pub trait AddressbookWriteStore: Send + Sync + 'static {
         id: &str,
         addressbook: Addressbook,
     ) -> Result<(), Error>;
+    /// The change log of the new addressbook continues from `addressbook.synctoken`
     async fn insert_addressbook(&self, addressbook: Addressbook) -> Result<(), Error>;
 
     async fn delete_addressbook(
@@ -0,0 +1,78 @@
+use super::Principal;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Revoked tokens stay valid for at most this long
+const TTL: Duration = Duration::from_secs(30);
+const MAX_ENTRIES: usize = 1024;
+
+/// Short-lived cache of verified app token credentials,
+/// so that a client syncing many collections is only checked against the store once
+#[derive(Debug, Default)]
+pub struct CredentialCache {
+    entries: Mutex<HashMap<[u8; 32], (Instant, Principal)>>,
+}
+
+// The credentials are only kept as a hash
+fn cache_key(user_id: &str, password: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(user_id.as_bytes());
+    hasher.update([0]);
+    hasher.update(password.as_bytes());
+    hasher.finalize().into()
+}
+
+impl CredentialCache {
+    pub fn get(&self, user_id: &str, password: &str) -> Option<Principal> {
+        let key = cache_key(user_id, password);
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(&key) {
+            Some((verified_at, principal)) if verified_at.elapsed() < TTL => {
+                Some(principal.clone())
+            }
+            Some(_) => {
+                entries.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, user_id: &str, password: &str, principal: Principal) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, (verified_at, _)| verified_at.elapsed() < TTL);
+        }
+        if entries.len() < MAX_ENTRIES {
+            entries.insert(cache_key(user_id, password), (Instant::now(), principal));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CredentialCache;
+    use crate::auth::{Principal, PrincipalType};
+
+    #[test]
+    fn test_credential_cache() {
+        let cache = CredentialCache::default();
+        let principal = Principal {
+            id: "user".to_owned(),
+            displayname: None,
+            principal_type: PrincipalType::Individual,
+            password: None,
+            memberships: vec![],
+            app_token: None,
+        };
+        assert!(cache.get("user", "token").is_none());
+        cache.insert("user", "token", principal);
+        assert_eq!(cache.get("user", "token").unwrap().id, "user");
+        assert!(cache.get("user", "other").is_none());
+        assert!(cache.get("other", "token").is_none());
+    }
+}
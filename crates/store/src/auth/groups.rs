@@ -0,0 +1,71 @@
+use super::{AuthenticationProvider, Principal, PrincipalType};
+use crate::Error;
+use tracing::{info, warn};
+
+/// Makes `principal` a member of exactly `groups` among the group principals
+/// for identity sources that own the group memberships, like a directory, a proxy or an OIDC provider.
+/// Missing group principals are created, names of other principal types are skipped
+/// and memberships in them are kept.
+/// Only memberships that differ are looked up, returns whether any of them changed.
+pub async fn sync_group_memberships(
+    auth_provider: &impl AuthenticationProvider,
+    principal: &Principal,
+    groups: &[String],
+) -> Result<bool, Error> {
+    let mut changed = false;
+    for member_of in &principal.memberships {
+        if !groups.contains(member_of)
+            && auth_provider
+                .get_principal(member_of)
+                .await?
+                .is_some_and(|group| group.principal_type == PrincipalType::Group)
+        {
+            auth_provider
+                .remove_membership(&principal.id, member_of)
+                .await?;
+            info!("Removed {} from group {member_of}", principal.id);
+            changed = true;
+        }
+    }
+    for group in groups {
+        if principal.memberships.contains(group) {
+            continue;
+        }
+        match auth_provider.get_principal(group).await? {
+            Some(existing) if existing.principal_type != PrincipalType::Group => {
+                // Joining another user's principal would grant access to its collections
+                warn!(
+                    "Skipping group {group} of {} since that principal is not a group",
+                    principal.id
+                );
+                continue;
+            }
+            Some(_) => {}
+            None => {
+                let result = auth_provider
+                    .insert_principal(
+                        Principal {
+                            id: group.clone(),
+                            displayname: None,
+                            principal_type: PrincipalType::Group,
+                            password: None,
+                            memberships: vec![],
+                            app_token: None,
+                        },
+                        false,
+                    )
+                    .await;
+                if let Err(Error::InvalidPrincipalId) = result {
+                    warn!("Skipping group {group} since it is not a valid principal id");
+                    continue;
+                }
+                result?;
+                info!("Created group {group}");
+            }
+        }
+        auth_provider.add_membership(&principal.id, group).await?;
+        info!("Added {} to group {group}", principal.id);
+        changed = true;
+    }
+    Ok(changed)
+}
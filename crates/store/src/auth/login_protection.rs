@@ -0,0 +1,412 @@
+use super::proxy::deserialize_trusted_proxies;
+use crate::Error;
+use async_trait::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use chrono::{DateTime, Duration, Utc};
+use http::{Extensions, HeaderMap};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+};
+use tracing::warn;
+
+/// Stores the failed login attempts and lockouts
+#[async_trait]
+pub trait LoginFailureStore: Send + Sync + 'static {
+    async fn get_login_failures(
+        &self,
+        subject: &LoginSubject,
+    ) -> Result<Option<LoginFailures>, Error>;
+
+    async fn list_login_failures(&self) -> Result<Vec<LoginFailures>, Error>;
+
+    /// Counts a failed login of a subject in one atomic step and returns the updated record.
+    /// The count starts over if the subject isn't locked and its last failure was before `window_start`,
+    /// reaching `max_failures` locks it out until `lock_until`.
+    async fn add_login_failure(
+        &self,
+        subject: &LoginSubject,
+        now: DateTime<Utc>,
+        window_start: DateTime<Utc>,
+        max_failures: u32,
+        lock_until: DateTime<Utc>,
+    ) -> Result<LoginFailures, Error>;
+
+    /// Forgets the failed logins of a subject, which also lifts a lockout
+    async fn remove_login_failures(&self, subject: &LoginSubject) -> Result<(), Error>;
+
+    /// Removes records whose last failure was before `before`, returns their count
+    async fn prune_login_failures(&self, before: DateTime<Utc>) -> Result<u64, Error>;
+}
+
+/// What failed login attempts are counted for
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LoginSubject {
+    Principal(String),
+    Address(IpAddr),
+}
+
+impl std::fmt::Display for LoginSubject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Principal(principal) => write!(f, "principal:{principal}"),
+            Self::Address(address) => write!(f, "address:{address}"),
+        }
+    }
+}
+
+impl FromStr for LoginSubject {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once(':') {
+            Some(("principal", principal)) => Ok(Self::Principal(principal.to_owned())),
+            Some(("address", address)) => address
+                .parse()
+                .map(Self::Address)
+                .map_err(|err| Error::Other(anyhow::anyhow!("invalid address {address}: {err}"))),
+            _ => Err(Error::Other(anyhow::anyhow!(
+                "invalid login subject {value}"
+            ))),
+        }
+    }
+}
+
+/// Recent failed login attempts of a principal or client address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoginFailures {
+    pub subject: LoginSubject,
+    pub count: u32,
+    pub last_failure_at: DateTime<Utc>,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+impl LoginFailures {
+    #[must_use]
+    pub fn is_locked(&self) -> bool {
+        self.locked_until
+            .is_some_and(|locked_until| locked_until > Utc::now())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields, default)]
+pub struct LoginProtectionConfig {
+    /// Failed logins are logged either way
+    pub enabled: bool,
+    /// Failed logins after which a principal is locked out
+    pub max_principal_failures: u32,
+    /// Failed logins after which a client address is locked out
+    pub max_address_failures: u32,
+    /// Failures older than this are forgotten
+    pub failure_window_minutes: u32,
+    pub lockout_minutes: u32,
+    /// A failed login is answered after a delay that doubles with every failure up to this
+    pub max_delay_ms: u64,
+    /// Proxies whose `X-Forwarded-For` header determines the client address
+    #[serde(deserialize_with = "deserialize_trusted_proxies")]
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+impl Default for LoginProtectionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_principal_failures: 10,
+            max_address_failures: 50,
+            failure_window_minutes: 60,
+            lockout_minutes: 15,
+            max_delay_ms: 5000,
+            trusted_proxies: vec![],
+        }
+    }
+}
+
+impl LoginProtectionConfig {
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net| net.contains(&ip))
+    }
+
+    /// Address of the client, taken from `X-Forwarded-For` if the peer is a trusted proxy.
+    /// Connections over a unix socket are always trusted.
+    #[must_use]
+    pub fn client_ip(&self, extensions: &Extensions, headers: &HeaderMap) -> Option<IpAddr> {
+        let peer = match extensions.get::<ConnectInfo<SocketAddr>>() {
+            // IPv4 peers of a dual stack socket
+            Some(ConnectInfo(addr)) => Some(addr.ip().to_canonical()),
+            None if extensions
+                .get::<ConnectInfo<tokio::net::unix::SocketAddr>>()
+                .is_some() =>
+            {
+                None
+            }
+            None => return None,
+        };
+        if let Some(peer) = peer
+            && !self.is_trusted(peer)
+        {
+            return Some(peer);
+        }
+        let forwarded: Vec<&str> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+        // Every proxy appends the address it got the request from,
+        // so the last untrusted entry is the first one that wasn't added by a proxy we trust
+        for entry in forwarded.into_iter().rev() {
+            let Ok(ip) = entry.trim().parse::<IpAddr>() else {
+                break;
+            };
+            let ip = ip.to_canonical();
+            if !self.is_trusted(ip) {
+                return Some(ip);
+            }
+        }
+        peer
+    }
+
+    fn window(&self) -> Duration {
+        Duration::minutes(self.failure_window_minutes.into())
+    }
+
+    /// Failure records that are older than this can be removed
+    #[must_use]
+    pub fn expiry(&self) -> DateTime<Utc> {
+        Utc::now()
+            - self
+                .window()
+                .max(Duration::minutes(self.lockout_minutes.into()))
+    }
+
+    fn delay(&self, failures: u32) -> std::time::Duration {
+        let delay_ms = 100u64.saturating_mul(1 << failures.saturating_sub(1).min(32));
+        std::time::Duration::from_millis(delay_ms.min(self.max_delay_ms))
+    }
+
+    /// Returns until when the principal or the client address is locked out
+    pub async fn locked_until<S: LoginFailureStore + ?Sized>(
+        &self,
+        store: &S,
+        principal: &str,
+        client_ip: Option<IpAddr>,
+    ) -> Result<Option<DateTime<Utc>>, Error> {
+        self.subjects_locked_until(store, subjects(principal, client_ip))
+            .await
+    }
+
+    /// Returns until when the client address is locked out.
+    /// Logins with an app token only check this, so failed password logins can't lock out the DAV clients of a principal.
+    pub async fn address_locked_until<S: LoginFailureStore + ?Sized>(
+        &self,
+        store: &S,
+        client_ip: Option<IpAddr>,
+    ) -> Result<Option<DateTime<Utc>>, Error> {
+        self.subjects_locked_until(store, client_ip.map(LoginSubject::Address))
+            .await
+    }
+
+    async fn subjects_locked_until<S: LoginFailureStore + ?Sized>(
+        &self,
+        store: &S,
+        subjects: impl IntoIterator<Item = LoginSubject>,
+    ) -> Result<Option<DateTime<Utc>>, Error> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        let mut locked_until = None;
+        for subject in subjects {
+            if let Some(failures) = store.get_login_failures(&subject).await?
+                && failures.is_locked()
+            {
+                locked_until = locked_until.max(failures.locked_until);
+            }
+        }
+        Ok(locked_until)
+    }
+
+    /// Logs a failed login, counts it and delays the response
+    pub async fn record_failure<S: LoginFailureStore + ?Sized>(
+        &self,
+        store: &S,
+        principal: &str,
+        client_ip: Option<IpAddr>,
+        method: &str,
+    ) -> Result<(), Error> {
+        // Stable format for tools like fail2ban, the principal is escaped to prevent log injection
+        warn!(
+            target: "rustical::auth",
+            client_ip = %client_ip.map_or_else(|| "unknown".to_owned(), |ip| ip.to_string()),
+            principal = ?principal,
+            method = %method,
+            "Failed login"
+        );
+        if !self.enabled {
+            return Ok(());
+        }
+        let now = Utc::now();
+        let mut most_failures = 0;
+        for subject in subjects(principal, client_ip) {
+            let max_failures = match subject {
+                LoginSubject::Principal(_) => self.max_principal_failures,
+                LoginSubject::Address(_) => self.max_address_failures,
+            };
+            // Every further failure extends the lockout
+            let failures = store
+                .add_login_failure(
+                    &subject,
+                    now,
+                    now - self.window(),
+                    max_failures,
+                    now + Duration::minutes(self.lockout_minutes.into()),
+                )
+                .await?;
+            if failures.count >= max_failures {
+                warn!(
+                    target: "rustical::auth",
+                    subject = %failures.subject,
+                    failures = failures.count,
+                    "Locked out after too many failed logins"
+                );
+            }
+            most_failures = most_failures.max(failures.count);
+        }
+        tokio::time::sleep(self.delay(most_failures)).await;
+        Ok(())
+    }
+
+    /// Forgets the failed logins of a principal, the count of its client address stays
+    pub async fn record_success<S: LoginFailureStore + ?Sized>(
+        &self,
+        store: &S,
+        principal: &str,
+    ) -> Result<(), Error> {
+        if !self.enabled {
+            return Ok(());
+        }
+        let subject = LoginSubject::Principal(principal.to_owned());
+        if store.get_login_failures(&subject).await?.is_some() {
+            store.remove_login_failures(&subject).await?;
+        }
+        Ok(())
+    }
+}
+
+fn subjects(principal: &str, client_ip: Option<IpAddr>) -> Vec<LoginSubject> {
+    std::iter::once(LoginSubject::Principal(principal.to_owned()))
+        .chain(client_ip.map(LoginSubject::Address))
+        .collect()
+}
+
+/// The login protection settings, provided as a request extension
+#[derive(Debug, Clone, Default)]
+pub struct LoginProtection(pub Arc<LoginProtectionConfig>);
+
+impl LoginProtection {
+    /// Falls back to the defaults if no settings were provided
+    #[must_use]
+    pub fn from_extensions(extensions: &Extensions) -> Self {
+        extensions.get::<Self>().cloned().unwrap_or_default()
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for LoginProtection {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self::from_extensions(&parts.extensions))
+    }
+}
+
+impl std::ops::Deref for LoginProtection {
+    type Target = LoginProtectionConfig;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Client address as determined by [`LoginProtectionConfig::client_ip`]
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let protection = LoginProtection::from_extensions(&parts.extensions);
+        Ok(Self(
+            protection.client_ip(&parts.extensions, &parts.headers),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LoginProtectionConfig, LoginSubject};
+    use axum::extract::ConnectInfo;
+    use http::{Extensions, HeaderMap, HeaderValue};
+    use std::net::{IpAddr, SocketAddr};
+
+    fn client_ip(config: &LoginProtectionConfig, peer: &str, forwarded: &str) -> Option<IpAddr> {
+        let mut extensions = Extensions::new();
+        extensions.insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_str(forwarded).unwrap());
+        config.client_ip(&extensions, &headers)
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let config = LoginProtectionConfig {
+            trusted_proxies: vec!["10.0.0.0/8".parse().unwrap()],
+            ..Default::default()
+        };
+        let ip = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+        // Untrusted peers can't spoof their address
+        assert_eq!(client_ip(&config, "1.2.3.4:1", "5.6.7.8"), ip("1.2.3.4"));
+        assert_eq!(client_ip(&config, "10.0.0.1:1", "5.6.7.8"), ip("5.6.7.8"));
+        assert_eq!(
+            client_ip(&config, "10.0.0.1:1", "9.9.9.9, 5.6.7.8, 10.0.0.2"),
+            ip("5.6.7.8")
+        );
+        assert_eq!(client_ip(&config, "10.0.0.1:1", "garbage"), ip("10.0.0.1"));
+        assert_eq!(
+            client_ip(&config, "[::ffff:1.2.3.4]:1", "5.6.7.8"),
+            ip("1.2.3.4")
+        );
+    }
+
+    #[test]
+    fn test_login_subject() {
+        for subject in [
+            LoginSubject::Principal("user:name".to_owned()),
+            LoginSubject::Address("::1".parse().unwrap()),
+        ] {
+            assert_eq!(
+                subject.to_string().parse::<LoginSubject>().unwrap(),
+                subject
+            );
+        }
+        assert!("user".parse::<LoginSubject>().is_err());
+    }
+
+    #[test]
+    fn test_delay() {
+        let config = LoginProtectionConfig::default();
+        assert_eq!(config.delay(1).as_millis(), 100);
+        assert_eq!(config.delay(3).as_millis(), 400);
+        assert_eq!(config.delay(1000).as_millis(), 5000);
+    }
+}
@@ -1,30 +1,124 @@
-use super::AuthenticationProvider;
-use axum::{extract::Request, response::Response};
+use super::{
+    AppToken, AuthenticationProvider, DavService, LoginProtection, Principal,
+    credential_cache::CredentialCache,
+};
+use crate::SESSION_KEY_USER;
+use axum::{
+    extract::Request,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Duration, Utc};
 use futures_core::future::BoxFuture;
-use headers::{Authorization, HeaderMapExt, authorization::Basic};
+use headers::{Authorization, HeaderMapExt, UserAgent, authorization::Basic};
+use http::{HeaderValue, Method, StatusCode, header::RETRY_AFTER};
 use std::{
+    net::IpAddr,
     sync::Arc,
     task::{Context, Poll},
 };
 use tower::{Layer, Service};
 use tower_sessions::Session;
-use tracing::{Instrument, info_span};
+use tracing::{Instrument, info_span, warn};
 
 pub struct AuthenticationLayer<AP: AuthenticationProvider> {
     auth_provider: Arc<AP>,
+    service: Option<DavService>,
+    credentials: Arc<CredentialCache>,
 }
 
 impl<AP: AuthenticationProvider> Clone for AuthenticationLayer<AP> {
     fn clone(&self) -> Self {
         Self {
             auth_provider: self.auth_provider.clone(),
+            service: self.service,
+            credentials: self.credentials.clone(),
         }
     }
 }
 
 impl<AP: AuthenticationProvider> AuthenticationLayer<AP> {
-    pub const fn new(auth_provider: Arc<AP>) -> Self {
-        Self { auth_provider }
+    /// Without a service only unrestricted app tokens are accepted
+    pub fn new(auth_provider: Arc<AP>) -> Self {
+        Self {
+            auth_provider,
+            service: None,
+            credentials: Arc::default(),
+        }
+    }
+
+    /// Accepts app tokens that are scoped to `service`
+    #[must_use]
+    pub const fn with_service(mut self, service: DavService) -> Self {
+        self.service = Some(service);
+        self
+    }
+}
+
+// Methods that a read-only app token may use
+fn is_read_method(method: &Method) -> bool {
+    matches!(
+        method.as_str(),
+        "GET" | "HEAD" | "OPTIONS" | "PROPFIND" | "REPORT"
+    )
+}
+
+// Avoids a write to the store on every request
+fn needs_use_record(app_token: &AppToken, user_agent: Option<&String>) -> bool {
+    app_token.last_user_agent.as_ref() != user_agent
+        || app_token
+            .last_used_at
+            .is_none_or(|last_used_at| Utc::now() - last_used_at >= Duration::minutes(1))
+}
+
+fn locked_out_response(locked_until: DateTime<Utc>) -> Response {
+    let retry_after = (locked_until - Utc::now()).num_seconds().max(1);
+    let mut response = StatusCode::TOO_MANY_REQUESTS.into_response();
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+    response
+}
+
+/// Verifies credentials that are not cached against the store,
+/// the flag tells whether they were verified just now.
+/// Fails with the end of the lockout if the client address is locked out,
+/// a locked out principal can still use its app tokens.
+/// A valid token doesn't reset the failures of the principal either, so DAV clients can't lift a lockout.
+async fn verify_credentials<AP: AuthenticationProvider>(
+    ap: &AP,
+    credentials: &CredentialCache,
+    protection: &LoginProtection,
+    client_ip: Option<IpAddr>,
+    user_id: &str,
+    password: &str,
+) -> Result<Option<(Principal, bool)>, DateTime<Utc>> {
+    if let Some(user) = credentials.get(user_id, password) {
+        return Ok(Some((user, false)));
+    }
+    match protection.address_locked_until(ap, client_ip).await {
+        Ok(Some(locked_until)) => return Err(locked_until),
+        Ok(None) => {}
+        Err(err) => warn!("Could not check failed logins: {err}"),
+    }
+    match ap
+        .validate_app_token(user_id, password)
+        .instrument(info_span!("validate_user_token"))
+        .await
+    {
+        Ok(Some(user)) => {
+            credentials.insert(user_id, password, user.clone());
+            Ok(Some((user, true)))
+        }
+        Ok(None) => {
+            if let Err(err) = protection
+                .record_failure(ap, user_id, client_ip, "basic")
+                .await
+            {
+                warn!("Could not record failed login: {err}");
+            }
+            Ok(None)
+        }
+        Err(_) => Ok(None),
     }
 }
 
@@ -35,6 +129,8 @@ impl<S, AP: AuthenticationProvider> Layer<S> for AuthenticationLayer<AP> {
         Self::Service {
             inner,
             auth_provider: self.auth_provider.clone(),
+            service: self.service,
+            credentials: self.credentials.clone(),
         }
     }
 }
@@ -42,6 +138,8 @@ impl<S, AP: AuthenticationProvider> Layer<S> for AuthenticationLayer<AP> {
 pub struct AuthenticationMiddleware<S, AP: AuthenticationProvider> {
     inner: S,
     auth_provider: Arc<AP>,
+    service: Option<DavService>,
+    credentials: Arc<CredentialCache>,
 }
 
 impl<S: Clone, AP: AuthenticationProvider> Clone for AuthenticationMiddleware<S, AP> {
@@ -49,6 +147,8 @@ impl<S: Clone, AP: AuthenticationProvider> Clone for AuthenticationMiddleware<S,
         Self {
             inner: self.inner.clone(),
             auth_provider: self.auth_provider.clone(),
+            service: self.service,
+            credentials: self.credentials.clone(),
         }
     }
 }
@@ -68,12 +168,22 @@ where
 
     fn call(&mut self, mut request: Request) -> Self::Future {
         let auth_header: Option<Authorization<Basic>> = request.headers().typed_get();
+        let user_agent = request
+            .headers()
+            .typed_get::<UserAgent>()
+            .map(|ua| ua.as_str().to_owned());
         let ap = self.auth_provider.clone();
+        let service = self.service;
+        let credentials = self.credentials.clone();
+        let protection = LoginProtection::from_extensions(request.extensions());
+        let client_ip = protection.client_ip(request.extensions(), request.headers());
         let mut inner = self.inner.clone();
 
         Box::pin(async move {
-            if let Some(session) = request.extensions().get::<Session>()
-                && let Ok(Some(user_id)) = session.get::<String>("user").await
+            // A principal might already be set by the ProxyAuthLayer
+            if request.extensions().get::<Principal>().is_none()
+                && let Some(session) = request.extensions().get::<Session>()
+                && let Ok(Some(user_id)) = session.get::<String>(SESSION_KEY_USER).await
                 && let Ok(Some(user)) = ap.get_principal(&user_id).await
             {
                 request.extensions_mut().insert(user);
@@ -93,17 +203,49 @@ where
                 };
                 let password = auth.password();
 
-                if let Ok(Some(user)) = ap
-                    .validate_app_token(user_id, password)
-                    .instrument(info_span!("validate_user_token"))
-                    .await
+                // Only credentials verified against the store count as a use
+                let verified = match verify_credentials(
+                    ap.as_ref(),
+                    &credentials,
+                    &protection,
+                    client_ip,
+                    user_id,
+                    password,
+                )
+                .await
                 {
+                    Ok(verified) => verified,
+                    Err(locked_until) => return Ok(locked_out_response(locked_until)),
+                };
+
+                if let Some((user, fresh)) = verified
+                    && let Some(app_token) = user.app_token.clone()
+                    && service.map_or_else(
+                        || app_token.scope.is_unrestricted(),
+                        |service| app_token.scope.allows_service(service),
+                    )
+                {
+                    if app_token.scope.read_only && !is_read_method(request.method()) {
+                        return Ok(StatusCode::FORBIDDEN.into_response());
+                    }
+                    if fresh
+                        && needs_use_record(&app_token, user_agent.as_ref())
+                        && let Err(err) = ap
+                            .record_app_token_use(&user.id, &app_token.id, user_agent)
+                            .await
+                    {
+                        warn!("Could not record app token use: {err}");
+                    }
+
                     // Make sure user is authorized to impersonate another principal
                     if let Some(impersonating) = impersonating {
                         if user.memberships().contains(&impersonating)
                             && let Ok(Some(impersonating)) = ap.get_principal(impersonating).await
                         {
-                            request.extensions_mut().insert(impersonating);
+                            request.extensions_mut().insert(Principal {
+                                app_token: Some(app_token),
+                                ..impersonating
+                            });
                         }
                     } else {
                         request.extensions_mut().insert(user);
@@ -1,7 +1,9 @@
+mod credential_cache;
 pub mod middleware;
 mod principal;
 use crate::error::Error;
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 
 mod principal_type;
 pub use principal_type::*;
@@ -9,11 +11,45 @@ pub use principal_type::*;
 mod error;
 pub use error::UnauthorizedError;
 
-pub use principal::{AppToken, Principal};
+mod groups;
+pub use groups::sync_group_memberships;
+
+mod login_protection;
+pub use login_protection::{
+    ClientIp, LoginFailureStore, LoginFailures, LoginProtection, LoginProtectionConfig,
+    LoginSubject,
+};
+
+mod password_reset;
+pub use password_reset::{password_reset_signature, verify_password_reset_signature};
+
+mod proxy;
+pub use proxy::{ProxyAuthConfig, ProxyAuthLayer};
+
+mod scope;
+pub use scope::{AppTokenScope, DavService};
+
+mod second_factor;
+pub use second_factor::SecondFactorStore;
+
+mod token_digest;
+pub use token_digest::{
+    app_token_digest, generate_app_token_pepper, load_or_generate_app_token_pepper,
+};
+
+mod totp;
+pub use totp::{
+    Totp, generate_recovery_codes, generate_totp_secret, totp_code_step, totp_generator,
+    validate_totp,
+};
+
+pub use principal::{AppToken, PasskeyCredential, Principal, hash_password};
 
 /// The `AuthenticationProvider` is the principal store for rustical.
+/// Second factors and failed logins have focused traits of their own
+/// which every principal store implements as well.
 #[async_trait]
-pub trait AuthenticationProvider: Send + Sync + 'static {
+pub trait AuthenticationProvider: SecondFactorStore + LoginFailureStore {
     /// Returns a list of all principals
     async fn get_principals(&self) -> Result<Vec<Principal>, Error>;
 
@@ -21,8 +57,18 @@ pub trait AuthenticationProvider: Send + Sync + 'static {
     /// If the principal does not exist `Ok(None)` is returned.
     async fn get_principal(&self, id: &str) -> Result<Option<Principal>, Error>;
 
+    /// Fails while the principal still owns calendars or addressbooks
     async fn remove_principal(&self, id: &str) -> Result<(), Error>;
 
+    /// Removes the principal together with its calendars, addressbooks, push subscriptions,
+    /// sessions and app tokens in a single transaction.
+    async fn purge_principal(&self, id: &str) -> Result<(), Error>;
+
+    /// Moves all calendars and addressbooks of `from` to `to` in a single transaction.
+    /// Sync tokens and change logs are kept, the collections get new push topics.
+    /// Fails with `Error::AlreadyExists` if `to` has a collection with the same id.
+    async fn transfer_collections(&self, from: &str, to: &str) -> Result<(), Error>;
+
     /// Inserts a principal and upserts it if `overwrite=true`.
     /// Ignores `Principal.membership` field which is instead managed
     /// via the `*_membership´ methods.
@@ -42,6 +88,9 @@ pub trait AuthenticationProvider: Send + Sync + 'static {
         let Some(password) = &user.password else {
             return Ok(None);
         };
+        if !self.is_active(user_id).await? {
+            return Ok(None);
+        }
 
         if password_auth::verify_password(password_input, password.as_ref()).is_ok() {
             return Ok(Some(user));
@@ -50,6 +99,7 @@ pub trait AuthenticationProvider: Send + Sync + 'static {
     }
 
     /// Validates an app token for a given principal id.
+    /// The returned principal carries the token to enforce its scope.
     async fn validate_app_token(
         &self,
         user_id: &str,
@@ -60,17 +110,43 @@ pub trait AuthenticationProvider: Send + Sync + 'static {
         // Example: asd_selgkh
         // where the app token id starts with asd and its value is selgkh
         let (token_id_prefix, token) = token.split_once('_').unwrap_or(("", token));
-
-        for app_token in &self.get_app_tokens(user_id).await? {
-            // Wrong token id
-            if !app_token.id.starts_with(token_id_prefix) {
-                continue;
-            }
-            if password_auth::verify_password(token, app_token.token.as_ref()).is_ok() {
-                return self.get_principal(user_id).await;
+        let pepper = load_or_generate_app_token_pepper(self).await?;
+        let digest = app_token_digest(&pepper, token);
+
+        let app_token = match self.get_app_token_by_digest(user_id, &digest).await? {
+            Some(app_token) => Some(app_token),
+            // Tokens created before digests existed or restored from a backup
+            // are verified against their hash once and get a digest afterwards
+            None => {
+                let mut verified = None;
+                for app_token in self.get_app_tokens(user_id).await? {
+                    // Already migrated or wrong token id
+                    if app_token.digest.is_some() || !app_token.id.starts_with(token_id_prefix) {
+                        continue;
+                    }
+                    if password_auth::verify_password(token, app_token.token.as_ref()).is_ok() {
+                        self.set_app_token_digest(user_id, &app_token.id, &digest)
+                            .await?;
+                        verified = Some(app_token);
+                        break;
+                    }
+                }
+                verified
             }
+        };
+        let Some(app_token) = app_token.filter(|app_token| !app_token.is_expired()) else {
+            return Ok(None);
+        };
+        if !self.is_active(user_id).await? {
+            return Ok(None);
         }
-        Ok(None)
+        Ok(self
+            .get_principal(user_id)
+            .await?
+            .map(|principal| Principal {
+                app_token: Some(app_token),
+                ..principal
+            }))
     }
 
     /// Returns a token identifier
@@ -79,16 +155,65 @@ pub trait AuthenticationProvider: Send + Sync + 'static {
         user_id: &str,
         name: String,
         token: String,
+        scope: AppTokenScope,
+        expires_at: Option<DateTime<Utc>>,
     ) -> Result<String, Error>;
+    /// Inserts an app token whose token is already hashed, e.g. from a backup
+    async fn insert_app_token(&self, user_id: &str, token: AppToken) -> Result<(), Error>;
     async fn remove_app_token(&self, user_id: &str, token_id: &str) -> Result<(), Error>;
 
     async fn get_app_tokens(&self, principal: &str) -> Result<Vec<AppToken>, Error>;
 
+    /// Returns the app token whose [`app_token_digest`] matches
+    async fn get_app_token_by_digest(
+        &self,
+        user_id: &str,
+        digest: &str,
+    ) -> Result<Option<AppToken>, Error>;
+
+    async fn set_app_token_digest(
+        &self,
+        user_id: &str,
+        token_id: &str,
+        digest: &str,
+    ) -> Result<(), Error>;
+
+    /// Returns the server key for app token digests
+    async fn get_app_token_pepper(&self) -> Result<Option<String>, Error>;
+
+    /// Stores the server key for app token digests unless one exists already
+    async fn insert_app_token_pepper(&self, pepper: &str) -> Result<(), Error>;
+
+    /// Updates `last_used_at` and the last user agent of an app token
+    async fn record_app_token_use(
+        &self,
+        user_id: &str,
+        token_id: &str,
+        user_agent: Option<String>,
+    ) -> Result<(), Error>;
+
     async fn add_membership(&self, principal: &str, member_of: &str) -> Result<(), Error>;
 
     async fn remove_membership(&self, principal: &str, member_of: &str) -> Result<(), Error>;
 
     async fn list_members(&self, principal: &str) -> Result<Vec<String>, Error>;
+
+    /// Returns the id of the external identity provider a principal is linked to
+    async fn get_identity_provider(&self, principal: &str) -> Result<Option<String>, Error>;
+
+    /// Links a principal to an external identity provider,
+    /// so that other providers can't log in to it
+    async fn set_identity_provider(&self, principal: &str, provider: &str) -> Result<(), Error>;
+
+    /// Returns the email address password reset links are sent to
+    async fn get_email(&self, principal: &str) -> Result<Option<String>, Error>;
+
+    async fn set_email(&self, principal: &str, email: Option<&str>) -> Result<(), Error>;
+
+    /// Deactivated principals keep their collections but can't log in anymore
+    async fn is_active(&self, principal: &str) -> Result<bool, Error>;
+
+    async fn set_active(&self, principal: &str, active: bool) -> Result<(), Error>;
 }
 
 pub use middleware::AuthenticationMiddleware;
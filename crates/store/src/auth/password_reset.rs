@@ -0,0 +1,115 @@
+use super::Principal;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::Sha256;
+
+/// Key of its own derived from the server secret, which is also the app token pepper
+fn password_reset_key(secret: &str) -> Vec<u8> {
+    let mut mac =
+        <Hmac<Sha256> as KeyInit>::new_from_slice(secret.as_bytes()).expect("HMAC takes any key");
+    mac.update(b"rustical password reset key");
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn password_reset_mac(secret: &str, principal: &Principal, expires: i64) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as KeyInit>::new_from_slice(&password_reset_key(secret))
+        .expect("HMAC takes any key");
+    mac.update(principal.id.as_bytes());
+    mac.update(b"\0");
+    mac.update(expires.to_string().as_bytes());
+    mac.update(b"\0");
+    // Every new password hash has a new salt, so a link can only be used once
+    if let Some(password) = &principal.password {
+        mac.update(password.0.as_bytes());
+    }
+    mac
+}
+
+/// Signs a password reset link of `principal` valid until `expires` (unix timestamp)
+#[must_use]
+pub fn password_reset_signature(secret: &str, principal: &Principal, expires: i64) -> String {
+    hex::encode(
+        password_reset_mac(secret, principal, expires)
+            .finalize()
+            .into_bytes(),
+    )
+}
+
+/// Checks the signature and expiry of a password reset link
+#[must_use]
+pub fn verify_password_reset_signature(
+    secret: &str,
+    principal: &Principal,
+    expires: i64,
+    signature: &str,
+    now: DateTime<Utc>,
+) -> bool {
+    if expires < now.timestamp() {
+        return false;
+    }
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    password_reset_mac(secret, principal, expires)
+        .verify_slice(&signature)
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{password_reset_signature, verify_password_reset_signature};
+    use crate::auth::{Principal, PrincipalType, app_token_digest, hash_password};
+    use chrono::Utc;
+
+    #[test]
+    fn test_password_reset_signature() {
+        let mut principal = Principal {
+            id: "user".to_owned(),
+            displayname: None,
+            principal_type: PrincipalType::Individual,
+            password: Some(hash_password("old")),
+            memberships: vec![],
+            app_token: None,
+        };
+        let now = Utc::now();
+        let expires = now.timestamp() + 3600;
+        let signature = password_reset_signature("pepper", &principal, expires);
+        // Not the digest an app token with the same content would have
+        let signed = format!(
+            "{}\0{expires}\0{}",
+            principal.id,
+            principal.password.as_ref().unwrap().0
+        );
+        assert_ne!(signature, app_token_digest("pepper", &signed));
+        assert!(verify_password_reset_signature(
+            "pepper", &principal, expires, &signature, now
+        ));
+        assert!(!verify_password_reset_signature(
+            "other pepper",
+            &principal,
+            expires,
+            &signature,
+            now
+        ));
+        // Extending the expiry breaks the signature
+        assert!(!verify_password_reset_signature(
+            "pepper",
+            &principal,
+            expires + 1,
+            &signature,
+            now
+        ));
+        assert!(!verify_password_reset_signature(
+            "pepper",
+            &principal,
+            expires,
+            &signature,
+            now + chrono::Duration::hours(2)
+        ));
+        // Used up once the password changed
+        principal.password = Some(hash_password("new"));
+        assert!(!verify_password_reset_signature(
+            "pepper", &principal, expires, &signature, now
+        ));
+    }
+}
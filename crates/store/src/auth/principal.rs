@@ -1,9 +1,10 @@
 use crate::{
     Secret,
-    auth::{PrincipalType, UnauthorizedError},
+    auth::{AppTokenScope, DavService, PrincipalType, UnauthorizedError},
 };
 use axum::extract::{FromRequestParts, OptionalFromRequestParts};
 use chrono::{DateTime, Utc};
+use rustical_dav::privileges::UserPrivilegeSet;
 use serde::{Deserialize, Serialize};
 use std::convert::Infallible;
 
@@ -13,6 +14,42 @@ pub struct AppToken {
     pub name: String,
     pub token: Secret<String>,
     pub created_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub scope: AppTokenScope,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub last_user_agent: Option<String>,
+    /// Keyed digest for fast lookups, specific to the server so it isn't backed up
+    #[serde(skip)]
+    pub digest: Option<String>,
+}
+
+impl AppToken {
+    #[must_use]
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+    }
+}
+
+/// A WebAuthn credential used for passwordless frontend login
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PasskeyCredential {
+    /// Hex encoded credential id
+    pub id: String,
+    pub name: String,
+    /// The serialized credential including its public key and signature counter
+    pub credential: String,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// Hashes a new password for [`Principal::password`]
+#[must_use]
+pub fn hash_password(password: &str) -> Secret<String> {
+    Secret::from(password_auth::generate_hash(password))
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -26,6 +63,9 @@ pub struct Principal {
     pub password: Option<Secret<String>>,
     #[serde(default)]
     pub memberships: Vec<String>,
+    /// The app token the request was authenticated with
+    #[serde(skip)]
+    pub app_token: Option<AppToken>,
 }
 
 impl Principal {
@@ -52,6 +92,31 @@ impl Principal {
     pub fn memberships_without_self(&self) -> Vec<&str> {
         self.memberships.iter().map(String::as_str).collect()
     }
+
+    /// Returns false if the collection is outside the scope of the app token in use
+    #[must_use]
+    pub fn can_access_collection(&self, principal: &str, collection: &str) -> bool {
+        self.app_token
+            .as_ref()
+            .is_none_or(|token| token.scope.allows_collection(principal, collection))
+    }
+
+    /// Applies the scope of the app token in use to the privileges on a resource
+    #[must_use]
+    pub fn scoped_privileges(
+        &self,
+        service: DavService,
+        principal: &str,
+        collection: Option<&str>,
+        privileges: UserPrivilegeSet,
+    ) -> UserPrivilegeSet {
+        match &self.app_token {
+            Some(token) => token
+                .scope
+                .restrict(service, principal, collection, privileges),
+            None => privileges,
+        }
+    }
 }
 
 impl rustical_dav::Principal for Principal {
@@ -0,0 +1,214 @@
+use super::{AuthenticationProvider, Principal, PrincipalType, sync_group_memberships};
+use crate::Error;
+use axum::{
+    extract::{ConnectInfo, Request},
+    response::Response,
+};
+use futures_core::future::BoxFuture;
+use ipnet::IpNet;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tower::{Layer, Service};
+use tracing::{error, warn};
+
+/// Accepts plain addresses as well as CIDR ranges
+pub(super) fn deserialize_trusted_proxies<'de, D>(deserializer: D) -> Result<Vec<IpNet>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|proxy| {
+            proxy
+                .parse::<IpNet>()
+                .or_else(|_| proxy.parse::<IpAddr>().map(IpNet::from))
+                .map_err(serde::de::Error::custom)
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyAuthConfig {
+    /// Header containing the user id, e.g. `Remote-User`
+    pub user_header: String,
+    /// Header containing comma separated group ids, e.g. `Remote-Groups`
+    pub groups_header: Option<String>,
+    /// Connections over a unix socket are always trusted
+    #[serde(default, deserialize_with = "deserialize_trusted_proxies")]
+    pub trusted_proxies: Vec<IpNet>,
+    #[serde(default)]
+    pub allow_sign_up: bool,
+}
+
+impl ProxyAuthConfig {
+    fn is_trusted(&self, request: &Request) -> bool {
+        if let Some(ConnectInfo(addr)) = request.extensions().get::<ConnectInfo<SocketAddr>>() {
+            // IPv4 peers of a dual stack socket
+            let ip = addr.ip().to_canonical();
+            return self.trusted_proxies.iter().any(|net| net.contains(&ip));
+        }
+        request
+            .extensions()
+            .get::<ConnectInfo<tokio::net::unix::SocketAddr>>()
+            .is_some()
+    }
+
+    fn header<'a>(request: &'a Request, name: &str) -> Option<&'a str> {
+        request
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    }
+}
+
+/// Authenticates requests with the user header set by a trusted reverse proxy.
+/// It has to wrap the routers since their `AuthenticationLayer` keeps a principal that is already set.
+pub struct ProxyAuthLayer<AP: AuthenticationProvider> {
+    config: Arc<ProxyAuthConfig>,
+    auth_provider: Arc<AP>,
+}
+
+impl<AP: AuthenticationProvider> Clone for ProxyAuthLayer<AP> {
+    fn clone(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+            auth_provider: self.auth_provider.clone(),
+        }
+    }
+}
+
+impl<AP: AuthenticationProvider> ProxyAuthLayer<AP> {
+    pub fn new(config: ProxyAuthConfig, auth_provider: Arc<AP>) -> Self {
+        Self {
+            config: Arc::new(config),
+            auth_provider,
+        }
+    }
+}
+
+impl<S, AP: AuthenticationProvider> Layer<S> for ProxyAuthLayer<AP> {
+    type Service = ProxyAuthMiddleware<S, AP>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Self::Service {
+            inner,
+            config: self.config.clone(),
+            auth_provider: self.auth_provider.clone(),
+        }
+    }
+}
+
+pub struct ProxyAuthMiddleware<S, AP: AuthenticationProvider> {
+    inner: S,
+    config: Arc<ProxyAuthConfig>,
+    auth_provider: Arc<AP>,
+}
+
+impl<S: Clone, AP: AuthenticationProvider> Clone for ProxyAuthMiddleware<S, AP> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            config: self.config.clone(),
+            auth_provider: self.auth_provider.clone(),
+        }
+    }
+}
+
+async fn proxy_principal(
+    config: &ProxyAuthConfig,
+    ap: &impl AuthenticationProvider,
+    user_id: &str,
+    groups: Option<Vec<String>>,
+) -> Result<Option<Principal>, Error> {
+    let principal = match ap.get_principal(user_id).await? {
+        Some(principal) => principal,
+        None if config.allow_sign_up => {
+            ap.insert_principal(
+                Principal {
+                    id: user_id.to_owned(),
+                    displayname: None,
+                    principal_type: PrincipalType::Individual,
+                    password: None,
+                    memberships: vec![],
+                    app_token: None,
+                },
+                false,
+            )
+            .await?;
+            ap.get_principal(user_id).await?.ok_or(Error::NotFound)?
+        }
+        None => return Ok(None),
+    };
+    if !ap.is_active(user_id).await? {
+        return Ok(None);
+    }
+    let Some(groups) = groups else {
+        return Ok(Some(principal));
+    };
+
+    // Memberships in other principal types are neither added nor removed,
+    // so only an actual change is worth reloading the principal for
+    if sync_group_memberships(ap, &principal, &groups).await? {
+        ap.get_principal(user_id).await
+    } else {
+        Ok(Some(principal))
+    }
+}
+
+impl<S, AP: AuthenticationProvider> Service<Request> for ProxyAuthMiddleware<S, AP>
+where
+    S: Service<Request, Response = Response> + Send + Clone + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        let config = self.config.clone();
+        let ap = self.auth_provider.clone();
+        let mut inner = self.inner.clone();
+
+        Box::pin(async move {
+            let user_id = ProxyAuthConfig::header(&request, &config.user_header).map(str::to_owned);
+            if let Some(user_id) = user_id {
+                if config.is_trusted(&request) {
+                    let groups = config.groups_header.as_ref().map(|name| {
+                        ProxyAuthConfig::header(&request, name)
+                            .unwrap_or_default()
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|group| !group.is_empty())
+                            .map(str::to_owned)
+                            .collect()
+                    });
+                    match proxy_principal(&config, ap.as_ref(), &user_id, groups).await {
+                        Ok(Some(principal)) => {
+                            request.extensions_mut().insert(principal);
+                        }
+                        Ok(None) => warn!("Proxy authenticated user {user_id} does not exist"),
+                        Err(err) => error!("Proxy authentication of {user_id} failed: {err}"),
+                    }
+                } else {
+                    warn!(
+                        "Ignoring {} header from an untrusted address",
+                        config.user_header
+                    );
+                }
+            }
+
+            inner.call(request).await
+        })
+    }
+}
@@ -0,0 +1,133 @@
+use rustical_dav::privileges::UserPrivilegeSet;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DavService {
+    Caldav,
+    Carddav,
+}
+
+impl DavService {
+    #[must_use]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Caldav => "caldav",
+            Self::Carddav => "carddav",
+        }
+    }
+}
+
+impl std::fmt::Display for DavService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Restrictions of an app token, the default grants everything the principal can do
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct AppTokenScope {
+    /// Services the token is valid for, all if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub services: Vec<DavService>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub read_only: bool,
+    /// Collections the token can access as `<principal>/<collection id>`, all if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub collections: Vec<String>,
+}
+
+impl AppTokenScope {
+    #[must_use]
+    pub fn is_unrestricted(&self) -> bool {
+        self == &Self::default()
+    }
+
+    #[must_use]
+    pub fn allows_service(&self, service: DavService) -> bool {
+        self.services.is_empty() || self.services.contains(&service)
+    }
+
+    #[must_use]
+    pub fn allows_collection(&self, principal: &str, collection: &str) -> bool {
+        self.collections.is_empty()
+            || self
+                .collections
+                .iter()
+                .any(|entry| entry.rsplit_once('/') == Some((principal, collection)))
+    }
+
+    /// Restricts the privileges on a resource.
+    /// `collection` is `None` for principal resources which stay readable for discovery.
+    #[must_use]
+    pub fn restrict(
+        &self,
+        service: DavService,
+        principal: &str,
+        collection: Option<&str>,
+        privileges: UserPrivilegeSet,
+    ) -> UserPrivilegeSet {
+        if !self.allows_service(service) {
+            return UserPrivilegeSet::default();
+        }
+        match collection {
+            Some(collection) if !self.allows_collection(principal, collection) => {
+                UserPrivilegeSet::default()
+            }
+            None if !self.collections.is_empty() => privileges.without_write(),
+            _ if self.read_only => privileges.without_write(),
+            _ => privileges,
+        }
+    }
+}
+
+impl std::fmt::Display for AppTokenScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_unrestricted() {
+            return f.write_str("full access");
+        }
+        let mut parts: Vec<&str> = self.services.iter().map(DavService::as_str).collect();
+        if self.read_only {
+            parts.push("read-only");
+        }
+        parts.extend(self.collections.iter().map(String::as_str));
+        f.write_str(&parts.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AppTokenScope, DavService};
+    use rustical_dav::privileges::{UserPrivilege, UserPrivilegeSet};
+
+    #[test]
+    fn test_restrict() {
+        let scope = AppTokenScope {
+            services: vec![DavService::Caldav],
+            read_only: true,
+            collections: vec!["user/work".to_owned()],
+        };
+        let all = UserPrivilegeSet::all;
+
+        let work = scope.restrict(DavService::Caldav, "user", Some("work"), all());
+        assert!(work.has(&UserPrivilege::Read));
+        assert!(!work.has(&UserPrivilege::WriteContent));
+
+        let home = scope.restrict(DavService::Caldav, "user", None, all());
+        assert!(home.has(&UserPrivilege::Read));
+        assert!(!home.has(&UserPrivilege::Write));
+
+        assert_eq!(
+            scope.restrict(DavService::Caldav, "user", Some("private"), all()),
+            UserPrivilegeSet::default()
+        );
+        assert_eq!(
+            scope.restrict(DavService::Carddav, "user", Some("work"), all()),
+            UserPrivilegeSet::default()
+        );
+        assert_eq!(
+            AppTokenScope::default().restrict(DavService::Carddav, "user", Some("work"), all()),
+            all()
+        );
+    }
+}
@@ -0,0 +1,28 @@
+use super::{PasskeyCredential, Totp};
+use crate::Error;
+use async_trait::async_trait;
+
+/// Stores the second factors of principals
+#[async_trait]
+pub trait SecondFactorStore: Send + Sync + 'static {
+    /// Returns the TOTP second factor of a principal if enabled
+    async fn get_totp(&self, principal: &str) -> Result<Option<Totp>, Error>;
+
+    /// Enables or replaces the TOTP second factor of a principal, `None` disables it
+    async fn set_totp(&self, principal: &str, totp: Option<Totp>) -> Result<(), Error>;
+
+    /// Records the time step of an accepted TOTP code.
+    /// Returns `false` if the same or a later step was used before, so a code can't be replayed.
+    async fn use_totp_step(&self, principal: &str, step: i64) -> Result<bool, Error>;
+
+    async fn get_passkeys(&self, principal: &str) -> Result<Vec<PasskeyCredential>, Error>;
+
+    /// Inserts a passkey or updates it if a passkey with the same id exists for the principal
+    async fn upsert_passkey(
+        &self,
+        principal: &str,
+        passkey: PasskeyCredential,
+    ) -> Result<(), Error>;
+
+    async fn remove_passkey(&self, principal: &str, id: &str) -> Result<(), Error>;
+}
@@ -0,0 +1,57 @@
+use super::AuthenticationProvider;
+use crate::Error;
+use hmac::{Hmac, KeyInit, Mac};
+use rand::{RngExt, distr::Alphanumeric};
+use sha2::Sha256;
+use tracing::info;
+
+/// Returns a new server key for app token digests
+#[must_use]
+pub fn generate_app_token_pepper() -> String {
+    rand::rng()
+        .sample_iter(Alphanumeric)
+        .map(char::from)
+        .take(64)
+        .collect()
+}
+
+/// Keyed digest to look up an app token by its value.
+/// App tokens are random so unlike passwords they don't need a slow hash.
+/// The pepper is kept in the same data store, so it doesn't protect the digests of a leaked database.
+#[must_use]
+pub fn app_token_digest(pepper: &str, token: &str) -> String {
+    let mut mac =
+        <Hmac<Sha256> as KeyInit>::new_from_slice(pepper.as_bytes()).expect("HMAC takes any key");
+    mac.update(token.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Loads the app token pepper from the store, a new one is generated on first use.
+/// Stores keep the pepper in memory once loaded since it is needed for every app token login.
+pub async fn load_or_generate_app_token_pepper<AP: AuthenticationProvider + ?Sized>(
+    store: &AP,
+) -> Result<String, Error> {
+    if let Some(pepper) = store.get_app_token_pepper().await? {
+        return Ok(pepper);
+    }
+    info!("Generating app token pepper");
+    store
+        .insert_app_token_pepper(&generate_app_token_pepper())
+        .await?;
+    // Another instance might have been faster
+    store.get_app_token_pepper().await?.ok_or(Error::NotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::app_token_digest;
+
+    #[test]
+    fn test_app_token_digest() {
+        let digest = app_token_digest("pepper", "token");
+        assert_eq!(digest.len(), 64);
+        assert_eq!(digest, app_token_digest("pepper", "token"));
+        assert_ne!(digest, app_token_digest("other pepper", "token"));
+        assert_ne!(digest, app_token_digest("pepper", "other token"));
+    }
+}
@@ -0,0 +1,139 @@
+use super::SecondFactorStore;
+use crate::{Error, Secret};
+use anyhow::anyhow;
+use chrono::Utc;
+use rand::{RngExt, distr::Alphanumeric};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use totp_rs::{Algorithm, TOTP};
+
+const ISSUER: &str = "RustiCal";
+const RECOVERY_CODE_COUNT: usize = 10;
+// Seconds a code is valid for
+const TOTP_STEP: i64 = 30;
+
+/// Time-based one-time password second factor of a principal
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Totp {
+    /// Base32 encoded shared secret
+    pub secret: Secret<String>,
+    /// Hashes of the unused recovery codes
+    pub recovery_codes: Vec<Secret<String>>,
+}
+
+/// Returns a new base32 encoded shared secret
+#[must_use]
+pub fn generate_totp_secret() -> String {
+    let secret: [u8; 20] = rand::rng().random();
+    totp_rs::Secret::Raw(secret.to_vec())
+        .to_encoded()
+        .to_string()
+}
+
+/// Returns the generator for authenticator apps and code checks
+pub fn totp_generator(secret: &str, principal: &str) -> Result<TOTP, Error> {
+    let secret = totp_rs::Secret::Encoded(secret.to_owned())
+        .to_bytes()
+        .map_err(|err| anyhow!("Invalid TOTP secret: {err}"))?;
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        TOTP_STEP as u64,
+        secret,
+        Some(ISSUER.to_owned()),
+        principal.to_owned(),
+    )
+    .map_err(|err| Error::Other(anyhow!("Invalid TOTP parameters: {err}")))
+}
+
+/// Checks a code of an authenticator app against a base32 encoded secret
+/// and returns the time step it belongs to.
+/// Codes of the previous and next step are accepted as well to allow for clock skew.
+pub fn totp_code_step(secret: &str, principal: &str, code: &str) -> Result<Option<i64>, Error> {
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    let totp = totp_generator(secret, principal)?;
+    let current = Utc::now().timestamp() / TOTP_STEP;
+    Ok((current - 1..=current + 1).find(|step| {
+        totp.generate((step * TOTP_STEP) as u64)
+            .as_bytes()
+            .ct_eq(code.as_bytes())
+            .into()
+    }))
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Returns new recovery codes and their hashes
+#[must_use]
+pub fn generate_recovery_codes() -> (Vec<String>, Vec<Secret<String>>) {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code: String = rand::rng()
+                .sample_iter(Alphanumeric)
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .take(10)
+                .collect();
+            let hash = password_auth::generate_hash(&code);
+            (format!("{}-{}", &code[..5], &code[5..]), Secret::from(hash))
+        })
+        .unzip()
+}
+
+/// Checks the second factor of a principal with TOTP enabled,
+/// either a code of the authenticator app which can't be used again or a recovery code which is used up.
+pub async fn validate_totp(
+    store: &impl SecondFactorStore,
+    principal: &str,
+    code: &str,
+) -> Result<bool, Error> {
+    let Some(mut totp) = store.get_totp(principal).await? else {
+        return Ok(false);
+    };
+    if let Some(step) = totp_code_step(totp.secret.as_ref(), principal, code)? {
+        return store.use_totp_step(principal, step).await;
+    }
+
+    let code = normalize_recovery_code(code);
+    let Some(index) = totp
+        .recovery_codes
+        .iter()
+        .position(|hash| password_auth::verify_password(&code, hash.as_ref()).is_ok())
+    else {
+        return Ok(false);
+    };
+    totp.recovery_codes.remove(index);
+    store.set_totp(principal, Some(totp)).await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_recovery_codes, generate_totp_secret, totp_code_step, totp_generator};
+
+    #[test]
+    fn test_totp_code() {
+        let secret = generate_totp_secret();
+        let code = totp_generator(&secret, "user")
+            .unwrap()
+            .generate_current()
+            .unwrap();
+        assert!(totp_code_step(&secret, "user", &code).unwrap().is_some());
+        let wrong = if code == "000000" { "111111" } else { "000000" };
+        assert!(totp_code_step(&secret, "user", wrong).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let (codes, hashes) = generate_recovery_codes();
+        assert_eq!(codes.len(), hashes.len());
+        assert_eq!(codes[0].len(), 11);
+        let code = super::normalize_recovery_code(&codes[0].to_uppercase());
+        assert!(password_auth::verify_password(&code, hashes[0].as_ref()).is_ok());
+    }
+}
@@ -1,6 +1,8 @@
 use crate::synctoken::format_synctoken;
 use chrono::NaiveDateTime;
+use rustical_dav::namespace::{NS_CALDAV, NS_DAV, NS_ICAL};
 use rustical_ical::CalendarObjectType;
+use rustical_xml::NamespaceOwned;
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
@@ -49,4 +51,33 @@ impl Calendar {
             .as_ref()
             .and_then(|tzid| vtimezones_rs::VTIMEZONES.get(tzid).copied())
     }
+
+    /// Returns the names of the WebDAV properties that differ from another version of this calendar
+    #[must_use]
+    pub fn changed_properties(&self, other: &Self) -> Vec<(Option<NamespaceOwned>, String)> {
+        let mut props = vec![];
+        if self.meta.displayname != other.meta.displayname {
+            props.push((Some(NS_DAV.into()), "displayname".to_owned()));
+        }
+        if self.meta.description != other.meta.description {
+            props.push((Some(NS_CALDAV.into()), "calendar-description".to_owned()));
+        }
+        if self.meta.color != other.meta.color {
+            props.push((Some(NS_ICAL.into()), "calendar-color".to_owned()));
+        }
+        if self.meta.order != other.meta.order {
+            props.push((Some(NS_ICAL.into()), "calendar-order".to_owned()));
+        }
+        if self.timezone_id != other.timezone_id {
+            props.push((Some(NS_CALDAV.into()), "calendar-timezone".to_owned()));
+            props.push((Some(NS_CALDAV.into()), "calendar-timezone-id".to_owned()));
+        }
+        if self.components != other.components {
+            props.push((
+                Some(NS_CALDAV.into()),
+                "supported-calendar-component-set".to_owned(),
+            ));
+        }
+        props
+    }
 }
@@ -56,6 +56,14 @@ pub trait CalendarReadStore: Send + Sync + 'static {
         object_id: &str,
         show_deleted: bool,
     ) -> Result<CalendarObject, Error>;
+    /// Objects in the trash bin, also if the calendar itself is trashed
+    async fn get_deleted_objects(
+        &self,
+        _principal: &str,
+        _cal_id: &str,
+    ) -> Result<Vec<(String, CalendarObject)>, Error> {
+        Ok(vec![])
+    }
 
     // read_only refers to objects, metadata may still be updated
     fn is_read_only(&self, cal_id: &str) -> bool;
@@ -69,6 +77,7 @@ pub trait CalendarWriteStore: Send + Sync + 'static {
         id: &str,
         calendar: Calendar,
     ) -> Result<(), Error>;
+    /// The change log of the new calendar continues from `calendar.synctoken`
     async fn insert_calendar(&self, calendar: Calendar) -> Result<(), Error>;
     async fn delete_calendar(
         &self,
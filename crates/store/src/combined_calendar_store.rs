@@ -98,6 +98,16 @@ impl CalendarReadStore for CombinedCalendarStore {
             .await
     }
 
+    async fn get_deleted_objects(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<(String, CalendarObject)>, crate::Error> {
+        self.store_for_id(cal_id)
+            .get_deleted_objects(principal, cal_id)
+            .await
+    }
+
     async fn get_object(
         &self,
         principal: &str,
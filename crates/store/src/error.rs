@@ -2,6 +2,7 @@ use axum::response::IntoResponse;
 use http::StatusCode;
 use tracing::error;
 
+use crate::InvalidCollectionTypeError;
 use crate::auth::InvalidPrincipalTypeError;
 
 #[derive(Debug, thiserror::Error)]
@@ -21,6 +22,9 @@ pub enum Error {
     #[error(transparent)]
     InvalidPrincipalType(#[from] InvalidPrincipalTypeError),
 
+    #[error(transparent)]
+    InvalidCollectionType(#[from] InvalidCollectionTypeError),
+
     #[error("Error generating password hash")]
     PasswordHash,
 
@@ -38,7 +42,9 @@ impl Error {
             Self::NotFound => StatusCode::NOT_FOUND,
             Self::AlreadyExists => StatusCode::CONFLICT,
             Self::ReadOnly => StatusCode::FORBIDDEN,
-            Self::InvalidPrincipalId | Self::InvalidPrincipalType(_) => StatusCode::BAD_REQUEST,
+            Self::InvalidPrincipalId
+            | Self::InvalidPrincipalType(_)
+            | Self::InvalidCollectionType(_) => StatusCode::BAD_REQUEST,
             Self::IcalError(_err) => StatusCode::INTERNAL_SERVER_ERROR,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
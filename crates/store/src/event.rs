@@ -0,0 +1,249 @@
+use crate::{Addressbook, Calendar, CollectionType};
+use chrono::TimeDelta;
+use rustical_xml::NamespaceOwned;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tracing::warn;
+
+/// The collection an event happened on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollectionRef {
+    pub principal: String,
+    pub collection_type: CollectionType,
+    pub id: String,
+    pub push_topic: String,
+}
+
+impl From<&Calendar> for CollectionRef {
+    fn from(calendar: &Calendar) -> Self {
+        Self {
+            principal: calendar.principal.clone(),
+            collection_type: CollectionType::Calendar,
+            id: calendar.id.clone(),
+            push_topic: calendar.push_topic.clone(),
+        }
+    }
+}
+
+impl From<&Addressbook> for CollectionRef {
+    fn from(addressbook: &Addressbook) -> Self {
+        Self {
+            principal: addressbook.principal.clone(),
+            collection_type: CollectionType::Addressbook,
+            id: addressbook.id.clone(),
+            push_topic: addressbook.push_topic.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ObjectChangeKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ObjectChange {
+    pub id: String,
+    // None for deleted objects
+    pub etag: Option<String>,
+    pub kind: ObjectChangeKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    // All object changes of one transaction, the sync token is the one after the last change
+    ObjectsChanged {
+        collection: CollectionRef,
+        sync_token: String,
+        changes: Vec<ObjectChange>,
+    },
+    CollectionCreated(CollectionRef),
+    CollectionUpdated {
+        collection: CollectionRef,
+        props: Vec<(Option<NamespaceOwned>, String)>,
+    },
+    CollectionDeleted {
+        collection: CollectionRef,
+        // Moved to the trashbin instead of being deleted permanently
+        trashed: bool,
+    },
+    CollectionRestored(CollectionRef),
+    PrincipalCreated(String),
+    // Also covers membership changes
+    PrincipalUpdated(String),
+    PrincipalDeleted(String),
+}
+
+impl Event {
+    #[must_use]
+    pub const fn collection(&self) -> Option<&CollectionRef> {
+        match self {
+            Self::ObjectsChanged { collection, .. }
+            | Self::CollectionCreated(collection)
+            | Self::CollectionUpdated { collection, .. }
+            | Self::CollectionDeleted { collection, .. }
+            | Self::CollectionRestored(collection) => Some(collection),
+            Self::PrincipalCreated(_) | Self::PrincipalUpdated(_) | Self::PrincipalDeleted(_) => {
+                None
+            }
+        }
+    }
+}
+
+/// Broadcasts store events to any number of subscribers
+///
+/// Publishing never blocks the store. A subscriber that falls more than `capacity` events behind
+/// misses the oldest ones and gets told so through [`EventReceiver::take_lagged`].
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+}
+
+impl EventBus {
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            sender: broadcast::Sender::new(capacity),
+        }
+    }
+
+    pub fn publish(&self, event: Event) {
+        // Only fails if nobody is listening
+        let _ = self.sender.send(event);
+    }
+
+    #[must_use]
+    pub fn subscribe(&self, name: &'static str) -> EventReceiver {
+        EventReceiver {
+            name,
+            receiver: self.sender.subscribe(),
+            lagged: false,
+        }
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(4096)
+    }
+}
+
+#[derive(Debug)]
+pub struct EventReceiver {
+    name: &'static str,
+    receiver: broadcast::Receiver<Event>,
+    lagged: bool,
+}
+
+impl EventReceiver {
+    /// Waits for the next event, returns None once the bus is gone
+    pub async fn recv(&mut self) -> Option<Event> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) => return Some(event),
+                Err(broadcast::error::RecvError::Lagged(missed)) => self.report_lag(missed),
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Waits for at least one event and then takes up to `limit` events that are already queued
+    pub async fn recv_many(&mut self, buffer: &mut Vec<Event>, limit: usize) -> usize {
+        let Some(event) = self.recv().await else {
+            return 0;
+        };
+        buffer.push(event);
+        1 + self.try_recv_many(buffer, limit.saturating_sub(1))
+    }
+
+    /// Takes up to `limit` events that are already queued without waiting
+    pub fn try_recv_many(&mut self, buffer: &mut Vec<Event>, limit: usize) -> usize {
+        let mut count = 0;
+        while count < limit {
+            match self.receiver.try_recv() {
+                Ok(event) => {
+                    buffer.push(event);
+                    count += 1;
+                }
+                Err(broadcast::error::TryRecvError::Lagged(missed)) => self.report_lag(missed),
+                Err(_) => break,
+            }
+        }
+        count
+    }
+
+    /// Whether events were missed since the last call,
+    /// subscribers that must not lose changes have to catch up some other way then
+    pub const fn take_lagged(&mut self) -> bool {
+        std::mem::replace(&mut self.lagged, false)
+    }
+
+    fn report_lag(&mut self, missed: u64) {
+        self.lagged = true;
+        warn!(
+            subscriber = self.name,
+            missed, "Event subscriber fell behind and missed events"
+        );
+    }
+}
+
+// Failed deliveries are retried after 30s, 1min, 2min, ... but at most after one hour
+const INITIAL_RETRY_DELAY: TimeDelta = TimeDelta::seconds(30);
+pub const MAX_RETRY_DELAY: TimeDelta = TimeDelta::hours(1);
+
+/// Backoff of the subscribers that deliver events to other servers, like webhooks and push
+pub fn retry_delay(attempts: i64) -> TimeDelta {
+    let exponent = u32::try_from(attempts).unwrap_or(u32::MAX).min(16);
+    INITIAL_RETRY_DELAY
+        .checked_mul(2i32.pow(exponent))
+        .unwrap_or(MAX_RETRY_DELAY)
+        .min(MAX_RETRY_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, EventBus, MAX_RETRY_DELAY, retry_delay};
+    use chrono::TimeDelta;
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(0), TimeDelta::seconds(30));
+        assert_eq!(retry_delay(1), TimeDelta::minutes(1));
+        assert_eq!(retry_delay(3), TimeDelta::minutes(4));
+        assert_eq!(retry_delay(10), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(i64::MAX), MAX_RETRY_DELAY);
+    }
+
+    #[tokio::test]
+    async fn test_event_bus() {
+        let bus = EventBus::new(2);
+        let mut first = bus.subscribe("first");
+        let mut second = bus.subscribe("second");
+
+        bus.publish(Event::PrincipalCreated("user".to_owned()));
+        assert!(matches!(
+            first.recv().await,
+            Some(Event::PrincipalCreated(id)) if id == "user"
+        ));
+
+        bus.publish(Event::PrincipalUpdated("user".to_owned()));
+        bus.publish(Event::PrincipalDeleted("user".to_owned()));
+        let mut events = vec![];
+        assert_eq!(first.recv_many(&mut events, 10).await, 2);
+
+        assert!(!first.take_lagged());
+
+        // The second subscriber missed the oldest event but keeps receiving
+        let mut events = vec![];
+        assert_eq!(second.recv_many(&mut events, 10).await, 2);
+        assert!(matches!(events[0], Event::PrincipalUpdated(_)));
+        assert!(second.take_lagged());
+        assert!(!second.take_lagged());
+
+        drop(bus);
+        assert!(second.recv().await.is_none());
+    }
+}
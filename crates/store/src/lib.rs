@@ -7,7 +7,9 @@ pub use error::Error;
 pub mod auth;
 mod calendar;
 mod combined_calendar_store;
+mod event;
 mod secret;
+mod session_store;
 pub mod synctoken;
 
 #[cfg(test)]
@@ -16,23 +18,51 @@ pub mod tests;
 pub use addressbook_store::*;
 pub use calendar_store::*;
 pub use combined_calendar_store::{CombinedCalendarStore, PrefixedCalendarStore};
+pub use event::*;
 pub use secret::Secret;
+pub use session_store::{SESSION_KEY_USER, SessionStore, session_principal};
 
 pub use addressbook::Addressbook;
 pub use calendar::{Calendar, CalendarMetadata};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
-pub enum CollectionOperationInfo {
-    // Sync-Token increased
-    Content { sync_token: String },
-    // Collection deleted
-    Delete,
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid collection type: {0}")]
+pub struct InvalidCollectionTypeError(String);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CollectionType {
+    Calendar,
+    Addressbook,
+}
+
+impl CollectionType {
+    #[must_use]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Calendar => "calendar",
+            Self::Addressbook => "addressbook",
+        }
+    }
+}
+
+impl TryFrom<&str> for CollectionType {
+    type Error = InvalidCollectionTypeError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Ok(match value {
+            "calendar" => Self::Calendar,
+            "addressbook" => Self::Addressbook,
+            _ => return Err(InvalidCollectionTypeError(value.to_owned())),
+        })
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct CollectionOperation {
-    pub topic: String,
-    pub data: CollectionOperationInfo,
+impl std::fmt::Display for CollectionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 #[derive(Default, Debug, Clone)]
@@ -0,0 +1,25 @@
+use crate::Error;
+use async_trait::async_trait;
+use tower_sessions::session::Record;
+
+/// Session key of the logged in principal
+pub const SESSION_KEY_USER: &str = "user";
+
+/// Returns the principal a session is logged in as
+#[must_use]
+pub fn session_principal(record: &Record) -> Option<&str> {
+    record
+        .data
+        .get(SESSION_KEY_USER)
+        .and_then(|user| user.as_str())
+}
+
+/// Persistent storage of the frontend sessions
+#[async_trait]
+pub trait SessionStore: tower_sessions::session_store::SessionStore + Clone {
+    /// Deletes the expired sessions and returns how many there were
+    async fn delete_expired_sessions(&self) -> Result<u64, Error>;
+
+    /// Logs a principal out everywhere, e.g. after a password change
+    async fn revoke_sessions(&self, principal: &str) -> Result<(), Error>;
+}
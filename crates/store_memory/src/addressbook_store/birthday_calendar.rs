@@ -0,0 +1,402 @@
+use super::{AddressbookCollection, MemoryAddressbookStore};
+use crate::Data;
+use async_trait::async_trait;
+use chrono::Utc;
+use hex::ToHex;
+use rustical_ical::{AddressObject, CalendarObject, CalendarObjectType};
+use rustical_store::{
+    Addressbook, AddressbookReadStore, Calendar, CalendarMetadata, CalendarStorePruneDeleted,
+    CollectionMetadata, CollectionRef, Error, Event, PrefixedCalendarStore,
+    calendar_store::{CalendarReadStore, CalendarWriteStore},
+};
+use sha2::{Digest, Sha256};
+use tracing::instrument;
+
+pub const BIRTHDAYS_PREFIX: &str = "_birthdays_";
+
+fn to_birthday_calendar(collection: &AddressbookCollection) -> Option<Calendar> {
+    collection
+        .meta
+        .birthday_calendar
+        .as_ref()
+        .map(|calendar| Calendar {
+            synctoken: collection.synctoken,
+            ..calendar.clone()
+        })
+}
+
+fn birthday_objects(
+    objects: impl IntoIterator<Item = (String, AddressObject)>,
+) -> Result<Vec<(String, CalendarObject)>, Error> {
+    let mut out_objects = vec![];
+    for (object_id, object) in objects {
+        if let Some(birthday) = object.get_birthday_object()? {
+            out_objects.push((format!("{object_id}-birthday"), birthday));
+        }
+        if let Some(anniversary) = object.get_anniversary_object()? {
+            out_objects.push((format!("{object_id}-anniversary"), anniversary));
+        }
+    }
+    Ok(out_objects)
+}
+
+impl PrefixedCalendarStore for MemoryAddressbookStore {
+    const PREFIX: &'static str = BIRTHDAYS_PREFIX;
+}
+
+impl MemoryAddressbookStore {
+    #[must_use]
+    pub fn default_birthday_calendar(addressbook: &Addressbook) -> Calendar {
+        let birthday_name = addressbook
+            .displayname
+            .as_ref()
+            .map(|name| format!("{name} birthdays"));
+        let birthday_push_topic = {
+            let mut hasher = Sha256::new();
+            hasher.update("birthdays");
+            hasher.update(&addressbook.push_topic);
+            format!(
+                "\"{}\"",
+                hasher.finalize().as_slice().encode_hex::<String>()
+            )
+        };
+        Calendar {
+            principal: addressbook.principal.clone(),
+            meta: CalendarMetadata {
+                displayname: birthday_name,
+                order: 0,
+                description: None,
+                color: None,
+            },
+            id: format!("{}{}", Self::PREFIX, addressbook.id),
+            components: vec![CalendarObjectType::Event],
+            timezone_id: None,
+            deleted_at: None,
+            synctoken: Default::default(),
+            subscription_url: None,
+            push_topic: birthday_push_topic,
+        }
+    }
+}
+
+impl Data {
+    // Birthday calendars stay around while their addressbook is in the trash bin
+    fn birthday_collection_mut(
+        &mut self,
+        principal: &str,
+        id: &str,
+        show_deleted: bool,
+    ) -> Result<&mut AddressbookCollection, Error> {
+        let addressbook_id = id.strip_prefix(BIRTHDAYS_PREFIX).ok_or(Error::NotFound)?;
+        let collection = self.addressbook_mut(principal, addressbook_id, true)?;
+        match &collection.meta.birthday_calendar {
+            Some(calendar) if show_deleted || calendar.deleted_at.is_none() => Ok(collection),
+            _ => Err(Error::NotFound),
+        }
+    }
+
+    fn birthday_collection(
+        &self,
+        principal: &str,
+        id: &str,
+        show_deleted: bool,
+    ) -> Result<&AddressbookCollection, Error> {
+        let addressbook_id = id.strip_prefix(BIRTHDAYS_PREFIX).ok_or(Error::NotFound)?;
+        let collection = self.addressbook(principal, addressbook_id, true)?;
+        match &collection.meta.birthday_calendar {
+            Some(calendar) if show_deleted || calendar.deleted_at.is_none() => Ok(collection),
+            _ => Err(Error::NotFound),
+        }
+    }
+
+    fn birthday_calendars(&self, principal: &str, deleted: bool) -> Vec<Calendar> {
+        self.addressbooks
+            .get(principal)
+            .into_iter()
+            .flat_map(|addressbooks| addressbooks.values())
+            // Only show the birthday calendars of trashed addressbooks in the trash bin
+            .filter(|collection| deleted || collection.meta.addressbook.deleted_at.is_none())
+            .filter_map(to_birthday_calendar)
+            .filter(|calendar| calendar.deleted_at.is_some() == deleted)
+            .collect()
+    }
+}
+
+#[async_trait]
+impl CalendarReadStore for MemoryAddressbookStore {
+    #[instrument]
+    async fn get_calendar(
+        &self,
+        principal: &str,
+        id: &str,
+        show_deleted: bool,
+    ) -> Result<Calendar, Error> {
+        let data = self.db.read().await;
+        to_birthday_calendar(data.birthday_collection(principal, id, show_deleted)?)
+            .ok_or(Error::NotFound)
+    }
+
+    #[instrument]
+    async fn get_calendars(&self, principal: &str) -> Result<Vec<Calendar>, Error> {
+        Ok(self.db.read().await.birthday_calendars(principal, false))
+    }
+
+    #[instrument]
+    async fn get_deleted_calendars(&self, principal: &str) -> Result<Vec<Calendar>, Error> {
+        Ok(self.db.read().await.birthday_calendars(principal, true))
+    }
+
+    #[instrument]
+    async fn sync_changes(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        synctoken: i64,
+    ) -> Result<(Vec<(String, CalendarObject)>, Vec<String>, i64), Error> {
+        let cal_id = cal_id
+            .strip_prefix(BIRTHDAYS_PREFIX)
+            .ok_or(Error::NotFound)?;
+        let (objects, deleted_objects, new_synctoken) =
+            AddressbookReadStore::sync_changes(self, principal, cal_id, synctoken).await?;
+
+        let deleted_objects = deleted_objects
+            .into_iter()
+            .flat_map(|object_id| {
+                [
+                    format!("{object_id}-birthday"),
+                    format!("{object_id}-anniversary"),
+                ]
+            })
+            .collect();
+
+        Ok((birthday_objects(objects)?, deleted_objects, new_synctoken))
+    }
+
+    #[instrument]
+    async fn calendar_metadata(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<CollectionMetadata, Error> {
+        let cal_id = cal_id
+            .strip_prefix(BIRTHDAYS_PREFIX)
+            .ok_or(Error::NotFound)?;
+        self.addressbook_metadata(principal, cal_id).await
+    }
+
+    #[instrument]
+    async fn get_objects(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<(String, CalendarObject)>, Error> {
+        let cal_id = cal_id
+            .strip_prefix(BIRTHDAYS_PREFIX)
+            .ok_or(Error::NotFound)?;
+        birthday_objects(AddressbookReadStore::get_objects(self, principal, cal_id).await?)
+    }
+
+    #[instrument]
+    async fn get_object(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+        show_deleted: bool,
+    ) -> Result<CalendarObject, Error> {
+        let cal_id = cal_id
+            .strip_prefix(BIRTHDAYS_PREFIX)
+            .ok_or(Error::NotFound)?;
+        let (addressobject_id, date_type) = object_id.rsplit_once('-').ok_or(Error::NotFound)?;
+        let obj = AddressbookReadStore::get_object(
+            self,
+            principal,
+            cal_id,
+            addressobject_id,
+            show_deleted,
+        )
+        .await?;
+        match date_type {
+            "birthday" => Ok(obj.get_birthday_object()?.ok_or(Error::NotFound)?),
+            "anniversary" => Ok(obj.get_anniversary_object()?.ok_or(Error::NotFound)?),
+            _ => Err(Error::NotFound),
+        }
+    }
+
+    fn is_read_only(&self, _cal_id: &str) -> bool {
+        true
+    }
+}
+
+#[async_trait]
+impl CalendarWriteStore for MemoryAddressbookStore {
+    #[instrument]
+    async fn update_calendar(
+        &self,
+        principal: &str,
+        id: &str,
+        calendar: Calendar,
+    ) -> Result<(), Error> {
+        assert_eq!(principal, calendar.principal);
+        assert_eq!(id, calendar.id);
+        let mut data = self.db.write().await;
+        let collection = data.birthday_collection_mut(principal, id, true)?;
+        let old_cal = to_birthday_calendar(collection).ok_or(Error::NotFound)?;
+        let props = old_cal.changed_properties(&calendar);
+
+        if let Some(meta) = collection.meta.birthday_calendar.as_mut() {
+            meta.meta = calendar.meta.clone();
+            meta.timezone_id.clone_from(&calendar.timezone_id);
+            meta.push_topic.clone_from(&calendar.push_topic);
+        }
+
+        if !props.is_empty() {
+            self.events.publish(Event::CollectionUpdated {
+                collection: CollectionRef::from(&old_cal),
+                props,
+            });
+        }
+        Ok(())
+    }
+
+    #[instrument]
+    async fn insert_calendar(&self, calendar: Calendar) -> Result<(), Error> {
+        let addressbook_id = calendar
+            .id
+            .strip_prefix(BIRTHDAYS_PREFIX)
+            .ok_or(Error::NotFound)?;
+        let mut data = self.db.write().await;
+        let collection = data.addressbook_mut(&calendar.principal, addressbook_id, true)?;
+        if collection.meta.birthday_calendar.is_some() {
+            return Err(Error::AlreadyExists);
+        }
+        collection.meta.birthday_calendar = Some(Calendar {
+            deleted_at: None,
+            ..calendar.clone()
+        });
+
+        self.events
+            .publish(Event::CollectionCreated(CollectionRef::from(&calendar)));
+        Ok(())
+    }
+
+    #[instrument]
+    async fn delete_calendar(
+        &self,
+        principal: &str,
+        id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let collection = match data.birthday_collection_mut(principal, id, true) {
+            Ok(collection) => collection,
+            Err(Error::NotFound) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let Some(cal) = to_birthday_calendar(collection) else {
+            return Ok(());
+        };
+
+        let meta = &mut collection.meta.birthday_calendar;
+        if use_trashbin {
+            if let Some(meta) = meta.as_mut() {
+                meta.deleted_at = Some(Utc::now().naive_utc());
+            }
+        } else {
+            *meta = None;
+        }
+
+        self.events.publish(Event::CollectionDeleted {
+            collection: CollectionRef::from(&cal),
+            trashed: use_trashbin,
+        });
+        Ok(())
+    }
+
+    #[instrument]
+    async fn restore_calendar(&self, principal: &str, id: &str) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let collection = data.birthday_collection_mut(principal, id, true)?;
+        if let Some(meta) = collection.meta.birthday_calendar.as_mut() {
+            meta.deleted_at = None;
+        }
+        let calendar = to_birthday_calendar(collection).ok_or(Error::NotFound)?;
+
+        self.events
+            .publish(Event::CollectionRestored(CollectionRef::from(&calendar)));
+        Ok(())
+    }
+
+    #[instrument]
+    async fn import_calendar(
+        &self,
+        _calendar: Calendar,
+        _objects: Vec<CalendarObject>,
+        _merge_existing: bool,
+    ) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    #[instrument]
+    async fn put_objects(
+        &self,
+        _principal: &str,
+        _cal_id: &str,
+        _objects: Vec<(String, CalendarObject)>,
+        _overwrite: bool,
+    ) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    #[instrument]
+    async fn delete_object(
+        &self,
+        _principal: &str,
+        _cal_id: &str,
+        _object_id: &str,
+        _use_trashbin: bool,
+    ) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    #[instrument]
+    async fn restore_object(
+        &self,
+        _principal: &str,
+        _cal_id: &str,
+        _object_id: &str,
+    ) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+}
+
+#[async_trait]
+impl CalendarStorePruneDeleted for MemoryAddressbookStore {
+    #[instrument(skip(self), fields(count = tracing::field::Empty))]
+    async fn prune_deleted_calendars(&self, before: chrono::NaiveDate) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let mut count = 0;
+        for collection in data
+            .addressbooks
+            .values_mut()
+            .flat_map(|addressbooks| addressbooks.values_mut())
+        {
+            let meta = &mut collection.meta.birthday_calendar;
+            if meta
+                .as_ref()
+                .and_then(|calendar| calendar.deleted_at)
+                .is_some_and(|deleted_at| deleted_at.date() < before)
+            {
+                *meta = None;
+                count += 1;
+            }
+        }
+        tracing::Span::current().record("count", count);
+        Ok(())
+    }
+
+    #[instrument]
+    async fn prune_deleted_objects(&self, _before: chrono::NaiveDate) -> Result<(), Error> {
+        Ok(())
+    }
+}
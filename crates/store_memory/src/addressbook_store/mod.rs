@@ -0,0 +1,460 @@
+use crate::collection::Collection;
+use crate::{Data, MemoryDb};
+use async_trait::async_trait;
+use chrono::Utc;
+use rustical_ical::AddressObject;
+use rustical_store::{
+    Addressbook, AddressbookReadStore, AddressbookWriteStore, Calendar, CollectionMetadata,
+    CollectionRef, Error, Event, EventBus, ObjectChange, ObjectChangeKind,
+    synctoken::format_synctoken,
+};
+use tracing::instrument;
+
+pub mod birthday_calendar;
+
+#[cfg(test)]
+#[path = "../../../store_sqlite/src/addressbook_store/tests.rs"]
+mod tests;
+
+#[derive(Debug, Clone)]
+pub struct AddressbookMeta {
+    // The synctokens in here are unused, the collection keeps track of it
+    addressbook: Addressbook,
+    // None once the birthday calendar got deleted for good
+    birthday_calendar: Option<Calendar>,
+}
+
+impl AddressbookMeta {
+    // The addressbook and its birthday calendar
+    pub(crate) fn collection_refs(&self) -> Vec<CollectionRef> {
+        let mut refs = vec![CollectionRef::from(&self.addressbook)];
+        refs.extend(self.birthday_calendar.as_ref().map(CollectionRef::from));
+        refs
+    }
+
+    /// Hands the addressbook and its birthday calendar over to `principal` under new push topics
+    pub(crate) fn transfer(&mut self, principal: &str) {
+        self.addressbook.principal = principal.to_owned();
+        self.addressbook.push_topic = uuid::Uuid::new_v4().to_string();
+        if let Some(calendar) = &mut self.birthday_calendar {
+            calendar.principal = principal.to_owned();
+            calendar.push_topic = uuid::Uuid::new_v4().to_string();
+        }
+    }
+}
+
+pub type AddressbookCollection = Collection<AddressbookMeta, AddressObject>;
+
+fn to_addressbook(collection: &AddressbookCollection) -> Addressbook {
+    Addressbook {
+        synctoken: collection.synctoken,
+        ..collection.meta.addressbook.clone()
+    }
+}
+
+impl Data {
+    fn addressbook(
+        &self,
+        principal: &str,
+        id: &str,
+        show_deleted: bool,
+    ) -> Result<&AddressbookCollection, Error> {
+        self.addressbooks
+            .get(principal)
+            .and_then(|addressbooks| addressbooks.get(id))
+            .filter(|collection| show_deleted || collection.meta.addressbook.deleted_at.is_none())
+            .ok_or(Error::NotFound)
+    }
+
+    fn addressbook_mut(
+        &mut self,
+        principal: &str,
+        id: &str,
+        show_deleted: bool,
+    ) -> Result<&mut AddressbookCollection, Error> {
+        self.addressbooks
+            .get_mut(principal)
+            .and_then(|addressbooks| addressbooks.get_mut(id))
+            .filter(|collection| show_deleted || collection.meta.addressbook.deleted_at.is_none())
+            .ok_or(Error::NotFound)
+    }
+
+    pub(crate) fn insert_addressbook(
+        &mut self,
+        collection: AddressbookCollection,
+    ) -> Result<(), Error> {
+        let Addressbook { principal, id, .. } = &collection.meta.addressbook;
+        self.check_principal(principal)?;
+        let addressbooks = self.addressbooks.entry(principal.clone()).or_default();
+        if addressbooks.contains_key(id) {
+            return Err(Error::AlreadyExists);
+        }
+        addressbooks.insert(id.clone(), collection);
+        Ok(())
+    }
+}
+
+pub(crate) fn new_collection(addressbook: Addressbook) -> AddressbookCollection {
+    let synctoken = addressbook.synctoken;
+    Collection::new(
+        AddressbookMeta {
+            birthday_calendar: Some(MemoryAddressbookStore::default_birthday_calendar(
+                &addressbook,
+            )),
+            addressbook,
+        },
+        synctoken,
+    )
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryAddressbookStore {
+    db: MemoryDb,
+    events: EventBus,
+}
+
+impl MemoryAddressbookStore {
+    #[must_use]
+    pub const fn new(db: MemoryDb, events: EventBus) -> Self {
+        Self { db, events }
+    }
+}
+
+#[async_trait]
+impl AddressbookReadStore for MemoryAddressbookStore {
+    #[instrument]
+    async fn get_addressbook(
+        &self,
+        principal: &str,
+        id: &str,
+        show_deleted: bool,
+    ) -> Result<Addressbook, Error> {
+        let data = self.db.read().await;
+        Ok(to_addressbook(data.addressbook(
+            principal,
+            id,
+            show_deleted,
+        )?))
+    }
+
+    #[instrument]
+    async fn get_addressbooks(&self, principal: &str) -> Result<Vec<Addressbook>, Error> {
+        let data = self.db.read().await;
+        Ok(data
+            .addressbooks
+            .get(principal)
+            .into_iter()
+            .flat_map(|addressbooks| addressbooks.values())
+            .filter(|collection| collection.meta.addressbook.deleted_at.is_none())
+            .map(to_addressbook)
+            .collect())
+    }
+
+    #[instrument]
+    async fn get_deleted_addressbooks(&self, principal: &str) -> Result<Vec<Addressbook>, Error> {
+        let data = self.db.read().await;
+        Ok(data
+            .addressbooks
+            .get(principal)
+            .into_iter()
+            .flat_map(|addressbooks| addressbooks.values())
+            .filter(|collection| collection.meta.addressbook.deleted_at.is_some())
+            .map(to_addressbook)
+            .collect())
+    }
+
+    #[instrument]
+    async fn sync_changes(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        synctoken: i64,
+    ) -> Result<(Vec<(String, AddressObject)>, Vec<String>, i64), Error> {
+        let data = self.db.read().await;
+        let collection = data.addressbook(principal, addressbook_id, false)?;
+
+        let mut updated_objects = vec![];
+        let mut deleted_objects = vec![];
+        for object_id in collection.changes_since(synctoken) {
+            match collection.get_object(&object_id, false) {
+                Ok(object) => updated_objects.push((object_id, object.clone())),
+                Err(_) => deleted_objects.push(object_id),
+            }
+        }
+        Ok((updated_objects, deleted_objects, collection.synctoken))
+    }
+
+    #[instrument]
+    async fn addressbook_metadata(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<CollectionMetadata, Error> {
+        let data = self.db.read().await;
+        Ok(data
+            .addressbook(principal, addressbook_id, true)?
+            .metadata())
+    }
+
+    #[instrument]
+    async fn get_objects(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<(String, AddressObject)>, Error> {
+        let data = self.db.read().await;
+        Ok(data
+            .addressbook(principal, addressbook_id, true)?
+            .objects()
+            .map(|(id, object)| (id.clone(), object.clone()))
+            .collect())
+    }
+
+    #[instrument]
+    async fn get_deleted_objects(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<(String, AddressObject)>, Error> {
+        let data = self.db.read().await;
+        Ok(data
+            .addressbook(principal, addressbook_id, true)?
+            .trashed_objects()
+            .map(|(id, object)| (id.clone(), object.clone()))
+            .collect())
+    }
+
+    #[instrument]
+    async fn get_object(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+        show_deleted: bool,
+    ) -> Result<AddressObject, Error> {
+        let data = self.db.read().await;
+        data.addressbook(principal, addressbook_id, true)?
+            .get_object(object_id, show_deleted)
+            .cloned()
+    }
+}
+
+#[async_trait]
+impl AddressbookWriteStore for MemoryAddressbookStore {
+    #[instrument]
+    async fn update_addressbook(
+        &self,
+        principal: &str,
+        id: &str,
+        addressbook: Addressbook,
+    ) -> Result<(), Error> {
+        assert_eq!(principal, &addressbook.principal);
+        assert_eq!(id, &addressbook.id);
+        let mut data = self.db.write().await;
+        let collection = data.addressbook_mut(principal, id, true)?;
+        let props = to_addressbook(collection).changed_properties(&addressbook);
+
+        let meta = &mut collection.meta.addressbook;
+        meta.displayname.clone_from(&addressbook.displayname);
+        meta.description.clone_from(&addressbook.description);
+        meta.push_topic.clone_from(&addressbook.push_topic);
+
+        if !props.is_empty() {
+            self.events.publish(Event::CollectionUpdated {
+                collection: CollectionRef::from(&addressbook),
+                props,
+            });
+        }
+        Ok(())
+    }
+
+    #[instrument]
+    async fn insert_addressbook(&self, addressbook: Addressbook) -> Result<(), Error> {
+        let collection = CollectionRef::from(&addressbook);
+        self.db
+            .write()
+            .await
+            .insert_addressbook(new_collection(addressbook))?;
+        self.events.publish(Event::CollectionCreated(collection));
+        Ok(())
+    }
+
+    #[instrument]
+    async fn delete_addressbook(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let addressbook = match data.addressbook_mut(principal, addressbook_id, true) {
+            Ok(collection) => {
+                let addressbook = to_addressbook(collection);
+                if use_trashbin {
+                    collection.meta.addressbook.deleted_at = Some(Utc::now().naive_utc());
+                }
+                addressbook
+            }
+            Err(Error::NotFound) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        if !use_trashbin && let Some(addressbooks) = data.addressbooks.get_mut(principal) {
+            addressbooks.remove(addressbook_id);
+        }
+
+        self.events.publish(Event::CollectionDeleted {
+            collection: CollectionRef::from(&addressbook),
+            trashed: use_trashbin,
+        });
+        Ok(())
+    }
+
+    #[instrument]
+    async fn restore_addressbook(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let collection = data.addressbook_mut(principal, addressbook_id, true)?;
+        collection.meta.addressbook.deleted_at = None;
+
+        self.events
+            .publish(Event::CollectionRestored(CollectionRef::from(
+                &to_addressbook(collection),
+            )));
+        Ok(())
+    }
+
+    #[instrument]
+    async fn put_object(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+        object: AddressObject,
+        overwrite: bool,
+    ) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let collection = data.addressbook_mut(principal, addressbook_id, true)?;
+        if !overwrite {
+            collection.check_new([(object_id, &object)])?;
+        }
+        let etag = object.get_etag();
+        let kind = collection.put_object(object_id, object);
+
+        self.events.publish(Event::ObjectsChanged {
+            collection: CollectionRef::from(&to_addressbook(collection)),
+            sync_token: format_synctoken(collection.synctoken),
+            changes: vec![ObjectChange {
+                id: object_id.to_owned(),
+                etag: Some(etag),
+                kind,
+            }],
+        });
+        Ok(())
+    }
+
+    #[instrument]
+    async fn delete_object(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let collection = data.addressbook_mut(principal, addressbook_id, true)?;
+        collection.delete_object(object_id, use_trashbin);
+
+        self.events.publish(Event::ObjectsChanged {
+            collection: CollectionRef::from(&to_addressbook(collection)),
+            sync_token: format_synctoken(collection.synctoken),
+            changes: vec![ObjectChange {
+                id: object_id.to_owned(),
+                etag: None,
+                kind: ObjectChangeKind::Deleted,
+            }],
+        });
+        Ok(())
+    }
+
+    #[instrument]
+    async fn restore_object(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let collection = data.addressbook_mut(principal, addressbook_id, true)?;
+        collection.restore_object(object_id)?;
+        let etag = collection.get_object(object_id, false)?.get_etag();
+
+        self.events.publish(Event::ObjectsChanged {
+            collection: CollectionRef::from(&to_addressbook(collection)),
+            sync_token: format_synctoken(collection.synctoken),
+            changes: vec![ObjectChange {
+                id: object_id.to_owned(),
+                etag: Some(etag),
+                kind: ObjectChangeKind::Created,
+            }],
+        });
+        Ok(())
+    }
+
+    #[instrument(skip(objects))]
+    async fn import_addressbook(
+        &self,
+        addressbook: Addressbook,
+        objects: Vec<(String, AddressObject)>,
+        merge_existing: bool,
+    ) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let (principal, id) = (addressbook.principal.clone(), addressbook.id.clone());
+
+        let is_new = match data.addressbook(&principal, &id, true) {
+            Ok(_) if !merge_existing => return Err(Error::AlreadyExists),
+            Ok(_) => false,
+            Err(Error::NotFound) => true,
+            Err(err) => return Err(err),
+        };
+        if is_new {
+            data.check_principal(&principal)?;
+        }
+
+        let mut created = None;
+        let collection = if is_new {
+            created.insert(new_collection(addressbook))
+        } else {
+            data.addressbook_mut(&principal, &id, true)?
+        };
+        collection.check_new(objects.iter().map(|(id, object)| (id.as_str(), object)))?;
+
+        let mut changes = vec![];
+        for (object_id, object) in objects {
+            changes.push(ObjectChange {
+                id: object_id.clone(),
+                etag: Some(object.get_etag()),
+                kind: ObjectChangeKind::Created,
+            });
+            collection.put_object(&object_id, object);
+        }
+        let collection_ref = CollectionRef::from(&to_addressbook(collection));
+        let sync_token = format_synctoken(collection.synctoken);
+
+        if let Some(collection) = created {
+            data.insert_addressbook(collection)?;
+            self.events
+                .publish(Event::CollectionCreated(collection_ref.clone()));
+        }
+        if !changes.is_empty() {
+            self.events.publish(Event::ObjectsChanged {
+                collection: collection_ref,
+                sync_token,
+                changes,
+            });
+        }
+        Ok(())
+    }
+}
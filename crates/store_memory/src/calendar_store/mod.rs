@@ -0,0 +1,479 @@
+use crate::collection::Collection;
+use crate::{Data, MemoryDb};
+use async_trait::async_trait;
+use chrono::Utc;
+use rustical_ical::CalendarObject;
+use rustical_store::calendar_store::{CalendarReadStore, CalendarWriteStore};
+use rustical_store::synctoken::format_synctoken;
+use rustical_store::{
+    Calendar, CalendarStorePruneDeleted, CollectionMetadata, CollectionRef, Error, Event, EventBus,
+    ObjectChange, ObjectChangeKind,
+};
+use tracing::instrument;
+
+#[cfg(test)]
+#[path = "../../../store_sqlite/src/calendar_store/tests.rs"]
+mod tests;
+
+// The synctoken of the stored calendar is unused, the collection keeps track of it
+pub type CalendarCollection = Collection<Calendar, CalendarObject>;
+
+fn to_calendar(collection: &CalendarCollection) -> Calendar {
+    Calendar {
+        synctoken: collection.synctoken,
+        ..collection.meta.clone()
+    }
+}
+
+fn new_collection(calendar: Calendar) -> CalendarCollection {
+    let synctoken = calendar.synctoken;
+    Collection::new(calendar, synctoken)
+}
+
+impl Data {
+    fn calendar(
+        &self,
+        principal: &str,
+        id: &str,
+        show_deleted: bool,
+    ) -> Result<&CalendarCollection, Error> {
+        self.calendars
+            .get(principal)
+            .and_then(|calendars| calendars.get(id))
+            .filter(|collection| show_deleted || collection.meta.deleted_at.is_none())
+            .ok_or(Error::NotFound)
+    }
+
+    fn calendar_mut(
+        &mut self,
+        principal: &str,
+        id: &str,
+        show_deleted: bool,
+    ) -> Result<&mut CalendarCollection, Error> {
+        self.calendars
+            .get_mut(principal)
+            .and_then(|calendars| calendars.get_mut(id))
+            .filter(|collection| show_deleted || collection.meta.deleted_at.is_none())
+            .ok_or(Error::NotFound)
+    }
+
+    pub(crate) fn insert_calendar(&mut self, collection: CalendarCollection) -> Result<(), Error> {
+        let Calendar { principal, id, .. } = &collection.meta;
+        self.check_principal(principal)?;
+        let calendars = self.calendars.entry(principal.clone()).or_default();
+        if calendars.contains_key(id) {
+            return Err(Error::AlreadyExists);
+        }
+        calendars.insert(id.clone(), collection);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryCalendarStore {
+    db: MemoryDb,
+    events: EventBus,
+}
+
+impl MemoryCalendarStore {
+    #[must_use]
+    pub const fn new(db: MemoryDb, events: EventBus) -> Self {
+        Self { db, events }
+    }
+}
+
+#[async_trait]
+impl CalendarReadStore for MemoryCalendarStore {
+    #[instrument]
+    async fn get_calendar(
+        &self,
+        principal: &str,
+        id: &str,
+        show_deleted: bool,
+    ) -> Result<Calendar, Error> {
+        let data = self.db.read().await;
+        Ok(to_calendar(data.calendar(principal, id, show_deleted)?))
+    }
+
+    #[instrument]
+    async fn get_calendars(&self, principal: &str) -> Result<Vec<Calendar>, Error> {
+        let data = self.db.read().await;
+        Ok(data
+            .calendars
+            .get(principal)
+            .into_iter()
+            .flat_map(|calendars| calendars.values())
+            .filter(|collection| collection.meta.deleted_at.is_none())
+            .map(to_calendar)
+            .collect())
+    }
+
+    #[instrument]
+    async fn get_deleted_calendars(&self, principal: &str) -> Result<Vec<Calendar>, Error> {
+        let data = self.db.read().await;
+        Ok(data
+            .calendars
+            .get(principal)
+            .into_iter()
+            .flat_map(|calendars| calendars.values())
+            .filter(|collection| collection.meta.deleted_at.is_some())
+            .map(to_calendar)
+            .collect())
+    }
+
+    async fn calendar_metadata(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<CollectionMetadata, Error> {
+        let data = self.db.read().await;
+        Ok(data.calendar(principal, cal_id, true)?.metadata())
+    }
+
+    #[instrument]
+    async fn get_objects(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<(String, CalendarObject)>, Error> {
+        let data = self.db.read().await;
+        Ok(data
+            .calendar(principal, cal_id, true)?
+            .objects()
+            .map(|(id, object)| (id.clone(), object.clone()))
+            .collect())
+    }
+
+    #[instrument]
+    async fn get_deleted_objects(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<(String, CalendarObject)>, Error> {
+        let data = self.db.read().await;
+        Ok(data
+            .calendar(principal, cal_id, true)?
+            .trashed_objects()
+            .map(|(id, object)| (id.clone(), object.clone()))
+            .collect())
+    }
+
+    #[instrument]
+    async fn get_object(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+        show_deleted: bool,
+    ) -> Result<CalendarObject, Error> {
+        let data = self.db.read().await;
+        data.calendar(principal, cal_id, true)?
+            .get_object(object_id, show_deleted)
+            .cloned()
+    }
+
+    #[instrument]
+    async fn sync_changes(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        synctoken: i64,
+    ) -> Result<(Vec<(String, CalendarObject)>, Vec<String>, i64), Error> {
+        let data = self.db.read().await;
+        let collection = data.calendar(principal, cal_id, false)?;
+
+        let mut updated_objects = vec![];
+        let mut deleted_objects = vec![];
+        for object_id in collection.changes_since(synctoken) {
+            match collection.get_object(&object_id, false) {
+                Ok(object) => updated_objects.push((object_id, object.clone())),
+                Err(_) => deleted_objects.push(object_id),
+            }
+        }
+        Ok((updated_objects, deleted_objects, collection.synctoken))
+    }
+
+    fn is_read_only(&self, _cal_id: &str) -> bool {
+        false
+    }
+}
+
+#[async_trait]
+impl CalendarWriteStore for MemoryCalendarStore {
+    #[instrument]
+    async fn insert_calendar(&self, calendar: Calendar) -> Result<(), Error> {
+        let collection = CollectionRef::from(&calendar);
+        self.db
+            .write()
+            .await
+            .insert_calendar(new_collection(calendar))?;
+        self.events.publish(Event::CollectionCreated(collection));
+        Ok(())
+    }
+
+    #[instrument]
+    async fn update_calendar(
+        &self,
+        principal: &str,
+        id: &str,
+        calendar: Calendar,
+    ) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let old_cal = to_calendar(data.calendar(principal, id, true)?);
+        let props = old_cal.changed_properties(&calendar);
+
+        let moved = (principal, id) != (calendar.principal.as_str(), calendar.id.as_str());
+        if moved {
+            data.check_principal(&calendar.principal)?;
+            if data
+                .calendar(&calendar.principal, &calendar.id, true)
+                .is_ok()
+            {
+                return Err(Error::AlreadyExists);
+            }
+        }
+        let mut collection = data
+            .calendars
+            .get_mut(principal)
+            .and_then(|calendars| calendars.remove(id))
+            .ok_or(Error::NotFound)?;
+        // The subscription of a calendar cannot be changed
+        collection.meta = Calendar {
+            deleted_at: old_cal.deleted_at,
+            subscription_url: old_cal.subscription_url,
+            ..calendar.clone()
+        };
+        data.calendars
+            .entry(calendar.principal.clone())
+            .or_default()
+            .insert(calendar.id.clone(), collection);
+
+        if !props.is_empty() {
+            self.events.publish(Event::CollectionUpdated {
+                collection: CollectionRef::from(&calendar),
+                props,
+            });
+        }
+        Ok(())
+    }
+
+    #[instrument]
+    async fn delete_calendar(
+        &self,
+        principal: &str,
+        id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let cal = match data.calendar_mut(principal, id, true) {
+            Ok(collection) => {
+                let cal = to_calendar(collection);
+                if use_trashbin {
+                    collection.meta.deleted_at = Some(Utc::now().naive_utc());
+                }
+                cal
+            }
+            Err(Error::NotFound) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        if !use_trashbin && let Some(calendars) = data.calendars.get_mut(principal) {
+            calendars.remove(id);
+        }
+
+        self.events.publish(Event::CollectionDeleted {
+            collection: CollectionRef::from(&cal),
+            trashed: use_trashbin,
+        });
+        Ok(())
+    }
+
+    #[instrument]
+    async fn restore_calendar(&self, principal: &str, id: &str) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let collection = data.calendar_mut(principal, id, true)?;
+        collection.meta.deleted_at = None;
+
+        self.events
+            .publish(Event::CollectionRestored(CollectionRef::from(
+                &to_calendar(collection),
+            )));
+        Ok(())
+    }
+
+    #[instrument(skip(objects))]
+    async fn import_calendar(
+        &self,
+        calendar: Calendar,
+        objects: Vec<CalendarObject>,
+        merge_existing: bool,
+    ) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let (principal, id) = (calendar.principal.clone(), calendar.id.clone());
+
+        let is_new = match data.calendar(&principal, &id, true) {
+            Ok(_) if !merge_existing => return Err(Error::AlreadyExists),
+            Ok(_) => false,
+            Err(Error::NotFound) => true,
+            Err(err) => return Err(err),
+        };
+        if is_new {
+            data.check_principal(&principal)?;
+        }
+
+        let mut created = None;
+        let collection = if is_new {
+            created.insert(new_collection(calendar))
+        } else {
+            data.calendar_mut(&principal, &id, true)?
+        };
+        collection.check_new(objects.iter().map(|object| (object.get_uid(), object)))?;
+
+        let mut changes = vec![];
+        for object in objects {
+            let object_id = object.get_uid().to_owned();
+            changes.push(ObjectChange {
+                id: object_id.clone(),
+                etag: Some(object.get_etag()),
+                kind: ObjectChangeKind::Created,
+            });
+            collection.put_object(&object_id, object);
+        }
+        let collection_ref = CollectionRef::from(&to_calendar(collection));
+        let sync_token = format_synctoken(collection.synctoken);
+
+        if let Some(collection) = created {
+            data.insert_calendar(collection)?;
+            self.events
+                .publish(Event::CollectionCreated(collection_ref.clone()));
+        }
+        if !changes.is_empty() {
+            self.events.publish(Event::ObjectsChanged {
+                collection: collection_ref,
+                sync_token,
+                changes,
+            });
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(objects))]
+    async fn put_objects(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        objects: Vec<(String, CalendarObject)>,
+        overwrite: bool,
+    ) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let collection = data.calendar_mut(principal, cal_id, true)?;
+        if collection.meta.subscription_url.is_some() {
+            // We cannot commit an object to a subscription calendar
+            return Err(Error::ReadOnly);
+        }
+        if !overwrite {
+            collection.check_new(objects.iter().map(|(id, object)| (id.as_str(), object)))?;
+        }
+
+        let mut changes = vec![];
+        for (object_id, object) in objects {
+            let etag = object.get_etag();
+            let kind = collection.put_object(&object_id, object);
+            changes.push(ObjectChange {
+                id: object_id,
+                etag: Some(etag),
+                kind,
+            });
+        }
+
+        if !changes.is_empty() {
+            self.events.publish(Event::ObjectsChanged {
+                collection: CollectionRef::from(&to_calendar(collection)),
+                sync_token: format_synctoken(collection.synctoken),
+                changes,
+            });
+        }
+        Ok(())
+    }
+
+    #[instrument]
+    async fn delete_object(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let collection = data.calendar_mut(principal, cal_id, true)?;
+        collection.delete_object(id, use_trashbin);
+
+        self.events.publish(Event::ObjectsChanged {
+            collection: CollectionRef::from(&to_calendar(collection)),
+            sync_token: format_synctoken(collection.synctoken),
+            changes: vec![ObjectChange {
+                id: id.to_owned(),
+                etag: None,
+                kind: ObjectChangeKind::Deleted,
+            }],
+        });
+        Ok(())
+    }
+
+    #[instrument]
+    async fn restore_object(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let collection = data.calendar_mut(principal, cal_id, true)?;
+        collection.restore_object(object_id)?;
+        let etag = collection.get_object(object_id, false)?.get_etag();
+
+        self.events.publish(Event::ObjectsChanged {
+            collection: CollectionRef::from(&to_calendar(collection)),
+            sync_token: format_synctoken(collection.synctoken),
+            changes: vec![ObjectChange {
+                id: object_id.to_owned(),
+                etag: Some(etag),
+                kind: ObjectChangeKind::Created,
+            }],
+        });
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CalendarStorePruneDeleted for MemoryCalendarStore {
+    #[instrument(skip(self), fields(count = tracing::field::Empty))]
+    async fn prune_deleted_calendars(&self, before: chrono::NaiveDate) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let mut count = 0;
+        for calendars in data.calendars.values_mut() {
+            let len = calendars.len();
+            calendars.retain(|_, collection| {
+                collection
+                    .meta
+                    .deleted_at
+                    .is_none_or(|deleted_at| deleted_at.date() >= before)
+            });
+            count += len - calendars.len();
+        }
+        tracing::Span::current().record("count", count);
+        Ok(())
+    }
+
+    #[instrument(skip(self), fields(count = tracing::field::Empty))]
+    async fn prune_deleted_objects(&self, before: chrono::NaiveDate) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let count: u64 = data
+            .calendars
+            .values_mut()
+            .flat_map(|calendars| calendars.values_mut())
+            .map(|collection| collection.prune_trashed(before))
+            .sum();
+        tracing::Span::current().record("count", count);
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+//! The objects of a calendar or addressbook together with their change log and trash bin
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+use rustical_ical::{AddressObject, CalendarObject};
+use rustical_store::{CollectionMetadata, Error, ObjectChangeKind};
+use std::collections::{BTreeMap, HashSet};
+
+pub trait MemoryObject: Clone {
+    fn etag(&self) -> String;
+    // Objects with a UID may only exist once per collection
+    fn uid(&self) -> Option<&str>;
+    fn size(&self) -> u64;
+}
+
+impl MemoryObject for CalendarObject {
+    fn etag(&self) -> String {
+        self.get_etag()
+    }
+
+    fn uid(&self) -> Option<&str> {
+        Some(self.get_uid())
+    }
+
+    fn size(&self) -> u64 {
+        self.get_ics().len() as u64
+    }
+}
+
+impl MemoryObject for AddressObject {
+    fn etag(&self) -> String {
+        self.get_etag()
+    }
+
+    fn uid(&self) -> Option<&str> {
+        None
+    }
+
+    fn size(&self) -> u64 {
+        self.get_vcf().len() as u64
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Collection<M, O> {
+    pub meta: M,
+    pub synctoken: i64,
+    // Synctoken of the latest change of every object that ever existed
+    changes: BTreeMap<String, i64>,
+    objects: BTreeMap<String, O>,
+    trashed: BTreeMap<String, (O, NaiveDateTime)>,
+}
+
+impl<M, O: MemoryObject> Collection<M, O> {
+    pub const fn new(meta: M, synctoken: i64) -> Self {
+        Self {
+            meta,
+            synctoken,
+            changes: BTreeMap::new(),
+            objects: BTreeMap::new(),
+            trashed: BTreeMap::new(),
+        }
+    }
+
+    fn log_change(&mut self, id: &str) {
+        self.synctoken += 1;
+        self.changes.insert(id.to_owned(), self.synctoken);
+    }
+
+    pub fn get_object(&self, id: &str, show_deleted: bool) -> Result<&O, Error> {
+        self.objects
+            .get(id)
+            .or_else(|| {
+                self.trashed
+                    .get(id)
+                    .filter(|_| show_deleted)
+                    .map(|(object, _)| object)
+            })
+            .ok_or(Error::NotFound)
+    }
+
+    pub fn objects(&self) -> impl Iterator<Item = (&String, &O)> {
+        self.objects.iter()
+    }
+
+    pub fn trashed_objects(&self) -> impl Iterator<Item = (&String, &O)> {
+        self.trashed.iter().map(|(id, (object, _))| (id, object))
+    }
+
+    /// Ids of the objects changed after `synctoken`, oldest change first
+    pub fn changes_since(&self, synctoken: i64) -> Vec<String> {
+        let mut changes: Vec<(&String, i64)> = self
+            .changes
+            .iter()
+            .map(|(id, synctoken)| (id, *synctoken))
+            .filter(|(_, change)| *change > synctoken)
+            .collect();
+        changes.sort_by_key(|(_, synctoken)| *synctoken);
+        changes.into_iter().map(|(id, _)| id.clone()).collect()
+    }
+
+    pub fn metadata(&self) -> CollectionMetadata {
+        CollectionMetadata {
+            len: self.objects.len(),
+            deleted_len: self.trashed.len(),
+            size: self.objects.values().map(MemoryObject::size).sum(),
+            deleted_size: self.trashed.values().map(|(object, _)| object.size()).sum(),
+        }
+    }
+
+    fn has_uid(&self, uid: &str) -> bool {
+        self.objects
+            .values()
+            .chain(self.trashed.values().map(|(object, _)| object))
+            .any(|object| object.uid() == Some(uid))
+    }
+
+    /// Fails if putting `objects` without overwriting would clash with an existing object
+    /// or with each other, so that a batch either goes in completely or not at all
+    pub fn check_new<'a>(
+        &self,
+        objects: impl IntoIterator<Item = (&'a str, &'a O)>,
+    ) -> Result<(), Error>
+    where
+        O: 'a,
+    {
+        let mut ids = HashSet::new();
+        let mut uids = HashSet::new();
+        for (id, object) in objects {
+            if self.objects.contains_key(id) || self.trashed.contains_key(id) || !ids.insert(id) {
+                return Err(Error::AlreadyExists);
+            }
+            if let Some(uid) = object.uid()
+                && (self.has_uid(uid) || !uids.insert(uid))
+            {
+                return Err(Error::AlreadyExists);
+            }
+        }
+        Ok(())
+    }
+
+    /// Puts an object, replacing any object with the same id or UID
+    pub fn put_object(&mut self, id: &str, object: O) -> ObjectChangeKind {
+        if let Some(uid) = object.uid() {
+            // Replaced objects vanish without a change like in the SQL stores
+            self.objects
+                .retain(|other_id, other| other_id == id || other.uid() != Some(uid));
+            self.trashed
+                .retain(|other_id, (other, _)| other_id == id || other.uid() != Some(uid));
+        }
+        self.trashed.remove(id);
+        let exists = self.objects.insert(id.to_owned(), object).is_some();
+        self.log_change(id);
+        if exists {
+            ObjectChangeKind::Updated
+        } else {
+            ObjectChangeKind::Created
+        }
+    }
+
+    pub fn delete_object(&mut self, id: &str, use_trashbin: bool) {
+        if let Some(object) = self.objects.remove(id) {
+            if use_trashbin {
+                self.trashed
+                    .insert(id.to_owned(), (object, Utc::now().naive_utc()));
+            }
+        } else if !use_trashbin {
+            self.trashed.remove(id);
+        }
+        self.log_change(id);
+    }
+
+    pub fn restore_object(&mut self, id: &str) -> Result<(), Error> {
+        if let Some((object, _)) = self.trashed.remove(id) {
+            self.objects.insert(id.to_owned(), object);
+        } else if !self.objects.contains_key(id) {
+            return Err(Error::NotFound);
+        }
+        self.log_change(id);
+        Ok(())
+    }
+
+    /// Removes the objects trashed before `before` and returns how many there were
+    pub fn prune_trashed(&mut self, before: NaiveDate) -> u64 {
+        let len = self.trashed.len();
+        self.trashed
+            .retain(|_, (_, deleted_at)| deleted_at.date() >= before);
+        (len - self.trashed.len()) as u64
+    }
+}
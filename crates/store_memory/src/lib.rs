@@ -0,0 +1,104 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+#![allow(clippy::missing_errors_doc)]
+//! Stores that keep everything in memory, all data is gone once the process exits.
+use addressbook_store::AddressbookCollection;
+use calendar_store::CalendarCollection;
+use rustical_dav_push::{PushDelivery, Subscription};
+use rustical_store::Error;
+use rustical_store::auth::{
+    AppToken, LoginFailures, LoginSubject, PasskeyCredential, Principal, Totp,
+};
+use rustical_webhook::{Webhook, WebhookDelivery};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tower_sessions::session::{Id, Record};
+
+pub mod addressbook_store;
+pub mod calendar_store;
+mod collection;
+pub mod principal_store;
+mod seed;
+pub mod session_store;
+pub mod subscription_store;
+pub mod webhook_store;
+
+#[cfg(test)]
+mod tests;
+
+// principal -> collection id -> collection
+type Collections<C> = BTreeMap<String, BTreeMap<String, C>>;
+
+#[derive(Debug)]
+struct PrincipalEntry {
+    // Memberships are kept in Data::memberships
+    principal: Principal,
+    app_tokens: Vec<AppToken>,
+    identity_provider: Option<String>,
+    email: Option<String>,
+    deactivated: bool,
+    totp: Option<Totp>,
+    // Time step of the last accepted TOTP code
+    totp_last_step: Option<i64>,
+    passkeys: Vec<PasskeyCredential>,
+}
+
+#[derive(Debug, Default)]
+struct Data {
+    principals: BTreeMap<String, PrincipalEntry>,
+    // (principal, member_of)
+    memberships: BTreeSet<(String, String)>,
+    calendars: Collections<CalendarCollection>,
+    addressbooks: Collections<AddressbookCollection>,
+    subscriptions: BTreeMap<String, Subscription>,
+    push_deliveries: BTreeMap<i64, PushDelivery>,
+    vapid_key: Option<String>,
+    app_token_pepper: Option<String>,
+    login_failures: HashMap<LoginSubject, LoginFailures>,
+    webhooks: BTreeMap<String, Webhook>,
+    webhook_deliveries: BTreeMap<i64, WebhookDelivery>,
+    // Ids of the delivery outboxes
+    last_delivery_id: i64,
+    sessions: HashMap<Id, Record>,
+}
+
+impl Data {
+    fn next_delivery_id(&mut self) -> i64 {
+        self.last_delivery_id += 1;
+        self.last_delivery_id
+    }
+
+    fn check_principal(&self, principal: &str) -> Result<(), Error> {
+        if self.principals.contains_key(principal) {
+            Ok(())
+        } else {
+            Err(Error::NotFound)
+        }
+    }
+}
+
+/// The data shared by all in-memory stores, clones refer to the same data
+#[derive(Debug, Clone, Default)]
+pub struct MemoryDb(Arc<RwLock<Data>>);
+
+impl MemoryDb {
+    async fn read(&self) -> RwLockReadGuard<'_, Data> {
+        self.0.read().await
+    }
+
+    async fn write(&self) -> RwLockWriteGuard<'_, Data> {
+        self.0.write().await
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryStore {
+    db: MemoryDb,
+}
+
+impl MemoryStore {
+    #[must_use]
+    pub const fn new(db: MemoryDb) -> Self {
+        Self { db }
+    }
+}
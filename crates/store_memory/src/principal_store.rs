@@ -0,0 +1,647 @@
+use crate::{Collections, Data, MemoryDb, PrincipalEntry};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use password_hash::{CustomizedPasswordHasher, phc::Salt};
+use pbkdf2::Params;
+use rand::rngs::SysRng;
+use rustical_store::{
+    CollectionRef, Error, Event, EventBus,
+    auth::{
+        AppToken, AppTokenScope, AuthenticationProvider, LoginFailureStore, LoginFailures,
+        LoginSubject, PasskeyCredential, Principal, SecondFactorStore, Totp, app_token_digest,
+        load_or_generate_app_token_pepper,
+    },
+    session_principal,
+};
+use tracing::instrument;
+
+#[derive(Debug, Clone)]
+pub struct MemoryPrincipalStore {
+    db: MemoryDb,
+    events: EventBus,
+}
+
+impl MemoryPrincipalStore {
+    #[must_use]
+    pub const fn new(db: MemoryDb, events: EventBus) -> Self {
+        Self { db, events }
+    }
+}
+
+// Whether both principals have a collection with the same id
+fn ids_overlap<C>(collections: &Collections<C>, from: &str, to: &str) -> bool {
+    collections
+        .get(from)
+        .zip(collections.get(to))
+        .is_some_and(|(from, to)| from.keys().any(|id| to.contains_key(id)))
+}
+
+impl Data {
+    // Removes everything that belongs to the principal besides its collections
+    fn delete_principal(&mut self, id: &str) {
+        self.principals.remove(id);
+        self.calendars.remove(id);
+        self.addressbooks.remove(id);
+        self.memberships
+            .retain(|(principal, member_of)| principal != id && member_of != id);
+        let webhook_ids: Vec<String> = self
+            .webhooks
+            .values()
+            .filter(|webhook| webhook.principal == id)
+            .map(|webhook| webhook.id.clone())
+            .collect();
+        for webhook_id in &webhook_ids {
+            self.remove_webhook(webhook_id);
+        }
+    }
+
+    fn to_principal(&self, entry: &PrincipalEntry) -> Principal {
+        Principal {
+            memberships: self
+                .memberships
+                .iter()
+                .filter(|(principal, _)| principal == &entry.principal.id)
+                .map(|(_, member_of)| member_of.clone())
+                .collect(),
+            ..entry.principal.clone()
+        }
+    }
+}
+
+#[async_trait]
+impl AuthenticationProvider for MemoryPrincipalStore {
+    #[instrument]
+    async fn get_principals(&self) -> Result<Vec<Principal>, Error> {
+        let data = self.db.read().await;
+        Ok(data
+            .principals
+            .values()
+            .map(|entry| data.to_principal(entry))
+            .collect())
+    }
+
+    #[instrument]
+    async fn get_principal(&self, id: &str) -> Result<Option<Principal>, Error> {
+        let data = self.db.read().await;
+        Ok(data
+            .principals
+            .get(id)
+            .map(|entry| data.to_principal(entry)))
+    }
+
+    #[instrument]
+    async fn remove_principal(&self, id: &str) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        if !data.principals.contains_key(id) {
+            return Ok(());
+        }
+        // Collections must be deleted first like with the foreign keys of the SQL stores
+        let owns_collections = data.calendars.get(id).is_some_and(|cals| !cals.is_empty())
+            || data
+                .addressbooks
+                .get(id)
+                .is_some_and(|books| !books.is_empty());
+        if owns_collections {
+            return Err(Error::Other(anyhow!(
+                "principal {id} still owns calendars or addressbooks"
+            )));
+        }
+
+        data.delete_principal(id);
+        self.events.publish(Event::PrincipalDeleted(id.to_owned()));
+        Ok(())
+    }
+
+    #[instrument]
+    async fn purge_principal(&self, id: &str) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        data.check_principal(id)?;
+        let mut collections: Vec<CollectionRef> = data
+            .calendars
+            .remove(id)
+            .unwrap_or_default()
+            .values()
+            .map(|collection| CollectionRef::from(&collection.meta))
+            .collect();
+        for collection in data.addressbooks.remove(id).unwrap_or_default().values() {
+            collections.extend(collection.meta.collection_refs());
+        }
+        let subscription_ids: Vec<String> = data
+            .subscriptions
+            .values()
+            .filter(|sub| {
+                collections
+                    .iter()
+                    .any(|collection| collection.push_topic == sub.topic)
+            })
+            .map(|sub| sub.id.clone())
+            .collect();
+        for subscription_id in &subscription_ids {
+            data.remove_subscription(subscription_id);
+        }
+        data.sessions
+            .retain(|_, record| session_principal(record) != Some(id));
+        data.delete_principal(id);
+        drop(data);
+
+        for collection in collections {
+            self.events.publish(Event::CollectionDeleted {
+                collection,
+                trashed: false,
+            });
+        }
+        self.events.publish(Event::PrincipalDeleted(id.to_owned()));
+        Ok(())
+    }
+
+    #[instrument]
+    async fn transfer_collections(&self, from: &str, to: &str) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        data.check_principal(from)?;
+        data.check_principal(to)?;
+        if ids_overlap(&data.calendars, from, to) || ids_overlap(&data.addressbooks, from, to) {
+            return Err(Error::AlreadyExists);
+        }
+
+        // (old, new) references of the moved collections
+        let mut moved = vec![];
+        for (id, mut collection) in data.calendars.remove(from).unwrap_or_default() {
+            let old = CollectionRef::from(&collection.meta);
+            collection.meta.principal = to.to_owned();
+            collection.meta.push_topic = uuid::Uuid::new_v4().to_string();
+            moved.push((old, CollectionRef::from(&collection.meta)));
+            data.calendars
+                .entry(to.to_owned())
+                .or_default()
+                .insert(id, collection);
+        }
+        for (id, mut collection) in data.addressbooks.remove(from).unwrap_or_default() {
+            let old = collection.meta.collection_refs();
+            collection.meta.transfer(to);
+            moved.extend(old.into_iter().zip(collection.meta.collection_refs()));
+            data.addressbooks
+                .entry(to.to_owned())
+                .or_default()
+                .insert(id, collection);
+        }
+        // Webhooks on single collections of the old owner point to nothing now
+        let webhook_ids: Vec<String> = data
+            .webhooks
+            .values()
+            .filter(|webhook| webhook.principal == from && webhook.collection.is_some())
+            .map(|webhook| webhook.id.clone())
+            .collect();
+        for webhook_id in &webhook_ids {
+            data.remove_webhook(webhook_id);
+        }
+        drop(data);
+
+        for (old, new) in moved {
+            self.events.publish(Event::CollectionDeleted {
+                collection: old,
+                trashed: false,
+            });
+            self.events.publish(Event::CollectionCreated(new));
+        }
+        Ok(())
+    }
+
+    #[instrument]
+    async fn insert_principal(&self, user: Principal, overwrite: bool) -> Result<(), Error> {
+        if user.id.contains(':') || user.id.contains('$') {
+            return Err(Error::InvalidPrincipalId);
+        }
+
+        let mut data = self.db.write().await;
+        let id = user.id.clone();
+        // Memberships are managed through add_membership
+        let principal = Principal {
+            memberships: vec![],
+            app_token: None,
+            ..user
+        };
+        let exists = if let Some(entry) = data.principals.get_mut(&id) {
+            if !overwrite {
+                return Err(Error::AlreadyExists);
+            }
+            entry.principal = principal;
+            true
+        } else {
+            data.principals.insert(
+                id.clone(),
+                PrincipalEntry {
+                    principal,
+                    app_tokens: vec![],
+                    identity_provider: None,
+                    email: None,
+                    deactivated: false,
+                    totp: None,
+                    totp_last_step: None,
+                    passkeys: vec![],
+                },
+            );
+            false
+        };
+
+        self.events.publish(if exists {
+            Event::PrincipalUpdated(id)
+        } else {
+            Event::PrincipalCreated(id)
+        });
+        Ok(())
+    }
+
+    #[instrument]
+    async fn get_app_tokens(&self, principal: &str) -> Result<Vec<AppToken>, Error> {
+        Ok(self
+            .db
+            .read()
+            .await
+            .principals
+            .get(principal)
+            .map(|entry| entry.app_tokens.clone())
+            .unwrap_or_default())
+    }
+
+    #[instrument]
+    async fn remove_app_token(&self, user_id: &str, token_id: &str) -> Result<(), Error> {
+        if let Some(entry) = self.db.write().await.principals.get_mut(user_id) {
+            entry.app_tokens.retain(|token| token.id != token_id);
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(token))]
+    async fn add_app_token(
+        &self,
+        user_id: &str,
+        name: String,
+        token: String,
+        scope: AppTokenScope,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<String, Error> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let salt = Salt::try_from_rng(&mut SysRng).map_err(|err| Error::Other(err.into()))?;
+        let token_hash = pbkdf2::Pbkdf2::SHA512
+            .hash_password_with_params(
+                token.as_bytes(),
+                &salt,
+                // App tokens have a high entropy, see the SQLite store
+                Params::new(1000).expect("1000 rounds are valid"),
+            )
+            .map_err(|_| Error::PasswordHash)?
+            .to_string();
+        let digest = app_token_digest(&load_or_generate_app_token_pepper(self).await?, &token);
+
+        let mut data = self.db.write().await;
+        let entry = data.principals.get_mut(user_id).ok_or(Error::NotFound)?;
+        entry.app_tokens.push(AppToken {
+            id: id.clone(),
+            name,
+            token: token_hash.into(),
+            created_at: Some(Utc::now()),
+            scope,
+            expires_at,
+            last_used_at: None,
+            last_user_agent: None,
+            digest: Some(digest),
+        });
+        Ok(id)
+    }
+
+    #[instrument(skip(digest))]
+    async fn get_app_token_by_digest(
+        &self,
+        user_id: &str,
+        digest: &str,
+    ) -> Result<Option<AppToken>, Error> {
+        Ok(self
+            .db
+            .read()
+            .await
+            .principals
+            .get(user_id)
+            .and_then(|entry| {
+                entry
+                    .app_tokens
+                    .iter()
+                    .find(|token| token.digest.as_deref() == Some(digest))
+                    .cloned()
+            }))
+    }
+
+    #[instrument(skip(digest))]
+    async fn set_app_token_digest(
+        &self,
+        user_id: &str,
+        token_id: &str,
+        digest: &str,
+    ) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        if let Some(token) = data.principals.get_mut(user_id).and_then(|entry| {
+            entry
+                .app_tokens
+                .iter_mut()
+                .find(|token| token.id == token_id)
+        }) {
+            token.digest = Some(digest.to_owned());
+        }
+        Ok(())
+    }
+
+    async fn get_app_token_pepper(&self) -> Result<Option<String>, Error> {
+        Ok(self.db.read().await.app_token_pepper.clone())
+    }
+
+    async fn insert_app_token_pepper(&self, pepper: &str) -> Result<(), Error> {
+        self.db
+            .write()
+            .await
+            .app_token_pepper
+            .get_or_insert_with(|| pepper.to_owned());
+        Ok(())
+    }
+
+    #[instrument]
+    async fn record_app_token_use(
+        &self,
+        user_id: &str,
+        token_id: &str,
+        user_agent: Option<String>,
+    ) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let token = data
+            .principals
+            .get_mut(user_id)
+            .and_then(|entry| {
+                entry
+                    .app_tokens
+                    .iter_mut()
+                    .find(|token| token.id == token_id)
+            })
+            .ok_or(Error::NotFound)?;
+        token.last_used_at = Some(Utc::now());
+        token.last_user_agent = user_agent;
+        Ok(())
+    }
+
+    #[instrument]
+    async fn insert_app_token(&self, user_id: &str, token: AppToken) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let entry = data.principals.get_mut(user_id).ok_or(Error::NotFound)?;
+        if entry
+            .app_tokens
+            .iter()
+            .any(|existing| existing.id == token.id)
+        {
+            return Err(Error::AlreadyExists);
+        }
+        entry.app_tokens.push(AppToken {
+            created_at: Some(token.created_at.unwrap_or_else(Utc::now)),
+            ..token
+        });
+        Ok(())
+    }
+
+    #[instrument]
+    async fn add_membership(&self, principal: &str, member_of: &str) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        data.check_principal(principal)?;
+        data.check_principal(member_of)?;
+        data.memberships
+            .insert((principal.to_owned(), member_of.to_owned()));
+        self.events
+            .publish(Event::PrincipalUpdated(principal.to_owned()));
+        Ok(())
+    }
+
+    #[instrument]
+    async fn remove_membership(&self, principal: &str, member_of: &str) -> Result<(), Error> {
+        self.db
+            .write()
+            .await
+            .memberships
+            .remove(&(principal.to_owned(), member_of.to_owned()));
+        self.events
+            .publish(Event::PrincipalUpdated(principal.to_owned()));
+        Ok(())
+    }
+
+    #[instrument]
+    async fn list_members(&self, principal: &str) -> Result<Vec<String>, Error> {
+        Ok(self
+            .db
+            .read()
+            .await
+            .memberships
+            .iter()
+            .filter(|(_, member_of)| member_of == principal)
+            .map(|(member, _)| member.clone())
+            .collect())
+    }
+
+    #[instrument]
+    async fn get_identity_provider(&self, principal: &str) -> Result<Option<String>, Error> {
+        Ok(self
+            .db
+            .read()
+            .await
+            .principals
+            .get(principal)
+            .and_then(|entry| entry.identity_provider.clone()))
+    }
+
+    #[instrument]
+    async fn set_identity_provider(&self, principal: &str, provider: &str) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let entry = data.principals.get_mut(principal).ok_or(Error::NotFound)?;
+        entry.identity_provider = Some(provider.to_owned());
+        Ok(())
+    }
+
+    #[instrument]
+    async fn get_email(&self, principal: &str) -> Result<Option<String>, Error> {
+        Ok(self
+            .db
+            .read()
+            .await
+            .principals
+            .get(principal)
+            .and_then(|entry| entry.email.clone()))
+    }
+
+    #[instrument]
+    async fn set_email(&self, principal: &str, email: Option<&str>) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let entry = data.principals.get_mut(principal).ok_or(Error::NotFound)?;
+        entry.email = email.map(str::to_owned);
+        Ok(())
+    }
+
+    #[instrument]
+    async fn is_active(&self, principal: &str) -> Result<bool, Error> {
+        Ok(self
+            .db
+            .read()
+            .await
+            .principals
+            .get(principal)
+            .is_none_or(|entry| !entry.deactivated))
+    }
+
+    #[instrument]
+    async fn set_active(&self, principal: &str, active: bool) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let entry = data.principals.get_mut(principal).ok_or(Error::NotFound)?;
+        entry.deactivated = !active;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SecondFactorStore for MemoryPrincipalStore {
+    #[instrument]
+    async fn get_totp(&self, principal: &str) -> Result<Option<Totp>, Error> {
+        Ok(self
+            .db
+            .read()
+            .await
+            .principals
+            .get(principal)
+            .and_then(|entry| entry.totp.clone()))
+    }
+
+    #[instrument]
+    async fn set_totp(&self, principal: &str, totp: Option<Totp>) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let entry = data.principals.get_mut(principal).ok_or(Error::NotFound)?;
+        if totp.is_none() {
+            entry.totp_last_step = None;
+        }
+        entry.totp = totp;
+        Ok(())
+    }
+
+    #[instrument]
+    async fn use_totp_step(&self, principal: &str, step: i64) -> Result<bool, Error> {
+        let mut data = self.db.write().await;
+        let Some(entry) = data
+            .principals
+            .get_mut(principal)
+            .filter(|entry| entry.totp.is_some())
+        else {
+            return Ok(false);
+        };
+        if entry.totp_last_step.is_some_and(|last| last >= step) {
+            return Ok(false);
+        }
+        entry.totp_last_step = Some(step);
+        Ok(true)
+    }
+
+    #[instrument]
+    async fn get_passkeys(&self, principal: &str) -> Result<Vec<PasskeyCredential>, Error> {
+        Ok(self
+            .db
+            .read()
+            .await
+            .principals
+            .get(principal)
+            .map(|entry| entry.passkeys.clone())
+            .unwrap_or_default())
+    }
+
+    #[instrument]
+    async fn upsert_passkey(
+        &self,
+        principal: &str,
+        mut passkey: PasskeyCredential,
+    ) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        let entry = data.principals.get_mut(principal).ok_or(Error::NotFound)?;
+        if let Some(existing) = entry.passkeys.iter_mut().find(|p| p.id == passkey.id) {
+            existing.name = passkey.name;
+            existing.credential = passkey.credential;
+        } else {
+            passkey.created_at.get_or_insert_with(Utc::now);
+            entry.passkeys.push(passkey);
+        }
+        Ok(())
+    }
+
+    #[instrument]
+    async fn remove_passkey(&self, principal: &str, id: &str) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        if let Some(entry) = data.principals.get_mut(principal) {
+            entry.passkeys.retain(|passkey| passkey.id != id);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl LoginFailureStore for MemoryPrincipalStore {
+    async fn get_login_failures(
+        &self,
+        subject: &LoginSubject,
+    ) -> Result<Option<LoginFailures>, Error> {
+        Ok(self.db.read().await.login_failures.get(subject).cloned())
+    }
+
+    async fn list_login_failures(&self) -> Result<Vec<LoginFailures>, Error> {
+        Ok(self
+            .db
+            .read()
+            .await
+            .login_failures
+            .values()
+            .cloned()
+            .collect())
+    }
+
+    async fn add_login_failure(
+        &self,
+        subject: &LoginSubject,
+        now: DateTime<Utc>,
+        window_start: DateTime<Utc>,
+        max_failures: u32,
+        lock_until: DateTime<Utc>,
+    ) -> Result<LoginFailures, Error> {
+        let mut data = self.db.write().await;
+        let failures = data
+            .login_failures
+            .entry(subject.clone())
+            .or_insert_with(|| LoginFailures {
+                subject: subject.clone(),
+                count: 0,
+                last_failure_at: now,
+                locked_until: None,
+            });
+        if !failures.locked_until.is_some_and(|until| until > now)
+            && failures.last_failure_at <= window_start
+        {
+            failures.count = 0;
+            failures.locked_until = None;
+        }
+        failures.count += 1;
+        failures.last_failure_at = now;
+        if failures.count >= max_failures {
+            failures.locked_until = Some(lock_until);
+        }
+        Ok(failures.clone())
+    }
+
+    async fn remove_login_failures(&self, subject: &LoginSubject) -> Result<(), Error> {
+        self.db.write().await.login_failures.remove(subject);
+        Ok(())
+    }
+
+    async fn prune_login_failures(&self, before: DateTime<Utc>) -> Result<u64, Error> {
+        let mut data = self.db.write().await;
+        let count = data.login_failures.len();
+        data.login_failures
+            .retain(|_, failures| failures.last_failure_at >= before);
+        Ok((count - data.login_failures.len()) as u64)
+    }
+}
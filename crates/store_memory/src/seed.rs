@@ -0,0 +1,193 @@
+//! Seeding from a directory laid out like the vdir store:
+//! `<principal>/calendars/<id>/*.ics` and `<principal>/addressbooks/<id>/*.vcf`
+//! with optional `displayname` and `color` files per collection
+//! and an optional plaintext `password` file per principal.
+use crate::addressbook_store::new_collection;
+use crate::collection::Collection;
+use crate::{Data, MemoryDb, PrincipalEntry};
+use anyhow::anyhow;
+use rustical_ical::{AddressObject, CalendarObject, CalendarObjectType};
+use rustical_store::auth::Principal;
+use rustical_store::{Addressbook, Calendar, CalendarMetadata, Error};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tracing::{info, warn};
+
+fn io_error(path: &Path, err: &std::io::Error) -> Error {
+    Error::Other(anyhow!("{}: {err}", path.display()))
+}
+
+async fn list_dirs(path: &Path) -> Result<Vec<(String, PathBuf)>, Error> {
+    let mut entries = match fs::read_dir(path).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(io_error(path, &err)),
+    };
+    let mut dirs = vec![];
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|err| io_error(path, &err))?
+    {
+        let Some(name) = entry.file_name().to_str().map(str::to_owned) else {
+            continue;
+        };
+        let is_dir = entry
+            .file_type()
+            .await
+            .is_ok_and(|file_type| file_type.is_dir());
+        if is_dir && !name.starts_with('.') {
+            dirs.push((name, entry.path()));
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// Returns `(id, content)` of all files with the given extension
+async fn list_objects(path: &Path, extension: &str) -> Result<Vec<(String, String)>, Error> {
+    let mut entries = fs::read_dir(path)
+        .await
+        .map_err(|err| io_error(path, &err))?;
+    let mut objects = vec![];
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|err| io_error(path, &err))?
+    {
+        let path = entry.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(extension) {
+            continue;
+        }
+        let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let content = fs::read_to_string(&path)
+            .await
+            .map_err(|err| io_error(&path, &err))?;
+        objects.push((id.to_owned(), content));
+    }
+    objects.sort();
+    Ok(objects)
+}
+
+async fn read_property(path: &Path, name: &str) -> Result<Option<String>, Error> {
+    let path = path.join(name);
+    match fs::read_to_string(&path).await {
+        Ok(value) => Ok(Some(value.trim().to_owned()).filter(|value| !value.is_empty())),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(io_error(&path, &err)),
+    }
+}
+
+impl Data {
+    async fn seed_calendar(
+        &mut self,
+        principal: &str,
+        id: String,
+        path: &Path,
+    ) -> Result<(), Error> {
+        let mut collection = Collection::new(
+            Calendar {
+                meta: CalendarMetadata {
+                    displayname: read_property(path, "displayname").await?,
+                    order: 0,
+                    description: None,
+                    color: read_property(path, "color").await?,
+                },
+                principal: principal.to_owned(),
+                id,
+                timezone_id: None,
+                deleted_at: None,
+                synctoken: 0,
+                subscription_url: None,
+                push_topic: uuid::Uuid::new_v4().to_string(),
+                components: vec![
+                    CalendarObjectType::Event,
+                    CalendarObjectType::Todo,
+                    CalendarObjectType::Journal,
+                ],
+            },
+            0,
+        );
+        for (object_id, ics) in list_objects(path, "ics").await? {
+            match CalendarObject::from_ics(ics) {
+                Ok(object) => {
+                    collection.put_object(&object_id, object);
+                }
+                Err(err) => warn!("Skipping invalid calendar object {object_id}: {err}"),
+            }
+        }
+        self.insert_calendar(collection)
+    }
+
+    async fn seed_addressbook(
+        &mut self,
+        principal: &str,
+        id: String,
+        path: &Path,
+    ) -> Result<(), Error> {
+        let mut collection = new_collection(Addressbook {
+            id,
+            principal: principal.to_owned(),
+            displayname: read_property(path, "displayname").await?,
+            description: None,
+            deleted_at: None,
+            synctoken: 0,
+            push_topic: uuid::Uuid::new_v4().to_string(),
+        });
+        for (object_id, vcf) in list_objects(path, "vcf").await? {
+            match AddressObject::from_vcf(vcf) {
+                Ok(object) => {
+                    collection.put_object(&object_id, object);
+                }
+                Err(err) => warn!("Skipping invalid address object {object_id}: {err}"),
+            }
+        }
+        self.insert_addressbook(collection)
+    }
+}
+
+impl MemoryDb {
+    /// Loads principals and their collections from `path`, principals that already exist are kept
+    pub async fn seed(&self, path: &Path) -> Result<(), Error> {
+        let mut data = self.write().await;
+        for (principal, principal_path) in list_dirs(path).await? {
+            if !data.principals.contains_key(&principal) {
+                let password = read_property(&principal_path, "password")
+                    .await?
+                    .map(|password| password_auth::generate_hash(password).into());
+                data.principals.insert(
+                    principal.clone(),
+                    PrincipalEntry {
+                        principal: Principal {
+                            id: principal.clone(),
+                            displayname: None,
+                            principal_type: Default::default(),
+                            password,
+                            memberships: vec![],
+                            app_token: None,
+                        },
+                        app_tokens: vec![],
+                        identity_provider: None,
+                        email: None,
+                        deactivated: false,
+                        totp: None,
+                        totp_last_step: None,
+                        passkeys: vec![],
+                    },
+                );
+            }
+
+            for (id, cal_path) in list_dirs(&principal_path.join("calendars")).await? {
+                data.seed_calendar(&principal, id, &cal_path).await?;
+            }
+            for (id, addr_path) in list_dirs(&principal_path.join("addressbooks")).await? {
+                data.seed_addressbook(&principal, id, &addr_path).await?;
+            }
+            info!("Seeded principal {principal}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+use crate::MemoryStore;
+use async_trait::async_trait;
+use chrono::Utc;
+use rustical_store::{Error, SessionStore, session_principal};
+use tower_sessions::{
+    session::{Id, Record},
+    session_store,
+};
+
+fn is_expired(record: &Record, now: i64) -> bool {
+    record.expiry_date.unix_timestamp() <= now
+}
+
+#[async_trait]
+impl session_store::SessionStore for MemoryStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        let mut data = self.db.write().await;
+        while data.sessions.contains_key(&record.id) {
+            // Session id collision
+            record.id = Id::default();
+        }
+        data.sessions.insert(record.id, record.clone());
+        Ok(())
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        self.db
+            .write()
+            .await
+            .sessions
+            .insert(record.id, record.clone());
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        let now = Utc::now().timestamp();
+        Ok(self
+            .db
+            .read()
+            .await
+            .sessions
+            .get(session_id)
+            .filter(|record| !is_expired(record, now))
+            .cloned())
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        self.db.write().await.sessions.remove(session_id);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemoryStore {
+    async fn delete_expired_sessions(&self) -> Result<u64, Error> {
+        let now = Utc::now().timestamp();
+        let mut data = self.db.write().await;
+        let count = data.sessions.len();
+        data.sessions.retain(|_, record| !is_expired(record, now));
+        Ok((count - data.sessions.len()) as u64)
+    }
+
+    async fn revoke_sessions(&self, principal: &str) -> Result<(), Error> {
+        self.db
+            .write()
+            .await
+            .sessions
+            .retain(|_, record| session_principal(record) != Some(principal));
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{Data, MemoryStore};
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use rustical_dav_push::{PushDelivery, Subscription, SubscriptionStore};
+use rustical_store::Error;
+
+impl Data {
+    pub(crate) fn remove_subscription(&mut self, id: &str) {
+        self.subscriptions.remove(id);
+        self.push_deliveries
+            .retain(|_, delivery| delivery.subscription_id != id);
+    }
+}
+
+#[async_trait]
+impl SubscriptionStore for MemoryStore {
+    async fn get_subscriptions(&self, topic: &str) -> Result<Vec<Subscription>, Error> {
+        Ok(self
+            .db
+            .read()
+            .await
+            .subscriptions
+            .values()
+            .filter(|sub| sub.topic == topic)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_topics(&self) -> Result<Vec<String>, Error> {
+        let mut topics: Vec<String> = self
+            .db
+            .read()
+            .await
+            .subscriptions
+            .values()
+            .map(|sub| sub.topic.clone())
+            .collect();
+        topics.sort();
+        topics.dedup();
+        Ok(topics)
+    }
+
+    async fn get_subscription(&self, id: &str) -> Result<Subscription, Error> {
+        self.db
+            .read()
+            .await
+            .subscriptions
+            .get(id)
+            .cloned()
+            .ok_or(Error::NotFound)
+    }
+
+    async fn upsert_subscription(&self, sub: Subscription) -> Result<bool, Error> {
+        Ok(self
+            .db
+            .write()
+            .await
+            .subscriptions
+            .insert(sub.id.clone(), sub)
+            .is_some())
+    }
+
+    async fn delete_subscription(&self, id: &str) -> Result<(), Error> {
+        self.db.write().await.remove_subscription(id);
+        Ok(())
+    }
+
+    async fn enqueue_delivery(
+        &self,
+        subscription_id: &str,
+        payload: &str,
+        unsubscribe: bool,
+    ) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        if !data.subscriptions.contains_key(subscription_id) {
+            return Err(Error::NotFound);
+        }
+        let id = data.next_delivery_id();
+        data.push_deliveries.insert(
+            id,
+            PushDelivery {
+                id,
+                subscription_id: subscription_id.to_owned(),
+                payload: payload.to_owned(),
+                attempts: 0,
+                next_attempt: chrono::Utc::now().naive_utc(),
+                unsubscribe,
+            },
+        );
+        Ok(())
+    }
+
+    async fn get_due_deliveries(
+        &self,
+        now: NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<PushDelivery>, Error> {
+        let data = self.db.read().await;
+        let mut deliveries: Vec<PushDelivery> = data
+            .push_deliveries
+            .values()
+            .filter(|delivery| delivery.next_attempt <= now)
+            .cloned()
+            .collect();
+        deliveries.sort_by_key(|delivery| delivery.next_attempt);
+        deliveries.truncate(usize::try_from(limit).unwrap_or_default());
+        Ok(deliveries)
+    }
+
+    async fn reschedule_delivery(
+        &self,
+        id: i64,
+        attempts: i64,
+        next_attempt: NaiveDateTime,
+    ) -> Result<(), Error> {
+        if let Some(delivery) = self.db.write().await.push_deliveries.get_mut(&id) {
+            delivery.attempts = attempts;
+            delivery.next_attempt = next_attempt;
+        }
+        Ok(())
+    }
+
+    async fn delete_delivery(&self, id: i64) -> Result<(), Error> {
+        self.db.write().await.push_deliveries.remove(&id);
+        Ok(())
+    }
+
+    async fn count_deliveries(&self) -> Result<u64, Error> {
+        Ok(self.db.read().await.push_deliveries.len() as u64)
+    }
+
+    async fn get_vapid_key(&self) -> Result<Option<String>, Error> {
+        Ok(self.db.read().await.vapid_key.clone())
+    }
+
+    async fn insert_vapid_key(&self, private_key: &str) -> Result<(), Error> {
+        self.db
+            .write()
+            .await
+            .vapid_key
+            .get_or_insert_with(|| private_key.to_owned());
+        Ok(())
+    }
+}
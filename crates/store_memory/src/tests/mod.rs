@@ -0,0 +1,72 @@
+//! Runs the store test suite of `rustical_store_sqlite` against the in-memory stores
+use crate::{
+    MemoryDb, MemoryStore, addressbook_store::MemoryAddressbookStore,
+    calendar_store::MemoryCalendarStore, principal_store::MemoryPrincipalStore,
+};
+use rstest::fixture;
+use rustical_store::EventBus;
+use rustical_store::auth::{AppTokenScope, AuthenticationProvider, Principal, PrincipalType};
+
+#[path = "../../../store_sqlite/src/tests/addressbook_store.rs"]
+mod addressbook_store;
+#[path = "../../../store_sqlite/src/tests/calendar_store.rs"]
+mod calendar_store;
+#[path = "../../../store_sqlite/src/tests/principal_store.rs"]
+mod principal_store;
+mod seed;
+#[path = "../../../store_sqlite/src/tests/subscription_store.rs"]
+mod subscription_store;
+#[path = "../../../store_sqlite/src/tests/webhook_store.rs"]
+mod webhook_store;
+
+#[derive(Debug, Clone)]
+pub struct TestStoreContext {
+    pub db: MemoryDb,
+    pub events: EventBus,
+    pub addr_store: MemoryAddressbookStore,
+    pub cal_store: MemoryCalendarStore,
+    pub principal_store: MemoryPrincipalStore,
+    pub sub_store: MemoryStore,
+}
+
+#[fixture]
+pub async fn test_store_context() -> TestStoreContext {
+    let events = EventBus::default();
+    let db = MemoryDb::default();
+
+    let principal_store = MemoryPrincipalStore::new(db.clone(), events.clone());
+    // Populate with test data
+    principal_store
+        .insert_principal(
+            Principal {
+                id: "user".to_owned(),
+                displayname: None,
+                memberships: vec![],
+                app_token: None,
+                password: None,
+                principal_type: PrincipalType::Individual,
+            },
+            false,
+        )
+        .await
+        .unwrap();
+    principal_store
+        .add_app_token(
+            "user",
+            "test".to_string(),
+            "pass".to_string(),
+            AppTokenScope::default(),
+            None,
+        )
+        .await
+        .unwrap();
+
+    TestStoreContext {
+        addr_store: MemoryAddressbookStore::new(db.clone(), events.clone()),
+        cal_store: MemoryCalendarStore::new(db.clone(), events.clone()),
+        principal_store,
+        sub_store: MemoryStore::new(db.clone()),
+        db,
+        events,
+    }
+}
@@ -0,0 +1,71 @@
+use super::{TestStoreContext, test_store_context};
+use rstest::rstest;
+use rustical_ical::{AddressObject, CalendarObject};
+use rustical_store::auth::AuthenticationProvider;
+use rustical_store::{AddressbookReadStore, CalendarReadStore};
+use tempfile::TempDir;
+
+#[rstest]
+#[tokio::test]
+async fn test_seed(
+    #[from(test_store_context)]
+    #[future]
+    context: TestStoreContext,
+) {
+    let TestStoreContext {
+        db,
+        addr_store,
+        cal_store,
+        principal_store,
+        ..
+    } = context.await;
+
+    let dir = TempDir::new().unwrap();
+    let calendar = dir.path().join("user/calendars/work");
+    std::fs::create_dir_all(&calendar).unwrap();
+    std::fs::write(calendar.join("displayname"), "Work\n").unwrap();
+    std::fs::write(
+        calendar.join("event.ics"),
+        CalendarObject::example_1().get_ics(),
+    )
+    .unwrap();
+    std::fs::write(calendar.join("broken.ics"), "BEGIN:VCALENDAR").unwrap();
+    let addressbook = dir.path().join("other/addressbooks/contacts");
+    std::fs::create_dir_all(&addressbook).unwrap();
+    std::fs::write(
+        addressbook.join("contact.vcf"),
+        AddressObject::example_minimal().get_vcf(),
+    )
+    .unwrap();
+    std::fs::write(dir.path().join("other/password"), "secret\n").unwrap();
+
+    db.seed(dir.path()).await.unwrap();
+
+    // Existing principals are kept
+    assert_eq!(
+        principal_store.get_app_tokens("user").await.unwrap().len(),
+        1
+    );
+    let calendar = cal_store.get_calendar("user", "work", false).await.unwrap();
+    assert_eq!(calendar.meta.displayname.as_deref(), Some("Work"));
+    let objects = cal_store.get_objects("user", "work").await.unwrap();
+    assert_eq!(objects.len(), 1);
+    assert_eq!(objects[0].0, "event");
+
+    let other = principal_store
+        .get_principal("other")
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(
+        principal_store
+            .validate_password("other", "secret")
+            .await
+            .unwrap()
+            .is_some()
+    );
+    assert!(other.password.is_some());
+    AddressbookReadStore::get_object(&addr_store, "other", "contacts", "contact", false)
+        .await
+        .unwrap();
+}
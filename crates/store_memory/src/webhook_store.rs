@@ -0,0 +1,162 @@
+use crate::{Data, MemoryStore};
+use async_trait::async_trait;
+use chrono::{NaiveDateTime, Utc};
+use rustical_store::Error;
+use rustical_webhook::{DeliveryState, Webhook, WebhookDelivery, WebhookStore};
+
+impl Data {
+    pub(crate) fn remove_webhook(&mut self, id: &str) {
+        self.webhooks.remove(id);
+        self.webhook_deliveries
+            .retain(|_, delivery| delivery.webhook_id != id);
+    }
+}
+
+#[async_trait]
+impl WebhookStore for MemoryStore {
+    async fn get_webhooks(&self, principal: &str) -> Result<Vec<Webhook>, Error> {
+        let mut webhooks: Vec<Webhook> = self
+            .db
+            .read()
+            .await
+            .webhooks
+            .values()
+            .filter(|webhook| webhook.principal == principal)
+            .cloned()
+            .collect();
+        webhooks.sort_by_key(|webhook| webhook.created_at);
+        Ok(webhooks)
+    }
+
+    async fn get_all_webhooks(&self) -> Result<Vec<Webhook>, Error> {
+        let mut webhooks: Vec<Webhook> = self.db.read().await.webhooks.values().cloned().collect();
+        webhooks.sort_by_key(|webhook| webhook.created_at);
+        Ok(webhooks)
+    }
+
+    async fn get_webhook(&self, id: &str) -> Result<Webhook, Error> {
+        self.db
+            .read()
+            .await
+            .webhooks
+            .get(id)
+            .cloned()
+            .ok_or(Error::NotFound)
+    }
+
+    async fn insert_webhook(&self, webhook: Webhook) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        data.check_principal(&webhook.principal)?;
+        if data.webhooks.contains_key(&webhook.id) {
+            return Err(Error::AlreadyExists);
+        }
+        data.webhooks.insert(
+            webhook.id.clone(),
+            Webhook {
+                created_at: Some(Utc::now().naive_utc()),
+                ..webhook
+            },
+        );
+        Ok(())
+    }
+
+    async fn delete_webhook(&self, id: &str) -> Result<(), Error> {
+        self.db.write().await.remove_webhook(id);
+        Ok(())
+    }
+
+    async fn enqueue_delivery(
+        &self,
+        webhook_id: &str,
+        event: &str,
+        payload: &str,
+    ) -> Result<(), Error> {
+        let mut data = self.db.write().await;
+        if !data.webhooks.contains_key(webhook_id) {
+            return Err(Error::NotFound);
+        }
+        let id = data.next_delivery_id();
+        let now = Utc::now().naive_utc();
+        data.webhook_deliveries.insert(
+            id,
+            WebhookDelivery {
+                id,
+                webhook_id: webhook_id.to_owned(),
+                event: event.to_owned(),
+                payload: payload.to_owned(),
+                state: DeliveryState::Pending,
+                attempts: 0,
+                next_attempt: now,
+                last_status: None,
+                last_error: None,
+                created_at: now,
+            },
+        );
+        Ok(())
+    }
+
+    async fn get_due_deliveries(
+        &self,
+        now: NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<WebhookDelivery>, Error> {
+        let mut deliveries: Vec<WebhookDelivery> = self
+            .db
+            .read()
+            .await
+            .webhook_deliveries
+            .values()
+            .filter(|delivery| {
+                delivery.state == DeliveryState::Pending && delivery.next_attempt <= now
+            })
+            .cloned()
+            .collect();
+        deliveries.sort_by_key(|delivery| delivery.next_attempt);
+        deliveries.truncate(usize::try_from(limit).unwrap_or_default());
+        Ok(deliveries)
+    }
+
+    async fn update_delivery(&self, delivery: &WebhookDelivery) -> Result<(), Error> {
+        if let Some(stored) = self
+            .db
+            .write()
+            .await
+            .webhook_deliveries
+            .get_mut(&delivery.id)
+        {
+            stored.state = delivery.state;
+            stored.attempts = delivery.attempts;
+            stored.next_attempt = delivery.next_attempt;
+            stored.last_status = delivery.last_status;
+            stored.last_error.clone_from(&delivery.last_error);
+        }
+        Ok(())
+    }
+
+    async fn get_deliveries(
+        &self,
+        webhook_id: &str,
+        limit: i64,
+    ) -> Result<Vec<WebhookDelivery>, Error> {
+        Ok(self
+            .db
+            .read()
+            .await
+            .webhook_deliveries
+            .values()
+            .rev()
+            .filter(|delivery| delivery.webhook_id == webhook_id)
+            .take(usize::try_from(limit).unwrap_or_default())
+            .cloned()
+            .collect())
+    }
+
+    async fn prune_deliveries(&self, before: NaiveDateTime) -> Result<u64, Error> {
+        let mut data = self.db.write().await;
+        let len = data.webhook_deliveries.len();
+        data.webhook_deliveries.retain(|_, delivery| {
+            delivery.state == DeliveryState::Pending || delivery.created_at >= before
+        });
+        Ok((len - data.webhook_deliveries.len()) as u64)
+    }
+}
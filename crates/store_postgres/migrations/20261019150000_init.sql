@@ -0,0 +1,218 @@
+-- Timestamps are stored without time zone and are always UTC
+
+CREATE TABLE principals (
+    id TEXT NOT NULL,
+    displayname TEXT,
+    principal_type TEXT NOT NULL,
+    password_hash TEXT,
+    PRIMARY KEY (id)
+);
+
+CREATE TABLE app_tokens (
+    id TEXT NOT NULL,
+    principal TEXT NOT NULL,
+    token TEXT NOT NULL,
+    displayname TEXT NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (id),
+    CONSTRAINT fk_app_token_principal FOREIGN KEY (principal)
+    REFERENCES principals (id) ON DELETE CASCADE
+);
+
+CREATE TABLE memberships (
+    principal TEXT NOT NULL,
+    member_of TEXT NOT NULL,
+    PRIMARY KEY (principal, member_of),
+    CONSTRAINT fk_membership_principal
+    FOREIGN KEY (principal) REFERENCES principals (id) ON DELETE CASCADE,
+    CONSTRAINT fk_membership_member_of
+    FOREIGN KEY (member_of) REFERENCES principals (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_memberships_member_of ON memberships (member_of);
+
+CREATE TABLE calendars (
+    principal TEXT NOT NULL,
+    id TEXT NOT NULL,
+    synctoken BIGINT DEFAULT 0 NOT NULL,
+    displayname TEXT,
+    description TEXT,
+    "order" BIGINT DEFAULT 0 NOT NULL,
+    color TEXT,
+    timezone_id TEXT,
+    deleted_at TIMESTAMP,
+    subscription_url TEXT,
+    push_topic TEXT UNIQUE NOT NULL,
+    comp_event BOOLEAN NOT NULL,
+    comp_todo BOOLEAN NOT NULL,
+    comp_journal BOOLEAN NOT NULL,
+    PRIMARY KEY (principal, id),
+    CONSTRAINT fk_calendar_principal FOREIGN KEY (principal)
+    REFERENCES principals (id) ON DELETE RESTRICT
+);
+
+CREATE TABLE calendarobjects (
+    principal TEXT NOT NULL,
+    cal_id TEXT NOT NULL,
+    id TEXT NOT NULL, -- filename
+    "uid" TEXT NOT NULL, -- global identifier
+    ics TEXT NOT NULL,
+    updated_at TIMESTAMP DEFAULT (now() AT TIME ZONE 'utc'),
+    deleted_at TIMESTAMP,
+
+    -- For more efficient calendar-queries
+    first_occurence DATE,
+    last_occurence DATE,
+    etag TEXT,
+    object_type SMALLINT NOT NULL,  -- VEVENT(0)/VTODO(1)/VJOURNAL(2)
+
+    CONSTRAINT pk_calendarobject_id PRIMARY KEY (principal, cal_id, id),
+    CONSTRAINT uq_calendarobject_uid UNIQUE (principal, cal_id, "uid"),
+    CONSTRAINT fk_calendarobject_calendar FOREIGN KEY (principal, cal_id)
+    REFERENCES calendars (principal, id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_calobjs_first_occurence ON calendarobjects (first_occurence);
+CREATE INDEX idx_calobjs_last_occurence ON calendarobjects (last_occurence);
+CREATE INDEX idx_calobjs_etag ON calendarobjects (etag);
+
+CREATE TABLE calendarobjectchangelog (
+    principal TEXT NOT NULL,
+    cal_id TEXT NOT NULL,
+    object_id TEXT NOT NULL,
+    "operation" INTEGER NOT NULL, -- Add(0)/Delete(1)
+    synctoken BIGINT DEFAULT 0 NOT NULL,
+    created_at TIMESTAMP DEFAULT (now() AT TIME ZONE 'utc') NOT NULL,
+    PRIMARY KEY (principal, cal_id, synctoken, created_at),
+    FOREIGN KEY (principal, cal_id)
+    REFERENCES calendars (principal, id) ON DELETE CASCADE
+);
+
+CREATE TABLE addressbooks (
+    principal TEXT NOT NULL,
+    id TEXT NOT NULL,
+    synctoken BIGINT DEFAULT 0 NOT NULL,
+    displayname TEXT,
+    description TEXT,
+    deleted_at TIMESTAMP,
+    push_topic TEXT UNIQUE NOT NULL,
+    PRIMARY KEY (principal, id),
+    CONSTRAINT fk_addressbook_principal FOREIGN KEY (principal)
+    REFERENCES principals (id) ON DELETE RESTRICT
+);
+
+CREATE TABLE addressobjects (
+    principal TEXT NOT NULL,
+    addressbook_id TEXT NOT NULL,
+    id TEXT NOT NULL,
+    vcf TEXT NOT NULL,
+    updated_at TIMESTAMP DEFAULT (now() AT TIME ZONE 'utc'),
+    deleted_at TIMESTAMP,
+    PRIMARY KEY (principal, addressbook_id, id),
+    FOREIGN KEY (principal, addressbook_id)
+    REFERENCES addressbooks (principal, id) ON DELETE CASCADE
+);
+
+CREATE TABLE addressobjectchangelog (
+    principal TEXT NOT NULL,
+    addressbook_id TEXT NOT NULL,
+    object_id TEXT NOT NULL,
+    "operation" INTEGER NOT NULL, -- Add(0)/Delete(1)
+    synctoken BIGINT DEFAULT 0 NOT NULL,
+    created_at TIMESTAMP DEFAULT (now() AT TIME ZONE 'utc') NOT NULL,
+    PRIMARY KEY (principal, addressbook_id, synctoken, created_at),
+    FOREIGN KEY (principal, addressbook_id)
+    REFERENCES addressbooks (principal, id) ON DELETE CASCADE
+);
+
+CREATE TABLE birthday_calendars (
+    principal TEXT NOT NULL,
+    id TEXT NOT NULL,
+    displayname TEXT,
+    description TEXT,
+    "order" BIGINT DEFAULT 0 NOT NULL,
+    color TEXT,
+    timezone_id TEXT,
+    deleted_at TIMESTAMP,
+    push_topic TEXT NOT NULL,
+    PRIMARY KEY (principal, id),
+    -- birthday calendar stores no meaningful data so we can cascade
+    CONSTRAINT fk_birthdays_addressbooks FOREIGN KEY (principal, id)
+    REFERENCES addressbooks (principal, id) ON DELETE CASCADE
+);
+
+CREATE TABLE davpush_subscriptions (
+    id TEXT NOT NULL,
+    topic TEXT NOT NULL,
+    expiration TIMESTAMP NOT NULL,
+    push_resource TEXT NOT NULL,
+    public_key TEXT NOT NULL,
+    public_key_type TEXT NOT NULL,
+    auth_secret TEXT NOT NULL,
+    PRIMARY KEY (id)
+);
+
+CREATE INDEX idx_davpush_subscriptions_topic ON davpush_subscriptions (topic);
+
+CREATE TABLE davpush_deliveries (
+    id BIGINT GENERATED ALWAYS AS IDENTITY,
+    subscription_id TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    attempts BIGINT DEFAULT 0 NOT NULL,
+    next_attempt TIMESTAMP NOT NULL,
+    -- Remove the subscription once the message is delivered (collection deleted)
+    unsubscribe BOOLEAN DEFAULT FALSE NOT NULL,
+    created_at TIMESTAMP DEFAULT (now() AT TIME ZONE 'utc'),
+    PRIMARY KEY (id),
+    CONSTRAINT fk_delivery_subscription FOREIGN KEY (subscription_id)
+    REFERENCES davpush_subscriptions (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_davpush_deliveries_next_attempt ON davpush_deliveries (next_attempt);
+
+-- There is only ever one VAPID key per server
+CREATE TABLE davpush_vapid_key (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    private_key TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT (now() AT TIME ZONE 'utc')
+);
+
+CREATE TABLE webhooks (
+    id TEXT NOT NULL,
+    principal TEXT NOT NULL,
+    -- Both NULL if the webhook covers all collections of the principal
+    collection_type TEXT,
+    collection_id TEXT,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT (now() AT TIME ZONE 'utc'),
+    PRIMARY KEY (id),
+    CONSTRAINT fk_webhook_principal FOREIGN KEY (principal)
+    REFERENCES principals (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_webhooks_principal ON webhooks (principal);
+
+CREATE TABLE webhook_deliveries (
+    id BIGINT GENERATED ALWAYS AS IDENTITY,
+    webhook_id TEXT NOT NULL,
+    event TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    -- pending, delivered or failed
+    state TEXT DEFAULT 'pending' NOT NULL,
+    attempts BIGINT DEFAULT 0 NOT NULL,
+    next_attempt TIMESTAMP NOT NULL,
+    last_status INTEGER,
+    last_error TEXT,
+    created_at TIMESTAMP DEFAULT (now() AT TIME ZONE 'utc') NOT NULL,
+    PRIMARY KEY (id),
+    CONSTRAINT fk_delivery_webhook FOREIGN KEY (webhook_id)
+    REFERENCES webhooks (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_webhook_deliveries_state ON webhook_deliveries (
+    state, next_attempt
+);
+CREATE INDEX idx_webhook_deliveries_webhook ON webhook_deliveries (
+    webhook_id, created_at
+);
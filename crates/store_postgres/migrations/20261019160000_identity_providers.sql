@@ -0,0 +1,7 @@
+CREATE TABLE identity_providers (
+    principal TEXT NOT NULL,
+    provider TEXT NOT NULL,
+    PRIMARY KEY (principal),
+    CONSTRAINT fk_identity_provider_principal
+    FOREIGN KEY (principal) REFERENCES principals (id) ON DELETE CASCADE
+);
@@ -0,0 +1,9 @@
+CREATE TABLE totp (
+    principal TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    -- Hashes of the unused recovery codes
+    recovery_codes TEXT[] NOT NULL,
+    PRIMARY KEY (principal),
+    CONSTRAINT fk_totp_principal
+    FOREIGN KEY (principal) REFERENCES principals (id) ON DELETE CASCADE
+);
@@ -0,0 +1,10 @@
+CREATE TABLE passkeys (
+    id TEXT NOT NULL,
+    principal TEXT NOT NULL,
+    displayname TEXT NOT NULL,
+    credential TEXT NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (principal, id),
+    CONSTRAINT fk_passkeys_principal FOREIGN KEY (principal)
+    REFERENCES principals (id) ON DELETE CASCADE
+);
@@ -0,0 +1,4 @@
+ALTER TABLE app_tokens ADD COLUMN scope JSONB NOT NULL DEFAULT '{}';
+ALTER TABLE app_tokens ADD COLUMN expires_at TIMESTAMPTZ;
+ALTER TABLE app_tokens ADD COLUMN last_used_at TIMESTAMPTZ;
+ALTER TABLE app_tokens ADD COLUMN last_user_agent TEXT;
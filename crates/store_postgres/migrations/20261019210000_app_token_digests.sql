@@ -0,0 +1,10 @@
+-- HMAC of the token to look it up without verifying every hash
+ALTER TABLE app_tokens ADD COLUMN token_digest TEXT;
+CREATE UNIQUE INDEX app_tokens_token_digest ON app_tokens (principal, token_digest);
+
+-- There is only ever one pepper per server
+CREATE TABLE app_token_pepper (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    pepper TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT (now() AT TIME ZONE 'utc')
+);
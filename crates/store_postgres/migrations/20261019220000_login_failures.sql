@@ -0,0 +1,7 @@
+-- Subjects are principal:<id> or address:<ip>
+CREATE TABLE login_failures (
+    subject TEXT NOT NULL PRIMARY KEY,
+    failures INTEGER NOT NULL,
+    last_failure_at TIMESTAMPTZ NOT NULL,
+    locked_until TIMESTAMPTZ
+);
@@ -0,0 +1,2 @@
+-- Time step of the last accepted code, a code is only accepted once
+ALTER TABLE totp ADD COLUMN last_used_step BIGINT;
@@ -0,0 +1,7 @@
+-- Principals that can't log in anymore but keep their collections
+CREATE TABLE deactivated_principals (
+    principal TEXT NOT NULL,
+    PRIMARY KEY (principal),
+    CONSTRAINT fk_deactivated_principal
+    FOREIGN KEY (principal) REFERENCES principals (id) ON DELETE CASCADE
+);
@@ -0,0 +1,8 @@
+-- Principals used to be linked to the first provider logging in to them.
+-- The ones OIDC created have neither a password nor a passkey and are linked to the single provider.
+INSERT INTO identity_providers (principal, provider)
+SELECT id, 'default' FROM principals
+WHERE principal_type = 'INDIVIDUAL'
+    AND password_hash IS NULL
+    AND id NOT IN (SELECT principal FROM identity_providers)
+    AND id NOT IN (SELECT principal FROM passkeys);
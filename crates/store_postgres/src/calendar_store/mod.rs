@@ -0,0 +1,1125 @@
+use super::ChangeOperation;
+use async_trait::async_trait;
+use caldata::parser::ParserError;
+use caldata::types::CalDateTime;
+use chrono::TimeDelta;
+use derive_more::derive::Constructor;
+use rustical_ical::{CalendarObject, CalendarObjectType};
+use rustical_store::calendar_store::{CalendarQuery, CalendarReadStore, CalendarWriteStore};
+use rustical_store::synctoken::format_synctoken;
+use rustical_store::{
+    Calendar, CalendarMetadata, CalendarStorePruneDeleted, CollectionMetadata, Error,
+};
+use rustical_store::{CollectionRef, Event, EventBus, ObjectChange, ObjectChangeKind};
+use sqlx::types::chrono::NaiveDateTime;
+use sqlx::{Acquire, Executor, PgPool, Postgres, Transaction};
+use tracing::{error, instrument, warn};
+
+#[cfg(all(test, feature = "test-postgres"))]
+#[path = "../../../store_sqlite/src/calendar_store/tests.rs"]
+mod tests;
+
+#[derive(Debug, Clone)]
+struct CalendarObjectRow {
+    id: String,
+    ics: String,
+    uid: String,
+}
+
+impl From<CalendarObjectRow> for (String, Result<CalendarObject, ParserError>) {
+    fn from(row: CalendarObjectRow) -> Self {
+        let result = CalendarObject::from_ics(row.ics).inspect(|object| {
+            if object.get_uid() != row.uid {
+                warn!(
+                    "Calendar object {}.ics: UID={} and row uid={} do not match",
+                    row.id,
+                    object.get_uid(),
+                    row.uid
+                );
+            }
+        });
+
+        (row.id, result)
+    }
+}
+
+impl TryFrom<CalendarObjectRow> for (String, CalendarObject) {
+    type Error = caldata::parser::ParserError;
+
+    fn try_from(row: CalendarObjectRow) -> Result<Self, Self::Error> {
+        let object = CalendarObject::from_ics(row.ics)?;
+        if object.get_uid() != row.uid {
+            warn!(
+                "Calendar object {}.ics: UID={} and row uid={} do not match",
+                row.id,
+                object.get_uid(),
+                row.uid
+            );
+        }
+        Ok((row.id, object))
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct CalendarRow {
+    principal: String,
+    id: String,
+    displayname: Option<String>,
+    order: i64,
+    description: Option<String>,
+    color: Option<String>,
+    timezone_id: Option<String>,
+    deleted_at: Option<NaiveDateTime>,
+    synctoken: i64,
+    subscription_url: Option<String>,
+    push_topic: String,
+    comp_event: bool,
+    comp_todo: bool,
+    comp_journal: bool,
+}
+
+impl From<CalendarRow> for Calendar {
+    fn from(value: CalendarRow) -> Self {
+        let mut components = vec![];
+        if value.comp_event {
+            components.push(CalendarObjectType::Event);
+        }
+        if value.comp_todo {
+            components.push(CalendarObjectType::Todo);
+        }
+        if value.comp_journal {
+            components.push(CalendarObjectType::Journal);
+        }
+        Self {
+            principal: value.principal,
+            id: value.id,
+            meta: CalendarMetadata {
+                displayname: value.displayname,
+                order: value.order,
+                description: value.description,
+                color: value.color,
+            },
+            timezone_id: value.timezone_id,
+            deleted_at: value.deleted_at,
+            synctoken: value.synctoken,
+            subscription_url: value.subscription_url,
+            push_topic: value.push_topic,
+            components,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Constructor)]
+pub struct PostgresCalendarStore {
+    db: PgPool,
+    events: EventBus,
+    skip_broken: bool,
+}
+
+impl PostgresCalendarStore {
+    // Logs an operation to the changelog and returns the new sync token
+    async fn log_object_operation(
+        tx: &mut Transaction<'_, Postgres>,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+        operation: ChangeOperation,
+    ) -> Result<String, Error> {
+        // Also locks the calendar row until the transaction ends,
+        // so concurrent writers get consecutive sync tokens
+        let synctoken = sqlx::query_scalar!(
+            r#"
+        UPDATE calendars
+        SET synctoken = synctoken + 1
+        WHERE (principal, id) = ($1, $2)
+        RETURNING synctoken"#,
+            principal,
+            cal_id
+        )
+        .fetch_one(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+
+        sqlx::query!(
+            r#"
+        INSERT INTO calendarobjectchangelog (principal, cal_id, object_id, "operation", synctoken)
+        VALUES ($1, $2, $3, $4, $5)"#,
+            principal,
+            cal_id,
+            object_id,
+            operation as _,
+            synctoken
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(format_synctoken(synctoken))
+    }
+
+    #[allow(clippy::missing_panics_doc)]
+    pub async fn validate_objects(&self, principal: &str) -> Result<(), Error> {
+        let mut success = true;
+        for calendar in self.get_calendars(principal).await? {
+            for (object_id, res) in Self::_get_objects(&self.db, principal, &calendar.id).await? {
+                if let Err(err) = res {
+                    warn!(
+                        "Invalid calendar object found at {principal}/{cal_id}/{object_id}.ics. Error: {err}",
+                        cal_id = calendar.id
+                    );
+                    success = false;
+                }
+            }
+        }
+        if !success {
+            if self.skip_broken {
+                error!(
+                    "Not all calendar objects are valid. Since data_store.postgres.skip_broken=true they will be hidden. You are still advised to manually remove or repair the object. If you need help feel free to open up an issue on GitHub."
+                );
+            } else {
+                error!(
+                    "Not all calendar objects are valid. Since data_store.postgres.skip_broken=false this causes a panic. Remove or repair the broken objects manually or set data_store.postgres.skip_broken=true as a temporary solution to ignore the error. If you need help feel free to open up an issue on GitHub."
+                );
+                panic!();
+            }
+        }
+        Ok(())
+    }
+
+    async fn _get_calendar<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        id: &str,
+        show_deleted: bool,
+    ) -> Result<Calendar, Error> {
+        let cal = sqlx::query_as!(
+            CalendarRow,
+            r#"SELECT principal, id, displayname, "order", description, color, timezone_id, deleted_at, synctoken, subscription_url, push_topic, comp_event, comp_todo, comp_journal
+                FROM calendars
+                WHERE (principal, id) = ($1, $2)
+                AND ((deleted_at IS NULL) OR $3)"#,
+            principal,
+            id,
+            show_deleted
+        )
+        .fetch_one(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(cal.into())
+    }
+
+    async fn _get_calendars<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+    ) -> Result<Vec<Calendar>, Error> {
+        let cals = sqlx::query_as!(
+            CalendarRow,
+            r#"SELECT principal, id, displayname, "order", description, color, timezone_id, deleted_at, synctoken, subscription_url, push_topic, comp_event, comp_todo, comp_journal
+                FROM calendars
+                WHERE principal = $1 AND deleted_at IS NULL"#,
+            principal
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(cals.into_iter().map(Calendar::from).collect())
+    }
+
+    async fn _get_deleted_calendars<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+    ) -> Result<Vec<Calendar>, Error> {
+        let cals = sqlx::query_as!(
+            CalendarRow,
+            r#"SELECT principal, id, displayname, "order", description, color, timezone_id, deleted_at, synctoken, subscription_url, push_topic, comp_event, comp_todo, comp_journal
+                FROM calendars
+                WHERE principal = $1 AND deleted_at IS NOT NULL"#,
+            principal
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(cals.into_iter().map(Calendar::from).collect())
+    }
+
+    async fn _insert_calendar<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        calendar: Calendar,
+    ) -> Result<(), Error> {
+        let comp_event = calendar.components.contains(&CalendarObjectType::Event);
+        let comp_todo = calendar.components.contains(&CalendarObjectType::Todo);
+        let comp_journal = calendar.components.contains(&CalendarObjectType::Journal);
+
+        sqlx::query!(
+            r#"INSERT INTO calendars (principal, id, displayname, description, "order", color, subscription_url, timezone_id, push_topic, comp_event, comp_todo, comp_journal, synctoken)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)"#,
+            calendar.principal,
+            calendar.id,
+            calendar.meta.displayname,
+            calendar.meta.description,
+            calendar.meta.order,
+            calendar.meta.color,
+            calendar.subscription_url,
+            calendar.timezone_id,
+            calendar.push_topic,
+            comp_event, comp_todo, comp_journal,
+            calendar.synctoken
+        )
+        .execute(executor)
+        .await.map_err(crate::Error::from)?;
+
+        Ok(())
+    }
+
+    async fn _update_calendar<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        id: &str,
+        calendar: Calendar,
+    ) -> Result<(), Error> {
+        let comp_event = calendar.components.contains(&CalendarObjectType::Event);
+        let comp_todo = calendar.components.contains(&CalendarObjectType::Todo);
+        let comp_journal = calendar.components.contains(&CalendarObjectType::Journal);
+
+        let result = sqlx::query!(
+            r#"UPDATE calendars SET principal = $1, id = $2, displayname = $3, description = $4, "order" = $5, color = $6, timezone_id = $7, push_topic = $8, comp_event = $9, comp_todo = $10, comp_journal = $11
+                WHERE (principal, id) = ($12, $13)"#,
+            calendar.principal,
+            calendar.id,
+            calendar.meta.displayname,
+            calendar.meta.description,
+            calendar.meta.order,
+            calendar.meta.color,
+            calendar.timezone_id,
+            calendar.push_topic,
+            comp_event, comp_todo, comp_journal,
+            principal,
+            id
+        ).execute(executor).await.map_err(crate::Error::from)?;
+        if result.rows_affected() == 0 {
+            return Err(rustical_store::Error::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn _delete_calendar<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        if use_trashbin {
+            sqlx::query!(
+                r#"UPDATE calendars SET deleted_at = (now() AT TIME ZONE 'utc') WHERE (principal, id) = ($1, $2)"#,
+                principal,
+                id
+            )
+            .execute(executor)
+            .await
+            .map_err(crate::Error::from)?
+        } else {
+            sqlx::query!(
+                r#"DELETE FROM calendars WHERE (principal, id) = ($1, $2)"#,
+                principal,
+                id
+            )
+            .execute(executor)
+            .await
+            .map_err(crate::Error::from)?
+        };
+        Ok(())
+    }
+
+    async fn _restore_calendar<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        id: &str,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            r"UPDATE calendars SET deleted_at = NULL WHERE (principal, id) = ($1, $2)",
+            principal,
+            id
+        )
+        .execute(executor)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn _prune_deleted_calendars<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        before: chrono::NaiveDate,
+    ) -> Result<u64, Error> {
+        sqlx::query!(
+            r"DELETE FROM calendars WHERE deleted_at IS NOT NULL AND deleted_at::date < $1",
+            before,
+        )
+        .execute(executor)
+        .await
+        .map(|result| result.rows_affected())
+        .map_err(crate::Error::from)
+        .map_err(Into::into)
+    }
+
+    async fn _list_objects<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<(u64, bool)>, rustical_store::Error> {
+        struct ObjectEntry {
+            length: i32,
+            deleted: bool,
+        }
+        Ok(sqlx::query_as!(
+            ObjectEntry,
+            r#"SELECT length(ics) AS "length!", (deleted_at IS NOT NULL) AS "deleted!" FROM calendarobjects WHERE principal = $1 AND cal_id = $2"#,
+            principal,
+            cal_id
+        )
+        .fetch_all(executor)
+        .await.map_err(crate::Error::from)?
+        .into_iter()
+        .map(|row| (u64::from(row.length.unsigned_abs()), row.deleted))
+        .collect())
+    }
+
+    async fn _get_objects<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<impl Iterator<Item = (String, Result<CalendarObject, ParserError>)>, Error> {
+        Ok(sqlx::query_as!(
+            CalendarObjectRow,
+            "SELECT id, uid, ics FROM calendarobjects WHERE principal = $1 AND cal_id = $2 AND deleted_at IS NULL",
+            principal,
+            cal_id
+        )
+        .fetch_all(executor)
+        .await.map_err(crate::Error::from)?
+        .into_iter()
+        .map(Into::into)
+        )
+    }
+
+    async fn _get_deleted_objects<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<impl Iterator<Item = (String, Result<CalendarObject, ParserError>)>, Error> {
+        Ok(sqlx::query_as!(
+            CalendarObjectRow,
+            "SELECT id, uid, ics FROM calendarobjects WHERE principal = $1 AND cal_id = $2 AND deleted_at IS NOT NULL",
+            principal,
+            cal_id
+        )
+        .fetch_all(executor)
+        .await.map_err(crate::Error::from)?
+        .into_iter()
+        .map(Into::into)
+        )
+    }
+
+    async fn _calendar_query<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+        query: CalendarQuery,
+    ) -> Result<impl Iterator<Item = (String, Result<CalendarObject, ParserError>)>, Error> {
+        // We extend our query interval by one day in each direction since we really don't want to
+        // miss any objects because of timezone differences
+        let start = query.time_start.map(|start| start - TimeDelta::days(1));
+        let end = query.time_end.map(|end| end + TimeDelta::days(1));
+
+        Ok(sqlx::query_as!(
+            CalendarObjectRow,
+            r"SELECT id, uid, ics FROM calendarobjects
+                WHERE principal = $1 AND cal_id = $2 AND deleted_at IS NULL
+                    AND (last_occurence IS NULL OR $3::date IS NULL OR last_occurence >= $3)
+                    AND (first_occurence IS NULL OR $4::date IS NULL OR first_occurence <= $4)
+            ",
+            principal,
+            cal_id,
+            start,
+            end,
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .into_iter()
+        .map(Into::into))
+    }
+
+    async fn _object_exists<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<bool, Error> {
+        Ok(sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM calendarobjects WHERE (principal, cal_id, id) = ($1, $2, $3) AND deleted_at IS NULL) AS "exists!""#,
+            principal,
+            cal_id,
+            object_id
+        )
+        .fetch_one(executor)
+        .await
+        .map_err(crate::Error::from)?)
+    }
+
+    async fn _get_object<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+        show_deleted: bool,
+    ) -> Result<CalendarObject, Error> {
+        let (row_id, object) = sqlx::query_as!(
+            CalendarObjectRow,
+            "SELECT id, uid, ics FROM calendarobjects WHERE (principal, cal_id, id) = ($1, $2, $3) AND ((deleted_at IS NULL) OR $4)",
+            principal,
+            cal_id,
+            object_id,
+            show_deleted
+        )
+        .fetch_one(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .try_into()?;
+        assert_eq!(object_id, row_id);
+        Ok(object)
+    }
+
+    #[instrument]
+    async fn _put_object<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+        object: &CalendarObject,
+        overwrite: bool,
+    ) -> Result<(), Error> {
+        let (uid, ics) = (object.get_uid(), object.get_ics());
+
+        let first_occurence = object
+            .get_inner()
+            .get_inner()
+            .get_first_occurence()
+            .as_ref()
+            .map(CalDateTime::date_floor);
+        let last_occurence = object
+            .get_inner()
+            .get_inner()
+            .get_last_occurence()
+            .as_ref()
+            .map(CalDateTime::date_ceil);
+        let etag = object.get_etag();
+        let object_type = object.get_object_type() as i16;
+
+        (if overwrite {
+            // Overwriting also revives a trashed object
+            sqlx::query!(
+                r#"INSERT INTO calendarobjects (principal, cal_id, id, uid, ics, first_occurence, last_occurence, etag, object_type) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                    ON CONFLICT (principal, cal_id, id) DO UPDATE SET
+                        (uid, ics, first_occurence, last_occurence, etag, object_type, updated_at, deleted_at)
+                        = (excluded.uid, excluded.ics, excluded.first_occurence, excluded.last_occurence, excluded.etag, excluded.object_type, now() AT TIME ZONE 'utc', NULL)"#,
+                principal,
+                cal_id,
+                object_id,
+                uid,
+                ics,
+                first_occurence,
+                last_occurence,
+                etag,
+                object_type,
+            )
+        } else {
+            // If the object already exists a database error is thrown and handled in error.rs
+            sqlx::query!(
+                "INSERT INTO calendarobjects (principal, cal_id, id, uid, ics, first_occurence, last_occurence, etag, object_type) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+                principal,
+                cal_id,
+                object_id,
+                uid,
+                ics,
+                first_occurence,
+                last_occurence,
+                etag,
+                object_type,
+            )
+        })
+        .execute(executor)
+        .await
+        .map_err(crate::Error::from)?;
+
+        Ok(())
+    }
+
+    /// Id of another object with the same UID
+    async fn _get_uid_conflict<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+        uid: &str,
+        object_id: &str,
+    ) -> Result<Option<String>, Error> {
+        Ok(sqlx::query_scalar!(
+            "SELECT id FROM calendarobjects WHERE (principal, cal_id, uid) = ($1, $2, $3) AND id != $4",
+            principal,
+            cal_id,
+            uid,
+            object_id
+        )
+        .fetch_optional(executor)
+        .await
+        .map_err(crate::Error::from)?)
+    }
+
+    #[instrument]
+    async fn _delete_object<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        if use_trashbin {
+            sqlx::query!(
+                "UPDATE calendarobjects SET deleted_at = (now() AT TIME ZONE 'utc'), updated_at = (now() AT TIME ZONE 'utc') WHERE (principal, cal_id, id) = ($1, $2, $3)",
+                principal,
+                cal_id,
+                object_id
+            )
+            .execute(executor)
+            .await.map_err(crate::Error::from)?;
+        } else {
+            sqlx::query!(
+                "DELETE FROM calendarobjects WHERE (principal, cal_id, id) = ($1, $2, $3)",
+                principal,
+                cal_id,
+                object_id
+            )
+            .execute(executor)
+            .await
+            .map_err(crate::Error::from)?;
+        }
+        Ok(())
+    }
+
+    #[instrument]
+    async fn _restore_object<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            r#"UPDATE calendarobjects SET deleted_at = NULL, updated_at = (now() AT TIME ZONE 'utc') WHERE (principal, cal_id, id) = ($1, $2, $3)"#,
+            principal,
+            cal_id,
+            object_id
+        )
+        .execute(executor)
+        .await.map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn _sync_changes<'a, A: Acquire<'a, Database = Postgres>>(
+        acquire: A,
+        principal: &str,
+        cal_id: &str,
+        synctoken: i64,
+        skip_broken: bool,
+    ) -> Result<(Vec<(String, CalendarObject)>, Vec<String>, i64), Error> {
+        let mut conn = acquire.acquire().await.map_err(crate::Error::from)?;
+
+        // The latest change of every object since the given sync token
+        let changes = sqlx::query_scalar!(
+            r#"
+                SELECT object_id AS "object_id!"
+                FROM (
+                    SELECT
+                        object_id,
+                        synctoken,
+                        ROW_NUMBER() OVER (PARTITION BY object_id ORDER BY synctoken DESC) as rn
+                    FROM calendarobjectchangelog
+                    WHERE (principal, cal_id) = ($1, $2)
+                    AND synctoken > $3
+                ) AS changes
+                WHERE rn = 1
+                ORDER BY synctoken ASC
+            "#,
+            principal,
+            cal_id,
+            synctoken
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(crate::Error::from)?;
+
+        let calendar = Self::_get_calendar(&mut *conn, principal, cal_id, false).await?;
+
+        let mut updated_objects = vec![];
+        let mut deleted_objects = vec![];
+
+        for object_id in changes {
+            match Self::_get_object(&mut *conn, principal, cal_id, &object_id, false).await {
+                Ok(object) => updated_objects.push((object_id, object)),
+                Err(rustical_store::Error::NotFound) => deleted_objects.push(object_id),
+                // Skip broken object
+                Err(rustical_store::Error::IcalError(_)) if skip_broken => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok((updated_objects, deleted_objects, calendar.synctoken))
+    }
+
+    async fn _prune_deleted_objects<'e, E: Executor<'e, Database = Postgres>>(
+        executor: E,
+        before: chrono::NaiveDate,
+    ) -> Result<u64, Error> {
+        sqlx::query!(
+            r"DELETE FROM calendarobjects WHERE deleted_at IS NOT NULL AND deleted_at::date < $1",
+            before,
+        )
+        .execute(executor)
+        .await
+        .map(|result| result.rows_affected())
+        .map_err(crate::Error::from)
+        .map_err(Into::into)
+    }
+}
+
+#[async_trait]
+impl CalendarReadStore for PostgresCalendarStore {
+    #[instrument]
+    async fn get_calendar(
+        &self,
+        principal: &str,
+        id: &str,
+        show_deleted: bool,
+    ) -> Result<Calendar, Error> {
+        Self::_get_calendar(&self.db, principal, id, show_deleted).await
+    }
+
+    #[instrument]
+    async fn get_calendars(&self, principal: &str) -> Result<Vec<Calendar>, Error> {
+        Self::_get_calendars(&self.db, principal).await
+    }
+
+    #[instrument]
+    async fn get_deleted_calendars(&self, principal: &str) -> Result<Vec<Calendar>, Error> {
+        Self::_get_deleted_calendars(&self.db, principal).await
+    }
+
+    #[instrument]
+    async fn calendar_query(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        query: CalendarQuery,
+    ) -> Result<Vec<(String, CalendarObject)>, Error> {
+        let objects = Self::_calendar_query(&self.db, principal, cal_id, query).await?;
+        if self.skip_broken {
+            Ok(objects
+                .filter_map(|(id, res)| Some((id, res.ok()?)))
+                .collect())
+        } else {
+            Ok(objects
+                .map(|(id, res)| res.map(|obj| (id, obj)))
+                .collect::<Result<Vec<_>, _>>()?)
+        }
+    }
+
+    async fn calendar_metadata(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<CollectionMetadata, Error> {
+        let mut sizes = vec![];
+        let mut deleted_sizes = vec![];
+        for (size, deleted) in Self::_list_objects(&self.db, principal, cal_id).await? {
+            if deleted {
+                deleted_sizes.push(size);
+            } else {
+                sizes.push(size);
+            }
+        }
+        Ok(CollectionMetadata {
+            len: sizes.len(),
+            deleted_len: deleted_sizes.len(),
+            size: sizes.iter().sum(),
+            deleted_size: deleted_sizes.iter().sum(),
+        })
+    }
+
+    #[instrument]
+    async fn get_objects(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<(String, CalendarObject)>, Error> {
+        let objects = Self::_get_objects(&self.db, principal, cal_id).await?;
+        if self.skip_broken {
+            Ok(objects
+                .filter_map(|(id, res)| Some((id, res.ok()?)))
+                .collect())
+        } else {
+            Ok(objects
+                .map(|(id, res)| res.map(|obj| (id, obj)))
+                .collect::<Result<Vec<_>, _>>()?)
+        }
+    }
+
+    #[instrument]
+    async fn get_deleted_objects(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<(String, CalendarObject)>, Error> {
+        let objects = Self::_get_deleted_objects(&self.db, principal, cal_id).await?;
+        if self.skip_broken {
+            Ok(objects
+                .filter_map(|(id, res)| Some((id, res.ok()?)))
+                .collect())
+        } else {
+            Ok(objects
+                .map(|(id, res)| res.map(|obj| (id, obj)))
+                .collect::<Result<Vec<_>, _>>()?)
+        }
+    }
+
+    #[instrument]
+    async fn get_object(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+        show_deleted: bool,
+    ) -> Result<CalendarObject, Error> {
+        Self::_get_object(&self.db, principal, cal_id, object_id, show_deleted).await
+    }
+
+    #[instrument]
+    async fn sync_changes(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        synctoken: i64,
+    ) -> Result<(Vec<(String, CalendarObject)>, Vec<String>, i64), Error> {
+        Self::_sync_changes(&self.db, principal, cal_id, synctoken, self.skip_broken).await
+    }
+
+    fn is_read_only(&self, _cal_id: &str) -> bool {
+        false
+    }
+}
+
+#[async_trait]
+impl CalendarWriteStore for PostgresCalendarStore {
+    #[instrument]
+    async fn insert_calendar(&self, calendar: Calendar) -> Result<(), Error> {
+        let collection = CollectionRef::from(&calendar);
+        Self::_insert_calendar(&self.db, calendar).await?;
+        self.events.publish(Event::CollectionCreated(collection));
+        Ok(())
+    }
+
+    #[instrument]
+    async fn update_calendar(
+        &self,
+        principal: &str,
+        id: &str,
+        calendar: Calendar,
+    ) -> Result<(), Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+
+        let old_cal = Self::_get_calendar(&mut *tx, principal, id, true).await?;
+        let props = old_cal.changed_properties(&calendar);
+        let event = (!props.is_empty()).then(|| Event::CollectionUpdated {
+            collection: CollectionRef::from(&calendar),
+            props,
+        });
+
+        Self::_update_calendar(&mut *tx, principal, id, calendar).await?;
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        if let Some(event) = event {
+            self.events.publish(event);
+        }
+        Ok(())
+    }
+
+    // Does not actually delete the calendar but just disables it
+    #[instrument]
+    async fn delete_calendar(
+        &self,
+        principal: &str,
+        id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+
+        let cal = match Self::_get_calendar(&mut *tx, principal, id, true).await {
+            Ok(cal) => Some(cal),
+            Err(Error::NotFound) => None,
+            Err(err) => return Err(err),
+        };
+
+        Self::_delete_calendar(&mut *tx, principal, id, use_trashbin).await?;
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        if let Some(cal) = cal {
+            self.events.publish(Event::CollectionDeleted {
+                collection: CollectionRef::from(&cal),
+                trashed: use_trashbin,
+            });
+        }
+        Ok(())
+    }
+
+    #[instrument]
+    async fn restore_calendar(&self, principal: &str, id: &str) -> Result<(), Error> {
+        Self::_restore_calendar(&self.db, principal, id).await?;
+        let calendar = self.get_calendar(principal, id, false).await?;
+        self.events
+            .publish(Event::CollectionRestored(CollectionRef::from(&calendar)));
+        Ok(())
+    }
+
+    #[instrument]
+    async fn import_calendar(
+        &self,
+        calendar: Calendar,
+        objects: Vec<CalendarObject>,
+        merge_existing: bool,
+    ) -> Result<(), Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+
+        let existing_cal =
+            match Self::_get_calendar(&mut *tx, &calendar.principal, &calendar.id, true).await {
+                Ok(cal) => Some(cal),
+                Err(Error::NotFound) => None,
+                Err(err) => return Err(err),
+            };
+        if existing_cal.is_some() && !merge_existing {
+            return Err(Error::AlreadyExists);
+        }
+        if existing_cal.is_none() {
+            Self::_insert_calendar(&mut *tx, calendar.clone()).await?;
+        }
+
+        let mut sync_token = None;
+        let mut changes = vec![];
+        for object in objects {
+            let object_id = object.get_uid();
+            changes.push(ObjectChange {
+                id: object_id.to_owned(),
+                etag: Some(object.get_etag()),
+                kind: ObjectChangeKind::Created,
+            });
+            Self::_put_object(
+                &mut *tx,
+                &calendar.principal,
+                &calendar.id,
+                object_id,
+                &object,
+                false,
+            )
+            .await?;
+
+            sync_token = Some(
+                Self::log_object_operation(
+                    &mut tx,
+                    &calendar.principal,
+                    &calendar.id,
+                    object_id,
+                    ChangeOperation::Add,
+                )
+                .await?,
+            );
+        }
+
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        let collection = CollectionRef::from(
+            &self
+                .get_calendar(&calendar.principal, &calendar.id, true)
+                .await?,
+        );
+        if existing_cal.is_none() {
+            self.events
+                .publish(Event::CollectionCreated(collection.clone()));
+        }
+        if let Some(sync_token) = sync_token {
+            self.events.publish(Event::ObjectsChanged {
+                collection,
+                sync_token,
+                changes,
+            });
+        }
+        Ok(())
+    }
+
+    #[instrument]
+    async fn put_objects(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        objects: Vec<(String, CalendarObject)>,
+        overwrite: bool,
+    ) -> Result<(), Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+
+        let calendar = Self::_get_calendar(&mut *tx, principal, cal_id, true).await?;
+        if calendar.subscription_url.is_some() {
+            // We cannot commit an object to a subscription calendar
+            return Err(Error::ReadOnly);
+        }
+
+        let mut sync_token = None;
+        let mut changes = vec![];
+        for (object_id, object) in objects {
+            // Like REPLACE in SQLite an object with the same UID gets replaced as well,
+            // which clients have to learn about through the change log
+            if overwrite
+                && let Some(conflict_id) = Self::_get_uid_conflict(
+                    &mut *tx,
+                    principal,
+                    cal_id,
+                    object.get_uid(),
+                    &object_id,
+                )
+                .await?
+            {
+                Self::_delete_object(&mut *tx, principal, cal_id, &conflict_id, false).await?;
+                sync_token = Some(
+                    Self::log_object_operation(
+                        &mut tx,
+                        principal,
+                        cal_id,
+                        &conflict_id,
+                        ChangeOperation::Delete,
+                    )
+                    .await?,
+                );
+                changes.push(ObjectChange {
+                    id: conflict_id,
+                    etag: None,
+                    kind: ObjectChangeKind::Deleted,
+                });
+            }
+            let kind = if overwrite
+                && Self::_object_exists(&mut *tx, principal, cal_id, &object_id).await?
+            {
+                ObjectChangeKind::Updated
+            } else {
+                ObjectChangeKind::Created
+            };
+            sync_token = Some(
+                Self::log_object_operation(
+                    &mut tx,
+                    principal,
+                    cal_id,
+                    &object_id,
+                    ChangeOperation::Add,
+                )
+                .await?,
+            );
+            Self::_put_object(&mut *tx, principal, cal_id, &object_id, &object, overwrite).await?;
+            changes.push(ObjectChange {
+                id: object_id,
+                etag: Some(object.get_etag()),
+                kind,
+            });
+        }
+
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        if let Some(sync_token) = sync_token {
+            self.events.publish(Event::ObjectsChanged {
+                collection: CollectionRef::from(&calendar),
+                sync_token,
+                changes,
+            });
+        }
+        Ok(())
+    }
+
+    #[instrument]
+    async fn delete_object(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+
+        Self::_delete_object(&mut *tx, principal, cal_id, id, use_trashbin).await?;
+
+        let sync_token =
+            Self::log_object_operation(&mut tx, principal, cal_id, id, ChangeOperation::Delete)
+                .await?;
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        self.events.publish(Event::ObjectsChanged {
+            collection: CollectionRef::from(&self.get_calendar(principal, cal_id, true).await?),
+            sync_token,
+            changes: vec![ObjectChange {
+                id: id.to_owned(),
+                etag: None,
+                kind: ObjectChangeKind::Deleted,
+            }],
+        });
+
+        Ok(())
+    }
+
+    #[instrument]
+    async fn restore_object(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<(), Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+
+        Self::_restore_object(&mut *tx, principal, cal_id, object_id).await?;
+        let etag = Self::_get_object(&mut *tx, principal, cal_id, object_id, false)
+            .await?
+            .get_etag();
+
+        let sync_token =
+            Self::log_object_operation(&mut tx, principal, cal_id, object_id, ChangeOperation::Add)
+                .await?;
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        self.events.publish(Event::ObjectsChanged {
+            collection: CollectionRef::from(&self.get_calendar(principal, cal_id, true).await?),
+            sync_token,
+            changes: vec![ObjectChange {
+                id: object_id.to_owned(),
+                etag: Some(etag),
+                kind: ObjectChangeKind::Created,
+            }],
+        });
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CalendarStorePruneDeleted for PostgresCalendarStore {
+    #[instrument(skip(self), fields(count = tracing::field::Empty))]
+    async fn prune_deleted_calendars(&self, before: chrono::NaiveDate) -> Result<(), Error> {
+        let count = Self::_prune_deleted_calendars(&self.db, before).await?;
+        tracing::Span::current().record("count", count);
+        Ok(())
+    }
+
+    #[instrument(skip(self), fields(count = tracing::field::Empty))]
+    async fn prune_deleted_objects(&self, before: chrono::NaiveDate) -> Result<(), Error> {
+        let count = Self::_prune_deleted_objects(&self.db, before).await?;
+        tracing::Span::current().record("count", count);
+        Ok(())
+    }
+}
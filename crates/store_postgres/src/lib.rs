@@ -0,0 +1,53 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+#![allow(clippy::missing_errors_doc)]
+pub use error::Error;
+use serde::Serialize;
+use sqlx::{PgPool, postgres::PgPoolOptions};
+use tracing::info;
+pub mod addressbook_store;
+pub mod calendar_store;
+pub mod error;
+pub mod principal_store;
+pub mod session_store;
+pub mod subscription_store;
+pub mod webhook_store;
+
+#[cfg(all(test, feature = "test-postgres"))]
+mod tests;
+
+#[derive(Debug, Clone, Serialize, sqlx::Type)]
+#[serde(rename_all = "kebab-case")]
+#[repr(i32)]
+pub(crate) enum ChangeOperation {
+    // There's no distinction between Add and Modify
+    Add = 0,
+    Delete = 1,
+}
+
+#[derive(Debug, Clone)]
+pub struct PostgresStore {
+    db: PgPool,
+}
+
+impl PostgresStore {
+    #[must_use]
+    pub const fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+}
+
+pub async fn create_db_pool(
+    db_url: &str,
+    max_connections: u32,
+    migrate: bool,
+) -> Result<PgPool, sqlx::Error> {
+    let db = PgPoolOptions::new()
+        .max_connections(max_connections)
+        .connect(db_url)
+        .await?;
+    if migrate {
+        info!("Running database migrations");
+        sqlx::migrate!("./migrations").run(&db).await?;
+    }
+    Ok(db)
+}
@@ -0,0 +1,989 @@
+use crate::addressbook_store::birthday_calendar::BIRTHDAYS_PREFIX;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use password_hash::{CustomizedPasswordHasher, phc::Salt};
+use pbkdf2::Params;
+use rand::rngs::SysRng;
+use rustical_store::{
+    CollectionRef, CollectionType, Error, Event, EventBus, Secret,
+    auth::{
+        AppToken, AppTokenScope, AuthenticationProvider, LoginFailureStore, LoginFailures,
+        LoginSubject, PasskeyCredential, Principal, SecondFactorStore, Totp, app_token_digest,
+        load_or_generate_app_token_pepper,
+    },
+};
+use sqlx::{PgPool, Postgres, Transaction, types::Json};
+use std::sync::{Arc, OnceLock};
+use tracing::instrument;
+
+#[derive(Debug, Clone)]
+struct AppTokenRow {
+    id: String,
+    name: String,
+    token: String,
+    created_at: Option<DateTime<Utc>>,
+    scope: Json<AppTokenScope>,
+    expires_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    last_user_agent: Option<String>,
+    digest: Option<String>,
+}
+
+impl From<AppTokenRow> for AppToken {
+    fn from(value: AppTokenRow) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            token: Secret::from(value.token),
+            created_at: value.created_at,
+            scope: value.scope.0,
+            expires_at: value.expires_at,
+            last_used_at: value.last_used_at,
+            last_user_agent: value.last_user_agent,
+            digest: value.digest,
+        }
+    }
+}
+
+fn encode_scope(scope: &AppTokenScope) -> Result<serde_json::Value, Error> {
+    serde_json::to_value(scope).map_err(|err| Error::Other(err.into()))
+}
+
+#[derive(Debug, Clone)]
+struct LoginFailuresRow {
+    subject: String,
+    failures: i32,
+    last_failure_at: DateTime<Utc>,
+    locked_until: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<LoginFailuresRow> for LoginFailures {
+    type Error = Error;
+
+    fn try_from(value: LoginFailuresRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            subject: value.subject.parse()?,
+            count: value.failures.try_into().unwrap_or_default(),
+            last_failure_at: value.last_failure_at,
+            locked_until: value.locked_until,
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct PrincipalRow {
+    id: String,
+    displayname: Option<String>,
+    principal_type: String,
+    password_hash: Option<String>,
+    memberships: Vec<String>,
+}
+
+impl TryFrom<PrincipalRow> for Principal {
+    type Error = Error;
+
+    fn try_from(value: PrincipalRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: value.id,
+            displayname: value.displayname,
+            password: value.password_hash.map(Secret::from),
+            principal_type: value.principal_type.as_str().try_into()?,
+            memberships: value.memberships,
+            app_token: None,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PostgresPrincipalStore {
+    db: PgPool,
+    events: EventBus,
+    // The pepper never changes once it is stored
+    app_token_pepper: Arc<OnceLock<String>>,
+}
+
+impl PostgresPrincipalStore {
+    #[must_use]
+    pub fn new(db: PgPool, events: EventBus) -> Self {
+        Self {
+            db,
+            events,
+            app_token_pepper: Arc::default(),
+        }
+    }
+
+    // The calendars, addressbooks and birthday calendars of a principal, including trashed ones
+    async fn collection_refs(
+        tx: &mut Transaction<'_, Postgres>,
+        principal: &str,
+    ) -> Result<Vec<CollectionRef>, Error> {
+        let calendars = sqlx::query!(
+            r#"SELECT id, push_topic FROM calendars WHERE principal = $1 FOR UPDATE"#,
+            principal
+        )
+        .fetch_all(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+        let addressbooks = sqlx::query!(
+            r#"SELECT id, push_topic FROM addressbooks WHERE principal = $1 FOR UPDATE"#,
+            principal
+        )
+        .fetch_all(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+        let birthday_calendars = sqlx::query!(
+            r#"SELECT id, push_topic FROM birthday_calendars WHERE principal = $1 FOR UPDATE"#,
+            principal
+        )
+        .fetch_all(&mut **tx)
+        .await
+        .map_err(crate::Error::from)?;
+
+        let collection_ref = |collection_type, id, push_topic| CollectionRef {
+            principal: principal.to_owned(),
+            collection_type,
+            id,
+            push_topic,
+        };
+        Ok(calendars
+            .into_iter()
+            .map(|row| collection_ref(CollectionType::Calendar, row.id, row.push_topic))
+            .chain(
+                addressbooks
+                    .into_iter()
+                    .map(|row| collection_ref(CollectionType::Addressbook, row.id, row.push_topic)),
+            )
+            .chain(birthday_calendars.into_iter().map(|row| {
+                collection_ref(
+                    CollectionType::Calendar,
+                    format!("{BIRTHDAYS_PREFIX}{}", row.id),
+                    row.push_topic,
+                )
+            }))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl AuthenticationProvider for PostgresPrincipalStore {
+    #[instrument]
+    async fn get_principals(&self) -> Result<Vec<Principal>, Error> {
+        let result: Result<Vec<Principal>, Error> = sqlx::query_as!(
+            PrincipalRow,
+            r#"
+            SELECT id, displayname, principal_type, password_hash, array_remove(array_agg(member_of), NULL) AS "memberships!"
+            FROM principals
+            LEFT JOIN memberships ON principals.id = memberships.principal
+            GROUP BY principals.id
+        "#,
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(crate::Error::from)?
+        .into_iter()
+        .map(Principal::try_from)
+        .collect();
+        Ok(result?)
+    }
+
+    #[instrument]
+    async fn get_principal(&self, id: &str) -> Result<Option<Principal>, Error> {
+        let row= sqlx::query_as!(
+            PrincipalRow,
+            r#"
+            SELECT id, displayname, principal_type, password_hash, array_remove(array_agg(member_of), NULL) AS "memberships!"
+            FROM principals
+            LEFT JOIN memberships ON principals.id = memberships.principal
+            WHERE principals.id = $1
+            GROUP BY principals.id
+        "#,
+            id
+        )
+            .fetch_optional(&self.db)
+            .await
+            .map_err(crate::Error::from)?
+            .map(Principal::try_from);
+        if let Some(row) = row {
+            Ok(Some(row?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    #[instrument]
+    async fn remove_principal(&self, id: &str) -> Result<(), Error> {
+        let result = sqlx::query!(r#"DELETE FROM principals WHERE id = $1"#, id)
+            .execute(&self.db)
+            .await
+            .map_err(crate::Error::from)?;
+        if result.rows_affected() > 0 {
+            self.events.publish(Event::PrincipalDeleted(id.to_owned()));
+        }
+        Ok(())
+    }
+
+    #[instrument]
+    async fn purge_principal(&self, id: &str) -> Result<(), Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+        let collections = Self::collection_refs(&mut tx, id).await?;
+
+        // The pending deliveries cascade
+        sqlx::query!(
+            r#"DELETE FROM davpush_subscriptions WHERE topic IN (
+                SELECT push_topic FROM calendars WHERE principal = $1
+                UNION SELECT push_topic FROM addressbooks WHERE principal = $1
+                UNION SELECT push_topic FROM birthday_calendars WHERE principal = $1
+            )"#,
+            id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(crate::Error::from)?;
+        // Objects, change logs and birthday calendars cascade
+        sqlx::query!(r#"DELETE FROM calendars WHERE principal = $1"#, id)
+            .execute(&mut *tx)
+            .await
+            .map_err(crate::Error::from)?;
+        sqlx::query!(r#"DELETE FROM addressbooks WHERE principal = $1"#, id)
+            .execute(&mut *tx)
+            .await
+            .map_err(crate::Error::from)?;
+        sqlx::query!(r#"DELETE FROM sessions WHERE principal = $1"#, id)
+            .execute(&mut *tx)
+            .await
+            .map_err(crate::Error::from)?;
+        // App tokens, memberships and webhooks cascade
+        let result = sqlx::query!(r#"DELETE FROM principals WHERE id = $1"#, id)
+            .execute(&mut *tx)
+            .await
+            .map_err(crate::Error::from)?;
+        if result.rows_affected() == 0 {
+            return Err(Error::NotFound);
+        }
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        for collection in collections {
+            self.events.publish(Event::CollectionDeleted {
+                collection,
+                trashed: false,
+            });
+        }
+        self.events.publish(Event::PrincipalDeleted(id.to_owned()));
+        Ok(())
+    }
+
+    #[instrument]
+    async fn transfer_collections(&self, from: &str, to: &str) -> Result<(), Error> {
+        let mut tx = self.db.begin().await.map_err(crate::Error::from)?;
+        for id in [from, to] {
+            sqlx::query!(r#"SELECT id FROM principals WHERE id = $1"#, id)
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(crate::Error::from)?;
+        }
+        let moved: Vec<(CollectionRef, CollectionRef)> = Self::collection_refs(&mut tx, from)
+            .await?
+            .into_iter()
+            .map(|old| {
+                let new = CollectionRef {
+                    principal: to.to_owned(),
+                    push_topic: uuid::Uuid::new_v4().to_string(),
+                    ..old.clone()
+                };
+                (old, new)
+            })
+            .collect();
+
+        // The objects and change logs can only be moved once the new rows exist,
+        // the birthday calendars come after their addressbooks
+        for (old, new) in &moved {
+            match (old.collection_type, old.id.strip_prefix(BIRTHDAYS_PREFIX)) {
+                (CollectionType::Calendar, Some(addressbook_id)) => sqlx::query!(
+                    r#"INSERT INTO birthday_calendars (principal, id, displayname, description, "order", color, timezone_id, deleted_at, push_topic)
+                    SELECT $1, id, displayname, description, "order", color, timezone_id, deleted_at, $2
+                    FROM birthday_calendars WHERE (principal, id) = ($3, $4)"#,
+                    to,
+                    new.push_topic,
+                    from,
+                    addressbook_id
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(crate::Error::from)?,
+                (CollectionType::Calendar, None) => sqlx::query!(
+                    r#"INSERT INTO calendars (principal, id, synctoken, displayname, description, "order", color, timezone_id, deleted_at, subscription_url, push_topic, comp_event, comp_todo, comp_journal)
+                    SELECT $1, id, synctoken, displayname, description, "order", color, timezone_id, deleted_at, subscription_url, $2, comp_event, comp_todo, comp_journal
+                    FROM calendars WHERE (principal, id) = ($3, $4)"#,
+                    to,
+                    new.push_topic,
+                    from,
+                    old.id
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(crate::Error::from)?,
+                (CollectionType::Addressbook, _) => sqlx::query!(
+                    r#"INSERT INTO addressbooks (principal, id, synctoken, displayname, description, deleted_at, push_topic)
+                    SELECT $1, id, synctoken, displayname, description, deleted_at, $2
+                    FROM addressbooks WHERE (principal, id) = ($3, $4)"#,
+                    to,
+                    new.push_topic,
+                    from,
+                    old.id
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(crate::Error::from)?,
+            };
+        }
+        for (old, _) in &moved {
+            match (old.collection_type, old.id.starts_with(BIRTHDAYS_PREFIX)) {
+                (CollectionType::Calendar, true) => {}
+                (CollectionType::Calendar, false) => {
+                    sqlx::query!(
+                        r#"UPDATE calendarobjects SET principal = $1 WHERE (principal, cal_id) = ($2, $3)"#,
+                        to,
+                        from,
+                        old.id
+                    )
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(crate::Error::from)?;
+                    sqlx::query!(
+                        r#"UPDATE calendarobjectchangelog SET principal = $1 WHERE (principal, cal_id) = ($2, $3)"#,
+                        to,
+                        from,
+                        old.id
+                    )
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(crate::Error::from)?;
+                    sqlx::query!(
+                        r#"DELETE FROM calendars WHERE (principal, id) = ($1, $2)"#,
+                        from,
+                        old.id
+                    )
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(crate::Error::from)?;
+                }
+                (CollectionType::Addressbook, _) => {
+                    sqlx::query!(
+                        r#"UPDATE addressobjects SET principal = $1 WHERE (principal, addressbook_id) = ($2, $3)"#,
+                        to,
+                        from,
+                        old.id
+                    )
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(crate::Error::from)?;
+                    sqlx::query!(
+                        r#"UPDATE addressobjectchangelog SET principal = $1 WHERE (principal, addressbook_id) = ($2, $3)"#,
+                        to,
+                        from,
+                        old.id
+                    )
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(crate::Error::from)?;
+                    // The old birthday calendar cascades
+                    sqlx::query!(
+                        r#"DELETE FROM addressbooks WHERE (principal, id) = ($1, $2)"#,
+                        from,
+                        old.id
+                    )
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(crate::Error::from)?;
+                }
+            }
+        }
+        // Webhooks on single collections of the old owner point to nothing now
+        sqlx::query!(
+            r#"DELETE FROM webhooks WHERE principal = $1 AND collection_id IS NOT NULL"#,
+            from
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(crate::Error::from)?;
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        for (old, new) in moved {
+            self.events.publish(Event::CollectionDeleted {
+                collection: old,
+                trashed: false,
+            });
+            self.events.publish(Event::CollectionCreated(new));
+        }
+        Ok(())
+    }
+
+    #[instrument]
+    async fn insert_principal(
+        &self,
+        user: Principal,
+        overwrite: bool,
+    ) -> Result<(), rustical_store::Error> {
+        if user.id.contains(':') || user.id.contains('$') {
+            return Err(rustical_store::Error::InvalidPrincipalId);
+        }
+
+        // Would be cleaner to put this into a transaction but for now it will be fine
+        let exists = self.get_principal(&user.id).await?.is_some();
+        if !overwrite && exists {
+            return Err(Error::AlreadyExists);
+        }
+        let principal_type = user.principal_type.as_str();
+        let password = user.password.map(Secret::into_inner);
+        sqlx::query!(
+            r#"
+            INSERT INTO principals
+            (id, displayname, principal_type, password_hash) VALUES ($1, $2, $3, $4)
+            ON CONFLICT(id) DO UPDATE SET
+                (displayname, principal_type, password_hash)
+                = (excluded.displayname, excluded.principal_type, excluded.password_hash)
+        "#,
+            user.id,
+            user.displayname,
+            principal_type,
+            password
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        self.events.publish(if exists {
+            Event::PrincipalUpdated(user.id)
+        } else {
+            Event::PrincipalCreated(user.id)
+        });
+        Ok(())
+    }
+
+    #[instrument]
+    async fn get_app_tokens(&self, principal: &str) -> Result<Vec<AppToken>, Error> {
+        Ok(sqlx::query_as!(
+            AppTokenRow,
+            r#"SELECT id, displayname AS name, token, created_at, scope AS "scope: _", expires_at, last_used_at, last_user_agent, token_digest AS digest
+                FROM app_tokens WHERE principal = $1"#,
+            principal
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(crate::Error::from)?
+        .into_iter()
+        .map(AppToken::from)
+        .collect())
+    }
+
+    #[instrument(skip(digest))]
+    async fn get_app_token_by_digest(
+        &self,
+        user_id: &str,
+        digest: &str,
+    ) -> Result<Option<AppToken>, Error> {
+        Ok(sqlx::query_as!(
+            AppTokenRow,
+            r#"SELECT id, displayname AS name, token, created_at, scope AS "scope: _", expires_at, last_used_at, last_user_agent, token_digest AS digest
+                FROM app_tokens WHERE principal = $1 AND token_digest = $2"#,
+            user_id,
+            digest
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(crate::Error::from)?
+        .map(AppToken::from))
+    }
+
+    #[instrument(skip(digest))]
+    async fn set_app_token_digest(
+        &self,
+        user_id: &str,
+        token_id: &str,
+        digest: &str,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            r#"UPDATE app_tokens SET token_digest = $1 WHERE (principal, id) = ($2, $3)"#,
+            digest,
+            user_id,
+            token_id
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn get_app_token_pepper(&self) -> Result<Option<String>, Error> {
+        if let Some(pepper) = self.app_token_pepper.get() {
+            return Ok(Some(pepper.clone()));
+        }
+        let pepper = sqlx::query!(r#"SELECT pepper FROM app_token_pepper WHERE id = 0"#)
+            .fetch_optional(&self.db)
+            .await
+            .map_err(crate::Error::from)?
+            .map(|row| row.pepper);
+        if let Some(pepper) = &pepper {
+            let _ = self.app_token_pepper.set(pepper.clone());
+        }
+        Ok(pepper)
+    }
+
+    async fn insert_app_token_pepper(&self, pepper: &str) -> Result<(), Error> {
+        sqlx::query!(
+            r#"INSERT INTO app_token_pepper (id, pepper) VALUES (0, $1) ON CONFLICT (id) DO NOTHING"#,
+            pepper
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    #[instrument]
+    async fn record_app_token_use(
+        &self,
+        user_id: &str,
+        token_id: &str,
+        user_agent: Option<String>,
+    ) -> Result<(), Error> {
+        let now = Utc::now();
+        sqlx::query!(
+            r#"UPDATE app_tokens SET last_used_at = $1, last_user_agent = $2 WHERE (principal, id) = ($3, $4)"#,
+            now,
+            user_agent,
+            user_id,
+            token_id
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    #[instrument]
+    async fn remove_app_token(&self, user_id: &str, token_id: &str) -> Result<(), Error> {
+        sqlx::query!(
+            r#"DELETE FROM app_tokens WHERE (principal, id) = ($1, $2)"#,
+            user_id,
+            token_id
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    #[instrument(skip(token))]
+    async fn add_app_token(
+        &self,
+        user_id: &str,
+        name: String,
+        token: String,
+        scope: AppTokenScope,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<String, Error> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let salt = Salt::try_from_rng(&mut SysRng).map_err(|err| Error::Other(err.into()))?;
+        let token_hash = pbkdf2::Pbkdf2::SHA512
+            .hash_password_with_params(
+                token.as_bytes(),
+                &salt,
+                // The app token has a high entropy so we are quite safe from quessing attacks
+                // Also if an attacker got access to the hashes they'd have already gotten
+                // access to the whole database.
+                Params::new(1000).expect("1000 rounds are valid"),
+            )
+            .map_err(|_| Error::PasswordHash)?
+            .to_string();
+        let digest = app_token_digest(&load_or_generate_app_token_pepper(self).await?, &token);
+        let scope = encode_scope(&scope)?;
+        sqlx::query!(
+            r#"
+            INSERT INTO app_tokens
+                (id, principal, token, displayname, scope, expires_at, token_digest)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+            id,
+            user_id,
+            token_hash,
+            name,
+            scope,
+            expires_at,
+            digest
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(id)
+    }
+
+    #[instrument]
+    async fn insert_app_token(&self, user_id: &str, token: AppToken) -> Result<(), Error> {
+        let token_hash = token.token.into_inner();
+        let created_at = token.created_at.unwrap_or_else(Utc::now);
+        let scope = encode_scope(&token.scope)?;
+        sqlx::query!(
+            r#"
+            INSERT INTO app_tokens
+                (id, principal, token, displayname, created_at, scope, expires_at, last_used_at, last_user_agent)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        "#,
+            token.id,
+            user_id,
+            token_hash,
+            token.name,
+            created_at,
+            scope,
+            token.expires_at,
+            token.last_used_at,
+            token.last_user_agent
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    #[instrument]
+    async fn add_membership(&self, principal: &str, member_of: &str) -> Result<(), Error> {
+        sqlx::query!(
+            r#"INSERT INTO memberships (principal, member_of) VALUES ($1, $2) ON CONFLICT DO NOTHING"#,
+            principal,
+            member_of
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        self.events
+            .publish(Event::PrincipalUpdated(principal.to_owned()));
+        Ok(())
+    }
+
+    #[instrument]
+    async fn remove_membership(&self, principal: &str, member_of: &str) -> Result<(), Error> {
+        sqlx::query!(
+            r#"DELETE FROM memberships WHERE (principal, member_of) = ($1, $2)"#,
+            principal,
+            member_of
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        self.events
+            .publish(Event::PrincipalUpdated(principal.to_owned()));
+        Ok(())
+    }
+
+    #[instrument]
+    async fn list_members(&self, principal: &str) -> Result<Vec<String>, Error> {
+        Ok(sqlx::query!(
+            r#"SELECT principal FROM memberships WHERE member_of = $1"#,
+            principal
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(crate::Error::from)?
+        .into_iter()
+        .map(|record| record.principal)
+        .collect())
+    }
+
+    #[instrument]
+    async fn get_identity_provider(&self, principal: &str) -> Result<Option<String>, Error> {
+        Ok(sqlx::query_scalar!(
+            r#"SELECT provider FROM identity_providers WHERE principal = $1"#,
+            principal
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(crate::Error::from)?)
+    }
+
+    #[instrument]
+    async fn set_identity_provider(&self, principal: &str, provider: &str) -> Result<(), Error> {
+        sqlx::query!(
+            r#"INSERT INTO identity_providers (principal, provider) VALUES ($1, $2)
+            ON CONFLICT (principal) DO UPDATE SET provider = excluded.provider"#,
+            principal,
+            provider
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    #[instrument]
+    async fn get_email(&self, principal: &str) -> Result<Option<String>, Error> {
+        Ok(sqlx::query_scalar!(
+            r#"SELECT email FROM principal_emails WHERE principal = $1"#,
+            principal
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(crate::Error::from)?)
+    }
+
+    #[instrument]
+    async fn set_email(&self, principal: &str, email: Option<&str>) -> Result<(), Error> {
+        if let Some(email) = email {
+            sqlx::query!(
+                r#"INSERT INTO principal_emails (principal, email) VALUES ($1, $2)
+                ON CONFLICT (principal) DO UPDATE SET email = excluded.email"#,
+                principal,
+                email
+            )
+            .execute(&self.db)
+            .await
+            .map_err(crate::Error::from)?;
+        } else {
+            sqlx::query!(
+                r#"DELETE FROM principal_emails WHERE principal = $1"#,
+                principal
+            )
+            .execute(&self.db)
+            .await
+            .map_err(crate::Error::from)?;
+        }
+        Ok(())
+    }
+
+    #[instrument]
+    async fn is_active(&self, principal: &str) -> Result<bool, Error> {
+        Ok(sqlx::query_scalar!(
+            r#"SELECT principal FROM deactivated_principals WHERE principal = $1"#,
+            principal
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(crate::Error::from)?
+        .is_none())
+    }
+
+    #[instrument]
+    async fn set_active(&self, principal: &str, active: bool) -> Result<(), Error> {
+        if active {
+            sqlx::query!(
+                r#"DELETE FROM deactivated_principals WHERE principal = $1"#,
+                principal
+            )
+            .execute(&self.db)
+            .await
+            .map_err(crate::Error::from)?;
+        } else {
+            sqlx::query!(
+                r#"INSERT INTO deactivated_principals (principal) VALUES ($1) ON CONFLICT DO NOTHING"#,
+                principal
+            )
+            .execute(&self.db)
+            .await
+            .map_err(crate::Error::from)?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SecondFactorStore for PostgresPrincipalStore {
+    #[instrument]
+    async fn get_totp(&self, principal: &str) -> Result<Option<Totp>, Error> {
+        Ok(sqlx::query!(
+            r#"SELECT secret, recovery_codes FROM totp WHERE principal = $1"#,
+            principal
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(crate::Error::from)?
+        .map(|row| Totp {
+            secret: Secret::from(row.secret),
+            recovery_codes: row.recovery_codes.into_iter().map(Secret::from).collect(),
+        }))
+    }
+
+    #[instrument]
+    async fn set_totp(&self, principal: &str, totp: Option<Totp>) -> Result<(), Error> {
+        if let Some(totp) = totp {
+            let secret = totp.secret.into_inner();
+            let recovery_codes: Vec<String> = totp
+                .recovery_codes
+                .into_iter()
+                .map(Secret::into_inner)
+                .collect();
+            sqlx::query!(
+                r#"INSERT INTO totp (principal, secret, recovery_codes) VALUES ($1, $2, $3)
+            ON CONFLICT (principal) DO UPDATE SET secret = excluded.secret, recovery_codes = excluded.recovery_codes"#,
+                principal,
+                secret,
+                &recovery_codes
+            )
+            .execute(&self.db)
+            .await
+            .map_err(crate::Error::from)?;
+        } else {
+            sqlx::query!(r#"DELETE FROM totp WHERE principal = $1"#, principal)
+                .execute(&self.db)
+                .await
+                .map_err(crate::Error::from)?;
+        }
+        Ok(())
+    }
+
+    #[instrument]
+    async fn use_totp_step(&self, principal: &str, step: i64) -> Result<bool, Error> {
+        let result = sqlx::query!(
+            r#"UPDATE totp SET last_used_step = $2
+            WHERE principal = $1 AND (last_used_step IS NULL OR last_used_step < $2)"#,
+            principal,
+            step
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    #[instrument]
+    async fn get_passkeys(&self, principal: &str) -> Result<Vec<PasskeyCredential>, Error> {
+        Ok(sqlx::query_as!(
+            PasskeyCredential,
+            r#"SELECT id, displayname AS name, credential, created_at FROM passkeys WHERE principal = $1"#,
+            principal
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(crate::Error::from)?)
+    }
+
+    #[instrument]
+    async fn upsert_passkey(
+        &self,
+        principal: &str,
+        passkey: PasskeyCredential,
+    ) -> Result<(), Error> {
+        let created_at = passkey.created_at.unwrap_or_else(Utc::now);
+        sqlx::query!(
+            r#"
+            INSERT INTO passkeys
+                (id, principal, displayname, credential, created_at)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (principal, id) DO UPDATE SET
+                displayname = excluded.displayname,
+                credential = excluded.credential
+        "#,
+            passkey.id,
+            principal,
+            passkey.name,
+            passkey.credential,
+            created_at
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    #[instrument]
+    async fn remove_passkey(&self, principal: &str, id: &str) -> Result<(), Error> {
+        sqlx::query!(
+            r#"DELETE FROM passkeys WHERE (principal, id) = ($1, $2)"#,
+            principal,
+            id
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl LoginFailureStore for PostgresPrincipalStore {
+    async fn get_login_failures(
+        &self,
+        subject: &LoginSubject,
+    ) -> Result<Option<LoginFailures>, Error> {
+        let subject = subject.to_string();
+        sqlx::query_as!(
+            LoginFailuresRow,
+            r#"SELECT subject, failures, last_failure_at, locked_until FROM login_failures WHERE subject = $1"#,
+            subject
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(crate::Error::from)?
+        .map(LoginFailures::try_from)
+        .transpose()
+    }
+
+    async fn list_login_failures(&self) -> Result<Vec<LoginFailures>, Error> {
+        sqlx::query_as!(
+            LoginFailuresRow,
+            r#"SELECT subject, failures, last_failure_at, locked_until FROM login_failures ORDER BY last_failure_at DESC"#
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(crate::Error::from)?
+        .into_iter()
+        .map(LoginFailures::try_from)
+        .collect()
+    }
+
+    async fn add_login_failure(
+        &self,
+        subject: &LoginSubject,
+        now: DateTime<Utc>,
+        window_start: DateTime<Utc>,
+        max_failures: u32,
+        lock_until: DateTime<Utc>,
+    ) -> Result<LoginFailures, Error> {
+        let subject = subject.to_string();
+        let max_failures = i32::try_from(max_failures).unwrap_or(i32::MAX);
+        // The count continues while the subject is locked or within the window
+        sqlx::query_as!(
+            LoginFailuresRow,
+            r#"INSERT INTO login_failures (subject, failures, last_failure_at, locked_until)
+            VALUES ($1, 1, $2, CASE WHEN $4 <= 1 THEN $5::timestamptz END)
+            ON CONFLICT (subject) DO UPDATE SET
+                failures = CASE
+                    WHEN login_failures.locked_until > $2 OR login_failures.last_failure_at > $3
+                    THEN login_failures.failures + 1 ELSE 1 END,
+                last_failure_at = $2,
+                locked_until = CASE
+                    WHEN (CASE
+                        WHEN login_failures.locked_until > $2 OR login_failures.last_failure_at > $3
+                        THEN login_failures.failures + 1 ELSE 1 END) >= $4 THEN $5::timestamptz
+                    WHEN login_failures.locked_until > $2 OR login_failures.last_failure_at > $3
+                    THEN login_failures.locked_until END
+            RETURNING subject, failures, last_failure_at, locked_until"#,
+            subject,
+            now,
+            window_start,
+            max_failures,
+            lock_until
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(crate::Error::from)?
+        .try_into()
+    }
+
+    async fn remove_login_failures(&self, subject: &LoginSubject) -> Result<(), Error> {
+        let subject = subject.to_string();
+        sqlx::query!(r#"DELETE FROM login_failures WHERE subject = $1"#, subject)
+            .execute(&self.db)
+            .await
+            .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn prune_login_failures(&self, before: DateTime<Utc>) -> Result<u64, Error> {
+        Ok(sqlx::query!(
+            r#"DELETE FROM login_failures WHERE last_failure_at < $1"#,
+            before
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?
+        .rows_affected())
+    }
+}
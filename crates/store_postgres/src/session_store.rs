@@ -0,0 +1,110 @@
+use crate::PostgresStore;
+use async_trait::async_trait;
+use chrono::Utc;
+use rustical_store::{Error, SessionStore, session_principal};
+use sqlx::types::Json;
+use tower_sessions::{
+    session::{Id, Record},
+    session_store,
+};
+use tracing::instrument;
+
+fn backend_error(err: sqlx::Error) -> session_store::Error {
+    session_store::Error::Backend(err.to_string())
+}
+
+fn encode_record(record: &Record) -> session_store::Result<serde_json::Value> {
+    serde_json::to_value(record).map_err(|err| session_store::Error::Encode(err.to_string()))
+}
+
+#[async_trait]
+impl session_store::SessionStore for PostgresStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        loop {
+            let id = record.id.to_string();
+            let principal = session_principal(record);
+            let expires_at = record.expiry_date.unix_timestamp();
+            let json = encode_record(record)?;
+            let result = sqlx::query!(
+                r#"INSERT INTO sessions (id, principal, record, expires_at) VALUES ($1, $2, $3, $4) ON CONFLICT (id) DO NOTHING"#,
+                id,
+                principal,
+                json,
+                expires_at
+            )
+            .execute(&self.db)
+            .await
+            .map_err(backend_error)?;
+            if result.rows_affected() > 0 {
+                return Ok(());
+            }
+            // Session id collision
+            record.id = Id::default();
+        }
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        let id = record.id.to_string();
+        let principal = session_principal(record);
+        let expires_at = record.expiry_date.unix_timestamp();
+        let json = encode_record(record)?;
+        sqlx::query!(
+            r#"INSERT INTO sessions (id, principal, record, expires_at) VALUES ($1, $2, $3, $4) ON CONFLICT (id) DO UPDATE SET principal = excluded.principal, record = excluded.record, expires_at = excluded.expires_at"#,
+            id,
+            principal,
+            json,
+            expires_at
+        )
+        .execute(&self.db)
+        .await
+        .map_err(backend_error)?;
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        let id = session_id.to_string();
+        let now = Utc::now().timestamp();
+        let record = sqlx::query_scalar!(
+            r#"SELECT record AS "record: Json<Record>" FROM sessions WHERE id = $1 AND expires_at > $2"#,
+            id,
+            now
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(backend_error)?;
+        Ok(record.map(|record| record.0))
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        let id = session_id.to_string();
+        sqlx::query!(r#"DELETE FROM sessions WHERE id = $1"#, id)
+            .execute(&self.db)
+            .await
+            .map_err(backend_error)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SessionStore for PostgresStore {
+    #[instrument]
+    async fn delete_expired_sessions(&self) -> Result<u64, Error> {
+        let now = Utc::now().timestamp();
+        Ok(
+            sqlx::query!(r#"DELETE FROM sessions WHERE expires_at <= $1"#, now)
+                .execute(&self.db)
+                .await
+                .map_err(crate::Error::from)?
+                .rows_affected(),
+        )
+    }
+
+    #[instrument]
+    async fn revoke_sessions(&self, principal: &str) -> Result<(), Error> {
+        sqlx::query!(r#"DELETE FROM sessions WHERE principal = $1"#, principal)
+            .execute(&self.db)
+            .await
+            .map_err(crate::Error::from)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+use crate::PostgresStore;
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use rustical_dav_push::{PushDelivery, Subscription, SubscriptionStore};
+use rustical_store::Error;
+
+#[async_trait]
+impl SubscriptionStore for PostgresStore {
+    async fn get_subscriptions(&self, topic: &str) -> Result<Vec<Subscription>, Error> {
+        Ok(sqlx::query_as!(
+            Subscription,
+            r#"SELECT id, topic, expiration, push_resource, public_key, public_key_type, auth_secret
+                FROM davpush_subscriptions
+                WHERE (topic) = ($1)"#,
+            topic
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(crate::Error::from)?)
+    }
+
+    async fn get_topics(&self) -> Result<Vec<String>, Error> {
+        Ok(
+            sqlx::query_scalar!(r#"SELECT DISTINCT topic AS "topic!" FROM davpush_subscriptions"#)
+                .fetch_all(&self.db)
+                .await
+                .map_err(crate::Error::from)?,
+        )
+    }
+
+    async fn get_subscription(&self, id: &str) -> Result<Subscription, Error> {
+        Ok(sqlx::query_as!(
+            Subscription,
+            r#"SELECT id, topic, expiration, push_resource, public_key, public_key_type, auth_secret
+                FROM davpush_subscriptions
+                WHERE (id) = ($1)"#,
+            id
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(crate::Error::from)?)
+    }
+
+    async fn upsert_subscription(&self, sub: Subscription) -> Result<bool, Error> {
+        let already_exists = match self.get_subscription(&sub.id).await {
+            Ok(_) => true,
+            Err(Error::NotFound) => false,
+            Err(err) => return Err(err),
+        };
+        sqlx::query!(
+            r#"INSERT INTO davpush_subscriptions (id, topic, expiration, push_resource, public_key, public_key_type, auth_secret) VALUES ($1, $2, $3, $4, $5, $6, $7)
+                ON CONFLICT (id) DO UPDATE SET
+                    (topic, expiration, push_resource, public_key, public_key_type, auth_secret)
+                    = (excluded.topic, excluded.expiration, excluded.push_resource, excluded.public_key, excluded.public_key_type, excluded.auth_secret)"#,
+            sub.id,
+            sub.topic,
+            sub.expiration,
+            sub.push_resource,
+            sub.public_key,
+            sub.public_key_type,
+            sub.auth_secret
+        ).execute(&self.db).await.map_err(crate::Error::from)?;
+        Ok(already_exists)
+    }
+    async fn delete_subscription(&self, id: &str) -> Result<(), Error> {
+        sqlx::query!(r#"DELETE FROM davpush_subscriptions WHERE id = $1 "#, id)
+            .execute(&self.db)
+            .await
+            .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn enqueue_delivery(
+        &self,
+        subscription_id: &str,
+        payload: &str,
+        unsubscribe: bool,
+    ) -> Result<(), Error> {
+        let now = chrono::Utc::now().naive_utc();
+        sqlx::query!(
+            r#"INSERT INTO davpush_deliveries (subscription_id, payload, next_attempt, unsubscribe) VALUES ($1, $2, $3, $4)"#,
+            subscription_id,
+            payload,
+            now,
+            unsubscribe
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn get_due_deliveries(
+        &self,
+        now: NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<PushDelivery>, Error> {
+        Ok(sqlx::query_as!(
+            PushDelivery,
+            r#"SELECT id, subscription_id, payload, attempts, next_attempt, unsubscribe
+                FROM davpush_deliveries
+                WHERE next_attempt <= $1
+                ORDER BY next_attempt
+                LIMIT $2"#,
+            now,
+            limit
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(crate::Error::from)?)
+    }
+
+    async fn reschedule_delivery(
+        &self,
+        id: i64,
+        attempts: i64,
+        next_attempt: NaiveDateTime,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            r#"UPDATE davpush_deliveries SET attempts = $1, next_attempt = $2 WHERE id = $3"#,
+            attempts,
+            next_attempt,
+            id
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn delete_delivery(&self, id: i64) -> Result<(), Error> {
+        sqlx::query!(r#"DELETE FROM davpush_deliveries WHERE id = $1"#, id)
+            .execute(&self.db)
+            .await
+            .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn count_deliveries(&self) -> Result<u64, Error> {
+        Ok(
+            sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM davpush_deliveries"#)
+                .fetch_one(&self.db)
+                .await
+                .map_err(crate::Error::from)?
+                .unsigned_abs(),
+        )
+    }
+
+    async fn get_vapid_key(&self) -> Result<Option<String>, Error> {
+        Ok(
+            sqlx::query!(r#"SELECT private_key FROM davpush_vapid_key WHERE id = 0"#)
+                .fetch_optional(&self.db)
+                .await
+                .map_err(crate::Error::from)?
+                .map(|row| row.private_key),
+        )
+    }
+
+    async fn insert_vapid_key(&self, private_key: &str) -> Result<(), Error> {
+        sqlx::query!(
+            r#"INSERT INTO davpush_vapid_key (id, private_key) VALUES (0, $1) ON CONFLICT (id) DO NOTHING"#,
+            private_key
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+//! Runs the store test suite of `rustical_store_sqlite` against a live Postgres,
+//! every fixture gets its own schema in the database at `RUSTICAL_TEST_POSTGRES_URL`.
+use crate::{
+    PostgresStore, addressbook_store::PostgresAddressbookStore,
+    calendar_store::PostgresCalendarStore, principal_store::PostgresPrincipalStore,
+};
+use core::str::FromStr;
+use rstest::{fixture, rstest};
+use rustical_store::EventBus;
+use rustical_store::auth::{AppTokenScope, AuthenticationProvider, Principal, PrincipalType};
+use sqlx::PgPool;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+
+#[path = "../../../store_sqlite/src/tests/addressbook_store.rs"]
+mod addressbook_store;
+#[path = "../../../store_sqlite/src/tests/calendar_store.rs"]
+mod calendar_store;
+#[path = "../../../store_sqlite/src/tests/principal_store.rs"]
+mod principal_store;
+#[path = "../../../store_sqlite/src/tests/subscription_store.rs"]
+mod subscription_store;
+#[path = "../../../store_sqlite/src/tests/webhook_store.rs"]
+mod webhook_store;
+
+#[derive(Debug, Clone)]
+pub struct TestStoreContext {
+    pub db: PgPool,
+    pub events: EventBus,
+    pub addr_store: PostgresAddressbookStore,
+    pub cal_store: PostgresCalendarStore,
+    pub principal_store: PostgresPrincipalStore,
+    pub sub_store: PostgresStore,
+}
+
+#[fixture]
+pub async fn test_store_context() -> TestStoreContext {
+    let url = std::env::var("RUSTICAL_TEST_POSTGRES_URL")
+        .expect("RUSTICAL_TEST_POSTGRES_URL must be set to run the Postgres tests");
+    let schema = format!("rustical_test_{}", uuid::Uuid::new_v4().simple());
+
+    let admin = PgPoolOptions::new()
+        .max_connections(1)
+        .connect(&url)
+        .await
+        .unwrap();
+    sqlx::query(sqlx::AssertSqlSafe(format!("CREATE SCHEMA {schema}")))
+        .execute(&admin)
+        .await
+        .unwrap();
+    admin.close().await;
+
+    let options = PgConnectOptions::from_str(&url)
+        .unwrap()
+        .options([("search_path", schema.as_str())]);
+    let db = PgPoolOptions::new()
+        .max_connections(4)
+        .connect_with(options)
+        .await
+        .unwrap();
+    sqlx::migrate!("./migrations").run(&db).await.unwrap();
+
+    let events = EventBus::default();
+    let principal_store = PostgresPrincipalStore::new(db.clone(), events.clone());
+    // Populate with test data
+    principal_store
+        .insert_principal(
+            Principal {
+                id: "user".to_owned(),
+                displayname: None,
+                memberships: vec![],
+                app_token: None,
+                password: None,
+                principal_type: PrincipalType::Individual,
+            },
+            false,
+        )
+        .await
+        .unwrap();
+    principal_store
+        .add_app_token(
+            "user",
+            "test".to_string(),
+            "pass".to_string(),
+            AppTokenScope::default(),
+            None,
+        )
+        .await
+        .unwrap();
+
+    TestStoreContext {
+        db: db.clone(),
+        addr_store: PostgresAddressbookStore::new(db.clone(), events.clone(), false),
+        cal_store: PostgresCalendarStore::new(db.clone(), events.clone(), false),
+        principal_store,
+        sub_store: PostgresStore::new(db),
+        events,
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_invalid_principal_id(
+    #[from(test_store_context)]
+    #[future]
+    context: TestStoreContext,
+) {
+    let principal_store = context.await.principal_store;
+
+    assert!(
+        matches!(
+            principal_store
+                .insert_principal(
+                    Principal {
+                        id: "group:nicegroup".to_owned(),
+                        displayname: None,
+                        principal_type: PrincipalType::Individual,
+                        password: None,
+                        memberships: vec![],
+                        app_token: None,
+                    },
+                    false,
+                )
+                .await,
+            Err(rustical_store::Error::InvalidPrincipalId)
+        ),
+        ": not allowed since it breaks basic auth"
+    );
+}
@@ -0,0 +1 @@
+DROP TABLE davpush_deliveries;
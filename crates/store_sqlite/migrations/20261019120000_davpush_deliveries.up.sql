@@ -0,0 +1,14 @@
+CREATE TABLE davpush_deliveries (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    subscription_id TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    attempts INTEGER DEFAULT 0 NOT NULL,
+    next_attempt DATETIME NOT NULL,
+    -- Remove the subscription once the message is delivered (collection deleted)
+    unsubscribe BOOLEAN DEFAULT FALSE NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT fk_delivery_subscription FOREIGN KEY (subscription_id)
+    REFERENCES davpush_subscriptions (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_davpush_deliveries_next_attempt ON davpush_deliveries (next_attempt);
@@ -0,0 +1,6 @@
+-- There is only ever one VAPID key per server
+CREATE TABLE davpush_vapid_key (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    private_key TEXT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,38 @@
+CREATE TABLE webhooks (
+    id TEXT NOT NULL,
+    principal TEXT NOT NULL,
+    -- Both NULL if the webhook covers all collections of the principal
+    collection_type TEXT,
+    collection_id TEXT,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (id),
+    CONSTRAINT fk_webhook_principal FOREIGN KEY (principal)
+    REFERENCES principals (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_webhooks_principal ON webhooks (principal);
+
+CREATE TABLE webhook_deliveries (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    webhook_id TEXT NOT NULL,
+    event TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    -- pending, delivered or failed
+    state TEXT DEFAULT 'pending' NOT NULL,
+    attempts INTEGER DEFAULT 0 NOT NULL,
+    next_attempt DATETIME NOT NULL,
+    last_status INTEGER,
+    last_error TEXT,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    CONSTRAINT fk_delivery_webhook FOREIGN KEY (webhook_id)
+    REFERENCES webhooks (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_webhook_deliveries_state ON webhook_deliveries (
+    state, next_attempt
+);
+CREATE INDEX idx_webhook_deliveries_webhook ON webhook_deliveries (
+    webhook_id, created_at
+);
@@ -0,0 +1,10 @@
+CREATE TABLE sessions (
+    id TEXT NOT NULL,
+    principal TEXT,
+    record TEXT NOT NULL,
+    expires_at INTEGER NOT NULL,
+    PRIMARY KEY (id)
+);
+
+CREATE INDEX idx_sessions_principal ON sessions (principal);
+CREATE INDEX idx_sessions_expires_at ON sessions (expires_at);
@@ -0,0 +1,4 @@
+ALTER TABLE app_tokens DROP COLUMN scope;
+ALTER TABLE app_tokens DROP COLUMN expires_at;
+ALTER TABLE app_tokens DROP COLUMN last_used_at;
+ALTER TABLE app_tokens DROP COLUMN last_user_agent;
@@ -0,0 +1,5 @@
+-- JSON object of the token's restrictions
+ALTER TABLE app_tokens ADD COLUMN scope TEXT NOT NULL DEFAULT '{}';
+ALTER TABLE app_tokens ADD COLUMN expires_at DATETIME;
+ALTER TABLE app_tokens ADD COLUMN last_used_at DATETIME;
+ALTER TABLE app_tokens ADD COLUMN last_user_agent TEXT;
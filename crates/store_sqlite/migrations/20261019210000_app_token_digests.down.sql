@@ -0,0 +1,3 @@
+DROP TABLE app_token_pepper;
+DROP INDEX app_tokens_token_digest;
+ALTER TABLE app_tokens DROP COLUMN token_digest;
@@ -0,0 +1,7 @@
+CREATE TABLE principal_emails (
+    principal TEXT NOT NULL,
+    email TEXT NOT NULL,
+    PRIMARY KEY (principal),
+    CONSTRAINT fk_principal_email_principal
+    FOREIGN KEY (principal) REFERENCES principals (id) ON DELETE CASCADE
+);
@@ -0,0 +1 @@
+-- The links can't be told apart from the ones made at login
@@ -1,11 +1,12 @@
+use crate::BEGIN_IMMEDIATE;
 use crate::addressbook_store::SqliteAddressbookStore;
 use async_trait::async_trait;
 use chrono::NaiveDateTime;
 use hex::ToHex;
 use rustical_ical::{CalendarObject, CalendarObjectType};
 use rustical_store::{
-    Addressbook, Calendar, CalendarMetadata, CalendarStorePruneDeleted, CollectionMetadata, Error,
-    PrefixedCalendarStore,
+    Addressbook, Calendar, CalendarMetadata, CalendarStorePruneDeleted, CollectionMetadata,
+    CollectionRef, Error, Event, PrefixedCalendarStore,
     addressbook_store::AddressbookReadStore,
     calendar_store::{CalendarReadStore, CalendarWriteStore},
 };
@@ -401,17 +402,45 @@ impl CalendarWriteStore for SqliteAddressbookStore {
     ) -> Result<(), Error> {
         assert_eq!(principal, calendar.principal);
         assert_eq!(id, calendar.id);
+        let mut tx = self
+            .db
+            .begin_with(BEGIN_IMMEDIATE)
+            .await
+            .map_err(crate::Error::from)?;
+
+        let old_cal = Self::_get_birthday_calendar(
+            &mut *tx,
+            principal,
+            id.strip_prefix(BIRTHDAYS_PREFIX).ok_or(Error::NotFound)?,
+            true,
+        )
+        .await?;
+        let props = old_cal.changed_properties(&calendar);
+        let event = (!props.is_empty()).then(|| Event::CollectionUpdated {
+            collection: CollectionRef::from(&old_cal),
+            props,
+        });
+
         calendar.id = calendar
             .id
             .strip_prefix(BIRTHDAYS_PREFIX)
             .ok_or(Error::NotFound)?
             .to_string();
-        Self::_update_birthday_calendar(&self.db, principal, &calendar).await
+        Self::_update_birthday_calendar(&mut *tx, principal, &calendar).await?;
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        if let Some(event) = event {
+            self.events.publish(event);
+        }
+        Ok(())
     }
 
     #[instrument]
     async fn insert_calendar(&self, calendar: Calendar) -> Result<(), Error> {
-        Self::_insert_birthday_calendar(&self.db, &calendar).await
+        Self::_insert_birthday_calendar(&self.db, &calendar).await?;
+        self.events
+            .publish(Event::CollectionCreated(CollectionRef::from(&calendar)));
+        Ok(())
     }
 
     #[instrument]
@@ -424,7 +453,28 @@ impl CalendarWriteStore for SqliteAddressbookStore {
         let Some(id) = id.strip_prefix(BIRTHDAYS_PREFIX) else {
             return Ok(());
         };
-        Self::_delete_birthday_calendar(&self.db, principal, id, use_trashbin).await
+        let mut tx = self
+            .db
+            .begin_with(BEGIN_IMMEDIATE)
+            .await
+            .map_err(crate::Error::from)?;
+
+        let cal = match Self::_get_birthday_calendar(&mut *tx, principal, id, true).await {
+            Ok(cal) => Some(cal),
+            Err(Error::NotFound) => None,
+            Err(err) => return Err(err),
+        };
+
+        Self::_delete_birthday_calendar(&mut *tx, principal, id, use_trashbin).await?;
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        if let Some(cal) = cal {
+            self.events.publish(Event::CollectionDeleted {
+                collection: CollectionRef::from(&cal),
+                trashed: use_trashbin,
+            });
+        }
+        Ok(())
     }
 
     #[instrument]
@@ -432,7 +482,11 @@ impl CalendarWriteStore for SqliteAddressbookStore {
         let Some(id) = id.strip_prefix(BIRTHDAYS_PREFIX) else {
             return Err(Error::NotFound);
         };
-        Self::_restore_birthday_calendar(&self.db, principal, id).await
+        Self::_restore_birthday_calendar(&self.db, principal, id).await?;
+        let calendar = Self::_get_birthday_calendar(&self.db, principal, id, false).await?;
+        self.events
+            .publish(Event::CollectionRestored(CollectionRef::from(&calendar)));
+        Ok(())
     }
 
     #[instrument]
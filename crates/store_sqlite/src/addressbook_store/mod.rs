@@ -5,12 +5,11 @@ use caldata::parser::ParserError;
 use derive_more::derive::Constructor;
 use rustical_ical::AddressObject;
 use rustical_store::{
-    Addressbook, AddressbookReadStore, AddressbookWriteStore, CollectionMetadata,
-    CollectionOperation, CollectionOperationInfo, Error, synctoken::format_synctoken,
+    Addressbook, AddressbookReadStore, AddressbookWriteStore, CollectionMetadata, CollectionRef,
+    Error, Event, EventBus, ObjectChange, ObjectChangeKind, synctoken::format_synctoken,
 };
 use sqlx::{Acquire, Executor, Sqlite, SqlitePool, Transaction};
-use tokio::sync::mpsc::Sender;
-use tracing::{error, error_span, instrument, warn};
+use tracing::{error, instrument, warn};
 
 pub mod birthday_calendar;
 
@@ -40,7 +39,7 @@ impl TryFrom<AddressObjectRow> for (String, AddressObject) {
 #[derive(Debug, Clone, Constructor)]
 pub struct SqliteAddressbookStore {
     db: SqlitePool,
-    sender: Sender<CollectionOperation>,
+    events: EventBus,
     skip_broken: bool,
 }
 
@@ -170,15 +169,6 @@ impl SqliteAddressbookStore {
         Ok(format_synctoken(synctoken))
     }
 
-    fn send_push_notification(&self, data: CollectionOperationInfo, topic: String) {
-        if let Err(err) = self.sender.try_send(CollectionOperation { topic, data }) {
-            error_span!(
-                "Error trying to send addressbook update notification:",
-                err = format!("{err}"),
-            );
-        }
-    }
-
     async fn _get_addressbook<'e, E: Executor<'e, Database = Sqlite>>(
         executor: E,
         principal: &str,
@@ -266,13 +256,14 @@ impl SqliteAddressbookStore {
         addressbook: &Addressbook,
     ) -> Result<(), rustical_store::Error> {
         sqlx::query!(
-            r#"INSERT INTO addressbooks (principal, id, displayname, description, push_topic)
-                VALUES (?, ?, ?, ?, ?)"#,
+            r#"INSERT INTO addressbooks (principal, id, displayname, description, push_topic, synctoken)
+                VALUES (?, ?, ?, ?, ?, ?)"#,
             addressbook.principal,
             addressbook.id,
             addressbook.displayname,
             addressbook.description,
             addressbook.push_topic,
+            addressbook.synctoken,
         )
         .execute(executor)
         .await
@@ -419,6 +410,42 @@ impl SqliteAddressbookStore {
         )
     }
 
+    async fn _get_deleted_objects<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<impl Iterator<Item = (String, Result<AddressObject, ParserError>)>, Error> {
+        Ok(sqlx::query_as!(
+            AddressObjectRow,
+            "SELECT id, vcf FROM addressobjects WHERE principal = ? AND addressbook_id = ? AND deleted_at IS NOT NULL",
+            principal,
+            addressbook_id
+        )
+        .fetch_all(executor)
+        .await.map_err(crate::Error::from)?
+        .into_iter()
+        .map(Into::into)
+        )
+    }
+
+    async fn _object_exists<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<bool, Error> {
+        Ok(sqlx::query!(
+            r#"SELECT EXISTS(SELECT 1 FROM addressobjects WHERE (principal, addressbook_id, id) = (?, ?, ?) AND deleted_at IS NULL) AS "exists!: bool""#,
+            principal,
+            addressbook_id,
+            object_id
+        )
+        .fetch_one(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .exists)
+    }
+
     async fn _get_object<'e, E: Executor<'e, Database = Sqlite>>(
         executor: E,
         principal: &str,
@@ -603,6 +630,24 @@ impl AddressbookReadStore for SqliteAddressbookStore {
         }
     }
 
+    #[instrument]
+    async fn get_deleted_objects(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<(String, AddressObject)>, rustical_store::Error> {
+        let objects = Self::_get_deleted_objects(&self.db, principal, addressbook_id).await?;
+        if self.skip_broken {
+            Ok(objects
+                .filter_map(|(id, res)| Some((id, res.ok()?)))
+                .collect())
+        } else {
+            Ok(objects
+                .map(|(id, res)| res.map(|obj| (id, obj)))
+                .collect::<Result<Vec<_>, _>>()?)
+        }
+    }
+
     #[instrument]
     async fn get_object(
         &self,
@@ -626,7 +671,24 @@ impl AddressbookWriteStore for SqliteAddressbookStore {
     ) -> Result<(), rustical_store::Error> {
         assert_eq!(principal, &addressbook.principal);
         assert_eq!(id, &addressbook.id);
-        Self::_update_addressbook(&self.db, principal, id, &addressbook).await
+        let mut tx = self
+            .db
+            .begin_with(BEGIN_IMMEDIATE)
+            .await
+            .map_err(crate::Error::from)?;
+
+        let old_addressbook = Self::_get_addressbook(&mut *tx, principal, id, true).await?;
+        Self::_update_addressbook(&mut *tx, principal, id, &addressbook).await?;
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        let props = old_addressbook.changed_properties(&addressbook);
+        if !props.is_empty() {
+            self.events.publish(Event::CollectionUpdated {
+                collection: CollectionRef::from(&addressbook),
+                props,
+            });
+        }
+        Ok(())
     }
 
     #[instrument]
@@ -640,9 +702,11 @@ impl AddressbookWriteStore for SqliteAddressbookStore {
             .await
             .map_err(crate::Error::from)?;
         Self::_insert_addressbook(&mut *tx, &addressbook).await?;
+        let collection = CollectionRef::from(&addressbook);
         let birthday_cal = Self::default_birthday_calendar(addressbook);
         Self::_insert_birthday_calendar(&mut *tx, &birthday_cal).await?;
         tx.commit().await.map_err(crate::Error::from)?;
+        self.events.publish(Event::CollectionCreated(collection));
         Ok(())
     }
 
@@ -670,7 +734,10 @@ impl AddressbookWriteStore for SqliteAddressbookStore {
         tx.commit().await.map_err(crate::Error::from)?;
 
         if let Some(addressbook) = addressbook {
-            self.send_push_notification(CollectionOperationInfo::Delete, addressbook.push_topic);
+            self.events.publish(Event::CollectionDeleted {
+                collection: CollectionRef::from(&addressbook),
+                trashed: use_trashbin,
+            });
         }
 
         Ok(())
@@ -682,7 +749,13 @@ impl AddressbookWriteStore for SqliteAddressbookStore {
         principal: &str,
         addressbook_id: &str,
     ) -> Result<(), rustical_store::Error> {
-        Self::_restore_addressbook(&self.db, principal, addressbook_id).await
+        Self::_restore_addressbook(&self.db, principal, addressbook_id).await?;
+        let addressbook = self
+            .get_addressbook(principal, addressbook_id, false)
+            .await?;
+        self.events
+            .publish(Event::CollectionRestored(CollectionRef::from(&addressbook)));
+        Ok(())
     }
 
     #[instrument]
@@ -700,6 +773,13 @@ impl AddressbookWriteStore for SqliteAddressbookStore {
             .await
             .map_err(crate::Error::from)?;
 
+        let kind = if overwrite
+            && Self::_object_exists(&mut *tx, principal, addressbook_id, object_id).await?
+        {
+            ObjectChangeKind::Updated
+        } else {
+            ObjectChangeKind::Created
+        };
         Self::_put_object(
             &mut *tx,
             principal,
@@ -722,12 +802,19 @@ impl AddressbookWriteStore for SqliteAddressbookStore {
 
         tx.commit().await.map_err(crate::Error::from)?;
 
-        self.send_push_notification(
-            CollectionOperationInfo::Content { sync_token },
-            self.get_addressbook(principal, addressbook_id, false)
-                .await?
-                .push_topic,
-        );
+        self.events.publish(Event::ObjectsChanged {
+            collection: CollectionRef::from(
+                &self
+                    .get_addressbook(principal, addressbook_id, false)
+                    .await?,
+            ),
+            sync_token,
+            changes: vec![ObjectChange {
+                id: object_id.to_owned(),
+                etag: Some(object.get_etag()),
+                kind,
+            }],
+        });
 
         Ok(())
     }
@@ -760,12 +847,19 @@ impl AddressbookWriteStore for SqliteAddressbookStore {
 
         tx.commit().await.map_err(crate::Error::from)?;
 
-        self.send_push_notification(
-            CollectionOperationInfo::Content { sync_token },
-            self.get_addressbook(principal, addressbook_id, false)
-                .await?
-                .push_topic,
-        );
+        self.events.publish(Event::ObjectsChanged {
+            collection: CollectionRef::from(
+                &self
+                    .get_addressbook(principal, addressbook_id, false)
+                    .await?,
+            ),
+            sync_token,
+            changes: vec![ObjectChange {
+                id: object_id.to_owned(),
+                etag: None,
+                kind: ObjectChangeKind::Deleted,
+            }],
+        });
         Ok(())
     }
 
@@ -783,6 +877,9 @@ impl AddressbookWriteStore for SqliteAddressbookStore {
             .map_err(crate::Error::from)?;
 
         Self::_restore_object(&mut *tx, principal, addressbook_id, object_id).await?;
+        let etag = Self::_get_object(&mut *tx, principal, addressbook_id, object_id, false)
+            .await?
+            .get_etag();
 
         let sync_token = Self::log_object_operation(
             &mut tx,
@@ -795,12 +892,19 @@ impl AddressbookWriteStore for SqliteAddressbookStore {
         .map_err(crate::Error::from)?;
         tx.commit().await.map_err(crate::Error::from)?;
 
-        self.send_push_notification(
-            CollectionOperationInfo::Content { sync_token },
-            self.get_addressbook(principal, addressbook_id, false)
-                .await?
-                .push_topic,
-        );
+        self.events.publish(Event::ObjectsChanged {
+            collection: CollectionRef::from(
+                &self
+                    .get_addressbook(principal, addressbook_id, false)
+                    .await?,
+            ),
+            sync_token,
+            changes: vec![ObjectChange {
+                id: object_id.to_owned(),
+                etag: Some(etag),
+                kind: ObjectChangeKind::Created,
+            }],
+        });
 
         Ok(())
     }
@@ -834,6 +938,7 @@ impl AddressbookWriteStore for SqliteAddressbookStore {
         }
 
         let mut sync_token = None;
+        let mut changes = vec![];
         for (object_id, object) in objects {
             Self::_put_object(
                 &mut *tx,
@@ -855,16 +960,29 @@ impl AddressbookWriteStore for SqliteAddressbookStore {
                 )
                 .await?,
             );
+            changes.push(ObjectChange {
+                id: object_id,
+                etag: Some(object.get_etag()),
+                kind: ObjectChangeKind::Created,
+            });
         }
 
         tx.commit().await.map_err(crate::Error::from)?;
+        let collection = CollectionRef::from(
+            &self
+                .get_addressbook(&addressbook.principal, &addressbook.id, true)
+                .await?,
+        );
+        if existing.is_none() {
+            self.events
+                .publish(Event::CollectionCreated(collection.clone()));
+        }
         if let Some(sync_token) = sync_token {
-            self.send_push_notification(
-                CollectionOperationInfo::Content { sync_token },
-                self.get_addressbook(&addressbook.principal, &addressbook.id, true)
-                    .await?
-                    .push_topic,
-            );
+            self.events.publish(Event::ObjectsChanged {
+                collection,
+                sync_token,
+                changes,
+            });
         }
         Ok(())
     }
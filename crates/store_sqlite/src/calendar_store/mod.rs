@@ -12,11 +12,10 @@ use rustical_store::synctoken::format_synctoken;
 use rustical_store::{
     Calendar, CalendarMetadata, CalendarStorePruneDeleted, CollectionMetadata, Error,
 };
-use rustical_store::{CollectionOperation, CollectionOperationInfo};
+use rustical_store::{CollectionRef, Event, EventBus, ObjectChange, ObjectChangeKind};
 use sqlx::types::chrono::NaiveDateTime;
 use sqlx::{Acquire, Executor, Sqlite, SqlitePool, Transaction};
-use tokio::sync::mpsc::Sender;
-use tracing::{error, error_span, instrument, warn};
+use tracing::{error, instrument, warn};
 
 #[cfg(test)]
 mod tests;
@@ -114,7 +113,7 @@ impl From<CalendarRow> for Calendar {
 #[derive(Debug, Clone, Constructor)]
 pub struct SqliteCalendarStore {
     db: SqlitePool,
-    sender: Sender<CollectionOperation>,
+    events: EventBus,
     skip_broken: bool,
 }
 
@@ -161,15 +160,6 @@ impl SqliteCalendarStore {
         Ok(format_synctoken(synctoken))
     }
 
-    fn send_push_notification(&self, data: CollectionOperationInfo, topic: String) {
-        if let Err(err) = self.sender.try_send(CollectionOperation { topic, data }) {
-            error_span!(
-                "Error trying to send calendar update notification:",
-                err = format!("{err}"),
-            );
-        }
-    }
-
     #[allow(clippy::missing_panics_doc)]
     pub async fn validate_objects(&self, principal: &str) -> Result<(), Error> {
         let mut success = true;
@@ -389,8 +379,8 @@ impl SqliteCalendarStore {
         let comp_journal = calendar.components.contains(&CalendarObjectType::Journal);
 
         sqlx::query!(
-            r#"INSERT INTO calendars (principal, id, displayname, description, "order", color, subscription_url, timezone_id, push_topic, comp_event, comp_todo, comp_journal)
-                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+            r#"INSERT INTO calendars (principal, id, displayname, description, "order", color, subscription_url, timezone_id, push_topic, comp_event, comp_todo, comp_journal, synctoken)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
             calendar.principal,
             calendar.id,
             calendar.meta.displayname,
@@ -400,7 +390,8 @@ impl SqliteCalendarStore {
             calendar.subscription_url,
             calendar.timezone_id,
             calendar.push_topic,
-            comp_event, comp_todo, comp_journal
+            comp_event, comp_todo, comp_journal,
+            calendar.synctoken
         )
         .execute(executor)
         .await.map_err(crate::Error::from)?;
@@ -538,6 +529,24 @@ impl SqliteCalendarStore {
         )
     }
 
+    async fn _get_deleted_objects<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<impl Iterator<Item = (String, Result<CalendarObject, ParserError>)>, Error> {
+        Ok(sqlx::query_as!(
+            CalendarObjectRow,
+            "SELECT id, uid, ics FROM calendarobjects WHERE principal = ? AND cal_id = ? AND deleted_at IS NOT NULL",
+            principal,
+            cal_id
+        )
+        .fetch_all(executor)
+        .await.map_err(crate::Error::from)?
+        .into_iter()
+        .map(Into::into)
+        )
+    }
+
     async fn _calendar_query<'e, E: Executor<'e, Database = Sqlite>>(
         executor: E,
         principal: &str,
@@ -571,6 +580,24 @@ impl SqliteCalendarStore {
         .map(Into::into))
     }
 
+    async fn _object_exists<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<bool, Error> {
+        Ok(sqlx::query!(
+            r#"SELECT EXISTS(SELECT 1 FROM calendarobjects WHERE (principal, cal_id, id) = (?, ?, ?) AND deleted_at IS NULL) AS "exists!: bool""#,
+            principal,
+            cal_id,
+            object_id
+        )
+        .fetch_one(executor)
+        .await
+        .map_err(crate::Error::from)?
+        .exists)
+    }
+
     async fn _get_object<'e, E: Executor<'e, Database = Sqlite>>(
         executor: E,
         principal: &str,
@@ -855,6 +882,24 @@ impl CalendarReadStore for SqliteCalendarStore {
         }
     }
 
+    #[instrument]
+    async fn get_deleted_objects(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<(String, CalendarObject)>, Error> {
+        let objects = Self::_get_deleted_objects(&self.db, principal, cal_id).await?;
+        if self.skip_broken {
+            Ok(objects
+                .filter_map(|(id, res)| Some((id, res.ok()?)))
+                .collect())
+        } else {
+            Ok(objects
+                .map(|(id, res)| res.map(|obj| (id, obj)))
+                .collect::<Result<Vec<_>, _>>()?)
+        }
+    }
+
     #[instrument]
     async fn get_object(
         &self,
@@ -885,7 +930,10 @@ impl CalendarReadStore for SqliteCalendarStore {
 impl CalendarWriteStore for SqliteCalendarStore {
     #[instrument]
     async fn insert_calendar(&self, calendar: Calendar) -> Result<(), Error> {
-        Self::_insert_calendar(&self.db, calendar).await
+        let collection = CollectionRef::from(&calendar);
+        Self::_insert_calendar(&self.db, calendar).await?;
+        self.events.publish(Event::CollectionCreated(collection));
+        Ok(())
     }
 
     #[instrument]
@@ -895,7 +943,26 @@ impl CalendarWriteStore for SqliteCalendarStore {
         id: &str,
         calendar: Calendar,
     ) -> Result<(), Error> {
-        Self::_update_calendar(&self.db, principal, id, calendar).await
+        let mut tx = self
+            .db
+            .begin_with(BEGIN_IMMEDIATE)
+            .await
+            .map_err(crate::Error::from)?;
+
+        let old_cal = Self::_get_calendar(&mut *tx, principal, id, true).await?;
+        let props = old_cal.changed_properties(&calendar);
+        let event = (!props.is_empty()).then(|| Event::CollectionUpdated {
+            collection: CollectionRef::from(&calendar),
+            props,
+        });
+
+        Self::_update_calendar(&mut *tx, principal, id, calendar).await?;
+        tx.commit().await.map_err(crate::Error::from)?;
+
+        if let Some(event) = event {
+            self.events.publish(event);
+        }
+        Ok(())
     }
 
     // Does not actually delete the calendar but just disables it
@@ -922,14 +989,21 @@ impl CalendarWriteStore for SqliteCalendarStore {
         tx.commit().await.map_err(crate::Error::from)?;
 
         if let Some(cal) = cal {
-            self.send_push_notification(CollectionOperationInfo::Delete, cal.push_topic);
+            self.events.publish(Event::CollectionDeleted {
+                collection: CollectionRef::from(&cal),
+                trashed: use_trashbin,
+            });
         }
         Ok(())
     }
 
     #[instrument]
     async fn restore_calendar(&self, principal: &str, id: &str) -> Result<(), Error> {
-        Self::_restore_calendar(&self.db, principal, id).await
+        Self::_restore_calendar(&self.db, principal, id).await?;
+        let calendar = self.get_calendar(principal, id, false).await?;
+        self.events
+            .publish(Event::CollectionRestored(CollectionRef::from(&calendar)));
+        Ok(())
     }
 
     #[instrument]
@@ -959,8 +1033,14 @@ impl CalendarWriteStore for SqliteCalendarStore {
         }
 
         let mut sync_token = None;
+        let mut changes = vec![];
         for object in objects {
             let object_id = object.get_uid();
+            changes.push(ObjectChange {
+                id: object_id.to_owned(),
+                etag: Some(object.get_etag()),
+                kind: ObjectChangeKind::Created,
+            });
             Self::_put_object(
                 &mut *tx,
                 &calendar.principal,
@@ -985,13 +1065,21 @@ impl CalendarWriteStore for SqliteCalendarStore {
 
         tx.commit().await.map_err(crate::Error::from)?;
 
+        let collection = CollectionRef::from(
+            &self
+                .get_calendar(&calendar.principal, &calendar.id, true)
+                .await?,
+        );
+        if existing_cal.is_none() {
+            self.events
+                .publish(Event::CollectionCreated(collection.clone()));
+        }
         if let Some(sync_token) = sync_token {
-            self.send_push_notification(
-                CollectionOperationInfo::Content { sync_token },
-                self.get_calendar(&calendar.principal, &calendar.id, true)
-                    .await?
-                    .push_topic,
-            );
+            self.events.publish(Event::ObjectsChanged {
+                collection,
+                sync_token,
+                changes,
+            });
         }
         Ok(())
     }
@@ -1017,7 +1105,15 @@ impl CalendarWriteStore for SqliteCalendarStore {
         }
 
         let mut sync_token = None;
+        let mut changes = vec![];
         for (object_id, object) in objects {
+            let kind = if overwrite
+                && Self::_object_exists(&mut *tx, principal, cal_id, &object_id).await?
+            {
+                ObjectChangeKind::Updated
+            } else {
+                ObjectChangeKind::Created
+            };
             sync_token = Some(
                 Self::log_object_operation(
                     &mut tx,
@@ -1029,15 +1125,21 @@ impl CalendarWriteStore for SqliteCalendarStore {
                 .await?,
             );
             Self::_put_object(&mut *tx, principal, cal_id, &object_id, &object, overwrite).await?;
+            changes.push(ObjectChange {
+                id: object_id,
+                etag: Some(object.get_etag()),
+                kind,
+            });
         }
 
         tx.commit().await.map_err(crate::Error::from)?;
 
         if let Some(sync_token) = sync_token {
-            self.send_push_notification(
-                CollectionOperationInfo::Content { sync_token },
-                self.get_calendar(principal, cal_id, true).await?.push_topic,
-            );
+            self.events.publish(Event::ObjectsChanged {
+                collection: CollectionRef::from(&calendar),
+                sync_token,
+                changes,
+            });
         }
         Ok(())
     }
@@ -1063,10 +1165,15 @@ impl CalendarWriteStore for SqliteCalendarStore {
                 .await?;
         tx.commit().await.map_err(crate::Error::from)?;
 
-        self.send_push_notification(
-            CollectionOperationInfo::Content { sync_token },
-            self.get_calendar(principal, cal_id, true).await?.push_topic,
-        );
+        self.events.publish(Event::ObjectsChanged {
+            collection: CollectionRef::from(&self.get_calendar(principal, cal_id, true).await?),
+            sync_token,
+            changes: vec![ObjectChange {
+                id: id.to_owned(),
+                etag: None,
+                kind: ObjectChangeKind::Deleted,
+            }],
+        });
 
         Ok(())
     }
@@ -1085,16 +1192,24 @@ impl CalendarWriteStore for SqliteCalendarStore {
             .map_err(crate::Error::from)?;
 
         Self::_restore_object(&mut *tx, principal, cal_id, object_id).await?;
+        let etag = Self::_get_object(&mut *tx, principal, cal_id, object_id, false)
+            .await?
+            .get_etag();
 
         let sync_token =
             Self::log_object_operation(&mut tx, principal, cal_id, object_id, ChangeOperation::Add)
                 .await?;
         tx.commit().await.map_err(crate::Error::from)?;
 
-        self.send_push_notification(
-            CollectionOperationInfo::Content { sync_token },
-            self.get_calendar(principal, cal_id, true).await?.push_topic,
-        );
+        self.events.publish(Event::ObjectsChanged {
+            collection: CollectionRef::from(&self.get_calendar(principal, cal_id, true).await?),
+            sync_token,
+            changes: vec![ObjectChange {
+                id: object_id.to_owned(),
+                etag: Some(etag),
+                kind: ObjectChangeKind::Created,
+            }],
+        });
         Ok(())
     }
 }
@@ -2,7 +2,10 @@ use std::collections::HashSet;
 
 use rstest::rstest;
 use rustical_ical::{CalendarObject, CalendarObjectType};
-use rustical_store::{Calendar, CalendarMetadata, CalendarReadStore, CalendarWriteStore};
+use rustical_store::{
+    Calendar, CalendarMetadata, CalendarReadStore, CalendarWriteStore, CollectionRef, Event,
+    ObjectChange, ObjectChangeKind,
+};
 
 use crate::tests::{TestStoreContext, test_store_context};
 
@@ -279,3 +282,109 @@ async fn test_sync_no_changes_token(
             .unwrap(),
     );
 }
+
+#[rstest]
+#[tokio::test]
+async fn test_calendar_events(
+    #[from(test_store_context)]
+    #[future]
+    context: TestStoreContext,
+) {
+    let TestStoreContext {
+        cal_store, events, ..
+    } = context.await;
+    let mut recv = events.subscribe("test");
+
+    let principal = "user".to_string();
+    let cal_id = "cal".to_string();
+
+    let mut calendar = Calendar {
+        id: cal_id.clone(),
+        principal: principal.clone(),
+        timezone_id: None,
+        meta: CalendarMetadata {
+            description: None,
+            order: 0,
+            color: None,
+            displayname: None,
+        },
+        deleted_at: None,
+        synctoken: 0,
+        push_topic: "alskdj".to_string(),
+        components: vec![CalendarObjectType::Event],
+        subscription_url: None,
+    };
+    cal_store.insert_calendar(calendar.clone()).await.unwrap();
+    assert!(matches!(
+        recv.recv().await.unwrap(),
+        Event::CollectionCreated(CollectionRef { id, .. }) if id == cal_id
+    ));
+
+    // Nothing changed, nothing to notify
+    cal_store
+        .update_calendar(&principal, &cal_id, calendar.clone())
+        .await
+        .unwrap();
+
+    calendar.meta.displayname = Some("Calendar".to_string());
+    calendar.meta.color = Some("#ff0000".to_string());
+    cal_store
+        .update_calendar(&principal, &cal_id, calendar)
+        .await
+        .unwrap();
+    let Event::CollectionUpdated { collection, props } = recv.recv().await.unwrap() else {
+        panic!("expected a property update");
+    };
+    assert_eq!(collection.push_topic, "alskdj");
+    assert_eq!(collection.id, cal_id);
+    assert_eq!(
+        props.into_iter().map(|(_, name)| name).collect::<Vec<_>>(),
+        vec!["displayname", "calendar-color"]
+    );
+
+    let object = CalendarObject::example_1();
+    let etag = object.get_etag();
+    for kind in [ObjectChangeKind::Created, ObjectChangeKind::Updated] {
+        cal_store
+            .put_object(&principal, &cal_id, "event", object.clone(), true)
+            .await
+            .unwrap();
+        let Event::ObjectsChanged { changes, .. } = recv.recv().await.unwrap() else {
+            panic!("expected an object change");
+        };
+        assert_eq!(
+            changes,
+            vec![ObjectChange {
+                id: "event".to_owned(),
+                etag: Some(etag.clone()),
+                kind,
+            }]
+        );
+    }
+
+    cal_store
+        .delete_object(&principal, &cal_id, "event", true)
+        .await
+        .unwrap();
+    let Event::ObjectsChanged { changes, .. } = recv.recv().await.unwrap() else {
+        panic!("expected an object change");
+    };
+    assert_eq!(changes[0].kind, ObjectChangeKind::Deleted);
+
+    cal_store
+        .delete_calendar(&principal, &cal_id, true)
+        .await
+        .unwrap();
+    assert!(matches!(
+        recv.recv().await.unwrap(),
+        Event::CollectionDeleted { trashed: true, .. }
+    ));
+    cal_store
+        .restore_calendar(&principal, &cal_id)
+        .await
+        .unwrap();
+    assert!(matches!(
+        recv.recv().await.unwrap(),
+        Event::CollectionRestored(_)
+    ));
+}
@@ -8,7 +8,9 @@ pub mod addressbook_store;
 pub mod calendar_store;
 pub mod error;
 pub mod principal_store;
+pub mod session_store;
 pub mod subscription_store;
+pub mod webhook_store;
 
 // Begin statement for write transactions
 pub const BEGIN_IMMEDIATE: &str = "BEGIN IMMEDIATE";
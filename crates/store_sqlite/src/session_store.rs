@@ -0,0 +1,106 @@
+use crate::SqliteStore;
+use async_trait::async_trait;
+use chrono::Utc;
+use rustical_store::{Error, SessionStore, session_principal};
+use sqlx::types::Json;
+use tower_sessions::{
+    session::{Id, Record},
+    session_store,
+};
+use tracing::instrument;
+
+fn backend_error(err: sqlx::Error) -> session_store::Error {
+    session_store::Error::Backend(err.to_string())
+}
+
+#[async_trait]
+impl session_store::SessionStore for SqliteStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        loop {
+            let id = record.id.to_string();
+            let principal = session_principal(record);
+            let expires_at = record.expiry_date.unix_timestamp();
+            let json = Json(&*record);
+            let result = sqlx::query!(
+                r#"INSERT INTO sessions (id, principal, record, expires_at) VALUES (?, ?, ?, ?) ON CONFLICT (id) DO NOTHING"#,
+                id,
+                principal,
+                json,
+                expires_at
+            )
+            .execute(&self.db)
+            .await
+            .map_err(backend_error)?;
+            if result.rows_affected() > 0 {
+                return Ok(());
+            }
+            // Session id collision
+            record.id = Id::default();
+        }
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        let id = record.id.to_string();
+        let principal = session_principal(record);
+        let expires_at = record.expiry_date.unix_timestamp();
+        let json = Json(record);
+        sqlx::query!(
+            r#"REPLACE INTO sessions (id, principal, record, expires_at) VALUES (?, ?, ?, ?)"#,
+            id,
+            principal,
+            json,
+            expires_at
+        )
+        .execute(&self.db)
+        .await
+        .map_err(backend_error)?;
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        let id = session_id.to_string();
+        let now = Utc::now().timestamp();
+        let record = sqlx::query_scalar!(
+            r#"SELECT record AS "record: Json<Record>" FROM sessions WHERE id = ? AND expires_at > ?"#,
+            id,
+            now
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(backend_error)?;
+        Ok(record.map(|record| record.0))
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        let id = session_id.to_string();
+        sqlx::query!(r#"DELETE FROM sessions WHERE id = ?"#, id)
+            .execute(&self.db)
+            .await
+            .map_err(backend_error)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SessionStore for SqliteStore {
+    #[instrument]
+    async fn delete_expired_sessions(&self) -> Result<u64, Error> {
+        let now = Utc::now().timestamp();
+        Ok(
+            sqlx::query!(r#"DELETE FROM sessions WHERE expires_at <= ?"#, now)
+                .execute(&self.db)
+                .await
+                .map_err(crate::Error::from)?
+                .rows_affected(),
+        )
+    }
+
+    #[instrument]
+    async fn revoke_sessions(&self, principal: &str) -> Result<(), Error> {
+        sqlx::query!(r#"DELETE FROM sessions WHERE principal = ?"#, principal)
+            .execute(&self.db)
+            .await
+            .map_err(crate::Error::from)?;
+        Ok(())
+    }
+}
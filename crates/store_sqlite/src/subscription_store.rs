@@ -1,6 +1,7 @@
 use crate::SqliteStore;
 use async_trait::async_trait;
-use rustical_dav_push::{Subscription, SubscriptionStore};
+use chrono::NaiveDateTime;
+use rustical_dav_push::{PushDelivery, Subscription, SubscriptionStore};
 use rustical_store::Error;
 
 #[async_trait]
@@ -18,6 +19,15 @@ impl SubscriptionStore for SqliteStore {
         .map_err(crate::Error::from)?)
     }
 
+    async fn get_topics(&self) -> Result<Vec<String>, Error> {
+        Ok(
+            sqlx::query_scalar!(r#"SELECT DISTINCT topic AS "topic!" FROM davpush_subscriptions"#)
+                .fetch_all(&self.db)
+                .await
+                .map_err(crate::Error::from)?,
+        )
+    }
+
     async fn get_subscription(&self, id: &str) -> Result<Subscription, Error> {
         Ok(sqlx::query_as!(
             Subscription,
@@ -56,4 +66,101 @@ impl SubscriptionStore for SqliteStore {
             .map_err(crate::Error::from)?;
         Ok(())
     }
+
+    async fn enqueue_delivery(
+        &self,
+        subscription_id: &str,
+        payload: &str,
+        unsubscribe: bool,
+    ) -> Result<(), Error> {
+        let now = chrono::Utc::now().naive_utc();
+        sqlx::query!(
+            r#"INSERT INTO davpush_deliveries (subscription_id, payload, next_attempt, unsubscribe) VALUES (?, ?, ?, ?)"#,
+            subscription_id,
+            payload,
+            now,
+            unsubscribe
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn get_due_deliveries(
+        &self,
+        now: NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<PushDelivery>, Error> {
+        Ok(sqlx::query_as!(
+            PushDelivery,
+            r#"SELECT id, subscription_id, payload, attempts, next_attempt, unsubscribe
+                FROM davpush_deliveries
+                WHERE next_attempt <= ?
+                ORDER BY next_attempt
+                LIMIT ?"#,
+            now,
+            limit
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(crate::Error::from)?)
+    }
+
+    async fn reschedule_delivery(
+        &self,
+        id: i64,
+        attempts: i64,
+        next_attempt: NaiveDateTime,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            r#"UPDATE davpush_deliveries SET attempts = ?, next_attempt = ? WHERE id = ?"#,
+            attempts,
+            next_attempt,
+            id
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn delete_delivery(&self, id: i64) -> Result<(), Error> {
+        sqlx::query!(r#"DELETE FROM davpush_deliveries WHERE id = ?"#, id)
+            .execute(&self.db)
+            .await
+            .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn count_deliveries(&self) -> Result<u64, Error> {
+        Ok(
+            sqlx::query!(r#"SELECT COUNT(*) AS "count!: u64" FROM davpush_deliveries"#)
+                .fetch_one(&self.db)
+                .await
+                .map_err(crate::Error::from)?
+                .count,
+        )
+    }
+
+    async fn get_vapid_key(&self) -> Result<Option<String>, Error> {
+        Ok(
+            sqlx::query!(r#"SELECT private_key FROM davpush_vapid_key WHERE id = 0"#)
+                .fetch_optional(&self.db)
+                .await
+                .map_err(crate::Error::from)?
+                .map(|row| row.private_key),
+        )
+    }
+
+    async fn insert_vapid_key(&self, private_key: &str) -> Result<(), Error> {
+        sqlx::query!(
+            r#"INSERT OR IGNORE INTO davpush_vapid_key (id, private_key) VALUES (0, ?)"#,
+            private_key
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
 }
@@ -162,6 +162,12 @@ END:VCALENDAR";
             .delete_calendar(&cal.principal, &cal.id, true)
             .await
             .unwrap();
+        let deleted_objects = cal_store
+            .get_deleted_objects(&cal.principal, &cal.id)
+            .await
+            .unwrap();
+        assert_eq!(deleted_objects.len(), 1);
+        assert_eq!(deleted_objects[0].0, object_id);
 
         //Verify we delete only BEFORE timestamp
         cal_store.prune_deleted_objects(now).await.expect("success");
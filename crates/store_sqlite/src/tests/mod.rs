@@ -2,16 +2,28 @@ use crate::{
     SqliteStore, addressbook_store::SqliteAddressbookStore, calendar_store::SqliteCalendarStore,
     create_db_pool, principal_store::SqlitePrincipalStore,
 };
+use chrono::{Duration, Utc};
 use rstest::{fixture, rstest};
-use rustical_store::auth::{AuthenticationProvider, Principal, PrincipalType};
+use rustical_store::EventBus;
+use rustical_store::auth::{
+    AppTokenScope, AuthenticationProvider, DavService, LoginFailureStore, LoginProtectionConfig,
+    LoginSubject, PasskeyCredential, Principal, PrincipalType, SecondFactorStore, Totp,
+    generate_recovery_codes, generate_totp_secret, sync_group_memberships, totp_generator,
+    validate_totp,
+};
 use sqlx::SqlitePool;
 
 mod addressbook_store;
 mod calendar_store;
+mod principal_store;
+mod session_store;
+mod subscription_store;
+mod webhook_store;
 
 #[derive(Debug, Clone)]
 pub struct TestStoreContext {
     pub db: SqlitePool,
+    pub events: EventBus,
     pub addr_store: SqliteAddressbookStore,
     pub cal_store: SqliteCalendarStore,
     pub principal_store: SqlitePrincipalStore,
@@ -20,11 +32,10 @@ pub struct TestStoreContext {
 
 #[fixture]
 pub async fn test_store_context() -> TestStoreContext {
-    let (send_addr, _recv) = tokio::sync::mpsc::channel(1);
-    let (send_cal, _recv) = tokio::sync::mpsc::channel(1);
+    let events = EventBus::default();
     let db = create_db_pool(":memory:", true).await.unwrap();
 
-    let principal_store = SqlitePrincipalStore::new(db.clone());
+    let principal_store = SqlitePrincipalStore::new(db.clone(), events.clone());
     // Populate with test data
     principal_store
         .insert_principal(
@@ -32,6 +43,7 @@ pub async fn test_store_context() -> TestStoreContext {
                 id: "user".to_owned(),
                 displayname: None,
                 memberships: vec![],
+                app_token: None,
                 password: None,
                 principal_type: PrincipalType::Individual,
             },
@@ -40,16 +52,23 @@ pub async fn test_store_context() -> TestStoreContext {
         .await
         .unwrap();
     principal_store
-        .add_app_token("user", "test".to_string(), "pass".to_string())
+        .add_app_token(
+            "user",
+            "test".to_string(),
+            "pass".to_string(),
+            AppTokenScope::default(),
+            None,
+        )
         .await
         .unwrap();
 
     TestStoreContext {
         db: db.clone(),
-        addr_store: SqliteAddressbookStore::new(db.clone(), send_addr, false),
-        cal_store: SqliteCalendarStore::new(db.clone(), send_cal, false),
+        addr_store: SqliteAddressbookStore::new(db.clone(), events.clone(), false),
+        cal_store: SqliteCalendarStore::new(db.clone(), events.clone(), false),
         principal_store,
         sub_store: SqliteStore::new(db),
+        events,
     }
 }
 
@@ -72,6 +91,7 @@ async fn test_invalid_principal_id(
                         principal_type: PrincipalType::Individual,
                         password: None,
                         memberships: vec![],
+                        app_token: None,
                     },
                     false,
                 )
@@ -91,6 +111,7 @@ async fn test_invalid_principal_id(
                         principal_type: PrincipalType::Individual,
                         password: None,
                         memberships: vec![],
+                        app_token: None,
                     },
                     false,
                 )
@@ -100,3 +121,552 @@ async fn test_invalid_principal_id(
         ": not allowed since '$' symbol is reserved for principal impersonation"
     );
 }
+
+#[rstest]
+#[tokio::test]
+async fn test_identity_provider(
+    #[from(test_store_context)]
+    #[future]
+    context: TestStoreContext,
+) {
+    let principal_store = context.await.principal_store;
+
+    assert_eq!(
+        principal_store.get_identity_provider("user").await.unwrap(),
+        None
+    );
+    principal_store
+        .set_identity_provider("user", "company")
+        .await
+        .unwrap();
+    principal_store
+        .set_identity_provider("user", "partners")
+        .await
+        .unwrap();
+    assert_eq!(
+        principal_store.get_identity_provider("user").await.unwrap(),
+        Some("partners".to_owned())
+    );
+
+    // The link is removed with the principal
+    principal_store.remove_principal("user").await.unwrap();
+    assert_eq!(
+        principal_store.get_identity_provider("user").await.unwrap(),
+        None
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_email(
+    #[from(test_store_context)]
+    #[future]
+    context: TestStoreContext,
+) {
+    let principal_store = context.await.principal_store;
+
+    assert_eq!(principal_store.get_email("user").await.unwrap(), None);
+    principal_store
+        .set_email("user", Some("user@example.com"))
+        .await
+        .unwrap();
+    assert_eq!(
+        principal_store.get_email("user").await.unwrap(),
+        Some("user@example.com".to_owned())
+    );
+    principal_store.set_email("user", None).await.unwrap();
+    assert_eq!(principal_store.get_email("user").await.unwrap(), None);
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_active(
+    #[from(test_store_context)]
+    #[future]
+    context: TestStoreContext,
+) {
+    let principal_store = context.await.principal_store;
+
+    assert!(principal_store.is_active("user").await.unwrap());
+    for _ in 0..2 {
+        principal_store.set_active("user", false).await.unwrap();
+    }
+    assert!(!principal_store.is_active("user").await.unwrap());
+    // The app token of the fixture doesn't work anymore
+    assert!(
+        principal_store
+            .validate_app_token("user", "pass")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    principal_store.set_active("user", true).await.unwrap();
+    assert!(principal_store.is_active("user").await.unwrap());
+    assert!(
+        principal_store
+            .validate_app_token("user", "pass")
+            .await
+            .unwrap()
+            .is_some()
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_sync_group_memberships(
+    #[from(test_store_context)]
+    #[future]
+    context: TestStoreContext,
+) {
+    let principal_store = context.await.principal_store;
+    for (id, principal_type) in [
+        ("former", PrincipalType::Group),
+        ("kept", PrincipalType::Group),
+        ("meeting-room", PrincipalType::Room),
+        ("boss", PrincipalType::Individual),
+        ("colleague", PrincipalType::Individual),
+    ] {
+        principal_store
+            .insert_principal(
+                Principal {
+                    id: id.to_owned(),
+                    displayname: None,
+                    principal_type,
+                    password: None,
+                    memberships: vec![],
+                    app_token: None,
+                },
+                false,
+            )
+            .await
+            .unwrap();
+    }
+    // Assigned by an admin, not by the identity source
+    for member_of in ["former", "kept", "meeting-room", "boss"] {
+        principal_store
+            .add_membership("user", member_of)
+            .await
+            .unwrap();
+    }
+    let memberships = |principal: Principal| {
+        let mut memberships = principal.memberships;
+        memberships.sort();
+        memberships
+    };
+
+    let groups = ["kept", "new", "colleague", "not:valid"].map(str::to_owned);
+    let user = principal_store
+        .get_principal("user")
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(
+        sync_group_memberships(&principal_store, &user, &groups)
+            .await
+            .unwrap()
+    );
+    let user = principal_store
+        .get_principal("user")
+        .await
+        .unwrap()
+        .unwrap();
+    // Other principal types are neither joined nor left
+    assert_eq!(
+        memberships(user.clone()),
+        ["boss", "kept", "meeting-room", "new"]
+    );
+    assert_eq!(
+        principal_store
+            .get_principal("new")
+            .await
+            .unwrap()
+            .unwrap()
+            .principal_type,
+        PrincipalType::Group
+    );
+    assert!(
+        principal_store
+            .get_principal("not:valid")
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    // Nothing changes the second time
+    assert!(
+        !sync_group_memberships(&principal_store, &user, &groups)
+            .await
+            .unwrap()
+    );
+
+    // Leaving all groups keeps the other memberships
+    assert!(
+        sync_group_memberships(&principal_store, &user, &[])
+            .await
+            .unwrap()
+    );
+    let user = principal_store
+        .get_principal("user")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(memberships(user), ["boss", "meeting-room"]);
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_totp(
+    #[from(test_store_context)]
+    #[future]
+    context: TestStoreContext,
+) {
+    let principal_store = context.await.principal_store;
+
+    assert!(principal_store.get_totp("user").await.unwrap().is_none());
+    assert!(
+        !validate_totp(&principal_store, "user", "123456")
+            .await
+            .unwrap()
+    );
+
+    let secret = generate_totp_secret();
+    let (recovery_codes, hashes) = generate_recovery_codes();
+    principal_store
+        .set_totp(
+            "user",
+            Some(Totp {
+                secret: secret.clone().into(),
+                recovery_codes: hashes,
+            }),
+        )
+        .await
+        .unwrap();
+
+    let code = totp_generator(&secret, "user")
+        .unwrap()
+        .generate_current()
+        .unwrap();
+    assert!(
+        validate_totp(&principal_store, "user", &code)
+            .await
+            .unwrap()
+    );
+    // Codes can't be replayed
+    assert!(
+        !validate_totp(&principal_store, "user", &code)
+            .await
+            .unwrap()
+    );
+
+    // Recovery codes can only be used once
+    let recovery_code = &recovery_codes[0];
+    assert!(
+        validate_totp(&principal_store, "user", recovery_code)
+            .await
+            .unwrap()
+    );
+    assert!(
+        !validate_totp(&principal_store, "user", recovery_code)
+            .await
+            .unwrap()
+    );
+    let totp = principal_store.get_totp("user").await.unwrap().unwrap();
+    assert_eq!(totp.recovery_codes.len(), recovery_codes.len() - 1);
+    // Using up a recovery code keeps the last used step
+    assert!(
+        !validate_totp(&principal_store, "user", &code)
+            .await
+            .unwrap()
+    );
+
+    principal_store.set_totp("user", None).await.unwrap();
+    assert!(principal_store.get_totp("user").await.unwrap().is_none());
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_passkeys(
+    #[from(test_store_context)]
+    #[future]
+    context: TestStoreContext,
+) {
+    let principal_store = context.await.principal_store;
+
+    assert!(
+        principal_store
+            .get_passkeys("user")
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    let passkey = PasskeyCredential {
+        id: "abcd".to_owned(),
+        name: "Laptop".to_owned(),
+        credential: "{}".to_owned(),
+        created_at: None,
+    };
+    principal_store
+        .upsert_passkey("user", passkey.clone())
+        .await
+        .unwrap();
+    principal_store
+        .upsert_passkey(
+            "user",
+            PasskeyCredential {
+                credential: r#"{"counter":1}"#.to_owned(),
+                ..passkey
+            },
+        )
+        .await
+        .unwrap();
+
+    let passkeys = principal_store.get_passkeys("user").await.unwrap();
+    assert_eq!(passkeys.len(), 1);
+    assert_eq!(passkeys[0].name, "Laptop");
+    assert_eq!(passkeys[0].credential, r#"{"counter":1}"#);
+    assert!(passkeys[0].created_at.is_some());
+
+    principal_store
+        .remove_passkey("user", "abcd")
+        .await
+        .unwrap();
+    assert!(
+        principal_store
+            .get_passkeys("user")
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_app_token_scope(
+    #[from(test_store_context)]
+    #[future]
+    context: TestStoreContext,
+) {
+    let principal_store = context.await.principal_store;
+
+    let scope = AppTokenScope {
+        services: vec![DavService::Caldav],
+        read_only: true,
+        collections: vec!["user/work".to_owned()],
+    };
+    let token_id = principal_store
+        .add_app_token(
+            "user",
+            "dashboard".to_owned(),
+            "secret".to_owned(),
+            scope.clone(),
+            Some(Utc::now() + Duration::days(1)),
+        )
+        .await
+        .unwrap();
+    let principal = principal_store
+        .validate_app_token("user", &format!("{token_id}_secret"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(principal.app_token.unwrap().scope, scope);
+
+    principal_store
+        .record_app_token_use("user", &token_id, Some("curl".to_owned()))
+        .await
+        .unwrap();
+    let tokens = principal_store.get_app_tokens("user").await.unwrap();
+    let token = tokens.iter().find(|token| token.id == token_id).unwrap();
+    assert!(token.last_used_at.is_some());
+    assert_eq!(token.last_user_agent.as_deref(), Some("curl"));
+
+    let expired_id = principal_store
+        .add_app_token(
+            "user",
+            "expired".to_owned(),
+            "secret".to_owned(),
+            AppTokenScope::default(),
+            Some(Utc::now() - Duration::days(1)),
+        )
+        .await
+        .unwrap();
+    assert!(
+        principal_store
+            .validate_app_token("user", &format!("{expired_id}_secret"))
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_app_token_digest_migration(
+    #[from(test_store_context)]
+    #[future]
+    context: TestStoreContext,
+) {
+    let context = context.await;
+    let principal_store = context.principal_store;
+
+    let token_id = principal_store
+        .add_app_token(
+            "user",
+            "phone".to_owned(),
+            "secret".to_owned(),
+            AppTokenScope::default(),
+            None,
+        )
+        .await
+        .unwrap();
+    // Tokens from before digests existed
+    sqlx::query("UPDATE app_tokens SET token_digest = NULL")
+        .execute(&context.db)
+        .await
+        .unwrap();
+    let digest_of = async |id: &str| {
+        principal_store
+            .get_app_tokens("user")
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|token| token.id == id)
+            .unwrap()
+            .digest
+    };
+    assert!(digest_of(&token_id).await.is_none());
+
+    for _ in 0..2 {
+        assert!(
+            principal_store
+                .validate_app_token("user", &format!("{token_id}_secret"))
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert!(digest_of(&token_id).await.is_some());
+    }
+    assert!(
+        principal_store
+            .validate_app_token("user", &format!("{token_id}_wrong"))
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_login_protection(
+    #[from(test_store_context)]
+    #[future]
+    context: TestStoreContext,
+) {
+    let principal_store = context.await.principal_store;
+    let config = LoginProtectionConfig {
+        max_principal_failures: 3,
+        max_address_failures: 5,
+        max_delay_ms: 0,
+        ..Default::default()
+    };
+    let address = "192.0.2.1".parse().unwrap();
+    let other_address = "192.0.2.2".parse().unwrap();
+
+    for _ in 0..2 {
+        config
+            .record_failure(&principal_store, "user", Some(address), "basic")
+            .await
+            .unwrap();
+    }
+    assert!(
+        config
+            .locked_until(&principal_store, "user", Some(other_address))
+            .await
+            .unwrap()
+            .is_none()
+    );
+    config
+        .record_failure(&principal_store, "user", Some(address), "basic")
+        .await
+        .unwrap();
+    // The principal is locked out from everywhere
+    assert!(
+        config
+            .locked_until(&principal_store, "user", Some(other_address))
+            .await
+            .unwrap()
+            .is_some()
+    );
+    // but can still use its app tokens
+    assert!(
+        config
+            .address_locked_until(&principal_store, Some(other_address))
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert_eq!(
+        principal_store.list_login_failures().await.unwrap().len(),
+        2
+    );
+
+    // A successful login only resets the principal
+    config
+        .record_success(&principal_store, "user")
+        .await
+        .unwrap();
+    let failures = principal_store.list_login_failures().await.unwrap();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].subject, LoginSubject::Address(address));
+    assert_eq!(failures[0].count, 3);
+
+    for _ in 0..2 {
+        config
+            .record_failure(&principal_store, "other", Some(address), "password")
+            .await
+            .unwrap();
+    }
+    assert!(
+        config
+            .locked_until(&principal_store, "user", Some(address))
+            .await
+            .unwrap()
+            .is_some()
+    );
+    principal_store
+        .remove_login_failures(&LoginSubject::Address(address))
+        .await
+        .unwrap();
+    assert!(
+        config
+            .locked_until(&principal_store, "user", Some(address))
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    assert_eq!(
+        principal_store
+            .prune_login_failures(Utc::now() + Duration::minutes(1))
+            .await
+            .unwrap(),
+        1
+    );
+    assert!(
+        principal_store
+            .list_login_failures()
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    // Concurrent failures are all counted
+    let failure = || config.record_failure(&principal_store, "user", None, "basic");
+    tokio::try_join!(failure(), failure(), failure()).unwrap();
+    let failures = principal_store
+        .get_login_failures(&LoginSubject::Principal("user".to_owned()))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(failures.count, 3);
+    assert!(failures.is_locked());
+}
@@ -0,0 +1,272 @@
+#[cfg(test)]
+mod tests {
+    use crate::tests::{TestStoreContext, test_store_context};
+    use chrono::NaiveDateTime;
+    use rstest::rstest;
+    use rustical_dav_push::{Subscription, SubscriptionStore};
+    use rustical_ical::CalendarObject;
+    use rustical_store::auth::{AuthenticationProvider, Principal, PrincipalType};
+    use rustical_store::{
+        Addressbook, AddressbookReadStore, AddressbookWriteStore, Calendar, CalendarMetadata,
+        CalendarReadStore, CalendarWriteStore, Error, Event, SESSION_KEY_USER,
+    };
+    use std::collections::HashMap;
+    use tower_sessions::{
+        cookie::time::{Duration, OffsetDateTime},
+        session::{Id, Record},
+        session_store::SessionStore as _,
+    };
+
+    const CALENDAR_OBJECT_ICS: &str = r"
+BEGIN:VCALENDAR
+VERSION:2.0
+PRODID:-//iCalendar Event//EN
+BEGIN:VEVENT
+UID:20260628T153000Z-123456@domain.com
+DTSTAMP:20260628T153000Z
+DTSTART:20260715T100000Z
+DTEND:20260715T110000Z
+SUMMARY:iCal Event
+END:VEVENT
+END:VCALENDAR";
+
+    fn calendar(principal: &str, push_topic: &str) -> Calendar {
+        Calendar {
+            principal: principal.to_owned(),
+            timezone_id: None,
+            deleted_at: None,
+            meta: CalendarMetadata::default(),
+            id: "cal".to_owned(),
+            synctoken: 0,
+            subscription_url: None,
+            push_topic: push_topic.to_owned(),
+            components: vec![],
+        }
+    }
+
+    fn addressbook(principal: &str, push_topic: &str) -> Addressbook {
+        Addressbook {
+            id: "book".to_owned(),
+            principal: principal.to_owned(),
+            displayname: None,
+            description: None,
+            deleted_at: None,
+            synctoken: 0,
+            push_topic: push_topic.to_owned(),
+        }
+    }
+
+    async fn populate(context: &TestStoreContext) {
+        let TestStoreContext {
+            cal_store,
+            addr_store,
+            ..
+        } = context;
+        cal_store
+            .insert_calendar(calendar("user", "cal-topic"))
+            .await
+            .unwrap();
+        cal_store
+            .put_object(
+                "user",
+                "cal",
+                "object",
+                CalendarObject::from_ics(CALENDAR_OBJECT_ICS.to_owned()).unwrap(),
+                false,
+            )
+            .await
+            .unwrap();
+        addr_store
+            .insert_addressbook(addressbook("user", "book-topic"))
+            .await
+            .unwrap();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_purge_principal(
+        #[future]
+        #[from(test_store_context)]
+        context: TestStoreContext,
+    ) {
+        let context = context.await;
+        populate(&context).await;
+        let TestStoreContext {
+            cal_store,
+            addr_store,
+            principal_store,
+            sub_store,
+            events,
+            ..
+        } = context;
+        sub_store
+            .upsert_subscription(Subscription {
+                id: "sub".to_owned(),
+                topic: "cal-topic".to_owned(),
+                expiration: NaiveDateTime::MAX,
+                push_resource: "https://push.example.com/endpoint".to_owned(),
+                public_key: "key".to_owned(),
+                public_key_type: "p256dh".to_owned(),
+                auth_secret: "secret".to_owned(),
+            })
+            .await
+            .unwrap();
+        let mut session = Record {
+            id: Id::default(),
+            data: HashMap::from([(SESSION_KEY_USER.to_owned(), "user".into())]),
+            expiry_date: OffsetDateTime::now_utc() + Duration::hours(1),
+        };
+        sub_store.create(&mut session).await.unwrap();
+
+        assert!(
+            principal_store.remove_principal("user").await.is_err(),
+            "The principal still owns collections"
+        );
+        let mut recv = events.subscribe("test");
+        principal_store.purge_principal("user").await.unwrap();
+
+        assert!(
+            principal_store
+                .get_principal("user")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            cal_store
+                .get_calendar("user", "cal", true)
+                .await
+                .unwrap_err()
+                .is_not_found()
+        );
+        assert!(
+            addr_store
+                .get_addressbook("user", "book", true)
+                .await
+                .unwrap_err()
+                .is_not_found()
+        );
+        assert!(sub_store.get_subscription("sub").await.is_err());
+        assert!(sub_store.load(&session.id).await.unwrap().is_none());
+
+        let mut received = vec![];
+        recv.try_recv_many(&mut received, 10);
+        // The calendar, the addressbook and its birthday calendar
+        let deleted = received
+            .iter()
+            .filter(|event| matches!(event, Event::CollectionDeleted { trashed: false, .. }))
+            .count();
+        assert_eq!(deleted, 3);
+        assert!(matches!(received.last(), Some(Event::PrincipalDeleted(id)) if id == "user"));
+
+        assert!(matches!(
+            principal_store.purge_principal("user").await,
+            Err(Error::NotFound)
+        ));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_transfer_collections(
+        #[future]
+        #[from(test_store_context)]
+        context: TestStoreContext,
+    ) {
+        let context = context.await;
+        populate(&context).await;
+        let TestStoreContext {
+            cal_store,
+            addr_store,
+            principal_store,
+            events,
+            ..
+        } = context;
+        principal_store
+            .insert_principal(
+                Principal {
+                    id: "other".to_owned(),
+                    displayname: None,
+                    memberships: vec![],
+                    app_token: None,
+                    password: None,
+                    principal_type: PrincipalType::Individual,
+                },
+                false,
+            )
+            .await
+            .unwrap();
+        let old_calendar = cal_store.get_calendar("user", "cal", false).await.unwrap();
+
+        // Nothing moves if a collection id is taken
+        cal_store
+            .insert_calendar(calendar("other", "other-topic"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            principal_store.transfer_collections("user", "other").await,
+            Err(Error::AlreadyExists)
+        ));
+        cal_store.get_calendar("user", "cal", false).await.unwrap();
+        addr_store
+            .get_addressbook("user", "book", false)
+            .await
+            .unwrap();
+        cal_store
+            .delete_calendar("other", "cal", false)
+            .await
+            .unwrap();
+
+        let mut recv = events.subscribe("test");
+        principal_store
+            .transfer_collections("user", "other")
+            .await
+            .unwrap();
+
+        assert!(cal_store.get_calendars("user").await.unwrap().is_empty());
+        assert!(
+            addr_store
+                .get_addressbooks("user")
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        let new_calendar = cal_store.get_calendar("other", "cal", false).await.unwrap();
+        assert_eq!(new_calendar.synctoken, old_calendar.synctoken);
+        assert_ne!(new_calendar.push_topic, old_calendar.push_topic);
+        cal_store
+            .get_object("other", "cal", "object", false)
+            .await
+            .unwrap();
+        addr_store
+            .get_addressbook("other", "book", false)
+            .await
+            .unwrap();
+
+        let mut received = vec![];
+        recv.try_recv_many(&mut received, 10);
+        assert!(received.iter().any(|event| matches!(
+            event,
+            Event::CollectionDeleted { collection, trashed: false }
+                if collection.push_topic == "cal-topic"
+        )));
+        assert!(received.iter().any(|event| matches!(
+            event,
+            Event::CollectionCreated(collection)
+                if collection.principal == "other" && collection.push_topic == new_calendar.push_topic
+        )));
+
+        // The synctoken keeps counting from where it was
+        cal_store
+            .delete_object("other", "cal", "object", false)
+            .await
+            .unwrap();
+        assert!(
+            cal_store
+                .get_calendar("other", "cal", false)
+                .await
+                .unwrap()
+                .synctoken
+                > old_calendar.synctoken
+        );
+    }
+}
@@ -0,0 +1,49 @@
+#[cfg(test)]
+mod tests {
+    use crate::tests::{TestStoreContext, test_store_context};
+    use rstest::rstest;
+    use rustical_store::{SESSION_KEY_USER, SessionStore};
+    use std::collections::HashMap;
+    use tower_sessions::{
+        cookie::time::{Duration, OffsetDateTime},
+        session::{Id, Record},
+        session_store::SessionStore as _,
+    };
+
+    fn record(user: &str, expires_in: Duration) -> Record {
+        Record {
+            id: Id::default(),
+            data: HashMap::from([(SESSION_KEY_USER.to_owned(), user.into())]),
+            expiry_date: OffsetDateTime::now_utc() + expires_in,
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_sessions(
+        #[future]
+        #[from(test_store_context)]
+        context: TestStoreContext,
+    ) {
+        let TestStoreContext { sub_store, .. } = context.await;
+
+        let mut session = record("user", Duration::hours(1));
+        sub_store.create(&mut session).await.unwrap();
+        let loaded = sub_store.load(&session.id).await.unwrap().unwrap();
+        assert_eq!(loaded.data, session.data);
+
+        let mut other = record("other", Duration::hours(1));
+        sub_store.create(&mut other).await.unwrap();
+        let mut expired = record("user", Duration::hours(-1));
+        sub_store.create(&mut expired).await.unwrap();
+        assert!(sub_store.load(&expired.id).await.unwrap().is_none());
+        assert_eq!(sub_store.delete_expired_sessions().await.unwrap(), 1);
+
+        sub_store.revoke_sessions("user").await.unwrap();
+        assert!(sub_store.load(&session.id).await.unwrap().is_none());
+        assert!(sub_store.load(&other.id).await.unwrap().is_some());
+
+        sub_store.delete(&other.id).await.unwrap();
+        assert!(sub_store.load(&other.id).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,93 @@
+#[cfg(test)]
+mod tests {
+    use crate::tests::{TestStoreContext, test_store_context};
+    use chrono::{NaiveDateTime, TimeDelta, Utc};
+    use rstest::rstest;
+    use rustical_dav_push::{Subscription, SubscriptionStore};
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_delivery_outbox(
+        #[future]
+        #[from(test_store_context)]
+        context: TestStoreContext,
+    ) {
+        let TestStoreContext { sub_store, .. } = context.await;
+
+        sub_store
+            .upsert_subscription(Subscription {
+                id: "sub".to_owned(),
+                topic: "topic".to_owned(),
+                expiration: NaiveDateTime::MAX,
+                push_resource: "https://push.example.com/endpoint".to_owned(),
+                public_key: "key".to_owned(),
+                public_key_type: "p256dh".to_owned(),
+                auth_secret: "secret".to_owned(),
+            })
+            .await
+            .unwrap();
+
+        sub_store
+            .enqueue_delivery("sub", "<push-message/>", false)
+            .await
+            .unwrap();
+        assert_eq!(sub_store.count_deliveries().await.unwrap(), 1);
+
+        let now = Utc::now().naive_utc();
+        let deliveries = sub_store.get_due_deliveries(now, 10).await.unwrap();
+        assert_eq!(deliveries.len(), 1);
+        let delivery = &deliveries[0];
+        assert_eq!(delivery.subscription_id, "sub");
+        assert_eq!(delivery.payload, "<push-message/>");
+        assert_eq!(delivery.attempts, 0);
+        assert!(!delivery.unsubscribe);
+
+        // After a failed attempt the delivery is not due until the backoff has passed
+        sub_store
+            .reschedule_delivery(delivery.id, 1, now + TimeDelta::minutes(1))
+            .await
+            .unwrap();
+        assert!(
+            sub_store
+                .get_due_deliveries(now, 10)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        let deliveries = sub_store
+            .get_due_deliveries(now + TimeDelta::minutes(2), 10)
+            .await
+            .unwrap();
+        assert_eq!(deliveries[0].attempts, 1);
+
+        sub_store.delete_delivery(deliveries[0].id).await.unwrap();
+        assert_eq!(sub_store.count_deliveries().await.unwrap(), 0);
+
+        // Removing the subscription also removes its pending deliveries
+        sub_store
+            .enqueue_delivery("sub", "<push-message/>", true)
+            .await
+            .unwrap();
+        sub_store.delete_subscription("sub").await.unwrap();
+        assert_eq!(sub_store.count_deliveries().await.unwrap(), 0);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_vapid_key(
+        #[future]
+        #[from(test_store_context)]
+        context: TestStoreContext,
+    ) {
+        let TestStoreContext { sub_store, .. } = context.await;
+
+        assert_eq!(sub_store.get_vapid_key().await.unwrap(), None);
+        sub_store.insert_vapid_key("first").await.unwrap();
+        // An existing key is never replaced
+        sub_store.insert_vapid_key("second").await.unwrap();
+        assert_eq!(
+            sub_store.get_vapid_key().await.unwrap().as_deref(),
+            Some("first")
+        );
+    }
+}
@@ -0,0 +1,102 @@
+#[cfg(test)]
+mod tests {
+    use crate::tests::{TestStoreContext, test_store_context};
+    use chrono::{TimeDelta, Utc};
+    use rstest::rstest;
+    use rustical_store::CollectionType;
+    use rustical_webhook::{DeliveryState, Webhook, WebhookStore};
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_webhook_deliveries(
+        #[future]
+        #[from(test_store_context)]
+        context: TestStoreContext,
+    ) {
+        let TestStoreContext { sub_store, .. } = context.await;
+
+        let webhook = Webhook::new(
+            "user".to_owned(),
+            Some((CollectionType::Calendar, "work".to_owned())),
+            "https://example.com/hook".to_owned(),
+        );
+        sub_store.insert_webhook(webhook.clone()).await.unwrap();
+        let webhooks = sub_store.get_webhooks("user").await.unwrap();
+        assert_eq!(webhooks.len(), 1);
+        assert_eq!(webhooks[0].collection, webhook.collection);
+        assert_eq!(webhooks[0].secret, webhook.secret);
+
+        sub_store
+            .enqueue_delivery(&webhook.id, "content", "{}")
+            .await
+            .unwrap();
+        let now = Utc::now().naive_utc();
+        let mut delivery = sub_store
+            .get_due_deliveries(now, 10)
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        assert_eq!(delivery.state, DeliveryState::Pending);
+        assert_eq!(delivery.event, "content");
+
+        // Backoff after a failed attempt
+        delivery.attempts = 1;
+        delivery.last_status = Some(503);
+        delivery.next_attempt = now + TimeDelta::minutes(1);
+        sub_store.update_delivery(&delivery).await.unwrap();
+        assert!(
+            sub_store
+                .get_due_deliveries(now, 10)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        // Delivered entries stay in the log until they are pruned
+        delivery.state = DeliveryState::Delivered;
+        delivery.last_status = Some(200);
+        sub_store.update_delivery(&delivery).await.unwrap();
+        assert!(
+            sub_store
+                .get_due_deliveries(now + TimeDelta::minutes(2), 10)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        let log = sub_store.get_deliveries(&webhook.id, 10).await.unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].state, DeliveryState::Delivered);
+        assert_eq!(log[0].last_status, Some(200));
+
+        assert_eq!(
+            sub_store
+                .prune_deliveries(now - TimeDelta::days(1))
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            sub_store
+                .prune_deliveries(now + TimeDelta::days(1))
+                .await
+                .unwrap(),
+            1
+        );
+
+        // Deleting the webhook also removes its outbox
+        sub_store
+            .enqueue_delivery(&webhook.id, "delete", "{}")
+            .await
+            .unwrap();
+        sub_store.delete_webhook(&webhook.id).await.unwrap();
+        assert!(sub_store.get_webhooks("user").await.unwrap().is_empty());
+        assert!(
+            sub_store
+                .get_due_deliveries(now + TimeDelta::minutes(2), 10)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
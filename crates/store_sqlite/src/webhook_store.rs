@@ -0,0 +1,246 @@
+use crate::SqliteStore;
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use rustical_store::Error;
+use rustical_webhook::{DeliveryState, Webhook, WebhookDelivery, WebhookStore};
+
+#[derive(Debug, Clone)]
+struct WebhookRow {
+    id: String,
+    principal: String,
+    collection_type: Option<String>,
+    collection_id: Option<String>,
+    url: String,
+    secret: String,
+    created_at: Option<NaiveDateTime>,
+}
+
+impl TryFrom<WebhookRow> for Webhook {
+    type Error = Error;
+
+    fn try_from(value: WebhookRow) -> Result<Self, Self::Error> {
+        let collection = match (value.collection_type, value.collection_id) {
+            (Some(collection_type), Some(collection_id)) => {
+                Some((collection_type.as_str().try_into()?, collection_id))
+            }
+            _ => None,
+        };
+        Ok(Self {
+            id: value.id,
+            principal: value.principal,
+            collection,
+            url: value.url,
+            secret: value.secret,
+            created_at: value.created_at,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct DeliveryRow {
+    id: i64,
+    webhook_id: String,
+    event: String,
+    payload: String,
+    state: String,
+    attempts: i64,
+    next_attempt: NaiveDateTime,
+    last_status: Option<i64>,
+    last_error: Option<String>,
+    created_at: NaiveDateTime,
+}
+
+impl TryFrom<DeliveryRow> for WebhookDelivery {
+    type Error = Error;
+
+    fn try_from(value: DeliveryRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: value.id,
+            webhook_id: value.webhook_id,
+            event: value.event,
+            payload: value.payload,
+            state: DeliveryState::try_from(value.state.as_str())
+                .map_err(|err| Error::Other(err.into()))?,
+            attempts: value.attempts,
+            next_attempt: value.next_attempt,
+            last_status: value
+                .last_status
+                .and_then(|status| u16::try_from(status).ok()),
+            last_error: value.last_error,
+            created_at: value.created_at,
+        })
+    }
+}
+
+#[async_trait]
+impl WebhookStore for SqliteStore {
+    async fn get_webhooks(&self, principal: &str) -> Result<Vec<Webhook>, Error> {
+        sqlx::query_as!(
+            WebhookRow,
+            r#"SELECT id, principal, collection_type, collection_id, url, secret, created_at AS "created_at: _"
+                FROM webhooks
+                WHERE principal = ?
+                ORDER BY created_at"#,
+            principal
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(crate::Error::from)?
+        .into_iter()
+        .map(Webhook::try_from)
+        .collect()
+    }
+
+    async fn get_all_webhooks(&self) -> Result<Vec<Webhook>, Error> {
+        sqlx::query_as!(
+            WebhookRow,
+            r#"SELECT id, principal, collection_type, collection_id, url, secret, created_at AS "created_at: _"
+                FROM webhooks
+                ORDER BY created_at"#
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(crate::Error::from)?
+        .into_iter()
+        .map(Webhook::try_from)
+        .collect()
+    }
+
+    async fn get_webhook(&self, id: &str) -> Result<Webhook, Error> {
+        sqlx::query_as!(
+            WebhookRow,
+            r#"SELECT id, principal, collection_type, collection_id, url, secret, created_at AS "created_at: _"
+                FROM webhooks
+                WHERE id = ?"#,
+            id
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(crate::Error::from)?
+        .try_into()
+    }
+
+    async fn insert_webhook(&self, webhook: Webhook) -> Result<(), Error> {
+        let (collection_type, collection_id) = webhook
+            .collection
+            .map(|(collection_type, collection_id)| (collection_type.as_str(), collection_id))
+            .unzip();
+        sqlx::query!(
+            r#"INSERT INTO webhooks (id, principal, collection_type, collection_id, url, secret) VALUES (?, ?, ?, ?, ?, ?)"#,
+            webhook.id,
+            webhook.principal,
+            collection_type,
+            collection_id,
+            webhook.url,
+            webhook.secret
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn delete_webhook(&self, id: &str) -> Result<(), Error> {
+        sqlx::query!(r#"DELETE FROM webhooks WHERE id = ?"#, id)
+            .execute(&self.db)
+            .await
+            .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn enqueue_delivery(
+        &self,
+        webhook_id: &str,
+        event: &str,
+        payload: &str,
+    ) -> Result<(), Error> {
+        let now = chrono::Utc::now().naive_utc();
+        sqlx::query!(
+            r#"INSERT INTO webhook_deliveries (webhook_id, event, payload, next_attempt) VALUES (?, ?, ?, ?)"#,
+            webhook_id,
+            event,
+            payload,
+            now
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn get_due_deliveries(
+        &self,
+        now: NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<WebhookDelivery>, Error> {
+        sqlx::query_as!(
+            DeliveryRow,
+            r#"SELECT id, webhook_id, event, payload, state, attempts, next_attempt, last_status, last_error, created_at
+                FROM webhook_deliveries
+                WHERE state = 'pending' AND next_attempt <= ?
+                ORDER BY next_attempt
+                LIMIT ?"#,
+            now,
+            limit
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(crate::Error::from)?
+        .into_iter()
+        .map(WebhookDelivery::try_from)
+        .collect()
+    }
+
+    async fn update_delivery(&self, delivery: &WebhookDelivery) -> Result<(), Error> {
+        let state = delivery.state.as_str();
+        sqlx::query!(
+            r#"UPDATE webhook_deliveries
+                SET state = ?, attempts = ?, next_attempt = ?, last_status = ?, last_error = ?
+                WHERE id = ?"#,
+            state,
+            delivery.attempts,
+            delivery.next_attempt,
+            delivery.last_status,
+            delivery.last_error,
+            delivery.id
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+
+    async fn get_deliveries(
+        &self,
+        webhook_id: &str,
+        limit: i64,
+    ) -> Result<Vec<WebhookDelivery>, Error> {
+        sqlx::query_as!(
+            DeliveryRow,
+            r#"SELECT id, webhook_id, event, payload, state, attempts, next_attempt, last_status, last_error, created_at
+                FROM webhook_deliveries
+                WHERE webhook_id = ?
+                ORDER BY id DESC
+                LIMIT ?"#,
+            webhook_id,
+            limit
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(crate::Error::from)?
+        .into_iter()
+        .map(WebhookDelivery::try_from)
+        .collect()
+    }
+
+    async fn prune_deliveries(&self, before: NaiveDateTime) -> Result<u64, Error> {
+        Ok(sqlx::query!(
+            r#"DELETE FROM webhook_deliveries WHERE state != 'pending' AND created_at < ?"#,
+            before
+        )
+        .execute(&self.db)
+        .await
+        .map_err(crate::Error::from)?
+        .rows_affected())
+    }
+}
@@ -0,0 +1,449 @@
+use super::{AddressbookCollection, VdirAddressbookStore};
+use crate::collection::{TRASH, list_dirs};
+use async_trait::async_trait;
+use chrono::{NaiveDateTime, Utc};
+use hex::ToHex;
+use rustical_ical::{CalendarObject, CalendarObjectType};
+use rustical_store::{
+    Addressbook, Calendar, CalendarMetadata, CalendarStorePruneDeleted, CollectionMetadata,
+    CollectionRef, Error, Event, PrefixedCalendarStore,
+    calendar_store::{CalendarReadStore, CalendarWriteStore},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::instrument;
+
+pub const BIRTHDAYS_PREFIX: &str = "_birthdays_";
+
+/// The birthday calendar's properties, kept in the sidecar of its addressbook
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BirthdayCalendarMeta {
+    #[serde(default)]
+    displayname: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    order: i64,
+    #[serde(default)]
+    color: Option<String>,
+    #[serde(default)]
+    timezone_id: Option<String>,
+    push_topic: String,
+    #[serde(default)]
+    deleted_at: Option<NaiveDateTime>,
+}
+
+impl BirthdayCalendarMeta {
+    pub(super) fn new(addressbook: &Addressbook) -> Self {
+        let push_topic = {
+            let mut hasher = Sha256::new();
+            hasher.update("birthdays");
+            hasher.update(&addressbook.push_topic);
+            format!(
+                "\"{}\"",
+                hasher.finalize().as_slice().encode_hex::<String>()
+            )
+        };
+        Self {
+            displayname: addressbook
+                .displayname
+                .as_ref()
+                .map(|name| format!("{name} birthdays")),
+            description: None,
+            order: 0,
+            color: None,
+            timezone_id: None,
+            push_topic,
+            deleted_at: None,
+        }
+    }
+}
+
+fn to_birthday_calendar(
+    principal: &str,
+    addressbook_id: &str,
+    collection: &AddressbookCollection,
+) -> Option<Calendar> {
+    let meta = collection.sidecar.meta.birthday_calendar.as_ref()?;
+    Some(Calendar {
+        principal: principal.to_owned(),
+        id: format!("{BIRTHDAYS_PREFIX}{addressbook_id}"),
+        meta: CalendarMetadata {
+            displayname: meta.displayname.clone(),
+            order: meta.order,
+            description: meta.description.clone(),
+            color: meta.color.clone(),
+        },
+        deleted_at: meta.deleted_at,
+        components: vec![CalendarObjectType::Event],
+        timezone_id: meta.timezone_id.clone(),
+        synctoken: collection.sidecar.synctoken,
+        subscription_url: None,
+        push_topic: meta.push_topic.clone(),
+    })
+}
+
+impl PrefixedCalendarStore for VdirAddressbookStore {
+    const PREFIX: &'static str = BIRTHDAYS_PREFIX;
+}
+
+impl VdirAddressbookStore {
+    async fn _get_birthday_calendar(
+        &self,
+        principal: &str,
+        id: &str,
+        show_deleted: bool,
+    ) -> Result<(AddressbookCollection, Calendar), Error> {
+        let addressbook_id = id.strip_prefix(BIRTHDAYS_PREFIX).ok_or(Error::NotFound)?;
+        let collection = self.load(principal, addressbook_id, true).await?;
+        let calendar = to_birthday_calendar(principal, addressbook_id, &collection)
+            .filter(|calendar| show_deleted || calendar.deleted_at.is_none())
+            .ok_or(Error::NotFound)?;
+        Ok((collection, calendar))
+    }
+
+    async fn _get_birthday_calendars(
+        &self,
+        principal: &str,
+        deleted: bool,
+    ) -> Result<Vec<Calendar>, Error> {
+        let mut addressbooks = self._get_addressbooks(principal, false).await?;
+        if deleted {
+            addressbooks.extend(self._get_addressbooks(principal, true).await?);
+        }
+        Ok(addressbooks
+            .iter()
+            .filter_map(|(id, collection)| to_birthday_calendar(principal, id, collection))
+            .filter(|calendar| calendar.deleted_at.is_some() == deleted)
+            .collect())
+    }
+
+    async fn _prune_deleted_birthday_calendars(
+        &self,
+        before: chrono::NaiveDate,
+    ) -> Result<u64, Error> {
+        let mut count = 0;
+        for principal in list_dirs(&self.root).await? {
+            let addressbooks = principal.join("addressbooks");
+            for path in [
+                list_dirs(&addressbooks).await?,
+                list_dirs(&addressbooks.join(TRASH)).await?,
+            ]
+            .concat()
+            {
+                let mut collection = AddressbookCollection::open(path).await?;
+                let meta = &mut collection.sidecar.meta;
+                if meta
+                    .birthday_calendar
+                    .as_ref()
+                    .and_then(|calendar| calendar.deleted_at)
+                    .is_some_and(|deleted_at| deleted_at.date() < before)
+                {
+                    meta.birthday_calendar = None;
+                    collection.save().await?;
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+}
+
+#[async_trait]
+impl CalendarReadStore for VdirAddressbookStore {
+    #[instrument]
+    async fn get_calendar(
+        &self,
+        principal: &str,
+        id: &str,
+        show_deleted: bool,
+    ) -> Result<Calendar, Error> {
+        let _guard = self.lock.lock().await;
+        Ok(self
+            ._get_birthday_calendar(principal, id, show_deleted)
+            .await?
+            .1)
+    }
+
+    #[instrument]
+    async fn get_calendars(&self, principal: &str) -> Result<Vec<Calendar>, Error> {
+        let _guard = self.lock.lock().await;
+        self._get_birthday_calendars(principal, false).await
+    }
+
+    #[instrument]
+    async fn get_deleted_calendars(&self, principal: &str) -> Result<Vec<Calendar>, Error> {
+        let _guard = self.lock.lock().await;
+        self._get_birthday_calendars(principal, true).await
+    }
+
+    #[instrument]
+    async fn sync_changes(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        synctoken: i64,
+    ) -> Result<(Vec<(String, CalendarObject)>, Vec<String>, i64), Error> {
+        let cal_id = cal_id
+            .strip_prefix(BIRTHDAYS_PREFIX)
+            .ok_or(Error::NotFound)?;
+        let _guard = self.lock.lock().await;
+        let (objects, deleted_objects, new_synctoken) =
+            self._sync_changes(principal, cal_id, synctoken).await?;
+
+        let mut out_objects = vec![];
+
+        for (object_id, object) in objects {
+            if let Some(birthday) = object.get_birthday_object()? {
+                out_objects.push((format!("{object_id}-birthday"), birthday));
+            }
+            if let Some(anniversary) = object.get_anniversary_object()? {
+                out_objects.push((format!("{object_id}-anniversary"), anniversary));
+            }
+        }
+
+        let deleted_objects = deleted_objects
+            .into_iter()
+            .flat_map(|object_id| {
+                [
+                    format!("{object_id}-birthday"),
+                    format!("{object_id}-anniversary"),
+                ]
+            })
+            .collect();
+
+        Ok((out_objects, deleted_objects, new_synctoken))
+    }
+
+    #[instrument]
+    async fn calendar_metadata(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<CollectionMetadata, Error> {
+        let cal_id = cal_id
+            .strip_prefix(BIRTHDAYS_PREFIX)
+            .ok_or(Error::NotFound)?;
+        let _guard = self.lock.lock().await;
+        Ok(self.load(principal, cal_id, true).await?.metadata())
+    }
+
+    #[instrument]
+    async fn get_objects(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<(String, CalendarObject)>, Error> {
+        let cal_id = cal_id
+            .strip_prefix(BIRTHDAYS_PREFIX)
+            .ok_or(Error::NotFound)?;
+        let _guard = self.lock.lock().await;
+        let mut objects = vec![];
+        for (object_id, object) in self._get_objects(principal, cal_id).await? {
+            if let Some(birthday) = object.get_birthday_object()? {
+                objects.push((format!("{object_id}-birthday"), birthday));
+            }
+            if let Some(anniversary) = object.get_anniversary_object()? {
+                objects.push((format!("{object_id}-anniversary"), anniversary));
+            }
+        }
+        Ok(objects)
+    }
+
+    #[instrument]
+    async fn get_object(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+        show_deleted: bool,
+    ) -> Result<CalendarObject, Error> {
+        let cal_id = cal_id
+            .strip_prefix(BIRTHDAYS_PREFIX)
+            .ok_or(Error::NotFound)?;
+        let (addressobject_id, date_type) = object_id.rsplit_once('-').ok_or(Error::NotFound)?;
+        let _guard = self.lock.lock().await;
+        let obj = self
+            ._get_object(principal, cal_id, addressobject_id, show_deleted)
+            .await?;
+        match date_type {
+            "birthday" => Ok(obj.get_birthday_object()?.ok_or(Error::NotFound)?),
+            "anniversary" => Ok(obj.get_anniversary_object()?.ok_or(Error::NotFound)?),
+            _ => Err(Error::NotFound),
+        }
+    }
+
+    fn is_read_only(&self, _cal_id: &str) -> bool {
+        true
+    }
+}
+
+#[async_trait]
+impl CalendarWriteStore for VdirAddressbookStore {
+    #[instrument]
+    async fn update_calendar(
+        &self,
+        principal: &str,
+        id: &str,
+        calendar: Calendar,
+    ) -> Result<(), Error> {
+        assert_eq!(principal, calendar.principal);
+        assert_eq!(id, calendar.id);
+        let _guard = self.lock.lock().await;
+        let (mut collection, old_cal) = self._get_birthday_calendar(principal, id, true).await?;
+        let props = old_cal.changed_properties(&calendar);
+
+        if let Some(meta) = collection.sidecar.meta.birthday_calendar.as_mut() {
+            meta.displayname.clone_from(&calendar.meta.displayname);
+            meta.description.clone_from(&calendar.meta.description);
+            meta.order = calendar.meta.order;
+            meta.color.clone_from(&calendar.meta.color);
+            meta.timezone_id.clone_from(&calendar.timezone_id);
+            meta.push_topic.clone_from(&calendar.push_topic);
+        }
+        collection.save().await?;
+
+        if !props.is_empty() {
+            self.events.publish(Event::CollectionUpdated {
+                collection: CollectionRef::from(&old_cal),
+                props,
+            });
+        }
+        Ok(())
+    }
+
+    #[instrument]
+    async fn insert_calendar(&self, calendar: Calendar) -> Result<(), Error> {
+        let addressbook_id = calendar
+            .id
+            .strip_prefix(BIRTHDAYS_PREFIX)
+            .ok_or(Error::NotFound)?;
+        let _guard = self.lock.lock().await;
+        let mut collection = self.load(&calendar.principal, addressbook_id, true).await?;
+        if collection.sidecar.meta.birthday_calendar.is_some() {
+            return Err(Error::AlreadyExists);
+        }
+        collection.sidecar.meta.birthday_calendar = Some(BirthdayCalendarMeta {
+            displayname: calendar.meta.displayname.clone(),
+            description: calendar.meta.description.clone(),
+            order: calendar.meta.order,
+            color: calendar.meta.color.clone(),
+            timezone_id: calendar.timezone_id.clone(),
+            push_topic: calendar.push_topic.clone(),
+            deleted_at: None,
+        });
+        collection.save().await?;
+
+        self.events
+            .publish(Event::CollectionCreated(CollectionRef::from(&calendar)));
+        Ok(())
+    }
+
+    #[instrument]
+    async fn delete_calendar(
+        &self,
+        principal: &str,
+        id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        if !id.starts_with(BIRTHDAYS_PREFIX) {
+            return Ok(());
+        }
+        let _guard = self.lock.lock().await;
+        let (mut collection, cal) = match self._get_birthday_calendar(principal, id, true).await {
+            Ok(res) => res,
+            Err(Error::NotFound) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        let meta = &mut collection.sidecar.meta.birthday_calendar;
+        if use_trashbin {
+            if let Some(meta) = meta.as_mut() {
+                meta.deleted_at = Some(Utc::now().naive_utc());
+            }
+        } else {
+            *meta = None;
+        }
+        collection.save().await?;
+
+        self.events.publish(Event::CollectionDeleted {
+            collection: CollectionRef::from(&cal),
+            trashed: use_trashbin,
+        });
+        Ok(())
+    }
+
+    #[instrument]
+    async fn restore_calendar(&self, principal: &str, id: &str) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        let (mut collection, mut calendar) =
+            self._get_birthday_calendar(principal, id, true).await?;
+        if let Some(meta) = collection.sidecar.meta.birthday_calendar.as_mut() {
+            meta.deleted_at = None;
+        }
+        collection.save().await?;
+        calendar.deleted_at = None;
+
+        self.events
+            .publish(Event::CollectionRestored(CollectionRef::from(&calendar)));
+        Ok(())
+    }
+
+    #[instrument]
+    async fn import_calendar(
+        &self,
+        _calendar: Calendar,
+        _objects: Vec<CalendarObject>,
+        _merge_existing: bool,
+    ) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    #[instrument]
+    async fn put_objects(
+        &self,
+        _principal: &str,
+        _cal_id: &str,
+        _objects: Vec<(String, CalendarObject)>,
+        _overwrite: bool,
+    ) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    #[instrument]
+    async fn delete_object(
+        &self,
+        _principal: &str,
+        _cal_id: &str,
+        _object_id: &str,
+        _use_trashbin: bool,
+    ) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    #[instrument]
+    async fn restore_object(
+        &self,
+        _principal: &str,
+        _cal_id: &str,
+        _object_id: &str,
+    ) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+}
+
+#[async_trait]
+impl CalendarStorePruneDeleted for VdirAddressbookStore {
+    #[instrument(skip(self), fields(count = tracing::field::Empty))]
+    async fn prune_deleted_calendars(&self, before: chrono::NaiveDate) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        let count = self._prune_deleted_birthday_calendars(before).await?;
+        tracing::Span::current().record("count", count);
+        Ok(())
+    }
+
+    #[instrument]
+    async fn prune_deleted_objects(&self, _before: chrono::NaiveDate) -> Result<(), Error> {
+        Ok(())
+    }
+}
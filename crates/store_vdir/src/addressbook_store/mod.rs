@@ -0,0 +1,667 @@
+use crate::collection::{Collection, CollectionMeta, TRASH, check_id, list_dirs};
+use async_trait::async_trait;
+use chrono::Utc;
+use rustical_ical::AddressObject;
+use rustical_store::auth::AuthenticationProvider;
+use rustical_store::{
+    Addressbook, AddressbookReadStore, AddressbookWriteStore, CollectionMetadata, CollectionRef,
+    Error, Event, EventBus, ObjectChange, ObjectChangeKind, synctoken::format_synctoken,
+};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::{error, instrument, warn};
+
+pub mod birthday_calendar;
+use birthday_calendar::BirthdayCalendarMeta;
+
+#[cfg(test)]
+#[path = "../../../store_sqlite/src/addressbook_store/tests.rs"]
+mod tests;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddressbookMeta {
+    #[serde(default)]
+    description: Option<String>,
+    push_topic: String,
+    // None once the birthday calendar got deleted for good
+    #[serde(default)]
+    birthday_calendar: Option<BirthdayCalendarMeta>,
+}
+
+impl Default for AddressbookMeta {
+    fn default() -> Self {
+        Self {
+            description: None,
+            push_topic: uuid::Uuid::new_v4().to_string(),
+            birthday_calendar: None,
+        }
+    }
+}
+
+impl CollectionMeta for AddressbookMeta {
+    type Object = AddressObject;
+}
+
+type AddressbookCollection = Collection<AddressbookMeta>;
+
+fn to_addressbook(principal: &str, id: &str, collection: &AddressbookCollection) -> Addressbook {
+    Addressbook {
+        id: id.to_owned(),
+        principal: principal.to_owned(),
+        displayname: collection.displayname.clone(),
+        description: collection.sidecar.meta.description.clone(),
+        deleted_at: collection.sidecar.deleted_at,
+        synctoken: collection.sidecar.synctoken,
+        push_topic: collection.sidecar.meta.push_topic.clone(),
+    }
+}
+
+/// Stores every addressbook as a vdir at `<root>/<principal>/addressbooks/<id>/`
+#[derive(Clone)]
+pub struct VdirAddressbookStore {
+    root: PathBuf,
+    principals: Arc<dyn AuthenticationProvider>,
+    events: EventBus,
+    skip_broken: bool,
+    // Serialises all access to the files
+    lock: Arc<Mutex<()>>,
+}
+
+impl std::fmt::Debug for VdirAddressbookStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VdirAddressbookStore")
+            .field("root", &self.root)
+            .field("skip_broken", &self.skip_broken)
+            .finish_non_exhaustive()
+    }
+}
+
+impl VdirAddressbookStore {
+    pub fn new(
+        root: impl Into<PathBuf>,
+        principals: Arc<dyn AuthenticationProvider>,
+        events: EventBus,
+        skip_broken: bool,
+    ) -> Self {
+        Self {
+            root: root.into(),
+            principals,
+            events,
+            skip_broken,
+            lock: Arc::default(),
+        }
+    }
+
+    fn addressbooks_path(&self, principal: &str) -> PathBuf {
+        self.root.join(principal).join("addressbooks")
+    }
+
+    fn addressbook_path(&self, principal: &str, id: &str) -> PathBuf {
+        self.addressbooks_path(principal).join(id)
+    }
+
+    fn trashed_addressbook_path(&self, principal: &str, id: &str) -> PathBuf {
+        self.addressbooks_path(principal).join(TRASH).join(id)
+    }
+
+    #[allow(clippy::missing_panics_doc)]
+    pub async fn validate_objects(&self, principal: &str) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        let mut success = true;
+        for path in list_dirs(&self.addressbooks_path(principal)).await? {
+            let collection = AddressbookCollection::open(path).await?;
+            for (object_id, res) in collection.get_objects().await? {
+                if let Err(err) = res {
+                    warn!(
+                        "Invalid address object found at {}/{object_id}.vcf. Error: {err}",
+                        collection.path().display()
+                    );
+                    success = false;
+                }
+            }
+        }
+        if !success {
+            if self.skip_broken {
+                error!(
+                    "Not all address objects are valid. Since data_store.vdir.skip_broken=true they will be hidden. You are still advised to manually remove or repair the object. If you need help feel free to open up an issue on GitHub."
+                );
+            } else {
+                error!(
+                    "Not all address objects are valid. Since data_store.vdir.skip_broken=false this causes a panic. Remove or repair the broken objects manually or set data_store.vdir.skip_broken=true as a temporary solution to ignore the error. If you need help feel free to open up an issue on GitHub."
+                );
+                panic!();
+            }
+        }
+        Ok(())
+    }
+
+    // Opens an addressbook and commits changes that were made to its files by someone else
+    async fn load(
+        &self,
+        principal: &str,
+        id: &str,
+        show_deleted: bool,
+    ) -> Result<AddressbookCollection, Error> {
+        check_id(principal)?;
+        check_id(id)?;
+        let mut collection = match AddressbookCollection::open(self.addressbook_path(principal, id))
+            .await
+            .map_err(Error::from)
+        {
+            Err(Error::NotFound) if show_deleted => {
+                let mut collection =
+                    AddressbookCollection::open(self.trashed_addressbook_path(principal, id))
+                        .await?;
+                collection
+                    .sidecar
+                    .deleted_at
+                    .get_or_insert_with(|| Utc::now().naive_utc());
+                collection
+            }
+            res => res?,
+        };
+
+        let changes = collection.refresh().await?;
+        if !changes.is_empty() {
+            collection.save().await?;
+            self.events.publish(Event::ObjectsChanged {
+                collection: CollectionRef::from(&to_addressbook(principal, id, &collection)),
+                sync_token: format_synctoken(collection.sidecar.synctoken),
+                changes,
+            });
+        }
+        Ok(collection)
+    }
+
+    async fn _get_addressbooks(
+        &self,
+        principal: &str,
+        deleted: bool,
+    ) -> Result<Vec<(String, AddressbookCollection)>, Error> {
+        check_id(principal)?;
+        let path = if deleted {
+            self.addressbooks_path(principal).join(TRASH)
+        } else {
+            self.addressbooks_path(principal)
+        };
+        let mut addressbooks = vec![];
+        for path in list_dirs(&path).await? {
+            let Some(id) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            addressbooks.push((id.to_owned(), self.load(principal, id, deleted).await?));
+        }
+        Ok(addressbooks)
+    }
+
+    async fn _insert_addressbook(
+        &self,
+        addressbook: &Addressbook,
+    ) -> Result<AddressbookCollection, Error> {
+        check_id(&addressbook.principal)?;
+        check_id(&addressbook.id)?;
+        if self
+            .principals
+            .get_principal(&addressbook.principal)
+            .await?
+            .is_none()
+        {
+            return Err(Error::NotFound);
+        }
+        if tokio::fs::try_exists(
+            self.trashed_addressbook_path(&addressbook.principal, &addressbook.id),
+        )
+        .await
+        .map_err(crate::Error::from)?
+        {
+            return Err(Error::AlreadyExists);
+        }
+        let mut collection = AddressbookCollection::new(
+            self.addressbook_path(&addressbook.principal, &addressbook.id),
+            addressbook.displayname.clone(),
+            None,
+            AddressbookMeta {
+                description: addressbook.description.clone(),
+                push_topic: addressbook.push_topic.clone(),
+                birthday_calendar: Some(BirthdayCalendarMeta::new(addressbook)),
+            },
+        );
+        collection.sidecar.synctoken = addressbook.synctoken;
+        collection.create().await?;
+        Ok(collection)
+    }
+
+    async fn _sync_changes(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        synctoken: i64,
+    ) -> Result<(Vec<(String, AddressObject)>, Vec<String>, i64), Error> {
+        let collection = self.load(principal, addressbook_id, false).await?;
+
+        let mut updated_objects = vec![];
+        let mut deleted_objects = vec![];
+
+        for object_id in collection.changes_since(synctoken) {
+            match collection
+                .get_object(&object_id, false)
+                .await
+                .map_err(Error::from)
+            {
+                Ok(object) => updated_objects.push((object_id, object)),
+                Err(Error::NotFound) => deleted_objects.push(object_id),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok((
+            updated_objects,
+            deleted_objects,
+            collection.sidecar.synctoken,
+        ))
+    }
+
+    async fn _get_objects(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<(String, AddressObject)>, Error> {
+        let objects = self
+            .load(principal, addressbook_id, true)
+            .await?
+            .get_objects()
+            .await?;
+        self.collect_objects(objects)
+    }
+
+    async fn _get_deleted_objects(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<(String, AddressObject)>, Error> {
+        let objects = self
+            .load(principal, addressbook_id, true)
+            .await?
+            .get_trashed_objects()
+            .await?;
+        self.collect_objects(objects)
+    }
+
+    fn collect_objects(
+        &self,
+        objects: Vec<(String, Result<AddressObject, caldata::parser::ParserError>)>,
+    ) -> Result<Vec<(String, AddressObject)>, Error> {
+        if self.skip_broken {
+            Ok(objects
+                .into_iter()
+                .filter_map(|(id, res)| Some((id, res.ok()?)))
+                .collect())
+        } else {
+            Ok(objects
+                .into_iter()
+                .map(|(id, res)| res.map(|obj| (id, obj)))
+                .collect::<Result<Vec<_>, _>>()?)
+        }
+    }
+
+    async fn _get_object(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+        show_deleted: bool,
+    ) -> Result<AddressObject, Error> {
+        let collection = self.load(principal, addressbook_id, true).await?;
+        Ok(collection.get_object(object_id, show_deleted).await?)
+    }
+}
+
+#[async_trait]
+impl AddressbookReadStore for VdirAddressbookStore {
+    #[instrument]
+    async fn get_addressbook(
+        &self,
+        principal: &str,
+        id: &str,
+        show_deleted: bool,
+    ) -> Result<Addressbook, Error> {
+        let _guard = self.lock.lock().await;
+        let collection = self.load(principal, id, show_deleted).await?;
+        Ok(to_addressbook(principal, id, &collection))
+    }
+
+    #[instrument]
+    async fn get_addressbooks(&self, principal: &str) -> Result<Vec<Addressbook>, Error> {
+        let _guard = self.lock.lock().await;
+        Ok(self
+            ._get_addressbooks(principal, false)
+            .await?
+            .iter()
+            .map(|(id, collection)| to_addressbook(principal, id, collection))
+            .collect())
+    }
+
+    #[instrument]
+    async fn get_deleted_addressbooks(&self, principal: &str) -> Result<Vec<Addressbook>, Error> {
+        let _guard = self.lock.lock().await;
+        Ok(self
+            ._get_addressbooks(principal, true)
+            .await?
+            .iter()
+            .map(|(id, collection)| to_addressbook(principal, id, collection))
+            .collect())
+    }
+
+    #[instrument]
+    async fn sync_changes(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        synctoken: i64,
+    ) -> Result<(Vec<(String, AddressObject)>, Vec<String>, i64), Error> {
+        let _guard = self.lock.lock().await;
+        self._sync_changes(principal, addressbook_id, synctoken)
+            .await
+    }
+
+    #[instrument]
+    async fn addressbook_metadata(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<CollectionMetadata, Error> {
+        let _guard = self.lock.lock().await;
+        Ok(self.load(principal, addressbook_id, true).await?.metadata())
+    }
+
+    #[instrument]
+    async fn get_objects(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<(String, AddressObject)>, Error> {
+        let _guard = self.lock.lock().await;
+        self._get_objects(principal, addressbook_id).await
+    }
+
+    #[instrument]
+    async fn get_deleted_objects(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<Vec<(String, AddressObject)>, Error> {
+        let _guard = self.lock.lock().await;
+        self._get_deleted_objects(principal, addressbook_id).await
+    }
+
+    #[instrument]
+    async fn get_object(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+        show_deleted: bool,
+    ) -> Result<AddressObject, Error> {
+        let _guard = self.lock.lock().await;
+        self._get_object(principal, addressbook_id, object_id, show_deleted)
+            .await
+    }
+}
+
+#[async_trait]
+impl AddressbookWriteStore for VdirAddressbookStore {
+    #[instrument]
+    async fn update_addressbook(
+        &self,
+        principal: &str,
+        id: &str,
+        addressbook: Addressbook,
+    ) -> Result<(), Error> {
+        assert_eq!(principal, &addressbook.principal);
+        assert_eq!(id, &addressbook.id);
+        let _guard = self.lock.lock().await;
+        let mut collection = self.load(principal, id, true).await?;
+        let props = to_addressbook(principal, id, &collection).changed_properties(&addressbook);
+
+        collection.displayname.clone_from(&addressbook.displayname);
+        collection
+            .sidecar
+            .meta
+            .description
+            .clone_from(&addressbook.description);
+        collection
+            .sidecar
+            .meta
+            .push_topic
+            .clone_from(&addressbook.push_topic);
+        collection.save().await?;
+
+        if !props.is_empty() {
+            self.events.publish(Event::CollectionUpdated {
+                collection: CollectionRef::from(&addressbook),
+                props,
+            });
+        }
+        Ok(())
+    }
+
+    #[instrument]
+    async fn insert_addressbook(&self, addressbook: Addressbook) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        self._insert_addressbook(&addressbook).await?;
+        self.events
+            .publish(Event::CollectionCreated(CollectionRef::from(&addressbook)));
+        Ok(())
+    }
+
+    #[instrument]
+    async fn delete_addressbook(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        let mut collection = match self.load(principal, addressbook_id, true).await {
+            Ok(collection) => collection,
+            Err(Error::NotFound) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let addressbook = to_addressbook(principal, addressbook_id, &collection);
+
+        let trash_path = self.trashed_addressbook_path(principal, addressbook_id);
+        if use_trashbin {
+            collection.sidecar.deleted_at = Some(Utc::now().naive_utc());
+            collection.save().await?;
+            if collection.path() != trash_path {
+                collection.move_to(trash_path).await?;
+            }
+        } else {
+            collection.remove().await?;
+        }
+
+        self.events.publish(Event::CollectionDeleted {
+            collection: CollectionRef::from(&addressbook),
+            trashed: use_trashbin,
+        });
+        Ok(())
+    }
+
+    #[instrument]
+    async fn restore_addressbook(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+    ) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        let mut collection = self.load(principal, addressbook_id, true).await?;
+        let path = self.addressbook_path(principal, addressbook_id);
+        if collection.path() != path {
+            collection.move_to(path).await?;
+        }
+        collection.sidecar.deleted_at = None;
+        collection.save().await?;
+
+        self.events
+            .publish(Event::CollectionRestored(CollectionRef::from(
+                &to_addressbook(principal, addressbook_id, &collection),
+            )));
+        Ok(())
+    }
+
+    #[instrument]
+    async fn put_object(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+        object: AddressObject,
+        overwrite: bool,
+    ) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        let mut collection = self.load(principal, addressbook_id, true).await?;
+        let kind = collection.put_object(object_id, &object, overwrite).await?;
+        collection.save().await?;
+
+        self.events.publish(Event::ObjectsChanged {
+            collection: CollectionRef::from(&to_addressbook(
+                principal,
+                addressbook_id,
+                &collection,
+            )),
+            sync_token: format_synctoken(collection.sidecar.synctoken),
+            changes: vec![ObjectChange {
+                id: object_id.to_owned(),
+                etag: Some(object.get_etag()),
+                kind,
+            }],
+        });
+        Ok(())
+    }
+
+    #[instrument]
+    async fn delete_object(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        let mut collection = self.load(principal, addressbook_id, true).await?;
+        collection.delete_object(object_id, use_trashbin).await?;
+        collection.save().await?;
+
+        self.events.publish(Event::ObjectsChanged {
+            collection: CollectionRef::from(&to_addressbook(
+                principal,
+                addressbook_id,
+                &collection,
+            )),
+            sync_token: format_synctoken(collection.sidecar.synctoken),
+            changes: vec![ObjectChange {
+                id: object_id.to_owned(),
+                etag: None,
+                kind: ObjectChangeKind::Deleted,
+            }],
+        });
+        Ok(())
+    }
+
+    #[instrument]
+    async fn restore_object(
+        &self,
+        principal: &str,
+        addressbook_id: &str,
+        object_id: &str,
+    ) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        let mut collection = self.load(principal, addressbook_id, true).await?;
+        collection.restore_object(object_id).await?;
+        collection.save().await?;
+        let etag = collection.get_object(object_id, false).await?.get_etag();
+
+        self.events.publish(Event::ObjectsChanged {
+            collection: CollectionRef::from(&to_addressbook(
+                principal,
+                addressbook_id,
+                &collection,
+            )),
+            sync_token: format_synctoken(collection.sidecar.synctoken),
+            changes: vec![ObjectChange {
+                id: object_id.to_owned(),
+                etag: Some(etag),
+                kind: ObjectChangeKind::Created,
+            }],
+        });
+        Ok(())
+    }
+
+    #[instrument(skip(objects))]
+    async fn import_addressbook(
+        &self,
+        addressbook: Addressbook,
+        objects: Vec<(String, AddressObject)>,
+        merge_existing: bool,
+    ) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        let (principal, id) = (addressbook.principal.as_str(), addressbook.id.as_str());
+
+        let existing = match self.load(principal, id, true).await {
+            Ok(collection) => Some(collection),
+            Err(Error::NotFound) => None,
+            Err(err) => return Err(err),
+        };
+        if existing.is_some() && !merge_existing {
+            return Err(Error::AlreadyExists);
+        }
+        // Check for conflicts upfront since there's no transaction to roll back
+        let mut ids = std::collections::HashSet::new();
+        for (object_id, _) in &objects {
+            let conflicts = existing.as_ref().is_some_and(|collection| {
+                collection.sidecar.objects.contains_key(object_id)
+                    || collection.sidecar.trashed.contains_key(object_id)
+            });
+            if conflicts || !ids.insert(object_id) {
+                return Err(Error::AlreadyExists);
+            }
+        }
+
+        let is_new = existing.is_none();
+        let mut collection = match existing {
+            Some(collection) => collection,
+            None => self._insert_addressbook(&addressbook).await?,
+        };
+
+        let mut changes = vec![];
+        let mut result = Ok(());
+        for (object_id, object) in objects {
+            if let Err(err) = collection.put_object(&object_id, &object, false).await {
+                result = Err(err);
+                break;
+            }
+            changes.push(ObjectChange {
+                id: object_id,
+                etag: Some(object.get_etag()),
+                kind: ObjectChangeKind::Created,
+            });
+        }
+        collection.save().await?;
+
+        let collection_ref = CollectionRef::from(&to_addressbook(principal, id, &collection));
+        if is_new {
+            self.events
+                .publish(Event::CollectionCreated(collection_ref.clone()));
+        }
+        if !changes.is_empty() {
+            self.events.publish(Event::ObjectsChanged {
+                collection: collection_ref,
+                sync_token: format_synctoken(collection.sidecar.synctoken),
+                changes,
+            });
+        }
+        Ok(result?)
+    }
+}
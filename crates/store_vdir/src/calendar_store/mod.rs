@@ -0,0 +1,704 @@
+use crate::collection::{Collection, CollectionMeta, TRASH, check_id, list_dirs};
+use async_trait::async_trait;
+use chrono::Utc;
+use rustical_ical::{CalendarObject, CalendarObjectType};
+use rustical_store::auth::AuthenticationProvider;
+use rustical_store::calendar_store::{CalendarReadStore, CalendarWriteStore};
+use rustical_store::synctoken::format_synctoken;
+use rustical_store::{
+    Calendar, CalendarMetadata, CalendarStorePruneDeleted, CollectionMetadata, CollectionRef,
+    Error, Event, EventBus, ObjectChange, ObjectChangeKind,
+};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::{error, instrument, warn};
+
+#[cfg(test)]
+#[path = "../../../store_sqlite/src/calendar_store/tests.rs"]
+mod tests;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CalendarMeta {
+    #[serde(default)]
+    order: i64,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    timezone_id: Option<String>,
+    #[serde(default)]
+    subscription_url: Option<String>,
+    push_topic: String,
+    components: Vec<CalendarObjectType>,
+}
+
+impl Default for CalendarMeta {
+    fn default() -> Self {
+        Self {
+            order: 0,
+            description: None,
+            timezone_id: None,
+            subscription_url: None,
+            push_topic: uuid::Uuid::new_v4().to_string(),
+            components: vec![
+                CalendarObjectType::Event,
+                CalendarObjectType::Todo,
+                CalendarObjectType::Journal,
+            ],
+        }
+    }
+}
+
+impl CollectionMeta for CalendarMeta {
+    type Object = CalendarObject;
+}
+
+type CalendarCollection = Collection<CalendarMeta>;
+
+fn to_calendar(principal: &str, id: &str, collection: &CalendarCollection) -> Calendar {
+    let meta = &collection.sidecar.meta;
+    Calendar {
+        principal: principal.to_owned(),
+        id: id.to_owned(),
+        meta: CalendarMetadata {
+            displayname: collection.displayname.clone(),
+            order: meta.order,
+            description: meta.description.clone(),
+            color: collection.color.clone(),
+        },
+        timezone_id: meta.timezone_id.clone(),
+        deleted_at: collection.sidecar.deleted_at,
+        synctoken: collection.sidecar.synctoken,
+        subscription_url: meta.subscription_url.clone(),
+        push_topic: meta.push_topic.clone(),
+        components: meta.components.clone(),
+    }
+}
+
+/// Stores every calendar as a vdir at `<root>/<principal>/calendars/<id>/`
+#[derive(Clone)]
+pub struct VdirCalendarStore {
+    root: PathBuf,
+    principals: Arc<dyn AuthenticationProvider>,
+    events: EventBus,
+    skip_broken: bool,
+    // Serialises all access to the files
+    lock: Arc<Mutex<()>>,
+}
+
+impl std::fmt::Debug for VdirCalendarStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VdirCalendarStore")
+            .field("root", &self.root)
+            .field("skip_broken", &self.skip_broken)
+            .finish_non_exhaustive()
+    }
+}
+
+impl VdirCalendarStore {
+    pub fn new(
+        root: impl Into<PathBuf>,
+        principals: Arc<dyn AuthenticationProvider>,
+        events: EventBus,
+        skip_broken: bool,
+    ) -> Self {
+        Self {
+            root: root.into(),
+            principals,
+            events,
+            skip_broken,
+            lock: Arc::default(),
+        }
+    }
+
+    fn calendars_path(&self, principal: &str) -> PathBuf {
+        self.root.join(principal).join("calendars")
+    }
+
+    fn calendar_path(&self, principal: &str, id: &str) -> PathBuf {
+        self.calendars_path(principal).join(id)
+    }
+
+    fn trashed_calendar_path(&self, principal: &str, id: &str) -> PathBuf {
+        self.calendars_path(principal).join(TRASH).join(id)
+    }
+
+    #[allow(clippy::missing_panics_doc)]
+    pub async fn validate_objects(&self, principal: &str) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        let mut success = true;
+        for path in list_dirs(&self.calendars_path(principal)).await? {
+            let collection = CalendarCollection::open(path).await?;
+            for (object_id, res) in collection.get_objects().await? {
+                if let Err(err) = res {
+                    warn!(
+                        "Invalid calendar object found at {}/{object_id}.ics. Error: {err}",
+                        collection.path().display()
+                    );
+                    success = false;
+                }
+            }
+        }
+        if !success {
+            if self.skip_broken {
+                error!(
+                    "Not all calendar objects are valid. Since data_store.vdir.skip_broken=true they will be hidden. You are still advised to manually remove or repair the object. If you need help feel free to open up an issue on GitHub."
+                );
+            } else {
+                error!(
+                    "Not all calendar objects are valid. Since data_store.vdir.skip_broken=false this causes a panic. Remove or repair the broken objects manually or set data_store.vdir.skip_broken=true as a temporary solution to ignore the error. If you need help feel free to open up an issue on GitHub."
+                );
+                panic!();
+            }
+        }
+        Ok(())
+    }
+
+    // Opens a calendar and commits changes that were made to its files by someone else
+    async fn load(
+        &self,
+        principal: &str,
+        id: &str,
+        show_deleted: bool,
+    ) -> Result<CalendarCollection, Error> {
+        check_id(principal)?;
+        check_id(id)?;
+        let mut collection = match CalendarCollection::open(self.calendar_path(principal, id))
+            .await
+            .map_err(Error::from)
+        {
+            Err(Error::NotFound) if show_deleted => {
+                let mut collection =
+                    CalendarCollection::open(self.trashed_calendar_path(principal, id)).await?;
+                collection
+                    .sidecar
+                    .deleted_at
+                    .get_or_insert_with(|| Utc::now().naive_utc());
+                collection
+            }
+            res => res?,
+        };
+
+        let changes = collection.refresh().await?;
+        if !changes.is_empty() {
+            collection.save().await?;
+            self.events.publish(Event::ObjectsChanged {
+                collection: CollectionRef::from(&to_calendar(principal, id, &collection)),
+                sync_token: format_synctoken(collection.sidecar.synctoken),
+                changes,
+            });
+        }
+        Ok(collection)
+    }
+
+    async fn _get_calendars(&self, principal: &str, deleted: bool) -> Result<Vec<Calendar>, Error> {
+        check_id(principal)?;
+        let path = if deleted {
+            self.calendars_path(principal).join(TRASH)
+        } else {
+            self.calendars_path(principal)
+        };
+        let mut calendars = vec![];
+        for path in list_dirs(&path).await? {
+            let Some(id) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let collection = self.load(principal, id, deleted).await?;
+            calendars.push(to_calendar(principal, id, &collection));
+        }
+        Ok(calendars)
+    }
+
+    fn collect_objects(
+        &self,
+        objects: Vec<(String, Result<CalendarObject, caldata::parser::ParserError>)>,
+    ) -> Result<Vec<(String, CalendarObject)>, Error> {
+        if self.skip_broken {
+            Ok(objects
+                .into_iter()
+                .filter_map(|(id, res)| Some((id, res.ok()?)))
+                .collect())
+        } else {
+            Ok(objects
+                .into_iter()
+                .map(|(id, res)| res.map(|obj| (id, obj)))
+                .collect::<Result<Vec<_>, _>>()?)
+        }
+    }
+
+    async fn _insert_calendar(&self, calendar: &Calendar) -> Result<CalendarCollection, Error> {
+        check_id(&calendar.principal)?;
+        check_id(&calendar.id)?;
+        if self
+            .principals
+            .get_principal(&calendar.principal)
+            .await?
+            .is_none()
+        {
+            return Err(Error::NotFound);
+        }
+        if tokio::fs::try_exists(self.trashed_calendar_path(&calendar.principal, &calendar.id))
+            .await
+            .map_err(crate::Error::from)?
+        {
+            return Err(Error::AlreadyExists);
+        }
+        let mut collection = CalendarCollection::new(
+            self.calendar_path(&calendar.principal, &calendar.id),
+            calendar.meta.displayname.clone(),
+            calendar.meta.color.clone(),
+            CalendarMeta {
+                order: calendar.meta.order,
+                description: calendar.meta.description.clone(),
+                timezone_id: calendar.timezone_id.clone(),
+                subscription_url: calendar.subscription_url.clone(),
+                push_topic: calendar.push_topic.clone(),
+                components: calendar.components.clone(),
+            },
+        );
+        collection.sidecar.synctoken = calendar.synctoken;
+        collection.create().await?;
+        Ok(collection)
+    }
+
+    async fn _prune_deleted_calendars(&self, before: chrono::NaiveDate) -> Result<u64, Error> {
+        let mut count = 0;
+        for principal in list_dirs(&self.root).await? {
+            for path in list_dirs(&principal.join("calendars").join(TRASH)).await? {
+                let collection = CalendarCollection::open(path).await?;
+                if collection
+                    .sidecar
+                    .deleted_at
+                    .is_none_or(|deleted_at| deleted_at.date() < before)
+                {
+                    collection.remove().await?;
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    async fn _prune_deleted_objects(&self, before: chrono::NaiveDate) -> Result<u64, Error> {
+        let mut count = 0;
+        for principal in list_dirs(&self.root).await? {
+            let calendars = principal.join("calendars");
+            for path in [
+                list_dirs(&calendars).await?,
+                list_dirs(&calendars.join(TRASH)).await?,
+            ]
+            .concat()
+            {
+                let mut collection = CalendarCollection::open(path).await?;
+                let pruned = collection.prune_trashed(before).await?;
+                if pruned > 0 {
+                    collection.save().await?;
+                    count += pruned;
+                }
+            }
+        }
+        Ok(count)
+    }
+}
+
+#[async_trait]
+impl CalendarReadStore for VdirCalendarStore {
+    #[instrument]
+    async fn get_calendar(
+        &self,
+        principal: &str,
+        id: &str,
+        show_deleted: bool,
+    ) -> Result<Calendar, Error> {
+        let _guard = self.lock.lock().await;
+        let collection = self.load(principal, id, show_deleted).await?;
+        Ok(to_calendar(principal, id, &collection))
+    }
+
+    #[instrument]
+    async fn get_calendars(&self, principal: &str) -> Result<Vec<Calendar>, Error> {
+        let _guard = self.lock.lock().await;
+        self._get_calendars(principal, false).await
+    }
+
+    #[instrument]
+    async fn get_deleted_calendars(&self, principal: &str) -> Result<Vec<Calendar>, Error> {
+        let _guard = self.lock.lock().await;
+        self._get_calendars(principal, true).await
+    }
+
+    async fn calendar_metadata(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<CollectionMetadata, Error> {
+        let _guard = self.lock.lock().await;
+        Ok(self.load(principal, cal_id, true).await?.metadata())
+    }
+
+    #[instrument]
+    async fn get_objects(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<(String, CalendarObject)>, Error> {
+        let _guard = self.lock.lock().await;
+        let collection = self.load(principal, cal_id, true).await?;
+        self.collect_objects(collection.get_objects().await?)
+    }
+
+    #[instrument]
+    async fn get_deleted_objects(
+        &self,
+        principal: &str,
+        cal_id: &str,
+    ) -> Result<Vec<(String, CalendarObject)>, Error> {
+        let _guard = self.lock.lock().await;
+        let collection = self.load(principal, cal_id, true).await?;
+        self.collect_objects(collection.get_trashed_objects().await?)
+    }
+
+    #[instrument]
+    async fn get_object(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+        show_deleted: bool,
+    ) -> Result<CalendarObject, Error> {
+        let _guard = self.lock.lock().await;
+        let collection = self.load(principal, cal_id, true).await?;
+        Ok(collection.get_object(object_id, show_deleted).await?)
+    }
+
+    #[instrument]
+    async fn sync_changes(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        synctoken: i64,
+    ) -> Result<(Vec<(String, CalendarObject)>, Vec<String>, i64), Error> {
+        let _guard = self.lock.lock().await;
+        let collection = self.load(principal, cal_id, false).await?;
+
+        let mut updated_objects = vec![];
+        let mut deleted_objects = vec![];
+
+        for object_id in collection.changes_since(synctoken) {
+            match collection
+                .get_object(&object_id, false)
+                .await
+                .map_err(Error::from)
+            {
+                Ok(object) => updated_objects.push((object_id, object)),
+                Err(Error::NotFound) => deleted_objects.push(object_id),
+                // Skip broken object
+                Err(Error::IcalError(_)) if self.skip_broken => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok((
+            updated_objects,
+            deleted_objects,
+            collection.sidecar.synctoken,
+        ))
+    }
+
+    fn is_read_only(&self, _cal_id: &str) -> bool {
+        false
+    }
+}
+
+#[async_trait]
+impl CalendarWriteStore for VdirCalendarStore {
+    #[instrument]
+    async fn insert_calendar(&self, calendar: Calendar) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        self._insert_calendar(&calendar).await?;
+        self.events
+            .publish(Event::CollectionCreated(CollectionRef::from(&calendar)));
+        Ok(())
+    }
+
+    #[instrument]
+    async fn update_calendar(
+        &self,
+        principal: &str,
+        id: &str,
+        calendar: Calendar,
+    ) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        let mut collection = self.load(principal, id, true).await?;
+        let props = to_calendar(principal, id, &collection).changed_properties(&calendar);
+
+        if (principal, id) != (calendar.principal.as_str(), calendar.id.as_str()) {
+            check_id(&calendar.principal)?;
+            check_id(&calendar.id)?;
+            let path = if collection.sidecar.deleted_at.is_some() {
+                self.trashed_calendar_path(&calendar.principal, &calendar.id)
+            } else {
+                self.calendar_path(&calendar.principal, &calendar.id)
+            };
+            collection.move_to(path).await?;
+        }
+        collection
+            .displayname
+            .clone_from(&calendar.meta.displayname);
+        collection.color.clone_from(&calendar.meta.color);
+        let meta = &mut collection.sidecar.meta;
+        meta.order = calendar.meta.order;
+        meta.description.clone_from(&calendar.meta.description);
+        meta.timezone_id.clone_from(&calendar.timezone_id);
+        meta.push_topic.clone_from(&calendar.push_topic);
+        meta.components.clone_from(&calendar.components);
+        collection.save().await?;
+
+        if !props.is_empty() {
+            self.events.publish(Event::CollectionUpdated {
+                collection: CollectionRef::from(&calendar),
+                props,
+            });
+        }
+        Ok(())
+    }
+
+    #[instrument]
+    async fn delete_calendar(
+        &self,
+        principal: &str,
+        id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        let mut collection = match self.load(principal, id, true).await {
+            Ok(collection) => collection,
+            Err(Error::NotFound) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let calendar = to_calendar(principal, id, &collection);
+
+        if use_trashbin {
+            collection.sidecar.deleted_at = Some(Utc::now().naive_utc());
+            collection.save().await?;
+            if collection.path() != self.trashed_calendar_path(principal, id) {
+                collection
+                    .move_to(self.trashed_calendar_path(principal, id))
+                    .await?;
+            }
+        } else {
+            collection.remove().await?;
+        }
+
+        self.events.publish(Event::CollectionDeleted {
+            collection: CollectionRef::from(&calendar),
+            trashed: use_trashbin,
+        });
+        Ok(())
+    }
+
+    #[instrument]
+    async fn restore_calendar(&self, principal: &str, id: &str) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        let mut collection = self.load(principal, id, true).await?;
+        if collection.path() != self.calendar_path(principal, id) {
+            collection
+                .move_to(self.calendar_path(principal, id))
+                .await?;
+        }
+        collection.sidecar.deleted_at = None;
+        collection.save().await?;
+
+        self.events
+            .publish(Event::CollectionRestored(CollectionRef::from(
+                &to_calendar(principal, id, &collection),
+            )));
+        Ok(())
+    }
+
+    #[instrument(skip(objects))]
+    async fn import_calendar(
+        &self,
+        calendar: Calendar,
+        objects: Vec<CalendarObject>,
+        merge_existing: bool,
+    ) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        let (principal, id) = (calendar.principal.as_str(), calendar.id.as_str());
+
+        let existing = match self.load(principal, id, true).await {
+            Ok(collection) => Some(collection),
+            Err(Error::NotFound) => None,
+            Err(err) => return Err(err),
+        };
+        if existing.is_some() && !merge_existing {
+            return Err(Error::AlreadyExists);
+        }
+        // Check for conflicts upfront since there's no transaction to roll back
+        let mut uids = std::collections::HashSet::new();
+        for object in &objects {
+            let uid = object.get_uid();
+            let conflicts = existing.as_ref().is_some_and(|collection| {
+                collection.sidecar.objects.contains_key(uid)
+                    || collection.sidecar.trashed.contains_key(uid)
+                    || collection
+                        .sidecar
+                        .objects
+                        .values()
+                        .any(|entry| entry.uid.as_deref() == Some(uid))
+            });
+            if conflicts || !uids.insert(uid) {
+                return Err(Error::AlreadyExists);
+            }
+        }
+
+        let is_new = existing.is_none();
+        let mut collection = match existing {
+            Some(collection) => collection,
+            None => self._insert_calendar(&calendar).await?,
+        };
+
+        let mut changes = vec![];
+        let mut result = Ok(());
+        for object in objects {
+            let object_id = object.get_uid().to_owned();
+            if let Err(err) = collection.put_object(&object_id, &object, false).await {
+                result = Err(err);
+                break;
+            }
+            changes.push(ObjectChange {
+                id: object_id,
+                etag: Some(object.get_etag()),
+                kind: ObjectChangeKind::Created,
+            });
+        }
+        collection.save().await?;
+
+        let collection_ref = CollectionRef::from(&to_calendar(principal, id, &collection));
+        if is_new {
+            self.events
+                .publish(Event::CollectionCreated(collection_ref.clone()));
+        }
+        if !changes.is_empty() {
+            self.events.publish(Event::ObjectsChanged {
+                collection: collection_ref,
+                sync_token: format_synctoken(collection.sidecar.synctoken),
+                changes,
+            });
+        }
+        Ok(result?)
+    }
+
+    #[instrument(skip(objects))]
+    async fn put_objects(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        objects: Vec<(String, CalendarObject)>,
+        overwrite: bool,
+    ) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        let mut collection = self.load(principal, cal_id, true).await?;
+        if collection.sidecar.meta.subscription_url.is_some() {
+            // We cannot commit an object to a subscription calendar
+            return Err(Error::ReadOnly);
+        }
+
+        let mut changes = vec![];
+        let mut result = Ok(());
+        for (object_id, object) in objects {
+            match collection.put_object(&object_id, &object, overwrite).await {
+                Ok(kind) => changes.push(ObjectChange {
+                    id: object_id,
+                    etag: Some(object.get_etag()),
+                    kind,
+                }),
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+        // Persist whatever made it to disk, even if an object failed
+        collection.save().await?;
+
+        if !changes.is_empty() {
+            self.events.publish(Event::ObjectsChanged {
+                collection: CollectionRef::from(&to_calendar(principal, cal_id, &collection)),
+                sync_token: format_synctoken(collection.sidecar.synctoken),
+                changes,
+            });
+        }
+        Ok(result?)
+    }
+
+    #[instrument]
+    async fn delete_object(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        id: &str,
+        use_trashbin: bool,
+    ) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        let mut collection = self.load(principal, cal_id, true).await?;
+        collection.delete_object(id, use_trashbin).await?;
+        collection.save().await?;
+
+        self.events.publish(Event::ObjectsChanged {
+            collection: CollectionRef::from(&to_calendar(principal, cal_id, &collection)),
+            sync_token: format_synctoken(collection.sidecar.synctoken),
+            changes: vec![ObjectChange {
+                id: id.to_owned(),
+                etag: None,
+                kind: ObjectChangeKind::Deleted,
+            }],
+        });
+        Ok(())
+    }
+
+    #[instrument]
+    async fn restore_object(
+        &self,
+        principal: &str,
+        cal_id: &str,
+        object_id: &str,
+    ) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        let mut collection = self.load(principal, cal_id, true).await?;
+        collection.restore_object(object_id).await?;
+        collection.save().await?;
+        let etag = collection.get_object(object_id, false).await?.get_etag();
+
+        self.events.publish(Event::ObjectsChanged {
+            collection: CollectionRef::from(&to_calendar(principal, cal_id, &collection)),
+            sync_token: format_synctoken(collection.sidecar.synctoken),
+            changes: vec![ObjectChange {
+                id: object_id.to_owned(),
+                etag: Some(etag),
+                kind: ObjectChangeKind::Created,
+            }],
+        });
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CalendarStorePruneDeleted for VdirCalendarStore {
+    #[instrument(skip(self), fields(count = tracing::field::Empty))]
+    async fn prune_deleted_calendars(&self, before: chrono::NaiveDate) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        let count = self._prune_deleted_calendars(before).await?;
+        tracing::Span::current().record("count", count);
+        Ok(())
+    }
+
+    #[instrument(skip(self), fields(count = tracing::field::Empty))]
+    async fn prune_deleted_objects(&self, before: chrono::NaiveDate) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        let count = self._prune_deleted_objects(before).await?;
+        tracing::Span::current().record("count", count);
+        Ok(())
+    }
+}
@@ -0,0 +1,567 @@
+//! A collection in the vdir layout: a directory with one `<id>.<extension>` file per object,
+//! the `displayname` and `color` files vdirsyncer syncs and a `.rustical.json` sidecar
+//! for everything else (remaining metadata, sync token, change log and trash bin).
+use crate::Error;
+use caldata::parser::ParserError;
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+use rustical_ical::{AddressObject, CalendarObject};
+use rustical_store::{CollectionMetadata, ObjectChange, ObjectChangeKind};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::Metadata;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tracing::warn;
+
+pub const SIDECAR: &str = ".rustical.json";
+// Hidden directories are skipped by vdir tools, so trashed objects and collections live in here
+pub const TRASH: &str = ".trash";
+
+pub trait VdirObject: Sized {
+    const EXTENSION: &'static str;
+
+    fn parse(content: String) -> Result<Self, ParserError>;
+    fn content(&self) -> &str;
+    fn etag(&self) -> String;
+    // Objects with a UID may only exist once per collection
+    fn uid(&self) -> Option<&str>;
+}
+
+impl VdirObject for CalendarObject {
+    const EXTENSION: &'static str = "ics";
+
+    fn parse(content: String) -> Result<Self, ParserError> {
+        Self::from_ics(content)
+    }
+
+    fn content(&self) -> &str {
+        self.get_ics()
+    }
+
+    fn etag(&self) -> String {
+        self.get_etag()
+    }
+
+    fn uid(&self) -> Option<&str> {
+        Some(self.get_uid())
+    }
+}
+
+impl VdirObject for AddressObject {
+    const EXTENSION: &'static str = "vcf";
+
+    fn parse(content: String) -> Result<Self, ParserError> {
+        Self::from_vcf(content)
+    }
+
+    fn content(&self) -> &str {
+        self.get_vcf()
+    }
+
+    fn etag(&self) -> String {
+        self.get_etag()
+    }
+
+    fn uid(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// The collection specific part of the sidecar
+pub trait CollectionMeta: Serialize + DeserializeOwned + Default + Send + Sync {
+    type Object: VdirObject;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<String>,
+    // Size and modification time tell us whether somebody else touched the file
+    pub size: u64,
+    pub mtime: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashedEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<String>,
+    pub size: u64,
+    pub deleted_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sidecar<M> {
+    #[serde(flatten)]
+    pub meta: M,
+    pub synctoken: i64,
+    #[serde(default)]
+    pub deleted_at: Option<NaiveDateTime>,
+    // Synctoken of the latest change of every object that ever existed
+    #[serde(default)]
+    pub changes: BTreeMap<String, i64>,
+    #[serde(default)]
+    pub objects: BTreeMap<String, ObjectEntry>,
+    #[serde(default)]
+    pub trashed: BTreeMap<String, TrashedEntry>,
+}
+
+impl<M> Sidecar<M> {
+    pub const fn new(meta: M) -> Self {
+        Self {
+            meta,
+            synctoken: 0,
+            deleted_at: None,
+            changes: BTreeMap::new(),
+            objects: BTreeMap::new(),
+            trashed: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Collection<M> {
+    path: PathBuf,
+    pub displayname: Option<String>,
+    pub color: Option<String>,
+    pub sidecar: Sidecar<M>,
+}
+
+impl<M: CollectionMeta> Collection<M> {
+    pub const fn new(
+        path: PathBuf,
+        displayname: Option<String>,
+        color: Option<String>,
+        meta: M,
+    ) -> Self {
+        Self {
+            path,
+            displayname,
+            color,
+            sidecar: Sidecar::new(meta),
+        }
+    }
+
+    /// Creates the directory, fails if it already exists
+    pub async fn create(&self) -> Result<(), Error> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::create_dir(&self.path).await?;
+        self.save().await
+    }
+
+    pub async fn open(path: PathBuf) -> Result<Self, Error> {
+        if !fs::metadata(&path).await?.is_dir() {
+            return Err(rustical_store::Error::NotFound.into());
+        }
+        let (sidecar, is_new) = match fs::read(path.join(SIDECAR)).await {
+            Ok(content) => (serde_json::from_slice(&content)?, false),
+            // A vdir that was created by another tool
+            Err(err) if err.kind() == ErrorKind::NotFound => (Sidecar::new(M::default()), true),
+            Err(err) => return Err(err.into()),
+        };
+        let collection = Self {
+            displayname: read_property(&path, "displayname").await?,
+            color: read_property(&path, "color").await?,
+            path,
+            sidecar,
+        };
+        if is_new {
+            collection.save().await?;
+        }
+        Ok(collection)
+    }
+
+    pub async fn save(&self) -> Result<(), Error> {
+        write_property(&self.path, "displayname", self.displayname.as_deref()).await?;
+        write_property(&self.path, "color", self.color.as_deref()).await?;
+        write_atomic(
+            &self.path.join(SIDECAR),
+            &serde_json::to_vec_pretty(&self.sidecar)?,
+        )
+        .await
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn move_to(&mut self, path: PathBuf) -> Result<(), Error> {
+        if fs::try_exists(&path).await? {
+            return Err(rustical_store::Error::AlreadyExists.into());
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::rename(&self.path, &path).await?;
+        self.path = path;
+        Ok(())
+    }
+
+    pub async fn remove(self) -> Result<(), Error> {
+        fs::remove_dir_all(&self.path).await?;
+        Ok(())
+    }
+
+    fn object_path(&self, id: &str) -> PathBuf {
+        self.path
+            .join(format!("{id}.{}", <M::Object as VdirObject>::EXTENSION))
+    }
+
+    fn trashed_object_path(&self, id: &str) -> PathBuf {
+        self.path
+            .join(TRASH)
+            .join(format!("{id}.{}", <M::Object as VdirObject>::EXTENSION))
+    }
+
+    fn log_change(&mut self, id: &str) {
+        self.sidecar.synctoken += 1;
+        self.sidecar
+            .changes
+            .insert(id.to_owned(), self.sidecar.synctoken);
+    }
+
+    /// Picks up objects that were added, changed or removed by someone else
+    pub async fn refresh(&mut self) -> Result<Vec<ObjectChange>, Error> {
+        let suffix = format!(".{}", <M::Object as VdirObject>::EXTENSION);
+        let mut found = BTreeMap::new();
+        let mut entries = fs::read_dir(&self.path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_name = entry.file_name();
+            let Some(id) = file_name
+                .to_str()
+                .and_then(|name| name.strip_suffix(&suffix))
+                .filter(|id| is_valid_id(id))
+            else {
+                continue;
+            };
+            let metadata = entry.metadata().await?;
+            if metadata.is_file() {
+                found.insert(id.to_owned(), fingerprint(&metadata));
+            }
+        }
+
+        let mut changes = vec![];
+        let removed: Vec<String> = self
+            .sidecar
+            .objects
+            .keys()
+            .filter(|id| !found.contains_key(*id))
+            .cloned()
+            .collect();
+        for id in removed {
+            self.sidecar.objects.remove(&id);
+            self.log_change(&id);
+            changes.push(ObjectChange {
+                id,
+                etag: None,
+                kind: ObjectChangeKind::Deleted,
+            });
+        }
+
+        for (id, (size, mtime)) in found {
+            let kind = match self.sidecar.objects.get(&id) {
+                Some(entry) if (entry.size, entry.mtime) == (size, mtime) => continue,
+                Some(_) => ObjectChangeKind::Updated,
+                None => ObjectChangeKind::Created,
+            };
+            let path = self.object_path(&id);
+            let object = <M::Object as VdirObject>::parse(fs::read_to_string(&path).await?)
+                .inspect_err(|err| warn!("Invalid object at {}: {err}", path.display()))
+                .ok();
+            self.sidecar.objects.insert(
+                id.clone(),
+                ObjectEntry {
+                    uid: object
+                        .as_ref()
+                        .and_then(VdirObject::uid)
+                        .map(ToOwned::to_owned),
+                    size,
+                    mtime,
+                },
+            );
+            self.log_change(&id);
+            changes.push(ObjectChange {
+                id,
+                etag: object.as_ref().map(VdirObject::etag),
+                kind,
+            });
+        }
+        Ok(changes)
+    }
+
+    pub fn object_exists(&self, id: &str) -> bool {
+        self.sidecar.objects.contains_key(id)
+    }
+
+    pub async fn get_object(&self, id: &str, show_deleted: bool) -> Result<M::Object, Error> {
+        let path = if self.sidecar.objects.contains_key(id) {
+            self.object_path(id)
+        } else if show_deleted && self.sidecar.trashed.contains_key(id) {
+            self.trashed_object_path(id)
+        } else {
+            return Err(rustical_store::Error::NotFound.into());
+        };
+        Ok(<M::Object as VdirObject>::parse(
+            fs::read_to_string(path).await?,
+        )?)
+    }
+
+    pub async fn get_objects(
+        &self,
+    ) -> Result<Vec<(String, Result<M::Object, ParserError>)>, Error> {
+        let mut objects = vec![];
+        for id in self.sidecar.objects.keys() {
+            let content = fs::read_to_string(self.object_path(id)).await?;
+            objects.push((id.clone(), <M::Object as VdirObject>::parse(content)));
+        }
+        Ok(objects)
+    }
+
+    pub async fn get_trashed_objects(
+        &self,
+    ) -> Result<Vec<(String, Result<M::Object, ParserError>)>, Error> {
+        let mut objects = vec![];
+        for id in self.sidecar.trashed.keys() {
+            let content = fs::read_to_string(self.trashed_object_path(id)).await?;
+            objects.push((id.clone(), <M::Object as VdirObject>::parse(content)));
+        }
+        Ok(objects)
+    }
+
+    /// Ids of the objects changed after `synctoken`, oldest change first
+    pub fn changes_since(&self, synctoken: i64) -> Vec<String> {
+        let mut changes: Vec<(&String, i64)> = self
+            .sidecar
+            .changes
+            .iter()
+            .map(|(id, synctoken)| (id, *synctoken))
+            .filter(|(_, change)| *change > synctoken)
+            .collect();
+        changes.sort_by_key(|(_, synctoken)| *synctoken);
+        changes.into_iter().map(|(id, _)| id.clone()).collect()
+    }
+
+    pub fn metadata(&self) -> CollectionMetadata {
+        CollectionMetadata {
+            len: self.sidecar.objects.len(),
+            deleted_len: self.sidecar.trashed.len(),
+            size: self.sidecar.objects.values().map(|entry| entry.size).sum(),
+            deleted_size: self.sidecar.trashed.values().map(|entry| entry.size).sum(),
+        }
+    }
+
+    /// With `overwrite` an object with the same id or UID gets replaced,
+    /// otherwise either of them already existing is an error.
+    pub async fn put_object(
+        &mut self,
+        id: &str,
+        object: &M::Object,
+        overwrite: bool,
+    ) -> Result<ObjectChangeKind, Error> {
+        check_id(id)?;
+        let exists = self.sidecar.objects.contains_key(id);
+        if !overwrite && (exists || self.sidecar.trashed.contains_key(id)) {
+            return Err(rustical_store::Error::AlreadyExists.into());
+        }
+
+        let uid = object.uid().map(ToOwned::to_owned);
+        if let Some(uid) = &uid {
+            let conflicts: Vec<String> = self
+                .sidecar
+                .objects
+                .iter()
+                .map(|(id, entry)| (id, &entry.uid))
+                .chain(
+                    self.sidecar
+                        .trashed
+                        .iter()
+                        .map(|(id, entry)| (id, &entry.uid)),
+                )
+                .filter(|(other_id, other_uid)| {
+                    other_id.as_str() != id && other_uid.as_ref() == Some(uid)
+                })
+                .map(|(other_id, _)| other_id.clone())
+                .collect();
+            if !conflicts.is_empty() && !overwrite {
+                return Err(rustical_store::Error::AlreadyExists.into());
+            }
+            for conflict in conflicts {
+                self.remove_object(&conflict).await?;
+            }
+        }
+        if self.sidecar.trashed.remove(id).is_some() {
+            remove_file(&self.trashed_object_path(id)).await?;
+        }
+
+        let path = self.object_path(id);
+        write_atomic(&path, object.content().as_bytes()).await?;
+        let (size, mtime) = fingerprint(&fs::metadata(&path).await?);
+        self.sidecar
+            .objects
+            .insert(id.to_owned(), ObjectEntry { uid, size, mtime });
+        self.log_change(id);
+
+        Ok(if exists {
+            ObjectChangeKind::Updated
+        } else {
+            ObjectChangeKind::Created
+        })
+    }
+
+    // Removes an object for good without logging a change
+    async fn remove_object(&mut self, id: &str) -> Result<(), Error> {
+        if self.sidecar.objects.remove(id).is_some() {
+            remove_file(&self.object_path(id)).await?;
+        }
+        if self.sidecar.trashed.remove(id).is_some() {
+            remove_file(&self.trashed_object_path(id)).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn delete_object(&mut self, id: &str, use_trashbin: bool) -> Result<(), Error> {
+        check_id(id)?;
+        if !use_trashbin {
+            self.remove_object(id).await?;
+        } else if self.sidecar.objects.contains_key(id) {
+            fs::create_dir_all(self.path.join(TRASH)).await?;
+            fs::rename(self.object_path(id), self.trashed_object_path(id)).await?;
+            if let Some(entry) = self.sidecar.objects.remove(id) {
+                self.sidecar.trashed.insert(
+                    id.to_owned(),
+                    TrashedEntry {
+                        uid: entry.uid,
+                        size: entry.size,
+                        deleted_at: Utc::now().naive_utc(),
+                    },
+                );
+            }
+        }
+        self.log_change(id);
+        Ok(())
+    }
+
+    pub async fn restore_object(&mut self, id: &str) -> Result<(), Error> {
+        if self.sidecar.trashed.contains_key(id) {
+            let path = self.object_path(id);
+            fs::rename(self.trashed_object_path(id), &path).await?;
+            let (size, mtime) = fingerprint(&fs::metadata(&path).await?);
+            if let Some(entry) = self.sidecar.trashed.remove(id) {
+                self.sidecar.objects.insert(
+                    id.to_owned(),
+                    ObjectEntry {
+                        uid: entry.uid,
+                        size,
+                        mtime,
+                    },
+                );
+            }
+        } else if !self.sidecar.objects.contains_key(id) {
+            return Err(rustical_store::Error::NotFound.into());
+        }
+        self.log_change(id);
+        Ok(())
+    }
+
+    /// Removes the objects trashed before `before` and returns how many there were
+    pub async fn prune_trashed(&mut self, before: NaiveDate) -> Result<u64, Error> {
+        let pruned: Vec<String> = self
+            .sidecar
+            .trashed
+            .iter()
+            .filter(|(_, entry)| entry.deleted_at.date() < before)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &pruned {
+            self.sidecar.trashed.remove(id);
+            remove_file(&self.trashed_object_path(id)).await?;
+        }
+        Ok(pruned.len() as u64)
+    }
+}
+
+// Ids end up as file names, so anything that escapes the directory or hides the file is out
+pub fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && !id.starts_with('.') && !id.contains(['/', '\\', '\0'])
+}
+
+pub fn check_id(id: &str) -> Result<(), Error> {
+    if is_valid_id(id) {
+        Ok(())
+    } else {
+        Err(rustical_store::Error::NotFound.into())
+    }
+}
+
+/// Lists the paths of all non-hidden directories in `path`
+pub async fn list_dirs(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut entries = match fs::read_dir(path).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err.into()),
+    };
+    let mut dirs = vec![];
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_name().to_str().is_some_and(is_valid_id) && entry.file_type().await?.is_dir()
+        {
+            dirs.push(entry.path());
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+fn fingerprint(metadata: &Metadata) -> (u64, u64) {
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |mtime| {
+            u64::try_from(mtime.as_nanos()).unwrap_or(u64::MAX)
+        });
+    (metadata.len(), mtime)
+}
+
+async fn remove_file(path: &Path) -> Result<(), Error> {
+    match fs::remove_file(path).await {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+// Writes to a temporary file first so that readers never see half a file
+async fn write_atomic(path: &Path, content: &[u8]) -> Result<(), Error> {
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let tmp_path = path.with_file_name(format!(".{file_name}.tmp"));
+    let mut file = fs::File::create(&tmp_path).await?;
+    file.write_all(content).await?;
+    file.sync_all().await?;
+    fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+async fn read_property(dir: &Path, name: &str) -> Result<Option<String>, Error> {
+    match fs::read_to_string(dir.join(name)).await {
+        Ok(value) => Ok(Some(value.trim().to_owned()).filter(|value| !value.is_empty())),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+async fn write_property(dir: &Path, name: &str, value: Option<&str>) -> Result<(), Error> {
+    if read_property(dir, name).await?.as_deref() == value {
+        return Ok(());
+    }
+    match value {
+        Some(value) => write_atomic(&dir.join(name), value.as_bytes()).await,
+        None => remove_file(&dir.join(name)).await,
+    }
+}
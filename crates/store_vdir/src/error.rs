@@ -0,0 +1,43 @@
+use std::io::ErrorKind;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    IoError(std::io::Error),
+
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    StoreError(rustical_store::Error),
+
+    #[error(transparent)]
+    IcalError(#[from] rustical_ical::Error),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        match value.kind() {
+            ErrorKind::NotFound => Self::StoreError(rustical_store::Error::NotFound),
+            ErrorKind::AlreadyExists => Self::StoreError(rustical_store::Error::AlreadyExists),
+            _ => Self::IoError(value),
+        }
+    }
+}
+
+impl From<Error> for rustical_store::Error {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::IoError(err) => Self::Other(err.into()),
+            Error::JsonError(err) => Self::Other(err.into()),
+            Error::IcalError(err) => Self::IcalError(err),
+            Error::StoreError(err) => err,
+        }
+    }
+}
+
+impl From<rustical_store::Error> for Error {
+    fn from(value: rustical_store::Error) -> Self {
+        Self::StoreError(value)
+    }
+}
@@ -0,0 +1,10 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+#![allow(clippy::missing_errors_doc)]
+pub use error::Error;
+pub mod addressbook_store;
+pub mod calendar_store;
+mod collection;
+pub mod error;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,127 @@
+use crate::tests::{TestStoreContext, test_store_context};
+use rstest::rstest;
+use rustical_ical::{CalendarObject, CalendarObjectType};
+use rustical_store::{
+    Calendar, CalendarMetadata, CalendarReadStore, CalendarWriteStore, Event, ObjectChangeKind,
+};
+
+fn calendar() -> Calendar {
+    Calendar {
+        principal: "user".to_string(),
+        id: "cal".to_string(),
+        timezone_id: None,
+        meta: CalendarMetadata {
+            displayname: Some("Work".to_string()),
+            order: 0,
+            description: None,
+            color: Some("#ff0000".to_string()),
+        },
+        deleted_at: None,
+        synctoken: 0,
+        push_topic: "alskdj".to_string(),
+        components: vec![CalendarObjectType::Event],
+        subscription_url: None,
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_vdir_layout(
+    #[from(test_store_context)]
+    #[future]
+    context: TestStoreContext,
+) {
+    let TestStoreContext { cal_store, dir, .. } = context.await;
+    cal_store.insert_calendar(calendar()).await.unwrap();
+    cal_store
+        .put_object("user", "cal", "event", CalendarObject::example_1(), false)
+        .await
+        .unwrap();
+
+    let path = dir.path().join("user/calendars/cal");
+    assert_eq!(
+        std::fs::read_to_string(path.join("displayname")).unwrap(),
+        "Work"
+    );
+    assert_eq!(
+        std::fs::read_to_string(path.join("color")).unwrap(),
+        "#ff0000"
+    );
+    assert_eq!(
+        std::fs::read_to_string(path.join("event.ics")).unwrap(),
+        CalendarObject::example_1().get_ics()
+    );
+
+    cal_store
+        .delete_object("user", "cal", "event", true)
+        .await
+        .unwrap();
+    assert!(!path.join("event.ics").exists());
+    assert!(path.join(".trash/event.ics").exists());
+
+    cal_store
+        .delete_calendar("user", "cal", true)
+        .await
+        .unwrap();
+    assert!(!path.exists());
+    assert!(dir.path().join("user/calendars/.trash/cal").exists());
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_external_changes(
+    #[from(test_store_context)]
+    #[future]
+    context: TestStoreContext,
+) {
+    let TestStoreContext {
+        cal_store,
+        dir,
+        events,
+        ..
+    } = context.await;
+    cal_store.insert_calendar(calendar()).await.unwrap();
+    let mut recv = events.subscribe("test");
+    let path = dir.path().join("user/calendars/cal");
+
+    // Another tool adds an object
+    let ics = CalendarObject::example_1().get_ics().to_owned();
+    std::fs::write(path.join("external.ics"), &ics).unwrap();
+
+    let (added, deleted, synctoken) = cal_store.sync_changes("user", "cal", 0).await.unwrap();
+    assert_eq!(
+        added.into_iter().map(|(id, _)| id).collect::<Vec<_>>(),
+        vec!["external"]
+    );
+    assert!(deleted.is_empty());
+    assert_eq!(synctoken, 1);
+    let Some(Event::ObjectsChanged { changes, .. }) = recv.recv().await else {
+        panic!("expected an object change");
+    };
+    assert_eq!(changes[0].kind, ObjectChangeKind::Created);
+
+    // Nothing changed since
+    let (added, _, synctoken) = cal_store.sync_changes("user", "cal", 1).await.unwrap();
+    assert!(added.is_empty());
+    assert_eq!(synctoken, 1);
+
+    // ... and removes it again
+    std::fs::remove_file(path.join("external.ics")).unwrap();
+    let (added, deleted, synctoken) = cal_store.sync_changes("user", "cal", 1).await.unwrap();
+    assert!(added.is_empty());
+    assert_eq!(deleted, vec!["external"]);
+    assert_eq!(synctoken, 2);
+
+    // The displayname file is authoritative
+    std::fs::write(path.join("displayname"), "Renamed\n").unwrap();
+    assert_eq!(
+        cal_store
+            .get_calendar("user", "cal", false)
+            .await
+            .unwrap()
+            .meta
+            .displayname
+            .as_deref(),
+        Some("Renamed")
+    );
+}
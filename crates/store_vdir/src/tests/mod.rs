@@ -0,0 +1,63 @@
+//! Runs the store test suite of `rustical_store_sqlite` against the vdir stores,
+//! principals live in an in-memory SQLite database.
+use crate::{addressbook_store::VdirAddressbookStore, calendar_store::VdirCalendarStore};
+use rstest::fixture;
+use rustical_store::EventBus;
+use rustical_store::auth::{AuthenticationProvider, Principal, PrincipalType};
+use rustical_store_sqlite::{create_db_pool, principal_store::SqlitePrincipalStore};
+use std::sync::Arc;
+use tempfile::TempDir;
+
+#[path = "../../../store_sqlite/src/tests/addressbook_store.rs"]
+mod addressbook_store;
+#[path = "../../../store_sqlite/src/tests/calendar_store.rs"]
+mod calendar_store;
+mod external_changes;
+
+#[derive(Debug, Clone)]
+pub struct TestStoreContext {
+    // Removes the directory once the last clone is gone
+    pub dir: Arc<TempDir>,
+    pub events: EventBus,
+    pub addr_store: VdirAddressbookStore,
+    pub cal_store: VdirCalendarStore,
+    pub principal_store: SqlitePrincipalStore,
+}
+
+#[fixture]
+pub async fn test_store_context() -> TestStoreContext {
+    let events = EventBus::default();
+    let db = create_db_pool(":memory:", true).await.unwrap();
+    let dir = Arc::new(TempDir::new().unwrap());
+
+    let principal_store = SqlitePrincipalStore::new(db, events.clone());
+    // Populate with test data
+    principal_store
+        .insert_principal(
+            Principal {
+                id: "user".to_owned(),
+                displayname: None,
+                memberships: vec![],
+                app_token: None,
+                password: None,
+                principal_type: PrincipalType::Individual,
+            },
+            false,
+        )
+        .await
+        .unwrap();
+
+    let principals = Arc::new(principal_store.clone());
+    TestStoreContext {
+        addr_store: VdirAddressbookStore::new(
+            dir.path(),
+            principals.clone(),
+            events.clone(),
+            false,
+        ),
+        cal_store: VdirCalendarStore::new(dir.path(), principals, events.clone(), false),
+        principal_store,
+        events,
+        dir,
+    }
+}
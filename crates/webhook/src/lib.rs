@@ -0,0 +1,698 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+#![allow(clippy::missing_errors_doc)]
+//! Outgoing webhooks for collection changes
+//!
+//! Every delivery is a JSON [`WebhookPayload`] POSTed to the webhook URL.
+//! The `X-Rustical-Signature` header has the form `t=<unix timestamp>,v1=<signature>`
+//! where the signature is the hex encoded HMAC-SHA256 of `<timestamp>.<body>`
+//! keyed with the webhook secret.
+//!
+//! If events were missed, e.g. under heavy load, every webhook gets a `resync` payload
+//! without object changes, receivers should then sync the collections themselves.
+use chrono::{DateTime, TimeDelta, Utc};
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use reqwest::{
+    StatusCode, Url,
+    dns::{Addrs, Name, Resolve, Resolving},
+    header,
+    redirect::Policy,
+};
+use rustical_store::{CollectionType, Event, EventReceiver, ObjectChange, retry_delay};
+use serde::Serialize;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::Notify;
+use tracing::{error, info, warn};
+
+mod store;
+pub use store::*;
+
+mod webhook;
+pub use webhook::*;
+
+pub const SIGNATURE_HEADER: &str = "X-Rustical-Signature";
+pub const EVENT_HEADER: &str = "X-Rustical-Event";
+pub const DELIVERY_HEADER: &str = "X-Rustical-Delivery";
+
+const DELIVERY_BATCH_SIZE: i64 = 100;
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Serialize)]
+pub struct WebhookPayload {
+    pub event: &'static str,
+    pub principal: String,
+    // Only missing for a resync of a webhook on all collections of the principal
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collection_type: Option<CollectionType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collection_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync_token: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub objects: Vec<ObjectChange>,
+    // Changed properties in Clark notation
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub properties: Vec<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl WebhookPayload {
+    /// Returns None for events that don't concern a collection
+    #[must_use]
+    pub fn new(event: &Event, timestamp: DateTime<Utc>) -> Option<Self> {
+        let collection = event.collection()?;
+        let mut payload = Self {
+            event: "",
+            principal: collection.principal.clone(),
+            collection_type: Some(collection.collection_type),
+            collection_id: Some(collection.id.clone()),
+            sync_token: None,
+            objects: vec![],
+            properties: vec![],
+            timestamp,
+        };
+        match event {
+            Event::ObjectsChanged {
+                sync_token,
+                changes,
+                ..
+            } => {
+                payload.event = "objects_changed";
+                payload.sync_token = Some(sync_token.clone());
+                payload.objects.clone_from(changes);
+            }
+            Event::CollectionCreated(_) => payload.event = "collection_created",
+            Event::CollectionUpdated { props, .. } => {
+                payload.event = "collection_updated";
+                payload.properties = props
+                    .iter()
+                    .map(|(ns, name)| match ns {
+                        Some(ns) => format!("{{{}}}{name}", String::from_utf8_lossy(&ns.0)),
+                        None => name.clone(),
+                    })
+                    .collect();
+            }
+            Event::CollectionDeleted { .. } => payload.event = "collection_deleted",
+            Event::CollectionRestored(_) => payload.event = "collection_restored",
+            Event::PrincipalCreated(_)
+            | Event::PrincipalUpdated(_)
+            | Event::PrincipalDeleted(_) => {
+                return None;
+            }
+        }
+        Some(payload)
+    }
+
+    /// Tells the receiver that changes on the collections of the webhook might have been missed
+    #[must_use]
+    pub fn resync(webhook: &Webhook, timestamp: DateTime<Utc>) -> Self {
+        let (collection_type, collection_id) = webhook.collection.clone().unzip();
+        Self {
+            event: "resync",
+            principal: webhook.principal.clone(),
+            collection_type,
+            collection_id,
+            sync_token: None,
+            objects: vec![],
+            properties: vec![],
+            timestamp,
+        }
+    }
+}
+
+/// Whether an address is reachable from the internet,
+/// as opposed to loopback, link-local, private and other special purpose ranges
+#[must_use]
+pub fn is_public_address(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || first == 0
+                // Shared address space for carrier-grade NAT
+                || (first == 100 && second & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => {
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local())
+        }
+    }
+}
+
+/// Drops the addresses that aren't public when connecting,
+/// so a host can't resolve to a public address for a check and a private one for the request
+struct PublicAddressResolver;
+
+impl Resolve for PublicAddressResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_address(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+fn http_client(allow_private_targets: bool) -> reqwest::Client {
+    let mut builder = reqwest::ClientBuilder::new()
+        .timeout(Duration::from_secs(30))
+        .connect_timeout(Duration::from_secs(10))
+        // A redirect could lead to any target
+        .redirect(Policy::none());
+    if !allow_private_targets {
+        builder = builder.dns_resolver(Arc::new(PublicAddressResolver));
+    }
+    builder
+        .build()
+        .expect("Could not build the webhook HTTP client")
+}
+
+/// Hex encoded HMAC-SHA256 of `<timestamp>.<body>`
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> Result<String, ErrorStack> {
+    let key = PKey::hmac(secret.as_bytes())?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(timestamp.to_string().as_bytes())?;
+    signer.update(b".")?;
+    signer.update(body.as_bytes())?;
+    Ok(hex::encode(signer.sign_to_vec()?))
+}
+
+#[derive(Debug)]
+pub struct WebhookController<S: WebhookStore> {
+    allowed_origins: Option<Vec<String>>,
+    allow_private_targets: bool,
+    client: reqwest::Client,
+    max_delivery_attempts: u32,
+    log_retention: TimeDelta,
+    store: Arc<S>,
+    outbox_notify: Notify,
+}
+
+impl<S: WebhookStore> WebhookController<S> {
+    pub fn new(
+        allowed_origins: Option<Vec<String>>,
+        allow_private_targets: bool,
+        max_delivery_attempts: u32,
+        log_retention: TimeDelta,
+        store: Arc<S>,
+    ) -> Self {
+        Self {
+            allowed_origins,
+            allow_private_targets,
+            client: http_client(allow_private_targets),
+            max_delivery_attempts,
+            log_retention,
+            store,
+            outbox_notify: Notify::new(),
+        }
+    }
+
+    pub async fn notifier(&self, mut recv: EventReceiver) {
+        while let Some(event) = recv.recv().await {
+            if recv.take_lagged() {
+                self.enqueue_resync().await;
+            }
+            self.enqueue(&event).await;
+            self.outbox_notify.notify_one();
+        }
+    }
+
+    /// Delivers the payloads from the outbox and retries failed deliveries
+    pub async fn deliverer(&self) {
+        let mut last_prune: Option<tokio::time::Instant> = None;
+        loop {
+            tokio::select! {
+                () = self.outbox_notify.notified() => {},
+                () = tokio::time::sleep(Duration::from_secs(10)) => {},
+            }
+            self.process_outbox().await;
+
+            if last_prune.is_none_or(|last_prune| last_prune.elapsed() >= PRUNE_INTERVAL) {
+                last_prune = Some(tokio::time::Instant::now());
+                self.prune_log().await;
+            }
+        }
+    }
+
+    async fn enqueue(&self, event: &Event) {
+        let (Some(collection), Some(payload)) =
+            (event.collection(), WebhookPayload::new(event, Utc::now()))
+        else {
+            return;
+        };
+        let webhooks = match self.store.get_webhooks(&collection.principal).await {
+            Ok(webhooks) => webhooks,
+            Err(err) => {
+                error!("{err}");
+                return;
+            }
+        };
+        let webhooks: Vec<_> = webhooks
+            .into_iter()
+            .filter(|webhook| webhook.matches(collection))
+            .collect();
+        if webhooks.is_empty() {
+            return;
+        }
+
+        let body = match serde_json::to_string(&payload) {
+            Ok(body) => body,
+            Err(err) => {
+                error!("Could not serialize webhook payload: {err}");
+                return;
+            }
+        };
+
+        for webhook in webhooks {
+            if let Err(err) = self
+                .store
+                .enqueue_delivery(&webhook.id, payload.event, &body)
+                .await
+            {
+                error!("Could not enqueue webhook delivery: {err}");
+            }
+        }
+    }
+
+    async fn enqueue_resync(&self) {
+        let webhooks = match self.store.get_all_webhooks().await {
+            Ok(webhooks) => webhooks,
+            Err(err) => {
+                error!("Could not load webhooks: {err}");
+                return;
+            }
+        };
+        warn!(
+            webhooks = webhooks.len(),
+            "Sending resync to all webhooks after missed events"
+        );
+        let timestamp = Utc::now();
+        for webhook in webhooks {
+            let payload = WebhookPayload::resync(&webhook, timestamp);
+            let body = match serde_json::to_string(&payload) {
+                Ok(body) => body,
+                Err(err) => {
+                    error!("Could not serialize webhook payload: {err}");
+                    continue;
+                }
+            };
+            if let Err(err) = self
+                .store
+                .enqueue_delivery(&webhook.id, payload.event, &body)
+                .await
+            {
+                error!("Could not enqueue webhook delivery: {err}");
+            }
+        }
+    }
+
+    async fn process_outbox(&self) {
+        let now = Utc::now().naive_utc();
+        let deliveries = match self
+            .store
+            .get_due_deliveries(now, DELIVERY_BATCH_SIZE)
+            .await
+        {
+            Ok(deliveries) => deliveries,
+            Err(err) => {
+                error!("Could not load webhook deliveries: {err}");
+                return;
+            }
+        };
+        if i64::try_from(deliveries.len()) == Ok(DELIVERY_BATCH_SIZE) {
+            // There might be more due deliveries, come back right after this batch
+            self.outbox_notify.notify_one();
+        }
+
+        for delivery in deliveries {
+            self.deliver(delivery).await;
+        }
+    }
+
+    async fn deliver(&self, mut delivery: WebhookDelivery) {
+        let webhook = match self.store.get_webhook(&delivery.webhook_id).await {
+            Ok(webhook) => webhook,
+            // The delivery log is removed together with the webhook
+            Err(rustical_store::Error::NotFound) => return,
+            Err(err) => {
+                error!("{err}");
+                return;
+            }
+        };
+
+        delivery.attempts += 1;
+        match send_delivery(
+            &self.client,
+            &webhook,
+            &delivery,
+            self.allowed_origins.as_deref(),
+            self.allow_private_targets,
+        )
+        .await
+        {
+            Ok(status) => {
+                delivery.state = DeliveryState::Delivered;
+                delivery.last_status = Some(status.as_u16());
+                delivery.last_error = None;
+            }
+            Err(err) => {
+                warn!(
+                    "Error delivering webhook {} to {}: {err}",
+                    webhook.id, webhook.url
+                );
+                delivery.last_status = err.status().map(|status| status.as_u16());
+                delivery.last_error = Some(err.to_string());
+                if err.is_retryable() && delivery.attempts < i64::from(self.max_delivery_attempts) {
+                    delivery.next_attempt =
+                        Utc::now().naive_utc() + retry_delay(delivery.attempts - 1);
+                } else {
+                    info!(
+                        "Giving up on delivery {} of webhook {} after {} attempts",
+                        delivery.id, webhook.id, delivery.attempts
+                    );
+                    delivery.state = DeliveryState::Failed;
+                }
+            }
+        }
+
+        if let Err(err) = self.store.update_delivery(&delivery).await {
+            error!("Error updating webhook delivery: {err}");
+        }
+    }
+
+    async fn prune_log(&self) {
+        let before = Utc::now().naive_utc() - self.log_retention;
+        match self.store.prune_deliveries(before).await {
+            Ok(0) => {}
+            Ok(count) => info!("Pruned {count} entries from the webhook delivery log"),
+            Err(err) => error!("Could not prune webhook delivery log: {err}"),
+        }
+    }
+}
+
+async fn send_delivery(
+    client: &reqwest::Client,
+    webhook: &Webhook,
+    delivery: &WebhookDelivery,
+    allowed_origins: Option<&[String]>,
+    allow_private_targets: bool,
+) -> Result<StatusCode, DeliveryError> {
+    let url: Url = webhook
+        .url
+        .parse()
+        .map_err(|_| DeliveryError::InvalidUrl(webhook.url.clone()))?;
+    if let Some(allowed_origins) = allowed_origins
+        && !allowed_origins.contains(&url.origin().unicode_serialization())
+    {
+        return Err(DeliveryError::OriginNotAllowed(webhook.url.clone()));
+    }
+    // Hostnames are checked by the resolver
+    let literal_ip = match url.host() {
+        Some(url::Host::Ipv4(ip)) => Some(IpAddr::V4(ip)),
+        Some(url::Host::Ipv6(ip)) => Some(IpAddr::V6(ip)),
+        _ => None,
+    };
+    if !allow_private_targets && literal_ip.is_some_and(|ip| !is_public_address(ip)) {
+        return Err(DeliveryError::PrivateAddress(webhook.url.clone()));
+    }
+
+    let timestamp = Utc::now().timestamp();
+    let signature = sign(&webhook.secret, timestamp, &delivery.payload)?;
+
+    let response = client
+        .post(url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, format!("t={timestamp},v1={signature}"))
+        .header(EVENT_HEADER, &delivery.event)
+        .header(DELIVERY_HEADER, delivery.id)
+        .body(delivery.payload.clone())
+        .send()
+        .await?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(DeliveryError::EndpointError(status));
+    }
+    Ok(status)
+}
+
+#[derive(Debug, thiserror::Error)]
+enum DeliveryError {
+    #[error("Invalid webhook URL: {0}")]
+    InvalidUrl(String),
+    #[error("Webhook URL {0} is not in the list of allowed origins")]
+    OriginNotAllowed(String),
+    #[error("Webhook URL {0} points to a private address")]
+    PrivateAddress(String),
+    #[error(transparent)]
+    OpensslError(#[from] ErrorStack),
+    #[error(transparent)]
+    ReqwestError(#[from] reqwest::Error),
+    #[error("Webhook endpoint responded with status {0}")]
+    EndpointError(StatusCode),
+}
+
+impl DeliveryError {
+    // Decide whether the delivery should be attempted again later
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::ReqwestError(_) => true,
+            Self::EndpointError(status) => {
+                status.is_server_error()
+                    || matches!(
+                        *status,
+                        StatusCode::TOO_MANY_REQUESTS | StatusCode::REQUEST_TIMEOUT
+                    )
+            }
+            _ => false,
+        }
+    }
+
+    const fn status(&self) -> Option<StatusCode> {
+        match self {
+            Self::EndpointError(status) => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        DeliveryState, SIGNATURE_HEADER, Webhook, WebhookDelivery, WebhookPayload, http_client,
+        is_public_address, send_delivery, sign,
+    };
+    use axum::{Router, http::HeaderMap, routing::post};
+    use chrono::{DateTime, Utc};
+    use http::StatusCode;
+    use rustical_store::{CollectionRef, CollectionType, Event, ObjectChange, ObjectChangeKind};
+
+    fn test_collection() -> CollectionRef {
+        CollectionRef {
+            principal: "user".to_owned(),
+            collection_type: CollectionType::Calendar,
+            id: "work".to_owned(),
+            push_topic: "topic".to_owned(),
+        }
+    }
+
+    fn test_delivery(webhook: &Webhook, payload: &str) -> WebhookDelivery {
+        WebhookDelivery {
+            id: 1,
+            webhook_id: webhook.id.clone(),
+            event: "objects_changed".to_owned(),
+            payload: payload.to_owned(),
+            state: DeliveryState::Pending,
+            attempts: 0,
+            next_attempt: Utc::now().naive_utc(),
+            last_status: None,
+            last_error: None,
+            created_at: Utc::now().naive_utc(),
+        }
+    }
+
+    #[test]
+    fn test_signature() {
+        assert_eq!(
+            sign("secret", 1_700_000_000, r#"{"hello":"world"}"#).unwrap(),
+            "654f06c856baf080af3fa272934823257a542d35cf1f88099338f850a60601a4"
+        );
+    }
+
+    #[test]
+    fn test_payload() {
+        let timestamp = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let payload = WebhookPayload::new(
+            &Event::ObjectsChanged {
+                collection: test_collection(),
+                sync_token: "github.com/lennart-k/rustical/ns/2".to_owned(),
+                changes: vec![ObjectChange {
+                    id: "event".to_owned(),
+                    etag: Some("\"abc\"".to_owned()),
+                    kind: ObjectChangeKind::Created,
+                }],
+            },
+            timestamp,
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::to_string(&payload).unwrap(),
+            r#"{"event":"objects_changed","principal":"user","collection_type":"calendar","collection_id":"work","sync_token":"github.com/lennart-k/rustical/ns/2","objects":[{"id":"event","etag":"\"abc\"","kind":"created"}],"timestamp":"2023-11-14T22:13:20Z"}"#
+        );
+
+        let payload = WebhookPayload::new(
+            &Event::CollectionUpdated {
+                collection: test_collection(),
+                props: vec![(Some("DAV:".into()), "displayname".to_owned())],
+            },
+            timestamp,
+        )
+        .unwrap();
+        assert_eq!(payload.event, "collection_updated");
+        assert_eq!(payload.properties, vec!["{DAV:}displayname"]);
+
+        assert!(
+            WebhookPayload::new(&Event::PrincipalCreated("user".to_owned()), timestamp).is_none()
+        );
+
+        let webhook = Webhook::new("user".to_owned(), None, "https://example.com".to_owned());
+        assert_eq!(
+            serde_json::to_string(&WebhookPayload::resync(&webhook, timestamp)).unwrap(),
+            r#"{"event":"resync","principal":"user","timestamp":"2023-11-14T22:13:20Z"}"#
+        );
+    }
+
+    #[test]
+    fn test_webhook_matches() {
+        let collection = test_collection();
+        let url = "https://example.com/hook".to_owned();
+        assert!(Webhook::new("user".to_owned(), None, url.clone()).matches(&collection));
+        assert!(!Webhook::new("other".to_owned(), None, url.clone()).matches(&collection));
+        assert!(
+            Webhook::new(
+                "user".to_owned(),
+                Some((CollectionType::Calendar, "work".to_owned())),
+                url.clone()
+            )
+            .matches(&collection)
+        );
+        assert!(
+            !Webhook::new(
+                "user".to_owned(),
+                Some((CollectionType::Addressbook, "work".to_owned())),
+                url
+            )
+            .matches(&collection)
+        );
+    }
+
+    #[test]
+    fn test_public_address() {
+        for ip in ["1.1.1.1", "2606:4700::1111", "::ffff:1.1.1.1"] {
+            assert!(is_public_address(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::ffff:127.0.0.1",
+            "fd00::1",
+            "fe80::1",
+        ] {
+            assert!(!is_public_address(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delivery() {
+        // Stand-in for a webhook receiver that verifies the signature
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route(
+            "/hook",
+            post(async |headers: HeaderMap, body: String| {
+                let Some(signature) = headers.get(SIGNATURE_HEADER) else {
+                    return StatusCode::UNAUTHORIZED;
+                };
+                let (timestamp, signature) = signature
+                    .to_str()
+                    .unwrap()
+                    .strip_prefix("t=")
+                    .unwrap()
+                    .split_once(",v1=")
+                    .unwrap();
+                if sign("secret", timestamp.parse().unwrap(), &body).unwrap() == signature {
+                    StatusCode::NO_CONTENT
+                } else {
+                    StatusCode::UNAUTHORIZED
+                }
+            }),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let mut webhook = Webhook::new("user".to_owned(), None, format!("http://{addr}/hook"));
+        webhook.secret = "secret".to_owned();
+        let delivery = test_delivery(&webhook, r#"{"hello":"world"}"#);
+        let client = http_client(true);
+        assert_eq!(
+            send_delivery(&client, &webhook, &delivery, None, true)
+                .await
+                .unwrap(),
+            StatusCode::NO_CONTENT
+        );
+
+        let err = send_delivery(
+            &client,
+            &webhook,
+            &delivery,
+            Some(&["https://example.com".to_owned()]),
+            true,
+        )
+        .await
+        .unwrap_err();
+        assert!(!err.is_retryable());
+
+        // The receiver is on the loopback interface
+        let err = send_delivery(&http_client(false), &webhook, &delivery, None, false)
+            .await
+            .unwrap_err();
+        assert!(!err.is_retryable());
+        let mut localhost = webhook.clone();
+        localhost.url = format!("http://localhost:{}/hook", addr.port());
+        assert!(
+            send_delivery(&http_client(false), &localhost, &delivery, None, false)
+                .await
+                .is_err()
+        );
+
+        webhook.secret = "wrong".to_owned();
+        let err = send_delivery(&client, &webhook, &delivery, None, true)
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::UNAUTHORIZED));
+        assert!(!err.is_retryable());
+    }
+}
@@ -0,0 +1,39 @@
+use crate::{Webhook, WebhookDelivery};
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use rustical_store::Error;
+
+#[async_trait]
+pub trait WebhookStore: Send + Sync + 'static {
+    async fn get_webhooks(&self, principal: &str) -> Result<Vec<Webhook>, Error>;
+    /// Webhooks of all principals
+    async fn get_all_webhooks(&self) -> Result<Vec<Webhook>, Error>;
+    async fn get_webhook(&self, id: &str) -> Result<Webhook, Error>;
+    async fn insert_webhook(&self, webhook: Webhook) -> Result<(), Error>;
+    /// Also removes the delivery log of the webhook
+    async fn delete_webhook(&self, id: &str) -> Result<(), Error>;
+
+    /// Puts a payload into the outbox
+    async fn enqueue_delivery(
+        &self,
+        webhook_id: &str,
+        event: &str,
+        payload: &str,
+    ) -> Result<(), Error>;
+    /// Returns up to `limit` pending deliveries whose next attempt is due at `now`
+    async fn get_due_deliveries(
+        &self,
+        now: NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<WebhookDelivery>, Error>;
+    /// Records the outcome of a delivery attempt
+    async fn update_delivery(&self, delivery: &WebhookDelivery) -> Result<(), Error>;
+    /// Returns the most recent deliveries of a webhook
+    async fn get_deliveries(
+        &self,
+        webhook_id: &str,
+        limit: i64,
+    ) -> Result<Vec<WebhookDelivery>, Error>;
+    /// Removes finished deliveries created before `before` from the log
+    async fn prune_deliveries(&self, before: NaiveDateTime) -> Result<u64, Error>;
+}
@@ -0,0 +1,111 @@
+use chrono::NaiveDateTime;
+use rand::{RngExt, distr::Alphanumeric};
+use rustical_store::{CollectionRef, CollectionType};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Webhook {
+    pub id: String,
+    // Changes on collections owned by this principal trigger the webhook
+    pub principal: String,
+    // Restricts the webhook to a single collection
+    pub collection: Option<(CollectionType, String)>,
+    pub url: String,
+    // Key for the HMAC signature of every delivery
+    pub secret: String,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+impl Webhook {
+    #[must_use]
+    pub fn new(
+        principal: String,
+        collection: Option<(CollectionType, String)>,
+        url: String,
+    ) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            principal,
+            collection,
+            url,
+            secret: generate_secret(),
+            created_at: None,
+        }
+    }
+
+    #[must_use]
+    pub fn matches(&self, collection: &CollectionRef) -> bool {
+        self.principal == collection.principal
+            && self
+                .collection
+                .as_ref()
+                .is_none_or(|(ty, id)| *ty == collection.collection_type && *id == collection.id)
+    }
+}
+
+fn generate_secret() -> String {
+    rand::rng()
+        .sample_iter(Alphanumeric)
+        .map(char::from)
+        .take(32)
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryState {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+impl DeliveryState {
+    #[must_use]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Delivered => "delivered",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid delivery state: {0}")]
+pub struct InvalidDeliveryStateError(String);
+
+impl TryFrom<&str> for DeliveryState {
+    type Error = InvalidDeliveryStateError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Ok(match value {
+            "pending" => Self::Pending,
+            "delivered" => Self::Delivered,
+            "failed" => Self::Failed,
+            _ => return Err(InvalidDeliveryStateError(value.to_owned())),
+        })
+    }
+}
+
+impl std::fmt::Display for DeliveryState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A webhook delivery, pending ones make up the outbox and finished ones the delivery log
+#[derive(Debug, Clone)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: String,
+    pub event: String,
+    pub payload: String,
+    pub state: DeliveryState,
+    pub attempts: i64,
+    // UTC
+    pub next_attempt: NaiveDateTime,
+    // Response status or error of the last attempt
+    pub last_status: Option<u16>,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+}
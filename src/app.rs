@@ -1,10 +1,10 @@
 use crate::config::NextcloudLoginConfig;
-use axum::Router;
 use axum::body::{Body, HttpBody};
 use axum::extract::{DefaultBodyLimit, Request};
 use axum::middleware::Next;
 use axum::response::{Redirect, Response};
 use axum::routing::{any, options};
+use axum::{Extension, Router};
 use axum_extra::TypedHeader;
 use headers::{HeaderMapExt, UserAgent};
 use http::header::CONNECTION;
@@ -13,19 +13,23 @@ use rustical_caldav::{CalDavConfig, caldav_router};
 use rustical_carddav::carddav_router;
 use rustical_dav_push::DavPushStore;
 use rustical_frontend::nextcloud_login::nextcloud_login_router;
-use rustical_frontend::{FrontendConfig, frontend_router};
-use rustical_oidc::OidcConfig;
-use rustical_store::auth::AuthenticationProvider;
+use rustical_frontend::{FrontendConfig, OidcUserStore, frontend_router};
+use rustical_oidc::{BearerAuthLayer, OidcProviders};
+use rustical_scim::{ScimConfig, scim_router};
+use rustical_store::auth::{
+    AuthenticationProvider, LoginProtection, LoginProtectionConfig, ProxyAuthConfig, ProxyAuthLayer,
+};
 use rustical_store::{
-    AddressbookStore, CalendarStore, CombinedCalendarStore, PrefixedCalendarStore,
+    AddressbookStore, CalendarStore, CombinedCalendarStore, PrefixedCalendarStore, SessionStore,
 };
+use rustical_webhook::WebhookStore;
 use std::sync::Arc;
 use std::time::Duration;
 use tower_http::catch_panic::CatchPanicLayer;
 use tower_http::classify::ServerErrorsFailureClass;
 use tower_http::trace::TraceLayer;
 use tower_sessions::cookie::SameSite;
-use tower_sessions::{Expiry, MemoryStore, SessionManagerLayer};
+use tower_sessions::{Expiry, SessionManagerLayer};
 use tracing::Span;
 use tracing::field::display;
 
@@ -38,18 +42,23 @@ use tracing::field::display;
 pub fn make_app<
     AS: AddressbookStore + PrefixedCalendarStore,
     CS: CalendarStore,
-    DP: DavPushStore,
+    DP: DavPushStore + WebhookStore + SessionStore,
 >(
     addr_store: Arc<AS>,
     cal_store: Arc<CS>,
     subscription_store: Arc<DP>,
     auth_provider: Arc<impl AuthenticationProvider>,
     frontend_config: FrontendConfig,
-    oidc_config: Option<OidcConfig>,
+    oidc_providers: Option<OidcProviders>,
+    proxy_auth_config: Option<ProxyAuthConfig>,
+    login_protection_config: LoginProtectionConfig,
+    scim_config: Option<ScimConfig>,
     caldav_config: CalDavConfig,
     nextcloud_login_config: &NextcloudLoginConfig,
     dav_push_enabled: bool,
+    vapid_public_key: Option<String>,
     session_cookie_samesite_strict: bool,
+    session_timeout_minutes: u32,
     payload_limit_mb: usize,
 ) -> Router<()> {
     let birthday_store = addr_store.clone();
@@ -68,6 +77,7 @@ pub fn make_app<
             subscription_store.clone(),
             false,
             caldav_config.clone(),
+            vapid_public_key.clone(),
         ))
         .merge(caldav_router(
             "/caldav-compat",
@@ -76,6 +86,7 @@ pub fn make_app<
             subscription_store.clone(),
             true,
             caldav_config,
+            vapid_public_key.clone(),
         ))
         .route(
             "/.well-known/caldav",
@@ -101,7 +112,17 @@ pub fn make_app<
             auth_provider.clone(),
             addr_store.clone(),
             subscription_store.clone(),
+            vapid_public_key,
+        ));
+
+    if let Some(oidc_providers) = &oidc_providers
+        && oidc_providers.iter().any(|(_, config)| config.bearer_auth)
+    {
+        router = router.layer(BearerAuthLayer::new(
+            oidc_providers,
+            OidcUserStore(auth_provider.clone()),
         ));
+    }
 
     // GNOME Accounts needs to discover a WebDAV Files endpoint to complete the setup
     // It looks at / as well as /remote.php/dav (Nextcloud)
@@ -121,26 +142,49 @@ pub fn make_app<
         }),
     );
 
-    let session_store = MemoryStore::default();
+    let session_store = subscription_store.as_ref().clone();
+    let session_expiry = Expiry::OnInactivity(tower_sessions::cookie::time::Duration::minutes(
+        session_timeout_minutes.into(),
+    ));
     if frontend_config.enabled {
         router = router.merge(frontend_router(
             "/frontend",
             auth_provider.clone(),
             combined_cal_store,
             addr_store,
+            subscription_store.clone(),
             frontend_config,
-            oidc_config,
+            oidc_providers,
         ));
     }
 
     if nextcloud_login_config.enabled {
-        router = router.nest("/index.php/login/v2", nextcloud_login_router(auth_provider));
+        router = router.nest(
+            "/index.php/login/v2",
+            nextcloud_login_router(auth_provider.clone()),
+        );
+    }
+
+    if let Some(scim_config) = scim_config {
+        router = router.merge(scim_router(
+            "/scim/v2",
+            scim_config,
+            auth_provider.clone(),
+            subscription_store.clone(),
+        ));
     }
 
     if dav_push_enabled {
         router = router.merge(rustical_dav_push::subscription_service(subscription_store));
     }
 
+    if let Some(proxy_auth_config) = proxy_auth_config {
+        router = router.layer(ProxyAuthLayer::new(proxy_auth_config, auth_provider));
+    }
+    router = router.layer(Extension(LoginProtection(Arc::new(
+        login_protection_config,
+    ))));
+
     router
         .layer(
             SessionManagerLayer::new(session_store)
@@ -151,9 +195,7 @@ pub fn make_app<
                 } else {
                     SameSite::Lax
                 })
-                .with_expiry(Expiry::OnInactivity(
-                    tower_sessions::cookie::time::Duration::hours(2),
-                )),
+                .with_expiry(session_expiry),
         )
         .layer(CatchPanicLayer::new())
         .layer(
@@ -1,6 +1,7 @@
+use chrono::{Duration, Utc};
 use clap::{Parser, Subcommand};
 use rand::{RngExt, distr::Alphanumeric};
-use rustical_store::auth::AuthenticationProvider;
+use rustical_store::auth::{AppTokenScope, AuthenticationProvider, DavService};
 
 // TODO: Move token generation to store
 pub fn generate_app_token() -> String {
@@ -16,6 +17,21 @@ pub struct CreateArgs {
     principal: String,
     #[arg(long, help = "The app name")]
     name: String,
+    #[arg(
+        value_enum,
+        long,
+        help = "Only allow access to this service (repeatable)"
+    )]
+    service: Vec<DavService>,
+    #[arg(long, help = "Only allow reading")]
+    read_only: bool,
+    #[arg(
+        long,
+        help = "Only allow access to this collection as <principal>/<collection> (repeatable)"
+    )]
+    collection: Vec<String>,
+    #[arg(long, help = "Let the token expire after this many days")]
+    expires_in_days: Option<u32>,
 }
 
 #[derive(Debug, Parser)]
@@ -54,10 +70,23 @@ pub async fn cmd_app_token(
     };
 
     match &command {
-        AppTokenCommand::Create(CreateArgs { name, .. }) => {
+        AppTokenCommand::Create(CreateArgs {
+            name,
+            service,
+            read_only,
+            collection,
+            expires_in_days,
+            ..
+        }) => {
+            let scope = AppTokenScope {
+                services: service.clone(),
+                read_only: *read_only,
+                collections: collection.clone(),
+            };
+            let expires_at = expires_in_days.map(|days| Utc::now() + Duration::days(days.into()));
             let token = generate_app_token();
             let mut token_id = user_store
-                .add_app_token(principal, name.clone(), token.clone())
+                .add_app_token(principal, name.clone(), token.clone(), scope, expires_at)
                 .await?;
             // Get first 4 characters of token identifier
             token_id.truncate(4);
@@ -74,7 +103,24 @@ pub async fn cmd_app_token(
                     .get_app_tokens(principal)
                     .await?
                     .iter()
-                    .map(|token| format!("{} - {}", token.id, token.name))
+                    .map(|token| {
+                        let expires = token
+                            .expires_at
+                            .map_or_else(|| "never".to_owned(), |at| at.to_rfc3339());
+                        let last_used = token.last_used_at.map_or_else(
+                            || "never".to_owned(),
+                            |at| {
+                                format!(
+                                    "{at} ({})",
+                                    token.last_user_agent.as_deref().unwrap_or("unknown client")
+                                )
+                            },
+                        );
+                        format!(
+                            "{} - {} [{}] expires: {expires}, last used: {last_used}",
+                            token.id, token.name, token.scope
+                        )
+                    })
                     .collect::<Vec<_>>()
                     .join("\n")
             );
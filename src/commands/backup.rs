@@ -0,0 +1,735 @@
+//! A backup is a tar archive with a `manifest.json` describing principals and collections
+//! and one file per object at `<principal>/calendars/<id>/<object>.ics`
+//! or `<principal>/addressbooks/<id>/<object>.vcf`.
+use crate::{config::Config, with_data_stores};
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use rustical_ical::{AddressObject, CalendarObject};
+use rustical_store::{
+    Addressbook, AddressbookStore, Calendar, CalendarStore, Secret,
+    auth::{AppToken, AuthenticationProvider, PasskeyCredential, Principal, PrincipalType, Totp},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+const MANIFEST: &str = "manifest.json";
+const BACKUP_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    created_at: DateTime<Utc>,
+    principals: Vec<PrincipalBackup>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PrincipalBackup {
+    id: String,
+    displayname: Option<String>,
+    principal_type: PrincipalType,
+    password_hash: Option<String>,
+    memberships: Vec<String>,
+    #[serde(default)]
+    email: Option<String>,
+    // OIDC provider that may log in to the principal
+    #[serde(default)]
+    identity_provider: Option<String>,
+    // Only the hashes
+    app_tokens: Vec<AppToken>,
+    #[serde(default)]
+    totp: Option<Totp>,
+    #[serde(default)]
+    passkeys: Vec<PasskeyCredential>,
+    calendars: Vec<CollectionBackup<Calendar>>,
+    addressbooks: Vec<CollectionBackup<Addressbook>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CollectionBackup<C> {
+    #[serde(flatten)]
+    collection: C,
+    objects: Vec<ObjectEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ObjectEntry {
+    id: String,
+    // In the trash bin
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    deleted: bool,
+}
+
+// Archive path -> content
+type Files = HashMap<String, String>;
+
+fn calendar_path(calendar: &Calendar, object_id: &str) -> String {
+    format!(
+        "{}/calendars/{}/{object_id}.ics",
+        calendar.principal, calendar.id
+    )
+}
+
+fn addressbook_path(addressbook: &Addressbook, object_id: &str) -> String {
+    format!(
+        "{}/addressbooks/{}/{object_id}.vcf",
+        addressbook.principal, addressbook.id
+    )
+}
+
+#[derive(Debug, Parser)]
+pub struct BackupArgs {
+    #[arg(help = "Path of the archive to create")]
+    pub output: PathBuf,
+    #[arg(long, help = "Only back up this principal")]
+    pub principal: Option<String>,
+    #[arg(
+        long,
+        help = "Also back up TOTP secrets and passkeys. WARNING: The archive is not encrypted, anyone who can read it can generate second factor codes for the backed up principals"
+    )]
+    pub include_secrets: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct RestoreArgs {
+    #[arg(help = "Path of the archive to restore")]
+    pub input: PathBuf,
+    #[arg(long, help = "Only restore this principal")]
+    pub principal: Option<String>,
+    #[arg(long, help = "Overwrite existing principals")]
+    pub overwrite: bool,
+    #[arg(
+        long,
+        help = "Continue the change logs from the backed up sync tokens so that clients keep syncing incrementally"
+    )]
+    pub keep_sync_tokens: bool,
+}
+
+#[allow(clippy::missing_errors_doc)]
+pub async fn cmd_backup(args: BackupArgs, config: Config) -> anyhow::Result<()> {
+    // Only reads, an outdated schema is left to the next server start
+    with_data_stores!(false, &config.data_store, (addr_store, cal_store, _, principal_store, _) => {
+        let (manifest, files) = create_backup(
+            args.principal.as_deref(),
+            args.include_secrets,
+            addr_store.as_ref(),
+            cal_store.as_ref(),
+            principal_store.as_ref(),
+        )
+        .await?;
+        write_archive(create_private_file(&args.output)?, &manifest, &files)?;
+        println!(
+            "Backed up {} principal(s) to {}",
+            manifest.principals.len(),
+            args.output.display()
+        );
+        Ok(())
+    })
+}
+
+#[allow(clippy::missing_errors_doc)]
+pub async fn cmd_restore(
+    args: RestoreArgs,
+    config: Config,
+    run_migrations: bool,
+) -> anyhow::Result<()> {
+    let (manifest, files) = read_archive(File::open(&args.input)?)?;
+    with_data_stores!(run_migrations, &config.data_store, (addr_store, cal_store, _, principal_store, _) => {
+        restore_backup(
+            manifest,
+            files,
+            &args,
+            addr_store.as_ref(),
+            cal_store.as_ref(),
+            principal_store.as_ref(),
+        )
+        .await
+    })
+}
+
+// The archive contains password and token hashes, so only the owner may read it
+fn create_private_file(path: &Path) -> std::io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+async fn create_backup(
+    principal_filter: Option<&str>,
+    include_secrets: bool,
+    addr_store: &impl AddressbookStore,
+    cal_store: &impl CalendarStore,
+    principal_store: &impl AuthenticationProvider,
+) -> anyhow::Result<(Manifest, Files)> {
+    let mut files = Files::new();
+    let mut principals = vec![];
+    for principal in principal_store.get_principals().await? {
+        if principal_filter.is_some_and(|id| id != principal.id) {
+            continue;
+        }
+
+        let mut calendars = vec![];
+        for calendar in cal_store
+            .get_calendars(&principal.id)
+            .await?
+            .into_iter()
+            .chain(cal_store.get_deleted_calendars(&principal.id).await?)
+        {
+            calendars.push(backup_calendar(cal_store, calendar, &mut files).await?);
+        }
+        let mut addressbooks = vec![];
+        for addressbook in addr_store
+            .get_addressbooks(&principal.id)
+            .await?
+            .into_iter()
+            .chain(addr_store.get_deleted_addressbooks(&principal.id).await?)
+        {
+            addressbooks.push(backup_addressbook(addr_store, addressbook, &mut files).await?);
+        }
+
+        let (totp, passkeys) = if include_secrets {
+            (
+                principal_store.get_totp(&principal.id).await?,
+                principal_store.get_passkeys(&principal.id).await?,
+            )
+        } else {
+            (None, vec![])
+        };
+        principals.push(PrincipalBackup {
+            app_tokens: principal_store.get_app_tokens(&principal.id).await?,
+            email: principal_store.get_email(&principal.id).await?,
+            identity_provider: principal_store.get_identity_provider(&principal.id).await?,
+            totp,
+            passkeys,
+            id: principal.id,
+            displayname: principal.displayname,
+            principal_type: principal.principal_type,
+            password_hash: principal.password.map(Secret::into_inner),
+            memberships: principal.memberships,
+            calendars,
+            addressbooks,
+        });
+    }
+    if let Some(id) = principal_filter
+        && principals.is_empty()
+    {
+        bail!("Principal {id} does not exist");
+    }
+
+    let manifest = Manifest {
+        version: BACKUP_VERSION,
+        created_at: Utc::now(),
+        principals,
+    };
+    Ok((manifest, files))
+}
+
+async fn backup_calendar(
+    cal_store: &impl CalendarStore,
+    calendar: Calendar,
+    files: &mut Files,
+) -> anyhow::Result<CollectionBackup<Calendar>> {
+    let (principal, cal_id) = (&calendar.principal, &calendar.id);
+    let mut objects = vec![];
+    for (id, object) in cal_store.get_objects(principal, cal_id).await? {
+        files.insert(calendar_path(&calendar, &id), object.get_ics().to_owned());
+        objects.push(ObjectEntry { id, deleted: false });
+    }
+    for (id, object) in cal_store.get_deleted_objects(principal, cal_id).await? {
+        files.insert(calendar_path(&calendar, &id), object.get_ics().to_owned());
+        objects.push(ObjectEntry { id, deleted: true });
+    }
+    Ok(CollectionBackup {
+        collection: calendar,
+        objects,
+    })
+}
+
+async fn backup_addressbook(
+    addr_store: &impl AddressbookStore,
+    addressbook: Addressbook,
+    files: &mut Files,
+) -> anyhow::Result<CollectionBackup<Addressbook>> {
+    let (principal, addr_id) = (&addressbook.principal, &addressbook.id);
+    let mut objects = vec![];
+    for (id, object) in addr_store.get_objects(principal, addr_id).await? {
+        files.insert(
+            addressbook_path(&addressbook, &id),
+            object.get_vcf().to_owned(),
+        );
+        objects.push(ObjectEntry { id, deleted: false });
+    }
+    for (id, object) in addr_store.get_deleted_objects(principal, addr_id).await? {
+        files.insert(
+            addressbook_path(&addressbook, &id),
+            object.get_vcf().to_owned(),
+        );
+        objects.push(ObjectEntry { id, deleted: true });
+    }
+    Ok(CollectionBackup {
+        collection: addressbook,
+        objects,
+    })
+}
+
+fn write_archive(writer: impl Write, manifest: &Manifest, files: &Files) -> anyhow::Result<()> {
+    let manifest_json = serde_json::to_string_pretty(manifest)?;
+    let mtime = u64::try_from(manifest.created_at.timestamp()).unwrap_or_default();
+    let mut paths: Vec<&String> = files.keys().collect();
+    paths.sort();
+
+    let mut builder = tar::Builder::new(writer);
+    // The manifest comes first so that it is easy to inspect
+    for (path, content) in std::iter::once((MANIFEST, manifest_json.as_str())).chain(
+        paths
+            .into_iter()
+            .map(|path| (path.as_str(), files[path].as_str())),
+    ) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o600);
+        header.set_mtime(mtime);
+        builder.append_data(&mut header, path, content.as_bytes())?;
+    }
+    builder.into_inner()?.flush()?;
+    Ok(())
+}
+
+fn read_archive(reader: impl Read) -> anyhow::Result<(Manifest, Files)> {
+    let mut manifest = None;
+    let mut files = Files::new();
+    for entry in tar::Archive::new(reader).entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let mut content = String::new();
+        entry.read_to_string(&mut content)?;
+        if path == MANIFEST {
+            manifest = Some(serde_json::from_str::<Manifest>(&content)?);
+        } else {
+            files.insert(path, content);
+        }
+    }
+    let manifest = manifest.ok_or_else(|| anyhow!("{MANIFEST} is missing in the backup"))?;
+    if manifest.version != BACKUP_VERSION {
+        bail!("Unsupported backup version {}", manifest.version);
+    }
+    Ok((manifest, files))
+}
+
+fn take_file(files: &mut Files, path: &str) -> anyhow::Result<String> {
+    files
+        .remove(path)
+        .ok_or_else(|| anyhow!("{path} is missing in the backup"))
+}
+
+async fn restore_backup(
+    manifest: Manifest,
+    mut files: Files,
+    args: &RestoreArgs,
+    addr_store: &impl AddressbookStore,
+    cal_store: &impl CalendarStore,
+    principal_store: &impl AuthenticationProvider,
+) -> anyhow::Result<()> {
+    let principals: Vec<PrincipalBackup> = manifest
+        .principals
+        .into_iter()
+        .filter(|principal| args.principal.as_ref().is_none_or(|id| id == &principal.id))
+        .collect();
+    if let Some(id) = &args.principal
+        && principals.is_empty()
+    {
+        bail!("Principal {id} is not in the backup");
+    }
+
+    for principal in &principals {
+        principal_store
+            .insert_principal(
+                Principal {
+                    id: principal.id.clone(),
+                    displayname: principal.displayname.clone(),
+                    principal_type: principal.principal_type.clone(),
+                    password: principal.password_hash.clone().map(Secret::from),
+                    memberships: vec![],
+                    app_token: None,
+                },
+                args.overwrite,
+            )
+            .await?;
+        for app_token in &principal.app_tokens {
+            principal_store
+                .remove_app_token(&principal.id, &app_token.id)
+                .await?;
+            principal_store
+                .insert_app_token(&principal.id, app_token.clone())
+                .await?;
+        }
+        if principal.email.is_some() {
+            principal_store
+                .set_email(&principal.id, principal.email.as_deref())
+                .await?;
+        }
+        if let Some(identity_provider) = &principal.identity_provider {
+            principal_store
+                .set_identity_provider(&principal.id, identity_provider)
+                .await?;
+        }
+        if principal.totp.is_some() {
+            principal_store
+                .set_totp(&principal.id, principal.totp.clone())
+                .await?;
+        }
+        for passkey in &principal.passkeys {
+            principal_store
+                .upsert_passkey(&principal.id, passkey.clone())
+                .await?;
+        }
+    }
+
+    // Memberships once all principals exist
+    for principal in &principals {
+        for member_of in &principal.memberships {
+            if principal_store.get_principal(member_of).await?.is_none() {
+                println!(
+                    "Skipping membership of {} in {member_of} which does not exist",
+                    principal.id
+                );
+                continue;
+            }
+            principal_store
+                .add_membership(&principal.id, member_of)
+                .await?;
+        }
+    }
+
+    for principal in principals {
+        for CollectionBackup {
+            collection: mut calendar,
+            objects,
+        } in principal.calendars
+        {
+            let mut cal_objects = vec![];
+            for ObjectEntry { id, .. } in &objects {
+                let ics = take_file(&mut files, &calendar_path(&calendar, id))?;
+                cal_objects.push((id.clone(), CalendarObject::from_ics(ics)?));
+            }
+            let deleted_at = calendar.deleted_at.take();
+            if !args.keep_sync_tokens {
+                calendar.synctoken = 0;
+            }
+            let (principal, cal_id) = (calendar.principal.clone(), calendar.id.clone());
+
+            cal_store.insert_calendar(calendar).await?;
+            if !cal_objects.is_empty() {
+                cal_store
+                    .put_objects(&principal, &cal_id, cal_objects, false)
+                    .await?;
+            }
+            for ObjectEntry { id, .. } in objects.iter().filter(|object| object.deleted) {
+                cal_store
+                    .delete_object(&principal, &cal_id, id, true)
+                    .await?;
+            }
+            if deleted_at.is_some() {
+                cal_store.delete_calendar(&principal, &cal_id, true).await?;
+            }
+        }
+
+        for CollectionBackup {
+            collection: mut addressbook,
+            objects,
+        } in principal.addressbooks
+        {
+            let mut addr_objects = vec![];
+            for ObjectEntry { id, .. } in &objects {
+                let vcf = take_file(&mut files, &addressbook_path(&addressbook, id))?;
+                addr_objects.push((id.clone(), AddressObject::from_vcf(vcf)?));
+            }
+            let deleted_at = addressbook.deleted_at.take();
+            if !args.keep_sync_tokens {
+                addressbook.synctoken = 0;
+            }
+            let (principal, addr_id) = (addressbook.principal.clone(), addressbook.id.clone());
+
+            addr_store
+                .import_addressbook(addressbook, addr_objects, false)
+                .await?;
+            for ObjectEntry { id, .. } in objects.iter().filter(|object| object.deleted) {
+                addr_store
+                    .delete_object(&principal, &addr_id, id, true)
+                    .await?;
+            }
+            if deleted_at.is_some() {
+                addr_store
+                    .delete_addressbook(&principal, &addr_id, true)
+                    .await?;
+            }
+        }
+        println!("Restored principal {}", principal.id);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RestoreArgs, create_backup, read_archive, restore_backup, write_archive};
+    use rustical_ical::{AddressObject, CalendarObject, CalendarObjectType};
+    use rustical_store::auth::{
+        AppTokenScope, AuthenticationProvider, Principal, PrincipalType, SecondFactorStore, Totp,
+    };
+    use rustical_store::{
+        Addressbook, AddressbookReadStore, AddressbookWriteStore, Calendar, CalendarReadStore,
+        CalendarWriteStore, EventBus, Secret,
+    };
+    use rustical_store_memory::{
+        MemoryDb, addressbook_store::MemoryAddressbookStore, calendar_store::MemoryCalendarStore,
+        principal_store::MemoryPrincipalStore,
+    };
+
+    fn memory_stores() -> (
+        MemoryAddressbookStore,
+        MemoryCalendarStore,
+        MemoryPrincipalStore,
+    ) {
+        let db = MemoryDb::default();
+        let events = EventBus::default();
+        (
+            MemoryAddressbookStore::new(db.clone(), events.clone()),
+            MemoryCalendarStore::new(db.clone(), events.clone()),
+            MemoryPrincipalStore::new(db, events),
+        )
+    }
+
+    fn calendar(id: &str) -> Calendar {
+        Calendar {
+            principal: "user".to_owned(),
+            id: id.to_owned(),
+            push_topic: id.to_owned(),
+            components: vec![CalendarObjectType::Event],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_backup_restore() {
+        let (addr_store, cal_store, principal_store) = memory_stores();
+        for (id, principal_type) in [
+            ("user", PrincipalType::Individual),
+            ("group", PrincipalType::Group),
+        ] {
+            principal_store
+                .insert_principal(
+                    Principal {
+                        id: id.to_owned(),
+                        displayname: None,
+                        principal_type,
+                        password: Some(Secret::from("hash".to_owned())),
+                        memberships: vec![],
+                        app_token: None,
+                    },
+                    false,
+                )
+                .await
+                .unwrap();
+        }
+        principal_store
+            .add_membership("user", "group")
+            .await
+            .unwrap();
+        principal_store
+            .set_email("user", Some("user@example.com"))
+            .await
+            .unwrap();
+        principal_store
+            .set_identity_provider("user", "company")
+            .await
+            .unwrap();
+        let token_id = principal_store
+            .add_app_token(
+                "user",
+                "phone".to_owned(),
+                "token".to_owned(),
+                AppTokenScope::default(),
+                None,
+            )
+            .await
+            .unwrap();
+
+        cal_store.insert_calendar(calendar("cal")).await.unwrap();
+        cal_store
+            .put_object("user", "cal", "event", CalendarObject::example_1(), false)
+            .await
+            .unwrap();
+        cal_store
+            .delete_object("user", "cal", "event", true)
+            .await
+            .unwrap();
+        // A trashed calendar keeps both its live and its trashed objects
+        cal_store.insert_calendar(calendar("old")).await.unwrap();
+        let other = CalendarObject::from_ics(
+            CalendarObject::example_1()
+                .get_ics()
+                .replace("fa915b604e6e3f36772501ff869439e6a3c5cf67", "other"),
+        )
+        .unwrap();
+        cal_store
+            .put_objects(
+                "user",
+                "old",
+                vec![
+                    ("event".to_owned(), CalendarObject::example_1()),
+                    ("other".to_owned(), other),
+                ],
+                false,
+            )
+            .await
+            .unwrap();
+        cal_store
+            .delete_object("user", "old", "other", true)
+            .await
+            .unwrap();
+        cal_store
+            .delete_calendar("user", "old", true)
+            .await
+            .unwrap();
+        let synctoken = cal_store
+            .get_calendar("user", "cal", false)
+            .await
+            .unwrap()
+            .synctoken;
+
+        addr_store
+            .insert_addressbook(Addressbook {
+                id: "contacts".to_owned(),
+                principal: "user".to_owned(),
+                displayname: None,
+                description: None,
+                deleted_at: None,
+                synctoken: 0,
+                push_topic: "contacts".to_owned(),
+            })
+            .await
+            .unwrap();
+        AddressbookWriteStore::put_object(
+            &addr_store,
+            "user",
+            "contacts",
+            "contact",
+            AddressObject::example_minimal(),
+            false,
+        )
+        .await
+        .unwrap();
+
+        principal_store
+            .set_totp(
+                "user",
+                Some(Totp {
+                    secret: Secret::from("secret".to_owned()),
+                    recovery_codes: vec![],
+                }),
+            )
+            .await
+            .unwrap();
+        // Second factors are only backed up on request
+        let (manifest, _) = create_backup(None, false, &addr_store, &cal_store, &principal_store)
+            .await
+            .unwrap();
+        assert!(
+            manifest
+                .principals
+                .iter()
+                .all(|principal| principal.totp.is_none())
+        );
+
+        let (manifest, files) =
+            create_backup(None, true, &addr_store, &cal_store, &principal_store)
+                .await
+                .unwrap();
+        let mut archive = vec![];
+        write_archive(&mut archive, &manifest, &files).unwrap();
+        let (manifest, files) = read_archive(archive.as_slice()).unwrap();
+
+        let (addr_store, cal_store, principal_store) = memory_stores();
+        let args = RestoreArgs {
+            input: Default::default(),
+            principal: None,
+            overwrite: false,
+            keep_sync_tokens: true,
+        };
+        restore_backup(
+            manifest,
+            files,
+            &args,
+            &addr_store,
+            &cal_store,
+            &principal_store,
+        )
+        .await
+        .unwrap();
+
+        let user = principal_store
+            .get_principal("user")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.memberships, vec!["group".to_owned()]);
+        assert_eq!(user.password.unwrap().into_inner(), "hash");
+        assert_eq!(
+            principal_store.get_email("user").await.unwrap().as_deref(),
+            Some("user@example.com")
+        );
+        assert_eq!(
+            principal_store
+                .get_identity_provider("user")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("company")
+        );
+        assert_eq!(principal_store.get_email("group").await.unwrap(), None);
+        assert!(principal_store.get_totp("user").await.unwrap().is_some());
+        let app_tokens = principal_store.get_app_tokens("user").await.unwrap();
+        assert_eq!(app_tokens.len(), 1);
+        assert_eq!(app_tokens[0].id, token_id);
+        assert!(
+            principal_store
+                .validate_app_token("user", "token")
+                .await
+                .unwrap()
+                .is_some()
+        );
+
+        let cal = cal_store.get_calendar("user", "cal", false).await.unwrap();
+        // Clients holding the old sync token get the restored objects as changes
+        assert!(cal.synctoken > synctoken);
+        assert!(
+            cal_store
+                .get_objects("user", "cal")
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        cal_store
+            .get_object("user", "cal", "event", true)
+            .await
+            .unwrap();
+        let deleted = cal_store.get_deleted_calendars("user").await.unwrap();
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0].id, "old");
+        let objects = cal_store.get_objects("user", "old").await.unwrap();
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].0, "event");
+        let objects = cal_store.get_deleted_objects("user", "old").await.unwrap();
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].0, "other");
+
+        AddressbookReadStore::get_object(&addr_store, "user", "contacts", "contact", false)
+            .await
+            .unwrap();
+    }
+}
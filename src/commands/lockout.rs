@@ -0,0 +1,56 @@
+use clap::{Parser, Subcommand};
+use rustical_store::auth::{LoginFailureStore, LoginSubject};
+use std::net::IpAddr;
+
+#[derive(Debug, Parser)]
+pub struct UnlockArgs {
+    #[arg(help = "Principal id or client address")]
+    subject: String,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum LockoutCommand {
+    /// Lists principals and client addresses with recent failed logins
+    List,
+    /// Lifts a lockout and forgets the failed logins
+    Unlock(UnlockArgs),
+}
+
+#[derive(Parser, Debug)]
+pub struct LockoutArgs {
+    #[command(subcommand)]
+    pub command: LockoutCommand,
+}
+
+#[allow(clippy::missing_errors_doc)]
+pub async fn cmd_lockout(
+    user_store: &impl LoginFailureStore,
+    LockoutArgs { command }: LockoutArgs,
+) -> anyhow::Result<()> {
+    match command {
+        LockoutCommand::List => {
+            for failures in user_store.list_login_failures().await? {
+                let status = match failures.locked_until {
+                    Some(locked_until) if failures.is_locked() => {
+                        format!("locked until {}", locked_until.to_rfc3339())
+                    }
+                    _ => "not locked".to_owned(),
+                };
+                println!(
+                    "{} - {} failures, last at {} [{status}]",
+                    failures.subject,
+                    failures.count,
+                    failures.last_failure_at.to_rfc3339()
+                );
+            }
+        }
+        LockoutCommand::Unlock(UnlockArgs { subject }) => {
+            let subject = subject
+                .parse::<IpAddr>()
+                .map_or_else(|_| LoginSubject::Principal(subject), LoginSubject::Address);
+            user_store.remove_login_failures(&subject).await?;
+            println!("Unlocked {subject}");
+        }
+    }
+    Ok(())
+}
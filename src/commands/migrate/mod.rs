@@ -0,0 +1,438 @@
+//! Imports principals, calendars and addressbooks from other servers
+use crate::{config::Config, with_data_stores};
+use clap::{Parser, Subcommand};
+use rustical_ical::{AddressObject, CalendarObject, CalendarObjectType};
+use rustical_store::{
+    Addressbook, AddressbookStore, Calendar, CalendarMetadata, CalendarStore, Error,
+    auth::{AuthenticationProvider, Principal, PrincipalType},
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+
+mod radicale;
+mod sabre;
+mod sql_dump;
+
+// Column -> value
+type Row = HashMap<String, Option<String>>;
+type Tables = HashMap<String, Vec<Row>>;
+
+/// A calendar or addressbook read from another server
+#[derive(Debug, Default)]
+struct SourceCollection {
+    principal: String,
+    id: String,
+    displayname: Option<String>,
+    description: Option<String>,
+    color: Option<String>,
+    order: i64,
+    components: Vec<CalendarObjectType>,
+    // (file name, content)
+    objects: Vec<(String, String)>,
+}
+
+#[derive(Debug, Default)]
+struct Source {
+    // id -> displayname
+    principals: BTreeMap<String, Option<String>>,
+    calendars: Vec<SourceCollection>,
+    addressbooks: Vec<SourceCollection>,
+    skipped: Vec<String>,
+}
+
+impl Source {
+    fn skip(&mut self, reason: String) {
+        self.skipped.push(reason);
+    }
+}
+
+// VEVENT,VTODO
+fn parse_components(components: &str) -> Vec<CalendarObjectType> {
+    components
+        .split(',')
+        .filter_map(|component| match component.trim() {
+            "VEVENT" => Some(CalendarObjectType::Event),
+            "VTODO" => Some(CalendarObjectType::Todo),
+            "VJOURNAL" => Some(CalendarObjectType::Journal),
+            _ => None,
+        })
+        .collect()
+}
+
+fn object_id(name: &str) -> &str {
+    name.strip_suffix(".ics")
+        .or_else(|| name.strip_suffix(".vcf"))
+        .unwrap_or(name)
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MigrateSource {
+    #[command(about = "Radicale filesystem storage")]
+    Radicale {
+        #[arg(help = "Radicale's filesystem_folder or its collection-root")]
+        path: PathBuf,
+    },
+    #[command(about = "Baïkal or another sabre/dav server")]
+    Baikal {
+        #[arg(help = "SQLite database or MySQL dump")]
+        path: PathBuf,
+        #[arg(long, default_value = "")]
+        table_prefix: String,
+    },
+    #[command(about = "Nextcloud calendars and contacts")]
+    Nextcloud {
+        #[arg(help = "SQLite database or MySQL dump")]
+        path: PathBuf,
+        #[arg(long, default_value = "oc_")]
+        table_prefix: String,
+    },
+}
+
+#[derive(Debug, Parser)]
+pub struct MigrateArgs {
+    #[command(subcommand)]
+    pub source: MigrateSource,
+    #[arg(long, global = true, help = "Only migrate this principal")]
+    pub principal: Option<String>,
+    #[arg(long, global = true, help = "Only report what would be migrated")]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Default)]
+struct Summary {
+    principals: Vec<String>,
+    calendars: usize,
+    addressbooks: usize,
+    objects: usize,
+    skipped: Vec<String>,
+}
+
+#[allow(clippy::missing_errors_doc)]
+pub async fn cmd_migrate(
+    args: MigrateArgs,
+    config: Config,
+    run_migrations: bool,
+) -> anyhow::Result<()> {
+    let mut source = match &args.source {
+        MigrateSource::Radicale { path } => radicale::read_source(path)?,
+        MigrateSource::Baikal { path, table_prefix }
+        | MigrateSource::Nextcloud { path, table_prefix } => {
+            sabre::read_source(sabre::read_tables(path, table_prefix).await?)
+        }
+    };
+    if let Some(principal) = &args.principal {
+        source.principals.retain(|id, _| id == principal);
+        source.calendars.retain(|cal| &cal.principal == principal);
+        source
+            .addressbooks
+            .retain(|book| &book.principal == principal);
+    }
+
+    let summary = with_data_stores!(run_migrations, &config.data_store, (addr_store, cal_store, _, principal_store, _) => {
+        migrate(
+            source,
+            args.dry_run,
+            addr_store.as_ref(),
+            cal_store.as_ref(),
+            principal_store.as_ref(),
+        )
+        .await
+    })?;
+
+    println!(
+        "{} {} principal(s), {} calendar(s), {} addressbook(s) and {} object(s)",
+        if args.dry_run {
+            "Would migrate"
+        } else {
+            "Migrated"
+        },
+        summary.principals.len(),
+        summary.calendars,
+        summary.addressbooks,
+        summary.objects
+    );
+    if !summary.skipped.is_empty() {
+        println!("Skipped {} item(s):", summary.skipped.len());
+        for reason in &summary.skipped {
+            println!("  {reason}");
+        }
+    }
+    if !args.dry_run && !summary.principals.is_empty() {
+        println!(
+            "The new principals {} have no password yet, set one with `rustical principals edit <id> --password`",
+            summary.principals.join(", ")
+        );
+    }
+    Ok(())
+}
+
+async fn migrate(
+    mut source: Source,
+    dry_run: bool,
+    addr_store: &impl AddressbookStore,
+    cal_store: &impl CalendarStore,
+    principal_store: &impl AuthenticationProvider,
+) -> anyhow::Result<Summary> {
+    let mut summary = Summary {
+        skipped: std::mem::take(&mut source.skipped),
+        ..Default::default()
+    };
+
+    // Nextcloud has no principals table so the owners are added as well
+    for collection in source.calendars.iter().chain(&source.addressbooks) {
+        source
+            .principals
+            .entry(collection.principal.clone())
+            .or_default();
+    }
+    let mut failed_principals = HashSet::new();
+    for (id, displayname) in source.principals {
+        if principal_store.get_principal(&id).await?.is_some() {
+            continue;
+        }
+        let principal = Principal {
+            id: id.clone(),
+            displayname,
+            principal_type: PrincipalType::Individual,
+            password: None,
+            memberships: vec![],
+            app_token: None,
+        };
+        if !dry_run && let Err(err) = principal_store.insert_principal(principal, false).await {
+            summary.skipped.push(format!("principal {id}: {err}"));
+            failed_principals.insert(id);
+            continue;
+        }
+        summary.principals.push(id);
+    }
+
+    for collection in source.calendars {
+        if !failed_principals.contains(&collection.principal) {
+            migrate_calendar(collection, dry_run, cal_store, &mut summary).await?;
+        }
+    }
+    for collection in source.addressbooks {
+        if !failed_principals.contains(&collection.principal) {
+            migrate_addressbook(collection, dry_run, addr_store, &mut summary).await?;
+        }
+    }
+    Ok(summary)
+}
+
+async fn migrate_calendar(
+    collection: SourceCollection,
+    dry_run: bool,
+    cal_store: &impl CalendarStore,
+    summary: &mut Summary,
+) -> anyhow::Result<()> {
+    let path = format!("{}/{}", collection.principal, collection.id);
+    let mut uids = HashSet::new();
+    let mut objects = vec![];
+    for (name, ics) in collection.objects {
+        match CalendarObject::import(&ics, None) {
+            Ok(object) if !uids.insert(object.get_uid().to_owned()) => summary
+                .skipped
+                .push(format!("{path}/{name}: duplicate UID {}", object.get_uid())),
+            Ok(object) => objects.push(object),
+            Err(err) => summary.skipped.push(format!("{path}/{name}: {err}")),
+        }
+    }
+
+    let mut components = collection.components;
+    if components.is_empty() {
+        for object in &objects {
+            let object_type = object.get_object_type();
+            if !components.contains(&object_type) {
+                components.push(object_type);
+            }
+        }
+    }
+    if components.is_empty() {
+        components = vec![
+            CalendarObjectType::Event,
+            CalendarObjectType::Todo,
+            CalendarObjectType::Journal,
+        ];
+    }
+
+    let count = objects.len();
+    let calendar = Calendar {
+        principal: collection.principal,
+        id: collection.id,
+        meta: CalendarMetadata {
+            displayname: collection.displayname,
+            order: collection.order,
+            description: collection.description,
+            color: collection.color,
+        },
+        timezone_id: None,
+        deleted_at: None,
+        synctoken: 0,
+        subscription_url: None,
+        push_topic: uuid::Uuid::new_v4().to_string(),
+        components,
+    };
+    if !dry_run {
+        match cal_store.import_calendar(calendar, objects, false).await {
+            Err(Error::AlreadyExists) => {
+                summary
+                    .skipped
+                    .push(format!("{path}: calendar already exists"));
+                return Ok(());
+            }
+            res => res?,
+        }
+    }
+    summary.calendars += 1;
+    summary.objects += count;
+    Ok(())
+}
+
+async fn migrate_addressbook(
+    collection: SourceCollection,
+    dry_run: bool,
+    addr_store: &impl AddressbookStore,
+    summary: &mut Summary,
+) -> anyhow::Result<()> {
+    let path = format!("{}/{}", collection.principal, collection.id);
+    let mut objects = vec![];
+    for (name, vcf) in collection.objects {
+        match AddressObject::from_vcf(vcf) {
+            Ok(object) => objects.push((object_id(&name).to_owned(), object)),
+            Err(err) => summary.skipped.push(format!("{path}/{name}: {err}")),
+        }
+    }
+
+    let count = objects.len();
+    let addressbook = Addressbook {
+        principal: collection.principal,
+        id: collection.id,
+        displayname: collection.displayname,
+        description: collection.description,
+        deleted_at: None,
+        synctoken: 0,
+        push_topic: uuid::Uuid::new_v4().to_string(),
+    };
+    if !dry_run {
+        match addr_store
+            .import_addressbook(addressbook, objects, false)
+            .await
+        {
+            Err(Error::AlreadyExists) => {
+                summary
+                    .skipped
+                    .push(format!("{path}: addressbook already exists"));
+                return Ok(());
+            }
+            res => res?,
+        }
+    }
+    summary.addressbooks += 1;
+    summary.objects += count;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{migrate, radicale, sabre};
+    use rustical_ical::CalendarObjectType;
+    use rustical_store::auth::AuthenticationProvider;
+    use rustical_store::{AddressbookReadStore, CalendarReadStore, EventBus};
+    use rustical_store_memory::{
+        MemoryDb, addressbook_store::MemoryAddressbookStore, calendar_store::MemoryCalendarStore,
+        principal_store::MemoryPrincipalStore,
+    };
+
+    const EVENT: &str = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:test\r\nBEGIN:VEVENT\r\nUID:event-1\r\nDTSTAMP:20240101T000000Z\r\nDTSTART:20240101T100000Z\r\nSUMMARY:Test\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n";
+    const CARD: &str = "BEGIN:VCARD\r\nVERSION:4.0\r\nUID:card-1\r\nFN:Test\r\nEND:VCARD\r\n";
+
+    #[tokio::test]
+    async fn test_migrate_radicale() {
+        let dir = tempfile::tempdir().unwrap();
+        let calendar = dir.path().join("collection-root/alice/work");
+        let contacts = dir.path().join("collection-root/alice/contacts");
+        std::fs::create_dir_all(&calendar).unwrap();
+        std::fs::create_dir_all(&contacts).unwrap();
+        std::fs::write(
+            calendar.join(".Radicale.props"),
+            r##"{"tag": "VCALENDAR", "D:displayname": "Work", "ICAL:calendar-color": "#ff0000ff", "C:supported-calendar-component-set": "VEVENT"}"##,
+        )
+        .unwrap();
+        std::fs::write(calendar.join("event-1.ics"), EVENT).unwrap();
+        std::fs::write(calendar.join("duplicate.ics"), EVENT).unwrap();
+        std::fs::write(calendar.join("broken.ics"), "BEGIN:VCALENDAR").unwrap();
+        std::fs::write(
+            contacts.join(".Radicale.props"),
+            r#"{"tag": "VADDRESSBOOK"}"#,
+        )
+        .unwrap();
+        std::fs::write(contacts.join("card-1.vcf"), CARD).unwrap();
+
+        let db = MemoryDb::default();
+        let events = EventBus::default();
+        let addr_store = MemoryAddressbookStore::new(db.clone(), events.clone());
+        let cal_store = MemoryCalendarStore::new(db.clone(), events.clone());
+        let principal_store = MemoryPrincipalStore::new(db, events);
+
+        let source = radicale::read_source(dir.path()).unwrap();
+        let summary = migrate(source, false, &addr_store, &cal_store, &principal_store)
+            .await
+            .unwrap();
+        assert_eq!(summary.principals, vec!["alice".to_owned()]);
+        assert_eq!(summary.calendars, 1);
+        assert_eq!(summary.addressbooks, 1);
+        assert_eq!(summary.objects, 2);
+        assert_eq!(summary.skipped.len(), 2);
+
+        assert!(
+            principal_store
+                .get_principal("alice")
+                .await
+                .unwrap()
+                .is_some()
+        );
+        let calendar = CalendarReadStore::get_calendar(&cal_store, "alice", "work", false)
+            .await
+            .unwrap();
+        assert_eq!(calendar.meta.displayname.as_deref(), Some("Work"));
+        assert_eq!(calendar.meta.color.as_deref(), Some("#ff0000ff"));
+        assert_eq!(calendar.components, vec![CalendarObjectType::Event]);
+        CalendarReadStore::get_object(&cal_store, "alice", "work", "event-1", false)
+            .await
+            .unwrap();
+        AddressbookReadStore::get_object(&addr_store, "alice", "contacts", "card-1", false)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_nextcloud_dump() {
+        let event = EVENT.replace("\r\n", "\\r\\n");
+        let dump = format!(
+            "CREATE TABLE `oc_calendars` (`id` bigint, `principaluri` varchar(255), `displayname` varchar(255), `uri` varchar(255), `calendarcolor` varchar(255), `components` varchar(64), `deleted_at` int);
+INSERT INTO `oc_calendars` VALUES (1,'principals/users/bob','Personal','personal','#0082c9','VEVENT,VTODO',NULL),(2,'principals/users/bob','Birthdays','contact_birthdays',NULL,'VEVENT',NULL);
+CREATE TABLE `oc_calendarobjects` (`id` bigint, `calendardata` longblob, `uri` varchar(255), `calendarid` bigint, `calendartype` int, `deleted_at` int);
+INSERT INTO `oc_calendarobjects` VALUES (1,'{event}','event-1.ics',1,0,NULL),(2,'{event}','old.ics',1,0,1700000000);
+"
+        );
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), dump).unwrap();
+
+        let source = sabre::read_source(sabre::read_tables(file.path(), "oc_").await.unwrap());
+        assert_eq!(source.calendars.len(), 1);
+        // The trashed object and the birthday calendar
+        assert_eq!(source.skipped.len(), 2);
+        let calendar = &source.calendars[0];
+        assert_eq!(calendar.principal, "bob");
+        assert_eq!(calendar.id, "personal");
+        assert_eq!(calendar.color.as_deref(), Some("#0082c9"));
+        assert_eq!(
+            calendar.components,
+            vec![CalendarObjectType::Event, CalendarObjectType::Todo]
+        );
+        assert_eq!(
+            calendar.objects,
+            vec![("event-1.ics".to_owned(), EVENT.to_owned())]
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! Radicale's filesystem storage keeps every collection in
+//! `collection-root/<user>/<collection>/` with its properties in `.Radicale.props`
+//! and one file per item.
+use super::{Source, SourceCollection, parse_components};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const PROPS: &str = ".Radicale.props";
+
+fn list_entries(path: &Path, dirs: bool) -> anyhow::Result<Vec<(String, PathBuf)>> {
+    let mut entries = vec![];
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let Some(name) = entry.file_name().to_str().map(str::to_owned) else {
+            continue;
+        };
+        // Radicale's own files and caches are hidden
+        if name.starts_with('.') || entry.file_type()?.is_dir() != dirs {
+            continue;
+        }
+        entries.push((name, entry.path()));
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+fn read_collection(
+    principal: &str,
+    id: String,
+    path: &Path,
+    source: &mut Source,
+) -> anyhow::Result<()> {
+    let props: HashMap<String, String> = match fs::read_to_string(path.join(PROPS)) {
+        Ok(props) => match serde_json::from_str(&props) {
+            Ok(props) => props,
+            Err(err) => {
+                source.skip(format!("{principal}/{id}: invalid {PROPS}: {err}"));
+                return Ok(());
+            }
+        },
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+        Err(err) => return Err(err.into()),
+    };
+    let prop = |name: &str| props.get(name).filter(|value| !value.is_empty()).cloned();
+
+    let mut objects = vec![];
+    for (name, item_path) in list_entries(path, false)? {
+        match fs::read_to_string(&item_path) {
+            Ok(content) => objects.push((name, content)),
+            Err(err) => source.skip(format!("{principal}/{id}/{name}: {err}")),
+        }
+    }
+    let collection = SourceCollection {
+        principal: principal.to_owned(),
+        displayname: prop("D:displayname"),
+        objects,
+        ..Default::default()
+    };
+    match props.get("tag").map(String::as_str) {
+        Some("VCALENDAR") => {
+            source.calendars.push(SourceCollection {
+                id,
+                description: prop("C:calendar-description"),
+                color: prop("ICAL:calendar-color"),
+                order: prop("ICAL:calendar-order")
+                    .and_then(|order| order.parse().ok())
+                    .unwrap_or_default(),
+                components: prop("C:supported-calendar-component-set")
+                    .as_deref()
+                    .map(parse_components)
+                    .unwrap_or_default(),
+                ..collection
+            });
+        }
+        Some("VADDRESSBOOK") => {
+            source.addressbooks.push(SourceCollection {
+                id,
+                description: prop("CR:addressbook-description"),
+                ..collection
+            });
+        }
+        Some(tag) => source.skip(format!(
+            "{principal}/{id}: unsupported collection type {tag}"
+        )),
+        None => source.skip(format!("{principal}/{id}: not a calendar or addressbook")),
+    }
+    Ok(())
+}
+
+/// Reads the Radicale storage at `path`, either the `filesystem_folder` or its `collection-root`
+pub fn read_source(path: &Path) -> anyhow::Result<Source> {
+    let collection_root = path.join("collection-root");
+    let root = if collection_root.is_dir() {
+        &collection_root
+    } else {
+        path
+    };
+
+    let mut source = Source::default();
+    for (principal, principal_path) in list_entries(root, true)? {
+        source.principals.insert(principal.clone(), None);
+        for (id, collection_path) in list_entries(&principal_path, true)? {
+            read_collection(&principal, id, &collection_path, &mut source)?;
+        }
+    }
+    Ok(source)
+}
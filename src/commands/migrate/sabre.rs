@@ -0,0 +1,249 @@
+//! Baïkal and Nextcloud both use the sabre/dav schema, either in an SQLite database or a MySQL dump.
+//! Nextcloud prefixes its tables with `oc_` and keeps the calendar metadata in `calendars`
+//! while sabre/dav 3 moved it into `calendarinstances`.
+use super::{Row, Source, SourceCollection, Tables, parse_components, sql_dump};
+use sqlx::{
+    AssertSqlSafe, Column, Row as _, SqlitePool, TypeInfo, ValueRef,
+    sqlite::{SqliteConnectOptions, SqliteRow},
+};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+
+const TABLES: [&str; 6] = [
+    "principals",
+    "calendars",
+    "calendarinstances",
+    "calendarobjects",
+    "addressbooks",
+    "cards",
+];
+// sabre/dav access level of the calendar owner
+const ACCESS_OWNER: &str = "1";
+// Generated by Nextcloud from the contacts
+const BIRTHDAY_CALENDAR: &str = "contact_birthdays";
+// Nextcloud's addressbook of all users
+const SYSTEM_PRINCIPAL: &str = "principals/system/system";
+
+fn get<'a>(row: &'a Row, column: &str) -> Option<&'a str> {
+    row.get(column)
+        .and_then(Option::as_deref)
+        .filter(|value| !value.is_empty())
+}
+
+// principals/alice or principals/users/alice
+fn principal_id(uri: &str) -> &str {
+    uri.rsplit('/').next().unwrap_or(uri)
+}
+
+fn sqlite_row(row: &SqliteRow) -> Result<Row, sqlx::Error> {
+    row.columns()
+        .iter()
+        .map(|column| {
+            let i = column.ordinal();
+            let raw = row.try_get_raw(i)?;
+            let value = if raw.is_null() {
+                None
+            } else {
+                Some(match raw.type_info().name() {
+                    "INTEGER" => row.try_get::<i64, _>(i)?.to_string(),
+                    "REAL" => row.try_get::<f64, _>(i)?.to_string(),
+                    "BLOB" => String::from_utf8_lossy(&row.try_get::<Vec<u8>, _>(i)?).into_owned(),
+                    _ => row.try_get::<String, _>(i)?,
+                })
+            };
+            Ok((column.name().to_owned(), value))
+        })
+        .collect()
+}
+
+async fn read_sqlite(path: &Path, wanted: &[String]) -> anyhow::Result<Tables> {
+    let pool = SqlitePool::connect_with(SqliteConnectOptions::new().filename(path).read_only(true))
+        .await?;
+    let names: Vec<String> =
+        sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'table'")
+            .fetch_all(&pool)
+            .await?;
+    let mut tables = Tables::new();
+    for name in names.into_iter().filter(|name| wanted.contains(name)) {
+        let rows = sqlx::query(AssertSqlSafe(format!("SELECT * FROM \"{name}\"")))
+            .fetch_all(&pool)
+            .await?
+            .iter()
+            .map(sqlite_row)
+            .collect::<Result<_, _>>()?;
+        tables.insert(name, rows);
+    }
+    pool.close().await;
+    Ok(tables)
+}
+
+/// Reads the sabre/dav tables from an SQLite database or a MySQL dump
+pub async fn read_tables(path: &Path, table_prefix: &str) -> anyhow::Result<Tables> {
+    let mut header = [0; 16];
+    let is_sqlite = std::fs::File::open(path)?.read_exact(&mut header).is_ok()
+        && &header == b"SQLite format 3\0";
+    let wanted: Vec<String> = TABLES
+        .iter()
+        .map(|table| format!("{table_prefix}{table}"))
+        .collect();
+    let tables = if is_sqlite {
+        read_sqlite(path, &wanted).await?
+    } else {
+        sql_dump::parse_dump(&std::fs::read(path)?, &wanted)?
+    };
+    if tables.is_empty() {
+        anyhow::bail!(
+            "No sabre/dav tables with the prefix '{table_prefix}' found in {}",
+            path.display()
+        );
+    }
+    Ok(tables
+        .into_iter()
+        .map(|(name, rows)| {
+            let name = name.strip_prefix(table_prefix).unwrap_or(&name).to_owned();
+            (name, rows)
+        })
+        .collect())
+}
+
+/// Groups the `data` column of `table` by `parent` as `(uri, data)`
+fn objects_by_parent(
+    tables: &mut Tables,
+    table: &str,
+    parent: &str,
+    data: &str,
+    source: &mut Source,
+) -> HashMap<String, Vec<(String, String)>> {
+    let mut objects: HashMap<String, Vec<(String, String)>> = HashMap::new();
+    for row in tables.remove(table).unwrap_or_default() {
+        let uri = get(&row, "uri").unwrap_or_default().to_owned();
+        // Nextcloud keeps subscriptions and its trash bin in the same table
+        if get(&row, "calendartype").is_some_and(|calendartype| calendartype != "0") {
+            continue;
+        }
+        if get(&row, "deleted_at").is_some() {
+            source.skip(format!("{table} {uri}: in the trash bin"));
+            continue;
+        }
+        let (Some(parent), Some(data)) = (get(&row, parent), get(&row, data)) else {
+            source.skip(format!("{table} {uri}: no data"));
+            continue;
+        };
+        objects
+            .entry(parent.to_owned())
+            .or_default()
+            .push((uri, data.to_owned()));
+    }
+    objects
+}
+
+fn calendar(
+    row: &Row,
+    objects: Vec<(String, String)>,
+    components: Option<&str>,
+) -> SourceCollection {
+    SourceCollection {
+        principal: principal_id(get(row, "principaluri").unwrap_or_default()).to_owned(),
+        id: get(row, "uri").unwrap_or_default().to_owned(),
+        displayname: get(row, "displayname").map(str::to_owned),
+        description: get(row, "description").map(str::to_owned),
+        color: get(row, "calendarcolor").map(str::to_owned),
+        order: get(row, "calendarorder")
+            .and_then(|order| order.parse().ok())
+            .unwrap_or_default(),
+        components: components.map(parse_components).unwrap_or_default(),
+        objects,
+    }
+}
+
+pub fn read_source(mut tables: Tables) -> Source {
+    let mut source = Source::default();
+
+    for row in tables.remove("principals").unwrap_or_default() {
+        // Skips the calendar-proxy-read and calendar-proxy-write principals
+        if let Some(id) = get(&row, "uri").and_then(|uri| uri.strip_prefix("principals/"))
+            && !id.contains('/')
+        {
+            source
+                .principals
+                .insert(id.to_owned(), get(&row, "displayname").map(str::to_owned));
+        }
+    }
+
+    let mut calendar_objects = objects_by_parent(
+        &mut tables,
+        "calendarobjects",
+        "calendarid",
+        "calendardata",
+        &mut source,
+    );
+    let calendars = tables.remove("calendars").unwrap_or_default();
+    if let Some(instances) = tables.remove("calendarinstances") {
+        let components: HashMap<&str, Option<&str>> = calendars
+            .iter()
+            .filter_map(|row| Some((get(row, "id")?, get(row, "components"))))
+            .collect();
+        for row in &instances {
+            let calendar_id = get(row, "calendarid").unwrap_or_default();
+            if get(row, "access").is_some_and(|access| access != ACCESS_OWNER) {
+                source.skip(format!(
+                    "calendar {}: shared with {}",
+                    get(row, "uri").unwrap_or_default(),
+                    get(row, "principaluri").unwrap_or_default()
+                ));
+                continue;
+            }
+            let objects = calendar_objects.remove(calendar_id).unwrap_or_default();
+            let components = components.get(calendar_id).copied().flatten();
+            source.calendars.push(calendar(row, objects, components));
+        }
+    } else {
+        for row in &calendars {
+            let uri = get(row, "uri").unwrap_or_default();
+            if get(row, "deleted_at").is_some() {
+                source.skip(format!("calendar {uri}: in the trash bin"));
+                continue;
+            }
+            if uri == BIRTHDAY_CALENDAR {
+                source.skip(format!("calendar {uri}: generated by Nextcloud"));
+                continue;
+            }
+            let objects = get(row, "id")
+                .and_then(|id| calendar_objects.remove(id))
+                .unwrap_or_default();
+            source
+                .calendars
+                .push(calendar(row, objects, get(row, "components")));
+        }
+    }
+
+    let mut cards = objects_by_parent(
+        &mut tables,
+        "cards",
+        "addressbookid",
+        "carddata",
+        &mut source,
+    );
+    for row in tables.remove("addressbooks").unwrap_or_default() {
+        let principal_uri = get(&row, "principaluri").unwrap_or_default();
+        if principal_uri == SYSTEM_PRINCIPAL {
+            source.skip(format!(
+                "addressbook {}: generated by Nextcloud",
+                get(&row, "uri").unwrap_or_default()
+            ));
+            continue;
+        }
+        source.addressbooks.push(SourceCollection {
+            principal: principal_id(principal_uri).to_owned(),
+            id: get(&row, "uri").unwrap_or_default().to_owned(),
+            displayname: get(&row, "displayname").map(str::to_owned),
+            description: get(&row, "description").map(str::to_owned),
+            objects: get(&row, "id")
+                .and_then(|id| cards.remove(id))
+                .unwrap_or_default(),
+            ..Default::default()
+        });
+    }
+    source
+}
@@ -0,0 +1,373 @@
+//! Minimal reader for MySQL/MariaDB dumps as written by `mysqldump`.
+//! Only the column lists of `CREATE TABLE` and the rows of `INSERT` statements are evaluated.
+use super::{Row, Tables};
+use anyhow::{anyhow, bail};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    // Quoted with backticks
+    Ident(String),
+    Str(String),
+    Punct(u8),
+}
+
+fn decode_hex(digits: &[u8]) -> anyhow::Result<String> {
+    let bytes = digits
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| anyhow!("Invalid hex literal in SQL dump"))
+        })
+        .collect::<anyhow::Result<Vec<u8>>>()?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+struct Lexer<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Lexer<'_> {
+    fn peek_at(&self, offset: usize) -> Option<u8> {
+        self.input.get(self.pos + offset).copied()
+    }
+
+    fn skip_until(&mut self, end: &[u8]) {
+        while self.pos < self.input.len() && !self.input[self.pos..].starts_with(end) {
+            self.pos += 1;
+        }
+        self.pos = (self.pos + end.len()).min(self.input.len());
+    }
+
+    fn skip_whitespace_and_comments(&mut self) {
+        loop {
+            match (self.peek_at(0), self.peek_at(1)) {
+                (Some(c), _) if c.is_ascii_whitespace() => self.pos += 1,
+                (Some(b'-'), Some(b'-')) | (Some(b'#'), _) => self.skip_until(b"\n"),
+                // Also skips the conditional /*!40101 ... */ statements
+                (Some(b'/'), Some(b'*')) => self.skip_until(b"*/"),
+                _ => return,
+            }
+        }
+    }
+
+    fn quoted(&mut self, quote: u8) -> anyhow::Result<Vec<u8>> {
+        self.pos += 1;
+        let mut out = vec![];
+        loop {
+            let c = self
+                .peek_at(0)
+                .ok_or_else(|| anyhow!("Unterminated string in SQL dump"))?;
+            self.pos += 1;
+            match c {
+                b'\\' if quote != b'`' => {
+                    let escaped = self
+                        .peek_at(0)
+                        .ok_or_else(|| anyhow!("Unterminated string in SQL dump"))?;
+                    self.pos += 1;
+                    match escaped {
+                        b'0' => out.push(0),
+                        b'b' => out.push(8),
+                        b'n' => out.push(b'\n'),
+                        b'r' => out.push(b'\r'),
+                        b't' => out.push(b'\t'),
+                        b'Z' => out.push(26),
+                        // Only escaped for LIKE patterns, the backslash stays
+                        b'%' | b'_' => out.extend([b'\\', escaped]),
+                        other => out.push(other),
+                    }
+                }
+                c if c == quote => {
+                    if self.peek_at(0) == Some(quote) {
+                        self.pos += 1;
+                        out.push(quote);
+                    } else {
+                        return Ok(out);
+                    }
+                }
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> anyhow::Result<Option<Token>> {
+        self.skip_whitespace_and_comments();
+        let Some(c) = self.peek_at(0) else {
+            return Ok(None);
+        };
+        let token = match (c, self.peek_at(1)) {
+            (b'`', _) => Token::Ident(String::from_utf8_lossy(&self.quoted(b'`')?).into_owned()),
+            (b'\'' | b'"', _) => Token::Str(String::from_utf8_lossy(&self.quoted(c)?).into_owned()),
+            // X'cafe'
+            (b'x' | b'X', Some(b'\'')) => {
+                self.pos += 1;
+                let digits = self.quoted(b'\'')?;
+                Token::Str(decode_hex(&digits)?)
+            }
+            (c, _) if c.is_ascii_alphanumeric() || c == b'_' || c == b'$' => {
+                let start = self.pos;
+                while self.peek_at(0).is_some_and(|c| {
+                    c.is_ascii_alphanumeric() || c == b'_' || c == b'$' || c == b'.'
+                }) {
+                    self.pos += 1;
+                }
+                let word = &self.input[start..self.pos];
+                // 0xcafe as written with --hex-blob
+                if let Some(digits) = word.strip_prefix(b"0x") {
+                    Token::Str(decode_hex(digits)?)
+                } else {
+                    Token::Word(String::from_utf8_lossy(word).into_owned())
+                }
+            }
+            (c, _) => {
+                self.pos += 1;
+                Token::Punct(c)
+            }
+        };
+        Ok(Some(token))
+    }
+
+    fn next_statement(&mut self) -> anyhow::Result<Option<Vec<Token>>> {
+        let mut statement = vec![];
+        loop {
+            match self.next_token()? {
+                None if statement.is_empty() => return Ok(None),
+                None | Some(Token::Punct(b';')) => return Ok(Some(statement)),
+                Some(token) => statement.push(token),
+            }
+        }
+    }
+}
+
+fn is_keyword(token: &Token, keyword: &str) -> bool {
+    matches!(token, Token::Word(word) if word.eq_ignore_ascii_case(keyword))
+}
+
+/// Returns the table name and the remaining tokens, the name may be qualified with the database
+fn table_name(tokens: &[Token]) -> Option<(String, &[Token])> {
+    let mut name = None;
+    let mut rest = tokens;
+    while let Some((token, tail)) = rest.split_first() {
+        match token {
+            Token::Ident(ident) => name = Some(ident.clone()),
+            Token::Word(word) if name.is_none() => {
+                name = Some(word.rsplit('.').next().unwrap_or(word).to_owned());
+            }
+            Token::Punct(b'.') => {}
+            _ => break,
+        }
+        rest = tail;
+    }
+    name.map(|name| (name, rest))
+}
+
+/// Splits the contents of the parentheses starting at `tokens[0]` at top level commas
+fn split_parens(tokens: &[Token]) -> anyhow::Result<(Vec<&[Token]>, &[Token])> {
+    if tokens.first() != Some(&Token::Punct(b'(')) {
+        bail!("Expected ( in SQL dump");
+    }
+    let mut items = vec![];
+    let mut depth = 0;
+    let mut start = 1;
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Punct(b'(') => depth += 1,
+            Token::Punct(b')') => {
+                depth -= 1;
+                if depth == 0 {
+                    if i > start {
+                        items.push(&tokens[start..i]);
+                    }
+                    return Ok((items, &tokens[i + 1..]));
+                }
+            }
+            Token::Punct(b',') if depth == 1 => {
+                items.push(&tokens[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    bail!("Unbalanced parentheses in SQL dump")
+}
+
+fn column_name(tokens: &[Token]) -> Option<String> {
+    const CONSTRAINTS: [&str; 8] = [
+        "PRIMARY",
+        "KEY",
+        "UNIQUE",
+        "INDEX",
+        "CONSTRAINT",
+        "FOREIGN",
+        "FULLTEXT",
+        "CHECK",
+    ];
+    match tokens.first()? {
+        Token::Ident(ident) => Some(ident.clone()),
+        Token::Word(word)
+            if !CONSTRAINTS
+                .iter()
+                .any(|keyword| word.eq_ignore_ascii_case(keyword)) =>
+        {
+            Some(word.clone())
+        }
+        _ => None,
+    }
+}
+
+fn value(tokens: &[Token]) -> anyhow::Result<Option<String>> {
+    match tokens {
+        [Token::Word(word)] if word.eq_ignore_ascii_case("NULL") => Ok(None),
+        [Token::Word(word)] => Ok(Some(word.clone())),
+        [Token::Punct(b'-'), Token::Word(word)] => Ok(Some(format!("-{word}"))),
+        [Token::Str(value)] => Ok(Some(value.clone())),
+        // Charset introducers like _binary 'abc'
+        [Token::Word(charset), Token::Str(value)] if charset.starts_with('_') => {
+            Ok(Some(value.clone()))
+        }
+        _ => bail!("Unsupported value in SQL dump: {tokens:?}"),
+    }
+}
+
+/// Parses the rows of the `wanted` tables
+pub fn parse_dump(dump: &[u8], wanted: &[String]) -> anyhow::Result<Tables> {
+    let mut lexer = Lexer {
+        input: dump,
+        pos: 0,
+    };
+    let mut tables = Tables::new();
+    let mut column_names = std::collections::HashMap::<String, Vec<String>>::new();
+    while let Some(statement) = lexer.next_statement()? {
+        match statement.as_slice() {
+            [create, table, rest @ ..]
+                if is_keyword(create, "CREATE") && is_keyword(table, "TABLE") =>
+            {
+                let rest = match rest {
+                    [r#if, not, exists, rest @ ..]
+                        if is_keyword(r#if, "IF")
+                            && is_keyword(not, "NOT")
+                            && is_keyword(exists, "EXISTS") =>
+                    {
+                        rest
+                    }
+                    rest => rest,
+                };
+                let Some((name, rest)) = table_name(rest) else {
+                    continue;
+                };
+                if !wanted.contains(&name) {
+                    continue;
+                }
+                let (items, _) = split_parens(rest)?;
+                column_names.insert(
+                    name.clone(),
+                    items.into_iter().filter_map(column_name).collect(),
+                );
+                tables.entry(name).or_default();
+            }
+            [insert, rest @ ..]
+                if is_keyword(insert, "INSERT") || is_keyword(insert, "REPLACE") =>
+            {
+                let Some(into) = rest.iter().position(|token| is_keyword(token, "INTO")) else {
+                    continue;
+                };
+                let Some((name, rest)) = table_name(&rest[into + 1..]) else {
+                    continue;
+                };
+                if !wanted.contains(&name) {
+                    continue;
+                }
+                let (names, mut rest) = if rest.first() == Some(&Token::Punct(b'(')) {
+                    let (items, rest) = split_parens(rest)?;
+                    let names = items
+                        .into_iter()
+                        .map(|item| column_name(item).ok_or_else(|| anyhow!("Invalid column list")))
+                        .collect::<anyhow::Result<Vec<_>>>()?;
+                    (names, rest)
+                } else {
+                    let names = column_names
+                        .get(&name)
+                        .ok_or_else(|| anyhow!("INSERT into {name} before CREATE TABLE"))?
+                        .clone();
+                    (names, rest)
+                };
+                match rest.split_first() {
+                    Some((values, tail))
+                        if is_keyword(values, "VALUES") || is_keyword(values, "VALUE") =>
+                    {
+                        rest = tail;
+                    }
+                    _ => bail!("Unsupported INSERT into {name}"),
+                }
+                let rows = tables.entry(name.clone()).or_default();
+                while !rest.is_empty() {
+                    let (items, tail) = split_parens(rest)?;
+                    if items.len() != names.len() {
+                        bail!(
+                            "INSERT into {name} has {} values for {} columns",
+                            items.len(),
+                            names.len()
+                        );
+                    }
+                    let row: Row = names
+                        .iter()
+                        .cloned()
+                        .zip(items.into_iter().map(value))
+                        .map(|(name, value)| value.map(|value| (name, value)))
+                        .collect::<anyhow::Result<_>>()?;
+                    rows.push(row);
+                    rest = match tail {
+                        [Token::Punct(b','), tail @ ..] => tail,
+                        tail => tail,
+                    };
+                    // ON DUPLICATE KEY UPDATE ...
+                    if rest.first().is_some_and(|token| is_keyword(token, "ON")) {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_dump;
+
+    #[test]
+    fn test_parse_dump() {
+        let dump = br"-- MySQL dump 10.13
+/*!40101 SET NAMES utf8mb4 */;
+DROP TABLE IF EXISTS `cards`;
+CREATE TABLE `cards` (
+  `id` int(11) unsigned NOT NULL AUTO_INCREMENT,
+  `addressbookid` int(11) unsigned NOT NULL,
+  `carddata` mediumblob DEFAULT NULL,
+  `uri` varbinary(200) DEFAULT NULL,
+  PRIMARY KEY (`id`),
+  KEY `addressbookid` (`addressbookid`,`uri`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+INSERT INTO `cards` VALUES (1,2,'BEGIN:VCARD\r\nFN:O\'Brien\\, Pat\r\nEND:VCARD\r\n','a.vcf'),(2,2,NULL,_binary 'b;c.vcf');
+INSERT INTO `other` VALUES (1);
+INSERT INTO `cards` (`uri`, `id`, `addressbookid`, `carddata`) VALUES ('c.vcf',3,-1,0x4142);
+";
+        let tables = parse_dump(dump, &["cards".to_owned()]).unwrap();
+        assert_eq!(tables.len(), 1);
+        let cards = &tables["cards"];
+        assert_eq!(cards.len(), 3);
+        assert_eq!(cards[0]["id"].as_deref(), Some("1"));
+        assert_eq!(
+            cards[0]["carddata"].as_deref(),
+            Some("BEGIN:VCARD\r\nFN:O'Brien\\, Pat\r\nEND:VCARD\r\n")
+        );
+        assert_eq!(cards[1]["carddata"], None);
+        assert_eq!(cards[1]["uri"].as_deref(), Some("b;c.vcf"));
+        assert_eq!(cards[2]["addressbookid"].as_deref(), Some("-1"));
+        assert_eq!(cards[2]["carddata"].as_deref(), Some("AB"));
+    }
+}
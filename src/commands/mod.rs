@@ -1,17 +1,24 @@
 use crate::config::{
     Config, DataStoreConfig, DavPushConfig, HttpConfig, MaintenanceConfig, NextcloudLoginConfig,
-    SqliteDataStoreConfig, TracingConfig,
+    SqliteDataStoreConfig, TracingConfig, WebhookConfig,
 };
 use clap::Parser;
 use rustical_caldav::CalDavConfig;
 use rustical_frontend::FrontendConfig;
+use rustical_store::auth::LoginProtectionConfig;
 
 pub mod app_token;
+mod backup;
 mod health;
+pub mod lockout;
 pub mod membership;
+mod migrate;
 pub mod principals;
+pub mod webhook;
 
+pub use backup::{BackupArgs, RestoreArgs, cmd_backup, cmd_restore};
 pub use health::{HealthArgs, cmd_health};
+pub use migrate::{MigrateArgs, cmd_migrate};
 pub use principals::{PrincipalsArgs, cmd_principals};
 
 #[derive(Debug, Parser)]
@@ -28,12 +35,14 @@ pub fn cmd_gen_config(_args: GenConfigArgs) -> anyhow::Result<()> {
             skip_broken: true,
         }),
         tracing: TracingConfig::default(),
-        frontend: FrontendConfig {
-            enabled: true,
-            allow_password_login: true,
-        },
+        frontend: FrontendConfig::default(),
         oidc: None,
+        ldap: None,
+        proxy_auth: None,
+        login_protection: LoginProtectionConfig::default(),
+        scim: None,
         dav_push: DavPushConfig::default(),
+        webhooks: WebhookConfig::default(),
         nextcloud_login: NextcloudLoginConfig::default(),
         maintenance: MaintenanceConfig::default(),
     };
@@ -3,17 +3,21 @@ use std::io::IsTerminal;
 use super::membership::MembershipArgs;
 use crate::{
     app_token::{AppTokenArgs, cmd_app_token},
-    config::Config,
-    get_data_stores,
+    config::{Config, DataStoreConfig, DavPushConfig},
+    lockout::{LockoutArgs, cmd_lockout},
     membership::cmd_membership,
+    webhook::{WebhookArgs, cmd_webhook},
+    with_data_stores,
 };
-use anyhow::anyhow;
-use argon2::password_hash::{PasswordHasher, SaltString, rand_core::OsRng};
+use anyhow::{Context, anyhow};
 use clap::{Parser, Subcommand};
+use rustical_dav_push::{DavPushController, DavPushStore};
 use rustical_store::{
-    Secret,
-    auth::{AuthenticationProvider, Principal, PrincipalType},
+    Error, EventBus, EventReceiver, SessionStore,
+    auth::{AuthenticationProvider, Principal, PrincipalType, hash_password},
 };
+use rustical_webhook::WebhookStore;
+use std::sync::Arc;
 
 fn prompt_password_or_read_stdio() -> anyhow::Result<String> {
     let stdin = std::io::stdin();
@@ -63,6 +67,24 @@ pub struct CreateArgs {
 #[derive(Parser, Debug)]
 pub struct RemoveArgs {
     pub id: String,
+    #[arg(
+        long,
+        help = "Also delete its calendars, addressbooks, push subscriptions, sessions and app tokens"
+    )]
+    pub purge: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct RevokeSessionsArgs {
+    pub id: String,
+}
+
+#[derive(Parser, Debug)]
+pub struct TransferArgs {
+    #[arg(help = "Principal whose calendars and addressbooks are moved")]
+    pub from: String,
+    #[arg(help = "Principal that receives them")]
+    pub to: String,
 }
 
 #[derive(Parser, Debug)]
@@ -79,10 +101,21 @@ pub struct EditArgs {
         help = "Remove password (If you only want to use OIDC for example)"
     )]
     pub remove_password: bool,
+    #[arg(long, help = "Disable two-factor authentication")]
+    pub remove_totp: bool,
     #[arg(short, long, help = "Change principal displayname")]
     pub name: Option<String>,
     #[arg(value_enum, short, long, help = "Change the principal type")]
     pub principal_type: Option<PrincipalType>,
+    #[arg(long, help = "Set the email address password reset links are sent to")]
+    pub email: Option<String>,
+    #[arg(long, conflicts_with = "email", help = "Remove the email address")]
+    pub remove_email: bool,
+    #[arg(
+        long,
+        help = "Let the OIDC provider with this id log in to the principal (`default` for a single provider)"
+    )]
+    pub identity_provider: Option<String>,
 }
 
 #[derive(Debug, Subcommand)]
@@ -91,17 +124,66 @@ pub enum PrincipalsCommand {
     Create(CreateArgs),
     Remove(RemoveArgs),
     Edit(EditArgs),
+    /// Logs the principal out of the frontend everywhere
+    RevokeSessions(RevokeSessionsArgs),
+    /// Moves all calendars and addressbooks to another principal
+    Transfer(TransferArgs),
     Membership(MembershipArgs),
     AppToken(AppTokenArgs),
+    Webhook(WebhookArgs),
+    /// Inspects and lifts lockouts after failed logins
+    Lockout(LockoutArgs),
 }
 
-#[allow(
-    clippy::missing_errors_doc,
-    clippy::missing_panics_doc,
-    clippy::too_many_lines
-)]
+#[allow(clippy::missing_errors_doc)]
 pub async fn cmd_principals(args: PrincipalsArgs, config: Config) -> anyhow::Result<()> {
-    let (_, _, _, principal_store, _) = get_data_stores(true, &config.data_store).await?;
+    let moves_collections = matches!(
+        args.command,
+        PrincipalsCommand::Remove(RemoveArgs { purge: true, .. }) | PrincipalsCommand::Transfer(_)
+    );
+    if moves_collections && let DataStoreConfig::Vdir(vdir) = &config.data_store {
+        return Err(anyhow!(
+            "The vdir data store doesn't support this, stop RustiCal and move or delete the principal's directory in {} by hand",
+            vdir.path.display()
+        ));
+    }
+    with_data_stores!(true, &config.data_store, (_, _, data_store, principal_store, events) => {
+        principals(args, &config.dav_push, principal_store.as_ref(), data_store, &events).await
+    })
+}
+
+/// Puts the push messages for the received events into the outbox of the data store,
+/// the running server delivers them
+async fn notify_dav_push(
+    config: &DavPushConfig,
+    data_store: Arc<impl DavPushStore>,
+    mut recv: EventReceiver,
+) {
+    if !config.enabled {
+        return;
+    }
+    let mut events = vec![];
+    recv.try_recv_many(&mut events, usize::MAX);
+    let controller = DavPushController::new(
+        config.allowed_push_servers.clone(),
+        config.max_delivery_attempts,
+        data_store,
+        None,
+    );
+    controller.enqueue_events(events).await;
+    if recv.take_lagged() {
+        controller.enqueue_resync().await;
+    }
+}
+
+#[allow(clippy::missing_panics_doc, clippy::too_many_lines)]
+async fn principals(
+    args: PrincipalsArgs,
+    dav_push: &DavPushConfig,
+    principal_store: &impl AuthenticationProvider,
+    data_store: Arc<impl WebhookStore + SessionStore + DavPushStore>,
+    events: &EventBus,
+) -> anyhow::Result<()> {
     match args.command {
         PrincipalsCommand::List => {
             for principal in principal_store.get_principals().await? {
@@ -128,15 +210,7 @@ pub async fn cmd_principals(args: PrincipalsArgs, config: Config) -> anyhow::Res
             } else {
                 None
             };
-            let password = password.map(|password| {
-                let salt = SaltString::generate(OsRng);
-                Secret::from(
-                    argon2::Argon2::default()
-                        .hash_password(password.as_bytes(), &salt)
-                        .unwrap()
-                        .to_string(),
-                )
-            });
+            let password = password.as_deref().map(hash_password);
 
             principal_store
                 .insert_principal(
@@ -146,23 +220,41 @@ pub async fn cmd_principals(args: PrincipalsArgs, config: Config) -> anyhow::Res
                         principal_type: principal_type.unwrap_or_default(),
                         password,
                         memberships: vec![],
+                        app_token: None,
                     },
                     overwrite,
                 )
                 .await?;
             println!("Principal created");
         }
-        PrincipalsCommand::Remove(RemoveArgs { id }) => {
-            principal_store.remove_principal(&id).await?;
+        PrincipalsCommand::Remove(RemoveArgs { id, purge: true }) => {
+            principal_store.purge_principal(&id).await?;
+            println!("Principal {id} and all its data removed");
+        }
+        PrincipalsCommand::Remove(RemoveArgs { id, purge: false }) => {
+            principal_store
+                .remove_principal(&id)
+                .await
+                .with_context(|| {
+                    format!(
+                        "Could not remove {id}, if it still owns calendars or addressbooks \
+                        transfer them with `rustical principals transfer` or pass --purge"
+                    )
+                })?;
+            data_store.revoke_sessions(&id).await?;
             println!("Principal {id} removed");
         }
         PrincipalsCommand::Edit(EditArgs {
             id,
             remove_password,
+            remove_totp,
             password,
             name,
             principal_type,
             for_testing_password_from_arg,
+            email,
+            remove_email,
+            identity_provider,
         }) => {
             let mut principal = principal_store
                 .get_principal(&id)
@@ -180,15 +272,8 @@ pub async fn cmd_principals(args: PrincipalsArgs, config: Config) -> anyhow::Res
             } else {
                 None
             };
-            let password = password.map(|password| {
-                let salt = SaltString::generate(OsRng);
-                Secret::from(
-                    argon2::Argon2::default()
-                        .hash_password(password.as_bytes(), &salt)
-                        .unwrap()
-                        .to_string(),
-                )
-            });
+            let password = password.as_deref().map(hash_password);
+            let password_changed = remove_password || password.is_some();
             if password.is_some() {
                 principal.password = password;
             }
@@ -200,13 +285,57 @@ pub async fn cmd_principals(args: PrincipalsArgs, config: Config) -> anyhow::Res
                 principal.principal_type = principal_type;
             }
             principal_store.insert_principal(principal, true).await?;
+            if remove_totp {
+                principal_store.set_totp(&id, None).await?;
+            }
+            if email.is_some() || remove_email {
+                principal_store.set_email(&id, email.as_deref()).await?;
+            }
+            if let Some(identity_provider) = identity_provider {
+                principal_store
+                    .set_identity_provider(&id, &identity_provider)
+                    .await?;
+            }
+            if password_changed {
+                data_store.revoke_sessions(&id).await?;
+            }
             println!("Principal {id} updated");
         }
+        PrincipalsCommand::RevokeSessions(RevokeSessionsArgs { id }) => {
+            data_store.revoke_sessions(&id).await?;
+            println!("Sessions of {id} revoked");
+        }
+        PrincipalsCommand::Transfer(TransferArgs { from, to }) => {
+            if from == to {
+                return Err(anyhow!(
+                    "Cannot transfer the collections of {from} to itself"
+                ));
+            }
+            let recv = events.subscribe("dav_push");
+            match principal_store.transfer_collections(&from, &to).await {
+                Ok(()) => {}
+                Err(Error::AlreadyExists) => {
+                    return Err(anyhow!(
+                        "{to} already has a calendar or addressbook with the same id as one of {from}"
+                    ));
+                }
+                Err(err) => return Err(err.into()),
+            }
+            // Subscribers of the old push topics learn that the collections are gone
+            notify_dav_push(dav_push, data_store, recv).await;
+            println!("Calendars and addressbooks of {from} transferred to {to}");
+        }
         PrincipalsCommand::Membership(args) => {
-            cmd_membership(principal_store.as_ref(), args).await?;
+            cmd_membership(principal_store, args).await?;
         }
         PrincipalsCommand::AppToken(args) => {
-            cmd_app_token(principal_store.as_ref(), args).await?;
+            cmd_app_token(principal_store, args).await?;
+        }
+        PrincipalsCommand::Webhook(args) => {
+            cmd_webhook(data_store.as_ref(), args).await?;
+        }
+        PrincipalsCommand::Lockout(args) => {
+            cmd_lockout(principal_store, args).await?;
         }
     }
     Ok(())
@@ -0,0 +1,126 @@
+use clap::{Parser, Subcommand};
+use rustical_store::CollectionType;
+use rustical_webhook::{Webhook, WebhookStore};
+
+#[derive(Debug, Parser)]
+pub struct CreateArgs {
+    principal: String,
+    #[arg(long, help = "The URL to POST the payload to")]
+    url: String,
+    #[arg(
+        value_enum,
+        long,
+        requires = "collection_id",
+        help = "Only trigger for changes on this type of collection"
+    )]
+    collection_type: Option<CollectionType>,
+    #[arg(
+        long,
+        requires = "collection_type",
+        help = "Only trigger for changes on this collection"
+    )]
+    collection_id: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct RemoveArgs {
+    principal: String,
+    id: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct ListArgs {
+    principal: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct LogArgs {
+    principal: String,
+    id: String,
+    #[arg(long, default_value_t = 20, help = "Number of deliveries to show")]
+    limit: i64,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum WebhookCommand {
+    Create(CreateArgs),
+    Remove(RemoveArgs),
+    List(ListArgs),
+    #[command(about = "Show the most recent deliveries")]
+    Log(LogArgs),
+}
+
+#[derive(Parser, Debug)]
+pub struct WebhookArgs {
+    #[command(subcommand)]
+    pub command: WebhookCommand,
+}
+
+#[allow(clippy::missing_errors_doc)]
+pub async fn cmd_webhook(
+    webhook_store: &impl WebhookStore,
+    WebhookArgs { command }: WebhookArgs,
+) -> anyhow::Result<()> {
+    match command {
+        WebhookCommand::Create(CreateArgs {
+            principal,
+            url,
+            collection_type,
+            collection_id,
+        }) => {
+            let webhook = Webhook::new(principal, collection_type.zip(collection_id), url);
+            webhook_store.insert_webhook(webhook.clone()).await?;
+            println!("{} - secret: {}", webhook.id, webhook.secret);
+        }
+        WebhookCommand::Remove(RemoveArgs { principal, id }) => {
+            let webhook = webhook_store.get_webhook(&id).await?;
+            if webhook.principal != principal {
+                return Err(rustical_store::Error::NotFound.into());
+            }
+            webhook_store.delete_webhook(&id).await?;
+        }
+        WebhookCommand::List(ListArgs { principal }) => {
+            println!(
+                "{}",
+                webhook_store
+                    .get_webhooks(&principal)
+                    .await?
+                    .iter()
+                    .map(|webhook| match &webhook.collection {
+                        Some((collection_type, collection_id)) => format!(
+                            "{} - {} ({collection_type} {collection_id})",
+                            webhook.id, webhook.url
+                        ),
+                        None => format!("{} - {}", webhook.id, webhook.url),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+        }
+        WebhookCommand::Log(LogArgs {
+            principal,
+            id,
+            limit,
+        }) => {
+            let webhook = webhook_store.get_webhook(&id).await?;
+            if webhook.principal != principal {
+                return Err(rustical_store::Error::NotFound.into());
+            }
+            for delivery in webhook_store.get_deliveries(&id, limit).await? {
+                println!(
+                    "{} {} [{}] attempts={} {}",
+                    delivery.created_at,
+                    delivery.event,
+                    delivery.state,
+                    delivery.attempts,
+                    delivery
+                        .last_status
+                        .map(|status| status.to_string())
+                        .or(delivery.last_error)
+                        .unwrap_or_default()
+                );
+            }
+        }
+    }
+    Ok(())
+}
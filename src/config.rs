@@ -5,7 +5,10 @@ use anyhow::anyhow;
 use reqwest::Url;
 use rustical_caldav::CalDavConfig;
 use rustical_frontend::FrontendConfig;
-use rustical_oidc::OidcConfig;
+use rustical_ldap::LdapConfig;
+use rustical_oidc::OidcProviders;
+use rustical_scim::ScimConfig;
+use rustical_store::auth::{LoginProtectionConfig, ProxyAuthConfig};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -16,6 +19,8 @@ pub struct HttpConfig {
     pub host: Option<String>,
     pub port: Option<u16>,
     pub session_cookie_samesite_strict: bool,
+    // Frontend sessions expire after this much inactivity
+    pub session_timeout_minutes: u32,
     pub payload_limit_mb: usize,
 }
 
@@ -176,6 +181,7 @@ impl Default for HttpConfig {
             host: None,
             port: None,
             session_cookie_samesite_strict: false,
+            session_timeout_minutes: 120,
             payload_limit_mb: 4,
         }
     }
@@ -191,11 +197,46 @@ pub struct SqliteDataStoreConfig {
     pub skip_broken: bool,
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PostgresDataStoreConfig {
+    pub db_url: String,
+    #[serde(default = "default_postgres_max_connections")]
+    pub max_connections: u32,
+    #[serde(default = "default_true")]
+    pub skip_broken: bool,
+}
+
+const fn default_postgres_max_connections() -> u32 {
+    10
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct VdirDataStoreConfig {
+    // Calendars and addressbooks are stored as vdirs below this directory
+    pub path: PathBuf,
+    // SQLite database for principals, app tokens, DAV Push and webhook subscriptions
+    pub db_url: String,
+    #[serde(default = "default_true")]
+    pub skip_broken: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryDataStoreConfig {
+    // Directory in the vdir layout to load principals, calendars and addressbooks from
+    pub seed_path: Option<PathBuf>,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "snake_case")]
 #[serde(deny_unknown_fields)]
 pub enum DataStoreConfig {
     Sqlite(SqliteDataStoreConfig),
+    Postgres(PostgresDataStoreConfig),
+    Vdir(VdirDataStoreConfig),
+    Memory(MemoryDataStoreConfig),
 }
 
 #[derive(Debug, Deserialize, Serialize, Default, Clone)]
@@ -217,6 +258,10 @@ pub struct DavPushConfig {
     // Allowed Push servers, accepts any by default
     // Specify as URL origins
     pub allowed_push_servers: Option<Vec<String>>,
+    // Failed deliveries are retried with exponential backoff until this many attempts were made
+    pub max_delivery_attempts: u32,
+    #[serde(default)]
+    pub vapid: VapidConfig,
 }
 
 impl Default for DavPushConfig {
@@ -224,6 +269,57 @@ impl Default for DavPushConfig {
         Self {
             enabled: true,
             allowed_push_servers: None,
+            max_delivery_attempts: 10,
+            vapid: VapidConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields, default)]
+pub struct VapidConfig {
+    // Sign push deliveries with the server key (RFC 8292)
+    // The key is generated on first start and kept in the data store
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    // Contact for the push service operator, a mailto: or https: URI
+    pub subject: Option<String>,
+}
+
+impl Default for VapidConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            subject: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields, default)]
+pub struct WebhookConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default)]
+    // Allowed webhook targets, accepts any by default
+    // Specify as URL origins
+    pub allowed_origins: Option<Vec<String>>,
+    // Allow targets on loopback, link-local and private addresses, e.g. a service on the same host
+    pub allow_private_targets: bool,
+    // Failed deliveries are retried with exponential backoff until this many attempts were made
+    pub max_delivery_attempts: u32,
+    // Finished deliveries are kept in the delivery log for this many days
+    pub log_retention_days: u32,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            allowed_origins: None,
+            allow_private_targets: false,
+            max_delivery_attempts: 10,
+            log_retention_days: 7,
         }
     }
 }
@@ -257,12 +353,22 @@ pub struct Config {
     #[serde(default)]
     pub frontend: FrontendConfig,
     #[serde(default)]
-    pub oidc: Option<OidcConfig>,
+    pub oidc: Option<OidcProviders>,
+    #[serde(default)]
+    pub ldap: Option<LdapConfig>,
+    #[serde(default)]
+    pub proxy_auth: Option<ProxyAuthConfig>,
+    #[serde(default)]
+    pub login_protection: LoginProtectionConfig,
+    #[serde(default)]
+    pub scim: Option<ScimConfig>,
     #[serde(default)]
     pub tracing: TracingConfig,
     #[serde(default)]
     pub dav_push: DavPushConfig,
     #[serde(default)]
+    pub webhooks: WebhookConfig,
+    #[serde(default)]
     pub nextcloud_login: NextcloudLoginConfig,
     #[serde(default)]
     pub caldav: CalDavConfig,
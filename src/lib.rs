@@ -4,22 +4,39 @@ use anyhow::{Result, anyhow};
 use app::make_app;
 use axum::ServiceExt;
 use axum::extract::Request;
+use chrono::TimeDelta;
 use clap::{Parser, Subcommand};
-use config::{DataStoreConfig, SqliteDataStoreConfig};
+use config::{
+    MemoryDataStoreConfig, PostgresDataStoreConfig, SqliteDataStoreConfig, VdirDataStoreConfig,
+};
 use provided_listeners::ProvidedListeners;
-use rustical_dav_push::{DavPushController, DavPushStore};
+use rustical_dav_push::{DavPushController, DavPushStore, Vapid};
+use rustical_ldap::LdapAuthenticationProvider;
 use rustical_store::auth::AuthenticationProvider;
-use rustical_store::{AddressbookStore, CalendarStore, CollectionOperation, PrefixedCalendarStore};
+use rustical_store::{
+    AddressbookStore, CalendarStore, EventBus, PrefixedCalendarStore, SessionStore,
+};
+use rustical_store_memory::addressbook_store::MemoryAddressbookStore;
+use rustical_store_memory::calendar_store::MemoryCalendarStore;
+use rustical_store_memory::principal_store::MemoryPrincipalStore;
+use rustical_store_memory::{MemoryDb, MemoryStore};
+use rustical_store_postgres::PostgresStore;
+use rustical_store_postgres::addressbook_store::PostgresAddressbookStore;
+use rustical_store_postgres::calendar_store::PostgresCalendarStore;
+use rustical_store_postgres::principal_store::PostgresPrincipalStore;
+use rustical_store_sqlite::SqliteStore;
 use rustical_store_sqlite::addressbook_store::SqliteAddressbookStore;
 use rustical_store_sqlite::calendar_store::SqliteCalendarStore;
 use rustical_store_sqlite::principal_store::SqlitePrincipalStore;
-use rustical_store_sqlite::{SqliteStore, create_db_pool};
+use rustical_store_vdir::addressbook_store::VdirAddressbookStore;
+use rustical_store_vdir::calendar_store::VdirCalendarStore;
+use rustical_webhook::{WebhookController, WebhookStore};
 use setup_tracing::setup_tracing;
 use std::fs;
+use std::net::SocketAddr;
 use std::os::unix::fs::FileTypeExt;
 use std::sync::Arc;
 use tokio::sync::Notify;
-use tokio::sync::mpsc::Receiver;
 use tower::Layer;
 use tower_http::normalize_path::NormalizePathLayer;
 use tracing::{info, warn};
@@ -53,60 +70,224 @@ pub enum Command {
     )]
     Health(HealthArgs),
     Principals(PrincipalsArgs),
+    #[command(about = "Back up principals, calendars and addressbooks into an archive")]
+    Backup(commands::BackupArgs),
+    #[command(about = "Restore a backup archive into the configured data store")]
+    Restore(commands::RestoreArgs),
+    #[command(
+        about = "Migrate principals, calendars and addressbooks from Radicale, Baïkal or Nextcloud"
+    )]
+    Migrate(commands::MigrateArgs),
+}
+
+/// Binds the stores of the configured backend to `$stores` and evaluates `$body`,
+/// every backend has its own concrete store types so the body is instantiated once per backend.
+macro_rules! with_data_stores {
+    ($migrate:expr, $config:expr, $stores:pat => $body:expr) => {
+        match $config {
+            $crate::config::DataStoreConfig::Sqlite(config) => {
+                let $stores = $crate::get_sqlite_data_stores($migrate, config).await?;
+                $body
+            }
+            $crate::config::DataStoreConfig::Postgres(config) => {
+                let $stores = $crate::get_postgres_data_stores($migrate, config).await?;
+                $body
+            }
+            $crate::config::DataStoreConfig::Vdir(config) => {
+                let $stores = $crate::get_vdir_data_stores($migrate, config).await?;
+                $body
+            }
+            $crate::config::DataStoreConfig::Memory(config) => {
+                let $stores = $crate::get_memory_data_stores($migrate, config).await?;
+                $body
+            }
+        }
+    };
 }
+pub(crate) use with_data_stores;
 
 #[allow(clippy::missing_errors_doc)]
-pub async fn get_data_stores(
+pub async fn get_sqlite_data_stores(
     migrate: bool,
-    config: &DataStoreConfig,
+    config: &SqliteDataStoreConfig,
 ) -> Result<(
-    Arc<impl AddressbookStore + PrefixedCalendarStore>,
-    Arc<impl CalendarStore>,
-    Arc<impl DavPushStore>,
-    Arc<impl AuthenticationProvider>,
-    Receiver<CollectionOperation>,
+    Arc<SqliteAddressbookStore>,
+    Arc<SqliteCalendarStore>,
+    Arc<SqliteStore>,
+    Arc<SqlitePrincipalStore>,
+    EventBus,
 )> {
-    Ok(match &config {
-        DataStoreConfig::Sqlite(SqliteDataStoreConfig {
-            db_url,
-            run_repairs,
-            skip_broken,
-        }) => {
-            let db = create_db_pool(db_url, migrate).await?;
-
-            // Channel to watch for changes (for DAV Push)
-            let (send, recv) = tokio::sync::mpsc::channel(1000);
-
-            let addressbook_store = Arc::new(SqliteAddressbookStore::new(
-                db.clone(),
-                send.clone(),
-                *skip_broken,
-            ));
-            let cal_store = Arc::new(SqliteCalendarStore::new(db.clone(), send, *skip_broken));
-            if *run_repairs {
-                info!("Running repair tasks");
-                addressbook_store.repair_orphans().await?;
-                cal_store.repair_invalid_version_4_0().await?;
-                cal_store.repair_orphans().await?;
-            }
-            let subscription_store = Arc::new(SqliteStore::new(db.clone()));
-            let principal_store = Arc::new(SqlitePrincipalStore::new(db));
+    let SqliteDataStoreConfig {
+        db_url,
+        run_repairs,
+        skip_broken,
+    } = config;
+    let db = rustical_store_sqlite::create_db_pool(db_url, migrate).await?;
 
-            // Validate all calendar objects
-            for principal in principal_store.get_principals().await? {
-                cal_store.validate_objects(&principal.id).await?;
-                addressbook_store.validate_objects(&principal.id).await?;
-            }
+    // Store changes for DAV Push, webhooks, ...
+    let events = EventBus::default();
 
-            (
-                addressbook_store,
-                cal_store,
-                subscription_store,
-                principal_store,
-                recv,
-            )
-        }
-    })
+    let addressbook_store = Arc::new(SqliteAddressbookStore::new(
+        db.clone(),
+        events.clone(),
+        *skip_broken,
+    ));
+    let cal_store = Arc::new(SqliteCalendarStore::new(
+        db.clone(),
+        events.clone(),
+        *skip_broken,
+    ));
+    if *run_repairs {
+        info!("Running repair tasks");
+        addressbook_store.repair_orphans().await?;
+        cal_store.repair_invalid_version_4_0().await?;
+        cal_store.repair_orphans().await?;
+    }
+    let subscription_store = Arc::new(SqliteStore::new(db.clone()));
+    let principal_store = Arc::new(SqlitePrincipalStore::new(db, events.clone()));
+
+    // Validate all calendar objects
+    for principal in principal_store.get_principals().await? {
+        cal_store.validate_objects(&principal.id).await?;
+        addressbook_store.validate_objects(&principal.id).await?;
+    }
+
+    Ok((
+        addressbook_store,
+        cal_store,
+        subscription_store,
+        principal_store,
+        events,
+    ))
+}
+
+#[allow(clippy::missing_errors_doc)]
+pub async fn get_postgres_data_stores(
+    migrate: bool,
+    config: &PostgresDataStoreConfig,
+) -> Result<(
+    Arc<PostgresAddressbookStore>,
+    Arc<PostgresCalendarStore>,
+    Arc<PostgresStore>,
+    Arc<PostgresPrincipalStore>,
+    EventBus,
+)> {
+    let PostgresDataStoreConfig {
+        db_url,
+        max_connections,
+        skip_broken,
+    } = config;
+    let db = rustical_store_postgres::create_db_pool(db_url, *max_connections, migrate).await?;
+
+    // Store changes for DAV Push, webhooks, ...
+    let events = EventBus::default();
+
+    let addressbook_store = Arc::new(PostgresAddressbookStore::new(
+        db.clone(),
+        events.clone(),
+        *skip_broken,
+    ));
+    let cal_store = Arc::new(PostgresCalendarStore::new(
+        db.clone(),
+        events.clone(),
+        *skip_broken,
+    ));
+    let subscription_store = Arc::new(PostgresStore::new(db.clone()));
+    let principal_store = Arc::new(PostgresPrincipalStore::new(db, events.clone()));
+
+    // Validate all calendar objects
+    for principal in principal_store.get_principals().await? {
+        cal_store.validate_objects(&principal.id).await?;
+        addressbook_store.validate_objects(&principal.id).await?;
+    }
+
+    Ok((
+        addressbook_store,
+        cal_store,
+        subscription_store,
+        principal_store,
+        events,
+    ))
+}
+
+#[allow(clippy::missing_errors_doc)]
+pub async fn get_vdir_data_stores(
+    migrate: bool,
+    config: &VdirDataStoreConfig,
+) -> Result<(
+    Arc<VdirAddressbookStore>,
+    Arc<VdirCalendarStore>,
+    Arc<SqliteStore>,
+    Arc<SqlitePrincipalStore>,
+    EventBus,
+)> {
+    let VdirDataStoreConfig {
+        path,
+        db_url,
+        skip_broken,
+    } = config;
+    // Everything that isn't a calendar or addressbook still lives in SQLite
+    let db = rustical_store_sqlite::create_db_pool(db_url, migrate).await?;
+
+    // Store changes for DAV Push, webhooks, ...
+    let events = EventBus::default();
+
+    let subscription_store = Arc::new(SqliteStore::new(db.clone()));
+    let principal_store = Arc::new(SqlitePrincipalStore::new(db, events.clone()));
+    let addressbook_store = Arc::new(VdirAddressbookStore::new(
+        path,
+        principal_store.clone(),
+        events.clone(),
+        *skip_broken,
+    ));
+    let cal_store = Arc::new(VdirCalendarStore::new(
+        path,
+        principal_store.clone(),
+        events.clone(),
+        *skip_broken,
+    ));
+
+    // Validate all calendar objects
+    for principal in principal_store.get_principals().await? {
+        cal_store.validate_objects(&principal.id).await?;
+        addressbook_store.validate_objects(&principal.id).await?;
+    }
+
+    Ok((
+        addressbook_store,
+        cal_store,
+        subscription_store,
+        principal_store,
+        events,
+    ))
+}
+
+#[allow(clippy::missing_errors_doc)]
+pub async fn get_memory_data_stores(
+    _migrate: bool,
+    config: &MemoryDataStoreConfig,
+) -> Result<(
+    Arc<MemoryAddressbookStore>,
+    Arc<MemoryCalendarStore>,
+    Arc<MemoryStore>,
+    Arc<MemoryPrincipalStore>,
+    EventBus,
+)> {
+    let db = MemoryDb::default();
+    if let Some(seed_path) = &config.seed_path {
+        db.seed(seed_path).await?;
+    }
+
+    // Store changes for DAV Push, webhooks, ...
+    let events = EventBus::default();
+
+    Ok((
+        Arc::new(MemoryAddressbookStore::new(db.clone(), events.clone())),
+        Arc::new(MemoryCalendarStore::new(db.clone(), events.clone())),
+        Arc::new(MemoryStore::new(db.clone())),
+        Arc::new(MemoryPrincipalStore::new(db, events.clone())),
+        events,
+    ))
 }
 
 #[allow(clippy::missing_errors_doc, clippy::missing_panics_doc)]
@@ -120,17 +301,88 @@ pub async fn cmd_serve(
         setup_tracing(&config.tracing);
     }
 
-    let (addr_store, cal_store, subscription_store, principal_store, update_recv) =
-        get_data_stores(!args.no_migrations, &config.data_store).await?;
+    with_data_stores!(!args.no_migrations, &config.data_store, (addr_store, cal_store, subscription_store, principal_store, events) => {
+        if let Some(ldap_config) = config.ldap.clone() {
+            let principal_store =
+                Arc::new(LdapAuthenticationProvider::new(ldap_config, principal_store));
+            let stores = (addr_store, cal_store, subscription_store, principal_store, events);
+            serve(config, stores, start_notifier).await
+        } else {
+            let stores = (addr_store, cal_store, subscription_store, principal_store, events);
+            serve(config, stores, start_notifier).await
+        }
+    })
+}
+
+async fn serve(
+    config: Config,
+    (addr_store, cal_store, subscription_store, principal_store, events): (
+        Arc<impl AddressbookStore + PrefixedCalendarStore>,
+        Arc<impl CalendarStore>,
+        Arc<impl DavPushStore + WebhookStore + SessionStore>,
+        Arc<impl AuthenticationProvider>,
+        EventBus,
+    ),
+    start_notifier: Option<Arc<Notify>>,
+) -> Result<()> {
+    if let Some(scim) = &config.scim
+        && scim.token.len() < 32
+    {
+        return Err(anyhow!("scim.token has to be at least 32 characters long"));
+    }
+
+    let vapid = if config.dav_push.enabled && config.dav_push.vapid.enabled {
+        Some(
+            Vapid::load_or_generate(
+                subscription_store.as_ref(),
+                config.dav_push.vapid.subject.clone(),
+            )
+            .await?,
+        )
+    } else {
+        None
+    };
+    let vapid_public_key = vapid.as_ref().map(|vapid| vapid.public_key().to_owned());
+
+    tokio::spawn(tasks::log_events(events.subscribe("log")));
 
     if config.dav_push.enabled {
-        let dav_push_controller = DavPushController::new(
+        let dav_push_controller = Arc::new(DavPushController::new(
             config.dav_push.allowed_push_servers,
+            config.dav_push.max_delivery_attempts,
             subscription_store.clone(),
-        );
-        // Atm we never join this task
+            vapid,
+        ));
+        // Atm we never join these tasks
+        tokio::spawn({
+            let dav_push_controller = dav_push_controller.clone();
+            let recv = events.subscribe("dav_push");
+            async move {
+                dav_push_controller.notifier(recv).await;
+            }
+        });
         tokio::spawn(async move {
-            dav_push_controller.notifier(update_recv).await;
+            dav_push_controller.deliverer().await;
+        });
+    }
+
+    if config.webhooks.enabled {
+        let webhook_controller = Arc::new(WebhookController::new(
+            config.webhooks.allowed_origins,
+            config.webhooks.allow_private_targets,
+            config.webhooks.max_delivery_attempts,
+            TimeDelta::days(config.webhooks.log_retention_days.into()),
+            subscription_store.clone(),
+        ));
+        tokio::spawn({
+            let webhook_controller = webhook_controller.clone();
+            let recv = events.subscribe("webhooks");
+            async move {
+                webhook_controller.notifier(recv).await;
+            }
+        });
+        tokio::spawn(async move {
+            webhook_controller.deliverer().await;
         });
     }
 
@@ -141,15 +393,19 @@ pub async fn cmd_serve(
         principal_store.clone(),
         config.frontend.clone(),
         config.oidc.clone(),
+        config.proxy_auth.clone(),
+        config.login_protection.clone(),
+        config.scim.clone(),
         config.caldav,
         &config.nextcloud_login,
         config.dav_push.enabled,
+        vapid_public_key,
         config.http.session_cookie_samesite_strict,
+        config.http.session_timeout_minutes,
         config.http.payload_limit_mb,
     );
-    let app = ServiceExt::<Request>::into_make_service(
-        NormalizePathLayer::trim_trailing_slash().layer(app),
-    );
+    // The peer address decides whether the proxy authentication headers are trusted
+    let app = NormalizePathLayer::trim_trailing_slash().layer(app);
 
     let mut provided_listeners = ProvidedListeners::from_env()?;
     if let Some(trash_retention_days) = config.maintenance.trash_retention_days {
@@ -160,6 +416,17 @@ pub async fn cmd_serve(
         ));
     }
 
+    tokio::spawn(tasks::cleanup_expired_sessions(
+        subscription_store.as_ref().clone(),
+        shutdown_signal(),
+    ));
+
+    tokio::spawn(tasks::cleanup_login_failures(
+        principal_store.clone(),
+        config.login_protection.clone(),
+        shutdown_signal(),
+    ));
+
     let bind_config = config.http.bind_config()?;
     let serve_task = match bind_config {
         HttpBindConfig::Tcp(address) => {
@@ -172,10 +439,13 @@ pub async fn cmd_serve(
                 if let Some(start_notifier) = start_notifier {
                     start_notifier.notify_waiters();
                 }
-                axum::serve(listener, app)
-                    .with_graceful_shutdown(shutdown_signal())
-                    .await
-                    .unwrap();
+                axum::serve(
+                    listener,
+                    ServiceExt::<Request>::into_make_service_with_connect_info::<SocketAddr>(app),
+                )
+                .with_graceful_shutdown(shutdown_signal())
+                .await
+                .unwrap();
             })
         }
 
@@ -204,10 +474,15 @@ pub async fn cmd_serve(
                 if let Some(start_notifier) = start_notifier {
                     start_notifier.notify_waiters();
                 }
-                axum::serve(listener, app)
-                    .with_graceful_shutdown(shutdown_signal())
-                    .await
-                    .unwrap();
+                axum::serve(
+                    listener,
+                    ServiceExt::<Request>::into_make_service_with_connect_info::<
+                        tokio::net::unix::SocketAddr,
+                    >(app),
+                )
+                .with_graceful_shutdown(shutdown_signal())
+                .await
+                .unwrap();
             })
         }
     };
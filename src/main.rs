@@ -5,7 +5,9 @@ use figment::Figment;
 use figment::providers::{Env, Format, Toml};
 use rustical::config::Config;
 use rustical::{Args, Command};
-use rustical::{cmd_gen_config, cmd_health, cmd_principals, cmd_serve};
+use rustical::{
+    cmd_backup, cmd_gen_config, cmd_health, cmd_migrate, cmd_principals, cmd_restore, cmd_serve,
+};
 use tracing::warn;
 
 #[tokio::main(flavor = "multi_thread")]
@@ -26,6 +28,13 @@ async fn main() -> Result<()> {
         Command::Principals(principals_args) => {
             cmd_principals(principals_args, parse_config()?).await
         }
+        Command::Backup(backup_args) => cmd_backup(backup_args, parse_config()?).await,
+        Command::Restore(restore_args) => {
+            cmd_restore(restore_args, parse_config()?, !args.no_migrations).await
+        }
+        Command::Migrate(migrate_args) => {
+            cmd_migrate(migrate_args, parse_config()?, !args.no_migrations).await
+        }
         Command::Health(health_args) => {
             let config: Config = parse_config()?;
             cmd_health(config.http, health_args).await
@@ -44,6 +53,7 @@ mod test_config {
         providers::{Env, Format, Toml},
     };
     use rustical::config::{Config, HttpBindConfig};
+    use rustical_oidc::OidcProviders;
 
     #[test]
     fn test_config_toml_http_host() {
@@ -74,6 +84,7 @@ allow_sign_up = true
             config.http.bind_config().unwrap(),
             HttpBindConfig::Tcp("0.0.0.0:4000".to_string())
         );
+        assert!(matches!(config.oidc, Some(OidcProviders::Single(_))));
     }
 
     #[test]
@@ -169,4 +180,38 @@ allow_sign_up = true
             Ok(())
         });
     }
+
+    #[test]
+    fn test_config_toml_oidc_providers() {
+        let config = r#"
+[data_store.sqlite]
+db_url = "/var/lib/rustical/db.sqlite3"
+
+[oidc.company]
+name = "Company"
+issuer = "https://auth.rustical.dev"
+client_id = "rustical"
+scopes = ["openid", "profile", "groups"]
+
+[oidc.partners]
+name = "Partners"
+issuer = "https://partners.rustical.dev"
+client_id = 1234
+scopes = ["openid", "profile"]
+claim_userid = "sub"
+"#;
+
+        let config: Config = Figment::new()
+            .merge(Toml::string(config))
+            .extract()
+            .unwrap();
+        let ids: Vec<_> = config
+            .oidc
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(ids, vec!["company", "partners"]);
+    }
 }
@@ -3,7 +3,8 @@ use core::num::NonZeroU32;
 use std::sync::Arc;
 
 use chrono::NaiveDate;
-use rustical_store::CalendarStorePruneDeleted;
+use rustical_store::auth::{LoginFailureStore, LoginProtectionConfig};
+use rustical_store::{CalendarStorePruneDeleted, EventReceiver, SessionStore};
 
 pub async fn cleanup_trashed_calendar_entities(
     cal_store: Arc<dyn CalendarStorePruneDeleted>,
@@ -55,6 +56,58 @@ pub async fn cleanup_trashed_calendar_entities(
     }
 }
 
+pub async fn cleanup_expired_sessions(
+    session_store: impl SessionStore,
+    shutdown_signal: impl Future + Send + 'static,
+) {
+    let mut shutdown_signal = core::pin::pin!(shutdown_signal);
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_hours(1));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                match session_store.delete_expired_sessions().await {
+                    Ok(0) => {}
+                    Ok(count) => tracing::info!("Deleted {count} expired sessions"),
+                    Err(error) => tracing::error!(?error, "Cleanup of expired sessions failed: {}", error),
+                }
+            }
+            _ = &mut shutdown_signal => {
+                break;
+            }
+        }
+    }
+}
+
+pub async fn cleanup_login_failures(
+    auth_provider: Arc<impl LoginFailureStore>,
+    config: LoginProtectionConfig,
+    shutdown_signal: impl Future + Send + 'static,
+) {
+    let mut shutdown_signal = core::pin::pin!(shutdown_signal);
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_hours(1));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                match auth_provider.prune_login_failures(config.expiry()).await {
+                    Ok(0) => {}
+                    Ok(count) => tracing::info!("Deleted {count} expired failed login records"),
+                    Err(error) => tracing::error!(?error, "Cleanup of failed login records failed: {}", error),
+                }
+            }
+            _ = &mut shutdown_signal => {
+                break;
+            }
+        }
+    }
+}
+
+/// Logs every store event, enable with `RUST_LOG=rustical::events=debug`
+pub async fn log_events(mut recv: EventReceiver) {
+    while let Some(event) = recv.recv().await {
+        tracing::debug!(target: "rustical::events", ?event);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use core::future;
@@ -63,7 +116,7 @@ mod tests {
 
     use super::cleanup_trashed_calendar_entities;
     use rustical_store::auth::AuthenticationProvider;
-    use rustical_store::{CalendarMetadata, CalendarReadStore, CalendarWriteStore};
+    use rustical_store::{CalendarMetadata, CalendarReadStore, CalendarWriteStore, EventBus};
     use rustical_store_sqlite::calendar_store::SqliteCalendarStore;
     use rustical_store_sqlite::create_db_pool;
     use rustical_store_sqlite::principal_store::SqlitePrincipalStore;
@@ -78,15 +131,16 @@ mod tests {
         let db = create_db_pool("sqlite://:memory:", true)
             .await
             .expect("to create db");
-        let (send, _recv) = tokio::sync::mpsc::channel(1000);
-        let cal_store = Arc::new(SqliteCalendarStore::new(db.clone(), send, true));
-        let principal_store = SqlitePrincipalStore::new(db);
+        let events = EventBus::default();
+        let cal_store = Arc::new(SqliteCalendarStore::new(db.clone(), events.clone(), true));
+        let principal_store = SqlitePrincipalStore::new(db, events);
         let principal = rustical_store::auth::Principal {
             id: "user".to_owned(),
             displayname: None,
             password: None,
             principal_type: rustical_store::auth::PrincipalType::Individual,
             memberships: Vec::new(),
+            app_token: None,
         };
         principal_store
             .insert_principal(principal, false)
@@ -62,8 +62,13 @@ pub fn rustical_process(
                     },
                     frontend: Default::default(),
                     oidc: None,
+                    ldap: None,
+                    proxy_auth: None,
+                    login_protection: Default::default(),
+                    scim: None,
                     tracing: Default::default(),
                     dav_push: Default::default(),
+                    webhooks: Default::default(),
                     nextcloud_login: Default::default(),
                     caldav: Default::default(),
                     maintenance: Default::default(),
@@ -9,6 +9,7 @@ use rustical::{
     membership::{AssignArgs, MembershipArgs, MembershipCommand},
     principals::{CreateArgs, EditArgs, PrincipalsCommand},
 };
+use rustical_store::EventBus;
 use rustical_store::auth::{AuthenticationProvider, PrincipalType};
 use rustical_store_sqlite::{create_db_pool, principal_store::SqlitePrincipalStore};
 use std::{collections::HashMap, time::Duration};
@@ -93,8 +94,13 @@ async fn test_initial_setup() {
                 http: Default::default(),
                 frontend: Default::default(),
                 oidc: None,
+                ldap: None,
+                proxy_auth: None,
+                login_protection: Default::default(),
+                scim: None,
                 tracing: Default::default(),
                 dav_push: Default::default(),
+                webhooks: Default::default(),
                 nextcloud_login: Default::default(),
                 caldav: Default::default(),
                 maintenance: Default::default(),
@@ -110,8 +116,12 @@ async fn test_initial_setup() {
                     name: None,
                     password: false,
                     remove_password: false,
+                    remove_totp: false,
                     for_testing_password_from_arg: Some("pass".to_owned()),
                     principal_type: Some(PrincipalType::Individual),
+                    email: None,
+                    remove_email: false,
+                    identity_provider: None,
                 }),
             },
             Config {
@@ -123,8 +133,13 @@ async fn test_initial_setup() {
                 http: Default::default(),
                 frontend: Default::default(),
                 oidc: None,
+                ldap: None,
+                proxy_auth: None,
+                login_protection: Default::default(),
+                scim: None,
                 tracing: Default::default(),
                 dav_push: Default::default(),
+                webhooks: Default::default(),
                 nextcloud_login: Default::default(),
                 caldav: Default::default(),
                 maintenance: Default::default(),
@@ -201,7 +216,7 @@ async fn test_initial_setup() {
         assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
 
         let db = create_db_pool(&db_path, false).await.unwrap();
-        let principal_store = SqlitePrincipalStore::new(db);
+        let principal_store = SqlitePrincipalStore::new(db, EventBus::default());
         principal_store.remove_principal("user").await.unwrap();
 
         let resp = reqwest::Client::new()
@@ -230,8 +245,13 @@ async fn test_principal_impersonation() {
             http: Default::default(),
             frontend: Default::default(),
             oidc: None,
+            ldap: None,
+            proxy_auth: None,
+            login_protection: Default::default(),
+            scim: None,
             tracing: Default::default(),
             dav_push: Default::default(),
+            webhooks: Default::default(),
             nextcloud_login: Default::default(),
             caldav: Default::default(),
             maintenance: Default::default(),
@@ -261,8 +281,12 @@ async fn test_principal_impersonation() {
                     name: None,
                     password: false,
                     remove_password: false,
+                    remove_totp: false,
                     for_testing_password_from_arg: Some("pass".to_owned()),
                     principal_type: Some(PrincipalType::Individual),
+                    email: None,
+                    remove_email: false,
+                    identity_provider: None,
                 }),
             },
             config.clone(),
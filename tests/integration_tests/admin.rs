@@ -0,0 +1,146 @@
+use super::get_app_with_config;
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Request};
+use http::{Method, StatusCode};
+use rstest::rstest;
+use rustical_frontend::FrontendConfig;
+use rustical_store::auth::{AuthenticationProvider, PrincipalType, ProxyAuthConfig};
+use rustical_store_sqlite::tests::{TestStoreContext, test_store_context};
+use std::net::SocketAddr;
+use tower::ServiceExt;
+
+fn request(method: Method, uri: &str, user: &str, groups: &str, form: &str) -> Request {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Remote-User", user)
+        .header("Remote-Groups", groups)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(Body::from(form.to_owned()))
+        .unwrap();
+    let peer: SocketAddr = "10.0.0.1:1234".parse().unwrap();
+    request.extensions_mut().insert(ConnectInfo(peer));
+    request
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_admin(
+    #[from(test_store_context)]
+    #[future]
+    context: TestStoreContext,
+) {
+    let context = context.await;
+    let principal_store = context.principal_store.clone();
+    let app = get_app_with_config(
+        context,
+        FrontendConfig {
+            admins: vec!["admins".to_owned()],
+            ..Default::default()
+        },
+        Some(ProxyAuthConfig {
+            user_header: "Remote-User".to_owned(),
+            groups_header: Some("Remote-Groups".to_owned()),
+            trusted_proxies: vec!["10.0.0.0/8".parse().unwrap()],
+            allow_sign_up: true,
+        }),
+        None,
+    );
+
+    let response = app
+        .clone()
+        .oneshot(request(Method::GET, "/frontend/admin", "bob", "", ""))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = app
+        .clone()
+        .oneshot(request(
+            Method::POST,
+            "/frontend/admin",
+            "bob",
+            "",
+            "id=carol&principal_type=INDIVIDUAL",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(
+        principal_store
+            .get_principal("carol")
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    // Members of an admin group are admins
+    let response = app
+        .clone()
+        .oneshot(request(
+            Method::GET,
+            "/frontend/admin",
+            "alice",
+            "admins",
+            "",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .clone()
+        .oneshot(request(
+            Method::POST,
+            "/frontend/admin",
+            "alice",
+            "admins",
+            "id=carol&displayname=Carol&principal_type=ROOM&password=secret",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let carol = principal_store
+        .get_principal("carol")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(carol.displayname.as_deref(), Some("Carol"));
+    assert_eq!(carol.principal_type, PrincipalType::Room);
+    assert!(carol.password.is_some());
+
+    let response = app
+        .clone()
+        .oneshot(request(
+            Method::POST,
+            "/frontend/admin/principal/carol/membership",
+            "alice",
+            "admins",
+            "member_of=bob",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(
+        principal_store.list_members("bob").await.unwrap(),
+        vec!["carol"]
+    );
+
+    let response = app
+        .oneshot(request(
+            Method::POST,
+            "/frontend/admin/principal/carol/delete",
+            "alice",
+            "admins",
+            "",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert!(
+        principal_store
+            .get_principal("carol")
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
@@ -4,7 +4,7 @@ use axum::extract::Request;
 use headers::{Authorization, HeaderMapExt};
 use http::StatusCode;
 use rstest::rstest;
-use rustical_store::auth::{AuthenticationProvider, Principal, PrincipalType};
+use rustical_store::auth::{AppTokenScope, AuthenticationProvider, Principal, PrincipalType};
 use rustical_store_sqlite::tests::{TestStoreContext, test_store_context};
 use tower::ServiceExt;
 
@@ -240,6 +240,7 @@ async fn test_report_email_principal(
                 id: principal.to_owned(),
                 displayname: None,
                 memberships: vec![],
+                app_token: None,
                 password: None,
                 principal_type: PrincipalType::Individual,
             },
@@ -248,7 +249,13 @@ async fn test_report_email_principal(
         .await
         .unwrap();
     principal_store
-        .add_app_token(principal, "test".to_string(), "pass".to_string())
+        .add_app_token(
+            principal,
+            "test".to_string(),
+            "pass".to_string(),
+            AppTokenScope::default(),
+            None,
+        )
         .await
         .unwrap();
     context.principal_store = principal_store;
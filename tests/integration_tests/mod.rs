@@ -4,11 +4,29 @@ use rstest::rstest;
 use rustical::{app::make_app, config::NextcloudLoginConfig};
 use rustical_caldav::CalDavConfig;
 use rustical_frontend::FrontendConfig;
+use rustical_scim::ScimConfig;
+use rustical_store::auth::{LoginProtectionConfig, ProxyAuthConfig};
 use rustical_store_sqlite::tests::{TestStoreContext, test_store_context};
 use std::sync::Arc;
 use tower::ServiceExt;
 
 pub fn get_app(context: TestStoreContext) -> axum::Router {
+    get_app_with_proxy_auth(context, None)
+}
+
+pub fn get_app_with_proxy_auth(
+    context: TestStoreContext,
+    proxy_auth_config: Option<ProxyAuthConfig>,
+) -> axum::Router {
+    get_app_with_config(context, FrontendConfig::default(), proxy_auth_config, None)
+}
+
+pub fn get_app_with_config(
+    context: TestStoreContext,
+    frontend_config: FrontendConfig,
+    proxy_auth_config: Option<ProxyAuthConfig>,
+    scim_config: Option<ScimConfig>,
+) -> axum::Router {
     let TestStoreContext {
         addr_store,
         cal_store,
@@ -22,15 +40,17 @@ pub fn get_app(context: TestStoreContext) -> axum::Router {
         Arc::new(cal_store),
         Arc::new(sub_store),
         Arc::new(principal_store),
-        FrontendConfig {
-            enabled: true,
-            allow_password_login: true,
-        },
+        frontend_config,
         None,
+        proxy_auth_config,
+        LoginProtectionConfig::default(),
+        scim_config,
         CalDavConfig::default(),
         &NextcloudLoginConfig { enabled: false },
         false,
+        None,
         true,
+        120,
         20,
     )
 }
@@ -65,5 +85,9 @@ async fn test_ping(
     assert!(response.status().is_success());
 }
 
+mod admin;
 mod caldav;
 mod carddav;
+mod password;
+mod proxy_auth;
+mod scim;
@@ -0,0 +1,420 @@
+use super::get_app_with_config;
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Request};
+use http::{Method, StatusCode, header};
+use rstest::rstest;
+use rustical_frontend::{FrontendConfig, PasskeyConfig, PasswordResetConfig};
+use rustical_store::auth::{
+    AuthenticationProvider, Principal, PrincipalType, hash_password,
+    load_or_generate_app_token_pepper, password_reset_signature,
+};
+use rustical_store_sqlite::tests::{TestStoreContext, test_store_context};
+use std::{net::SocketAddr, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    sync::mpsc,
+};
+use tower::ServiceExt;
+
+/// Stand-in for an SMTP relay that passes the content of every mail to the receiver
+async fn fake_smtp_server() -> (u16, mpsc::UnboundedReceiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (sender, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                let (read, mut write) = stream.into_split();
+                let mut lines = BufReader::new(read).lines();
+                write.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+                let mut data: Option<String> = None;
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if let Some(mail) = &mut data {
+                        if line == "." {
+                            sender.send(data.take().unwrap()).unwrap();
+                            write.write_all(b"250 OK\r\n").await.unwrap();
+                        } else {
+                            mail.push_str(&line);
+                            mail.push('\n');
+                        }
+                        continue;
+                    }
+                    let command = line.get(..4).unwrap_or_default().to_ascii_uppercase();
+                    let reply: &[u8] = match command.as_str() {
+                        "DATA" => {
+                            data = Some(String::new());
+                            b"354 Go ahead\r\n"
+                        }
+                        "QUIT" => b"221 Bye\r\n",
+                        _ => b"250 OK\r\n",
+                    };
+                    write.write_all(reply).await.unwrap();
+                }
+            });
+        }
+    });
+    (port, receiver)
+}
+
+/// Undoes the quoted-printable encoding of long lines
+fn decode_quoted_printable(text: &str) -> String {
+    let text = text.replace("=\n", "");
+    let mut decoded = String::new();
+    let mut rest = text.as_str();
+    while let Some(index) = rest.find('=') {
+        decoded.push_str(&rest[..index]);
+        decoded.push(u8::from_str_radix(&rest[index + 1..index + 3], 16).unwrap() as char);
+        rest = &rest[index + 3..];
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Path and query of the reset link in a mail
+fn reset_link(mail: &str) -> String {
+    let (headers, body) = mail.split_once("\n\n").unwrap();
+    let body = if headers.contains("Content-Transfer-Encoding: quoted-printable") {
+        decode_quoted_printable(body)
+    } else {
+        body.to_owned()
+    };
+    let start = body.find("/frontend/login/reset?").unwrap();
+    body[start..].split_whitespace().next().unwrap().to_owned()
+}
+
+fn request(method: Method, uri: &str, cookie: Option<&str>, form: &str) -> Request {
+    let mut builder = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::HOST, "dav.example.com")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+    if let Some(cookie) = cookie {
+        builder = builder.header(header::COOKIE, cookie);
+    }
+    let mut request = builder.body(Body::from(form.to_owned())).unwrap();
+    let peer: SocketAddr = "10.0.0.1:1234".parse().unwrap();
+    request.extensions_mut().insert(ConnectInfo(peer));
+    request
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_password_reset(
+    #[from(test_store_context)]
+    #[future]
+    context: TestStoreContext,
+) {
+    let context = context.await;
+    let principal_store = context.principal_store.clone();
+    principal_store
+        .insert_principal(
+            Principal {
+                id: "alice".to_owned(),
+                displayname: None,
+                principal_type: PrincipalType::Individual,
+                password: Some(hash_password("old")),
+                memberships: vec![],
+                app_token: None,
+            },
+            false,
+        )
+        .await
+        .unwrap();
+    principal_store
+        .set_email("alice", Some("alice@example.com"))
+        .await
+        .unwrap();
+    let (smtp_port, mut mails) = fake_smtp_server().await;
+    let app = get_app_with_config(
+        context,
+        FrontendConfig {
+            password_reset: Some(PasswordResetConfig {
+                smtp_url: format!("smtp://127.0.0.1:{smtp_port}"),
+                from: "RustiCal <dav@example.com>".to_owned(),
+                base_url: "https://dav.example.com".to_owned(),
+                link_expiry_minutes: 60,
+            }),
+            ..Default::default()
+        },
+        None,
+        None,
+    );
+
+    // Unknown principals get the same answer
+    for username in ["nobody", "alice"] {
+        let response = app
+            .clone()
+            .oneshot(request(
+                Method::POST,
+                "/frontend/login/forgot",
+                None,
+                &format!("username={username}"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    let mail = tokio::time::timeout(Duration::from_secs(5), mails.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(mail.contains("alice@example.com"));
+    let link = reset_link(&mail);
+    let query = link.split_once('?').unwrap().1;
+
+    let response = app
+        .clone()
+        .oneshot(request(Method::GET, &link, None, ""))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .clone()
+        .oneshot(request(
+            Method::GET,
+            &link.replace("expires=", "expires=1"),
+            None,
+            "",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = app
+        .clone()
+        .oneshot(request(
+            Method::POST,
+            "/frontend/login/reset",
+            None,
+            &format!("{query}&new_password=new&confirm_password=other"),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = app
+        .clone()
+        .oneshot(request(
+            Method::POST,
+            "/frontend/login/reset",
+            None,
+            &format!("{query}&new_password=new&confirm_password=new"),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert!(
+        principal_store
+            .validate_password("alice", "new")
+            .await
+            .unwrap()
+            .is_some()
+    );
+
+    // The link only works once
+    let response = app
+        .clone()
+        .oneshot(request(Method::GET, &link, None, ""))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Principals without a local password, like those using passkeys only, can't get one
+    let bob = Principal {
+        id: "bob".to_owned(),
+        displayname: None,
+        principal_type: PrincipalType::Individual,
+        password: None,
+        memberships: vec![],
+        app_token: None,
+    };
+    principal_store
+        .insert_principal(bob.clone(), false)
+        .await
+        .unwrap();
+    let pepper = load_or_generate_app_token_pepper(&principal_store)
+        .await
+        .unwrap();
+    let expires = chrono::Utc::now().timestamp() + 3600;
+    let signature = password_reset_signature(&pepper, &bob, expires);
+    let response = app
+        .clone()
+        .oneshot(request(
+            Method::POST,
+            "/frontend/login/reset",
+            None,
+            &format!(
+                "principal=bob&expires={expires}&signature={signature}&new_password=new&confirm_password=new"
+            ),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(
+        principal_store
+            .get_principal("bob")
+            .await
+            .unwrap()
+            .unwrap()
+            .password
+            .is_none()
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_password_change(
+    #[from(test_store_context)]
+    #[future]
+    context: TestStoreContext,
+) {
+    let context = context.await;
+    let principal_store = context.principal_store.clone();
+    principal_store
+        .insert_principal(
+            Principal {
+                id: "alice".to_owned(),
+                displayname: None,
+                principal_type: PrincipalType::Individual,
+                password: Some(hash_password("old")),
+                memberships: vec![],
+                app_token: None,
+            },
+            false,
+        )
+        .await
+        .unwrap();
+    let app = get_app_with_config(context, FrontendConfig::default(), None, None);
+
+    let response = app
+        .clone()
+        .oneshot(request(
+            Method::POST,
+            "/frontend/login",
+            None,
+            "username=alice&password=old",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let cookie = response.headers()[header::SET_COOKIE]
+        .to_str()
+        .unwrap()
+        .split(';')
+        .next()
+        .unwrap()
+        .to_owned();
+
+    let response = app
+        .clone()
+        .oneshot(request(
+            Method::POST,
+            "/frontend/user/alice/password",
+            Some(&cookie),
+            "current_password=wrong&new_password=new&confirm_password=new",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = app
+        .clone()
+        .oneshot(request(
+            Method::POST,
+            "/frontend/user/alice/password",
+            Some(&cookie),
+            "current_password=old&new_password=new&confirm_password=new",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert!(
+        principal_store
+            .validate_password("alice", "new")
+            .await
+            .unwrap()
+            .is_some()
+    );
+    // The session continues under a new id, the old one was revoked
+    let new_cookie = response.headers()[header::SET_COOKIE]
+        .to_str()
+        .unwrap()
+        .split(';')
+        .next()
+        .unwrap()
+        .to_owned();
+    assert_ne!(new_cookie, cookie);
+    let response = app
+        .clone()
+        .oneshot(request(
+            Method::GET,
+            "/frontend/user/alice",
+            Some(&cookie),
+            "",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = app
+        .clone()
+        .oneshot(request(
+            Method::GET,
+            "/frontend/user/alice",
+            Some(&new_cookie),
+            "",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_app_token_cannot_change_credentials(
+    #[from(test_store_context)]
+    #[future]
+    context: TestStoreContext,
+) {
+    let app = get_app_with_config(
+        context.await,
+        FrontendConfig {
+            passkeys: Some(PasskeyConfig {
+                rp_id: "dav.example.com".to_owned(),
+                origin: "https://dav.example.com".to_owned(),
+                allow_passkey_only: true,
+            }),
+            ..Default::default()
+        },
+        None,
+        None,
+    );
+
+    // An app token must not be enough to take over the account
+    for (method, uri, form) in [
+        (Method::GET, "/frontend/user/user/totp", ""),
+        (Method::POST, "/frontend/user/user/totp", "code=000000"),
+        (
+            Method::POST,
+            "/frontend/user/user/totp/delete",
+            "code=000000",
+        ),
+        (
+            Method::POST,
+            "/frontend/user/user/passkey/start",
+            "name=key",
+        ),
+        (Method::POST, "/frontend/user/user/passkey/abc/delete", ""),
+        (Method::POST, "/frontend/user/user/password/delete", ""),
+    ] {
+        let mut request = request(method, uri, None, form);
+        request.headers_mut().insert(
+            header::AUTHORIZATION,
+            // user:pass
+            "Basic dXNlcjpwYXNz".parse().unwrap(),
+        );
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{uri}");
+    }
+}
@@ -0,0 +1,115 @@
+use super::get_app_with_proxy_auth;
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Request};
+use http::StatusCode;
+use rstest::rstest;
+use rustical_store::auth::{
+    AuthenticationProvider, PrincipalType, ProxyAuthConfig, sync_group_memberships,
+};
+use rustical_store_sqlite::tests::{TestStoreContext, test_store_context};
+use std::net::SocketAddr;
+use tower::ServiceExt;
+
+fn config() -> ProxyAuthConfig {
+    ProxyAuthConfig {
+        user_header: "Remote-User".to_owned(),
+        groups_header: Some("Remote-Groups".to_owned()),
+        trusted_proxies: vec!["10.0.0.0/8".parse().unwrap()],
+        allow_sign_up: true,
+    }
+}
+
+fn request(user: &str, groups: &str, peer: Option<&str>) -> Request {
+    let mut request = Request::builder()
+        .method("PROPFIND")
+        .uri(format!("/caldav/principal/{user}"))
+        .header("Remote-User", user)
+        .header("Remote-Groups", groups)
+        .body(Body::empty())
+        .unwrap();
+    if let Some(peer) = peer {
+        let peer: SocketAddr = peer.parse().unwrap();
+        request.extensions_mut().insert(ConnectInfo(peer));
+    }
+    request
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_proxy_auth(
+    #[from(test_store_context)]
+    #[future]
+    context: TestStoreContext,
+) {
+    let context = context.await;
+    let principal_store = context.principal_store.clone();
+    let app = get_app_with_proxy_auth(context, Some(config()));
+
+    // Untrusted or unknown peers can't set the header
+    for peer in [Some("192.168.1.2:1234"), None] {
+        let response = app
+            .clone()
+            .oneshot(request("user", "", peer))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    let response = app
+        .clone()
+        .oneshot(request("user", "", Some("10.1.2.3:1234")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+
+    // Sign up with group memberships
+    let response = app
+        .clone()
+        .oneshot(request(
+            "alice",
+            "staff, family",
+            Some("[::ffff:10.0.0.1]:1234"),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+    let mut memberships = principal_store
+        .get_principal("alice")
+        .await
+        .unwrap()
+        .unwrap()
+        .memberships;
+    memberships.sort();
+    assert_eq!(memberships, vec!["family", "staff"]);
+    let group = principal_store
+        .get_principal("staff")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(group.principal_type, PrincipalType::Group);
+
+    // The groups follow the header, other principals can't be joined through it
+    app.oneshot(request("alice", "staff, user", Some("10.0.0.1:1234")))
+        .await
+        .unwrap();
+    let memberships = principal_store
+        .get_principal("alice")
+        .await
+        .unwrap()
+        .unwrap()
+        .memberships;
+    assert_eq!(memberships, vec!["staff"]);
+
+    // Skipped principals don't count as a change on later requests
+    let alice = principal_store
+        .get_principal("alice")
+        .await
+        .unwrap()
+        .unwrap();
+    let groups = ["staff".to_owned(), "user".to_owned()];
+    assert!(
+        !sync_group_memberships(&principal_store, &alice, &groups)
+            .await
+            .unwrap()
+    );
+}
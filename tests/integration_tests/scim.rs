@@ -0,0 +1,263 @@
+use super::{ResponseExtractString, get_app_with_config};
+use axum::body::Body;
+use axum::extract::Request;
+use http::{Method, StatusCode};
+use rstest::rstest;
+use rustical_frontend::FrontendConfig;
+use rustical_scim::ScimConfig;
+use rustical_store::auth::{AppTokenScope, AuthenticationProvider, PrincipalType};
+use rustical_store::{Calendar, CalendarReadStore, CalendarWriteStore};
+use rustical_store_sqlite::tests::{TestStoreContext, test_store_context};
+use serde_json::{Value, json};
+use tower::ServiceExt;
+
+const TOKEN: &str = "0123456789abcdef0123456789abcdef";
+
+fn request(method: Method, uri: &str, body: Option<Value>) -> Request {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Authorization", format!("Bearer {TOKEN}"))
+        .header("Content-Type", "application/scim+json")
+        .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+        .unwrap()
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_scim(
+    #[from(test_store_context)]
+    #[future]
+    context: TestStoreContext,
+) {
+    let context = context.await;
+    let principal_store = context.principal_store.clone();
+    let cal_store = context.cal_store.clone();
+    let app = get_app_with_config(
+        context,
+        FrontendConfig::default(),
+        None,
+        Some(ScimConfig {
+            token: TOKEN.to_owned(),
+            identity_provider: Some("company".to_owned()),
+        }),
+    );
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/scim/v2/Users")
+                .header("Authorization", "Bearer wrong")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = app
+        .clone()
+        .oneshot(request(
+            Method::POST,
+            "/scim/v2/Users",
+            Some(json!({
+                "schemas": ["urn:ietf:params:scim:schemas:core:2.0:User"],
+                "userName": "alice",
+                "displayName": "Alice",
+                "active": true,
+                "password": "secret",
+                "emails": [{"value": "alice@example.com", "primary": true}]
+            })),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let alice = principal_store
+        .get_principal("alice")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(alice.displayname.as_deref(), Some("Alice"));
+    assert_eq!(
+        principal_store.get_email("alice").await.unwrap().as_deref(),
+        Some("alice@example.com")
+    );
+    // Users log in through the OIDC provider they are provisioned from
+    assert_eq!(
+        principal_store
+            .get_identity_provider("alice")
+            .await
+            .unwrap()
+            .as_deref(),
+        Some("company")
+    );
+
+    let response = app
+        .clone()
+        .oneshot(request(
+            Method::GET,
+            "/scim/v2/Users?filter=userName%20eq%20%22alice%22",
+            None,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let list: Value = serde_json::from_str(&response.extract_string().await).unwrap();
+    assert_eq!(list["totalResults"], 1);
+    assert_eq!(list["Resources"][0]["id"], "alice");
+    assert_eq!(
+        list["Resources"][0]["emails"][0]["value"],
+        "alice@example.com"
+    );
+
+    let response = app
+        .clone()
+        .oneshot(request(
+            Method::POST,
+            "/scim/v2/Groups",
+            Some(json!({
+                "schemas": ["urn:ietf:params:scim:schemas:core:2.0:Group"],
+                "displayName": "staff",
+                "members": [{"value": "alice"}]
+            })),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let staff = principal_store
+        .get_principal("staff")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(staff.principal_type, PrincipalType::Group);
+    assert_eq!(
+        principal_store.list_members("staff").await.unwrap(),
+        vec!["alice"]
+    );
+
+    let response = app
+        .clone()
+        .oneshot(request(
+            Method::PATCH,
+            "/scim/v2/Groups/staff",
+            Some(json!({
+                "schemas": ["urn:ietf:params:scim:api:messages:2.0:PatchOp"],
+                "Operations": [{"op": "Remove", "path": "members[value eq \"alice\"]"}]
+            })),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(
+        principal_store
+            .list_members("staff")
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    // Deactivating a user in the identity provider revokes its access but keeps its collections
+    cal_store
+        .insert_calendar(Calendar {
+            principal: "alice".to_owned(),
+            id: "work".to_owned(),
+            push_topic: "alice-work".to_owned(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    principal_store
+        .add_app_token(
+            "alice",
+            "phone".to_owned(),
+            "token".to_owned(),
+            AppTokenScope::default(),
+            None,
+        )
+        .await
+        .unwrap();
+    let response = app
+        .clone()
+        .oneshot(request(
+            Method::PATCH,
+            "/scim/v2/Users/alice",
+            Some(json!({
+                "schemas": ["urn:ietf:params:scim:api:messages:2.0:PatchOp"],
+                "Operations": [{"op": "Replace", "path": "active", "value": "False"}]
+            })),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let user: Value = serde_json::from_str(&response.extract_string().await).unwrap();
+    assert_eq!(user["active"], false);
+    let alice = principal_store
+        .get_principal("alice")
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(alice.password.is_none());
+    assert!(!principal_store.is_active("alice").await.unwrap());
+    assert!(
+        principal_store
+            .get_app_tokens("alice")
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(
+        principal_store
+            .validate_password("alice", "secret")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(cal_store.get_calendar("alice", "work", false).await.is_ok());
+
+    let response = app
+        .clone()
+        .oneshot(request(Method::GET, "/scim/v2/Users/alice", None))
+        .await
+        .unwrap();
+    let user: Value = serde_json::from_str(&response.extract_string().await).unwrap();
+    assert_eq!(user["active"], false);
+
+    let response = app
+        .clone()
+        .oneshot(request(
+            Method::PATCH,
+            "/scim/v2/Users/alice",
+            Some(json!({
+                "schemas": ["urn:ietf:params:scim:api:messages:2.0:PatchOp"],
+                "Operations": [{"op": "replace", "path": "active", "value": true}]
+            })),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(principal_store.is_active("alice").await.unwrap());
+
+    // Deleting a user deactivates it as well
+    let response = app
+        .clone()
+        .oneshot(request(Method::DELETE, "/scim/v2/Users/alice", None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(!principal_store.is_active("alice").await.unwrap());
+    assert!(cal_store.get_calendar("alice", "work", false).await.is_ok());
+
+    let response = app
+        .oneshot(request(Method::DELETE, "/scim/v2/Groups/staff", None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(
+        principal_store
+            .get_principal("staff")
+            .await
+            .unwrap()
+            .is_none()
+    );
+}